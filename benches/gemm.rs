@@ -0,0 +1,66 @@
+// Compare the blocked gemm kernels against the naive triple loop.
+//
+// Run with `cargo bench --bench gemm`.
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use transformer_oxide::matrix::gemm::{gemm_with_kernel, naive_gemm, Kernel, MatRef};
+
+const MIN_RUN_TIME: Duration = Duration::from_millis(500);
+const SIZES: [usize; 5] = [32, 64, 128, 256, 512];
+
+// The mean time per call, repeating until enough time has passed to be stable
+fn time_per_call(mut call: impl FnMut()) -> Duration {
+    call();
+    let start = Instant::now();
+    let mut n_calls: u32 = 0;
+    while n_calls < 3 || start.elapsed() < MIN_RUN_TIME {
+        call();
+        n_calls += 1;
+    }
+    start.elapsed() / n_calls
+}
+
+fn gflops(size: usize, time: Duration) -> f64 {
+    2. * (size as f64).powi(3) / time.as_secs_f64() / 1e9
+}
+
+fn main() {
+    let mut kernels = vec![Kernel::Generic];
+    if Kernel::detect() != Kernel::Generic {
+        kernels.push(Kernel::detect());
+    }
+
+    println!(
+        "{:>6} {:>14} {}",
+        "size",
+        "naive GFLOP/s",
+        kernels
+            .iter()
+            .map(|kernel| format!("{:>24}", format!("{kernel:?} GFLOP/s (x)")))
+            .collect::<String>()
+    );
+    for size in SIZES {
+        let a: Vec<f32> = (0..size * size).map(|i| (i % 13) as f32 - 6.).collect();
+        let b: Vec<f32> = (0..size * size).map(|i| (i % 7) as f32 - 3.).collect();
+        let mut c = vec![0.; size * size];
+        let lhs = MatRef::row_major(&a, size, size);
+        let rhs = MatRef::row_major(&b, size, size);
+
+        let naive = time_per_call(|| naive_gemm(black_box(lhs), black_box(rhs), &mut c));
+        let mut row = format!("{size:>6} {:>14.2}", gflops(size, naive));
+        for kernel in &kernels {
+            let blocked =
+                time_per_call(|| gemm_with_kernel(*kernel, black_box(lhs), black_box(rhs), &mut c));
+            row.push_str(&format!(
+                "{:>24}",
+                format!(
+                    "{:.2} ({:.1})",
+                    gflops(size, blocked),
+                    naive.as_secs_f64() / blocked.as_secs_f64()
+                )
+            ));
+        }
+        println!("{row}");
+    }
+}
@@ -0,0 +1,71 @@
+// Switching gradient tracking off, for inference and for updating parameters.
+//
+// While tracking is off, operations on variables give constants with no history, so nothing is
+// kept around for a backward pass. The setting is per thread.
+use std::cell::Cell;
+
+thread_local! {
+    static GRAD_ENABLED: Cell<bool> = const { Cell::new(true) };
+}
+
+/// Whether operations on this thread currently record the graph for a backward pass.
+pub fn is_grad_enabled() -> bool {
+    GRAD_ENABLED.with(Cell::get)
+}
+
+/// Turns gradient tracking off until it's dropped, then restores the previous setting.
+#[must_use = "tracking is turned back on as soon as the guard is dropped"]
+pub struct NoGradGuard {
+    previous: bool,
+}
+
+impl NoGradGuard {
+    pub fn new() -> Self {
+        NoGradGuard {
+            previous: GRAD_ENABLED.with(|enabled| enabled.replace(false)),
+        }
+    }
+}
+
+impl Default for NoGradGuard {
+    fn default() -> Self {
+        NoGradGuard::new()
+    }
+}
+
+impl Drop for NoGradGuard {
+    fn drop(&mut self) {
+        GRAD_ENABLED.with(|enabled| enabled.set(self.previous));
+    }
+}
+
+/// Run `f` without gradient tracking.
+pub fn no_grad<R>(f: impl FnOnce() -> R) -> R {
+    let _guard = NoGradGuard::new();
+    f()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_grad() {
+        assert!(is_grad_enabled());
+        no_grad(|| {
+            assert!(!is_grad_enabled());
+            // Nesting keeps tracking off until the outermost scope ends
+            no_grad(|| assert!(!is_grad_enabled()));
+            assert!(!is_grad_enabled());
+        });
+        assert!(is_grad_enabled());
+
+        let guard = NoGradGuard::new();
+        assert!(!is_grad_enabled());
+        drop(guard);
+        assert!(is_grad_enabled());
+
+        // Other threads are unaffected
+        no_grad(|| assert!(std::thread::spawn(is_grad_enabled).join().unwrap()));
+    }
+}
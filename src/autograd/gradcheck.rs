@@ -0,0 +1,332 @@
+// Checking backward passes against finite differences.
+//
+// The function under test is evaluated in f64 and its output projected onto fixed random
+// weights, giving a scalar whose gradient involves every output element. Each input element is
+// then nudged both ways by `eps`, and the central difference (f(x + eps) - f(x - eps)) / 2eps
+// compared with the gradient from a backward pass.
+//
+// Errors are relative to the larger of the two gradients, but never to less than one, so tiny
+// gradients are compared absolutely rather than amplifying rounding noise.
+use anyhow::Result;
+
+use crate::autograd::grad_mode::no_grad;
+use crate::autograd::variable::Var;
+use crate::matrix::init::Init;
+use crate::matrix::random::Rng;
+use crate::matrix::tensor::{unravel_index, Tensor};
+
+/// Where the analytic and numeric gradients disagree most.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorstElement {
+    /// Which input, and the index into it.
+    pub input: usize,
+    pub index: Vec<usize>,
+    pub analytic: f64,
+    pub numeric: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GradCheckReport {
+    pub max_relative_error: f64,
+    /// None when the inputs have no elements.
+    pub worst: Option<WorstElement>,
+}
+
+impl GradCheckReport {
+    pub fn passed(&self, tolerance: f64) -> bool {
+        self.max_relative_error <= tolerance
+    }
+}
+
+/// Settings for a gradient check.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GradCheck {
+    /// How far each input element is moved either way.
+    pub eps: f64,
+    /// The largest relative error `assert_passes` accepts.
+    pub tolerance: f64,
+}
+
+impl Default for GradCheck {
+    fn default() -> Self {
+        GradCheck {
+            eps: 1e-6,
+            tolerance: 1e-6,
+        }
+    }
+}
+
+impl GradCheck {
+    /// Compare the gradients of `f` at `inputs` from a backward pass with finite differences.
+    pub fn check(
+        &self,
+        f: impl Fn(&[Var<f64>]) -> Result<Var<f64>>,
+        inputs: &[Tensor<f64>],
+    ) -> Result<GradCheckReport> {
+        let params: Vec<Var<f64>> = inputs.iter().cloned().map(Var::parameter).collect();
+        let output = f(&params)?;
+        let projection: Tensor<f64> =
+            Init::Normal { mean: 0., std: 1. }.tensor(&output.shape(), &mut Rng::new(0));
+        // An output which doesn't depend on the inputs has zero gradients
+        if output.requires_grad() {
+            output
+                .mul(&Var::constant(projection.clone()))?
+                .sum()
+                .backward()?;
+        }
+        let projected = |inputs: &[Tensor<f64>]| -> Result<f64> {
+            let constants: Vec<Var<f64>> = inputs.iter().cloned().map(Var::constant).collect();
+            let output = no_grad(|| f(&constants))?;
+            let value = output.value().mul(&projection)?.sum();
+            Ok(value)
+        };
+
+        let mut report = GradCheckReport {
+            max_relative_error: 0.,
+            worst: None,
+        };
+        let mut perturbed = inputs.to_vec();
+        for (input, param) in params.iter().enumerate() {
+            let analytic = param
+                .grad()
+                .unwrap_or_else(|| Tensor::zeros(inputs[input].shape()));
+            for position in 0..inputs[input].numel() {
+                let original = inputs[input].as_slice()[position];
+                perturbed[input].as_mut_slice()[position] = original + self.eps;
+                let above = projected(&perturbed)?;
+                perturbed[input].as_mut_slice()[position] = original - self.eps;
+                let below = projected(&perturbed)?;
+                perturbed[input].as_mut_slice()[position] = original;
+
+                let numeric = (above - below) / (2. * self.eps);
+                let analytic = analytic.as_slice()[position];
+                let error = (analytic - numeric).abs() / analytic.abs().max(numeric.abs()).max(1.);
+                // NaN errors always count as the worst
+                if report.worst.is_none() || error.is_nan() || error > report.max_relative_error {
+                    report.max_relative_error = error;
+                    report.worst = Some(WorstElement {
+                        input,
+                        index: unravel_index(position, inputs[input].shape()),
+                        analytic,
+                        numeric,
+                    });
+                    if error.is_nan() {
+                        return Ok(report);
+                    }
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Check the gradients of `f`, panicking with the report if they're wrong.
+    pub fn assert_passes(
+        &self,
+        f: impl Fn(&[Var<f64>]) -> Result<Var<f64>>,
+        inputs: &[Tensor<f64>],
+    ) {
+        let report = self.check(f, inputs).unwrap();
+        assert!(
+            report.passed(self.tolerance),
+            "gradient check failed with relative error {:e} (tolerance {:e}): {:?}",
+            report.max_relative_error,
+            self.tolerance,
+            report.worst
+        );
+    }
+}
+
+/// Check the gradients of `f` with the default settings. See `GradCheck::check`.
+pub fn gradcheck(
+    f: impl Fn(&[Var<f64>]) -> Result<Var<f64>>,
+    inputs: &[Tensor<f64>],
+) -> Result<GradCheckReport> {
+    GradCheck::default().check(f, inputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Normally distributed values, which are almost surely away from any kinks
+    fn random(shape: &[usize], seed: u64) -> Tensor<f64> {
+        Init::Normal { mean: 0., std: 1. }.tensor(shape, &mut Rng::new(seed))
+    }
+
+    fn positive(shape: &[usize], seed: u64) -> Tensor<f64> {
+        random(shape, seed).map(|x| x.abs() + 0.5)
+    }
+
+    fn check(f: impl Fn(&[Var<f64>]) -> Result<Var<f64>>, inputs: &[Tensor<f64>]) {
+        GradCheck::default().assert_passes(f, inputs)
+    }
+
+    #[test]
+    fn test_detects_wrong_gradients() {
+        // x² with the gradient of x³
+        let wrong_square = |x: &[Var<f64>]| {
+            let value = x[0].value().map(|x| x * x);
+            let input = x[0].clone();
+            let backward =
+                move |grad: &Tensor<f64>| Ok(vec![grad.mul(&input.value().map(|x| 3. * x * x))?]);
+            Ok(Var::from_op(value, &[&x[0]], Box::new(backward)))
+        };
+        let report = gradcheck(wrong_square, &[random(&[3], 0)]).unwrap();
+        assert!(!report.passed(1e-3), "{report:?}");
+        let worst = report.worst.unwrap();
+        assert_eq!(worst.input, 0);
+        // 3x² against 2x, whatever the projection
+        let x = random(&[3], 0).get(&worst.index).unwrap();
+        assert!(
+            (worst.analytic / worst.numeric - 1.5 * x).abs() < 1e-6,
+            "{worst:?}"
+        );
+
+        let report = gradcheck(|x| Ok(x[0].powi(2)), &[random(&[3], 0)]).unwrap();
+        assert!(report.passed(1e-6), "{report:?}");
+        // Outputs which don't depend on the inputs have zero gradients
+        let report = gradcheck(
+            |_| Ok(Var::constant(Tensor::ones(&[2]))),
+            &[random(&[2], 0)],
+        );
+        assert_eq!(report.unwrap().max_relative_error, 0.);
+    }
+
+    #[test]
+    fn test_elementwise() {
+        let (a, b) = (random(&[2, 3], 1), random(&[2, 3], 2));
+        check(|x| x[0].add(&x[1]), &[a.clone(), b.clone()]);
+        check(|x| x[0].sub(&x[1]), &[a.clone(), b.clone()]);
+        check(|x| x[0].mul(&x[1]), &[a.clone(), b.clone()]);
+        check(|x| x[0].div(&x[1]), &[a.clone(), positive(&[2, 3], 3)]);
+        check(|x| x[0].maximum(&x[1]), &[a.clone(), b.clone()]);
+        check(|x| x[0].minimum(&x[1]), &[a.clone(), b.clone()]);
+        check(|x| x[0].pow(&x[1]), &[positive(&[2, 3], 4), b.clone()]);
+        check(|x| Ok(x[0].neg()), std::slice::from_ref(&a));
+        check(|x| Ok(x[0].scale(-2.5)), std::slice::from_ref(&a));
+        check(|x| Ok(x[0].exp()), std::slice::from_ref(&a));
+        check(|x| Ok(x[0].ln()), &[positive(&[2, 3], 5)]);
+        check(|x| Ok(x[0].sqrt()), &[positive(&[2, 3], 6)]);
+        check(|x| Ok(x[0].abs()), std::slice::from_ref(&a));
+        check(|x| Ok(x[0].powi(3)), std::slice::from_ref(&a));
+        check(|x| Ok(x[0].relu()), std::slice::from_ref(&a));
+        check(|x| Ok(x[0].tanh()), std::slice::from_ref(&a));
+        check(|x| Ok(x[0].sigmoid()), std::slice::from_ref(&a));
+        check(|x| Ok(x[0].silu()), std::slice::from_ref(&a));
+        check(|x| Ok(x[0].gelu()), std::slice::from_ref(&a));
+        let mask = Tensor::from_slice(&[1u8, 0, 0, 1, 1, 0], &[2, 3]).unwrap();
+        check(|x| x[0].masked_fill(&mask, 2.), &[a]);
+    }
+
+    #[test]
+    fn test_broadcasting() {
+        let a = random(&[2, 3, 4], 7);
+        for shape in [&[4][..], &[3, 1], &[2, 1, 4], &[]] {
+            let b = positive(shape, 8);
+            check(|x| x[0].add(&x[1]), &[a.clone(), b.clone()]);
+            check(|x| x[0].sub(&x[1]), &[b.clone(), a.clone()]);
+            check(|x| x[0].mul(&x[1]), &[a.clone(), b.clone()]);
+            check(|x| x[0].div(&x[1]), &[a.clone(), b.clone()]);
+            check(|x| x[0].maximum(&x[1]), &[a.clone(), b.clone()]);
+            check(|x| x[0].pow(&x[1]), &[b.clone(), a.clone()]);
+        }
+        check(|x| x[0].broadcast_to(&[2, 3, 4]), &[random(&[3, 1], 9)]);
+    }
+
+    #[test]
+    fn test_matmul() {
+        let (a, b) = (random(&[3, 4], 10), random(&[4, 2], 11));
+        let (batched_a, batched_b) = (random(&[2, 3, 4], 12), random(&[2, 4, 2], 13));
+        for (a, b) in [
+            (&a, &b),
+            (&batched_a, &batched_b),
+            (&batched_a, &b),
+            (&a, &batched_b),
+        ] {
+            check(|x| x[0].matmul(&x[1]), &[a.clone(), b.clone()]);
+            let bt = b.transpose(b.ndim() - 2, b.ndim() - 1).unwrap();
+            check(|x| x[0].matmul_nt(&x[1]), &[a.clone(), bt]);
+            let at = a.transpose(a.ndim() - 2, a.ndim() - 1).unwrap();
+            check(|x| x[0].matmul_tn(&x[1]), &[at, b.clone()]);
+        }
+    }
+
+    #[test]
+    fn test_reductions() {
+        let a = random(&[2, 3, 4], 14);
+        check(|x| Ok(x[0].sum()), std::slice::from_ref(&a));
+        check(|x| Ok(x[0].mean()), std::slice::from_ref(&a));
+        for axis in 0..3 {
+            for keepdim in [false, true] {
+                check(|x| x[0].sum_axis(axis, keepdim), std::slice::from_ref(&a));
+                check(|x| x[0].mean_axis(axis, keepdim), std::slice::from_ref(&a));
+                check(|x| x[0].max_axis(axis, keepdim), std::slice::from_ref(&a));
+                check(|x| x[0].var_axis(axis, keepdim), std::slice::from_ref(&a));
+                check(
+                    |x| x[0].logsumexp_axis(axis, keepdim),
+                    std::slice::from_ref(&a),
+                );
+            }
+            let mut shape = a.shape().to_vec();
+            shape.remove(axis);
+            let indices = Tensor::from_vec(
+                (0..shape.iter().product()).map(|i: usize| i % 2).collect(),
+                &shape,
+            )
+            .unwrap();
+            check(
+                |x| x[0].gather_axis(axis, &indices),
+                std::slice::from_ref(&a),
+            );
+            check(|x| x[0].softmax_axis(axis), std::slice::from_ref(&a));
+            check(|x| x[0].log_softmax_axis(axis), std::slice::from_ref(&a));
+        }
+    }
+
+    #[test]
+    fn test_shapes() {
+        let a = random(&[2, 3, 4], 15);
+        check(|x| x[0].reshape(&[6, 4]), std::slice::from_ref(&a));
+        check(|x| x[0].unsqueeze(1), std::slice::from_ref(&a));
+        check(|x| x[0].transpose(0, 2), std::slice::from_ref(&a));
+        check(|x| x[0].permute(&[1, 2, 0]), std::slice::from_ref(&a));
+        check(|x| x[0].slice(2, 1..4, 2), std::slice::from_ref(&a));
+        check(|x| x[0].narrow(1, 1, 2), std::slice::from_ref(&a));
+        check(
+            |x| {
+                let parts = x[0].split(2, 3)?;
+                parts[0]
+                    .sum_axis(2, false)?
+                    .mul(&parts[1].sum_axis(2, false)?)
+            },
+            std::slice::from_ref(&a),
+        );
+        let b = random(&[2, 1, 4], 16);
+        check(|x| Var::cat(&[&x[0], &x[1], &x[0]], 1), &[a.clone(), b]);
+        check(|x| Var::stack(&[&x[0], &x[1]], 3), &[a.clone(), a.clone()]);
+    }
+
+    #[test]
+    fn test_linear_layer() {
+        use crate::layers::linear::LinearLayer;
+        use crate::matrix::matrix::Matrix2;
+
+        // The layer's forward pass, x Wᵀ + b, written with variables
+        let linear = |x: &[Var<f64>]| x[0].matmul_nt(&x[1])?.add(&x[2]);
+        let (inputs, weights, bias) = (random(&[5, 3], 17), random(&[2, 3], 18), random(&[2], 19));
+        check(linear, &[inputs.clone(), weights.clone(), bias.clone()]);
+
+        // And it computes the same thing as the layer
+        let layer =
+            LinearLayer::<3, 2>::try_from_tensors(&weights.cast(), Some(&bias.cast())).unwrap();
+        let states: Matrix2<5, 3> = (&inputs.cast()).try_into().unwrap();
+        let params = [inputs, weights, bias].map(Var::constant);
+        let expected = linear(&params).unwrap().value().cast::<f32>();
+        crate::assert_close!(
+            layer.forward_batch(&states),
+            expected,
+            rtol = 1e-5,
+            atol = 1e-6
+        );
+    }
+}
@@ -0,0 +1,4 @@
+pub mod grad_mode;
+pub mod gradcheck;
+pub mod ops;
+pub mod variable;
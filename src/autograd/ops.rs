@@ -0,0 +1,664 @@
+// Differentiable versions of the tensor operations in `matrix` and `ops`.
+//
+// Each operation computes its value with the tensor method of the same name, and records how to
+// map the gradient of its result back to its inputs. An input which was broadcast gets the sum
+// of the gradients of every element it was repeated into.
+use std::ops::Range;
+
+use anyhow::Result;
+
+use crate::autograd::variable::Var;
+use crate::matrix::element::{Element, Float};
+use crate::matrix::tensor::{contiguous_strides, Tensor};
+use crate::ops::activation::{gelu, gelu_grad, relu, sigmoid, sigmoid_grad, silu, silu_grad};
+use crate::ops::softmax::{log_softmax_axis, softmax_axis};
+
+/// Sum a gradient over the dimensions its input was broadcast along, giving the input's shape.
+pub(crate) fn sum_to_shape<T: Float>(mut grad: Tensor<T>, shape: &[usize]) -> Result<Tensor<T>> {
+    while grad.ndim() > shape.len() {
+        grad = grad.sum_axis(0, false)?;
+    }
+    for (axis, dim) in shape.iter().enumerate() {
+        if *dim == 1 && grad.shape()[axis] != 1 {
+            grad = grad.sum_axis(axis, true)?;
+        }
+    }
+    Ok(grad)
+}
+
+/// A tensor of the given shape which is one where the position along `axis` matches the index
+/// for that lane, and zero elsewhere. `indices` has the shape with `axis` removed.
+pub(crate) fn lane_mask<T: Float>(
+    shape: &[usize],
+    axis: usize,
+    indices: &Tensor<usize>,
+) -> Tensor<T> {
+    let axis_len = shape[axis];
+    let inner: usize = shape[axis + 1..].iter().product();
+    let data = (0..shape.iter().product())
+        .map(|i: usize| {
+            let lane = (i / (axis_len * inner)) * inner + i % inner;
+            if indices.as_slice()[lane] == (i / inner) % axis_len {
+                T::ONE
+            } else {
+                T::ZERO
+            }
+        })
+        .collect();
+    Tensor::from_vec(data, shape).unwrap()
+}
+
+// Spread the gradient of a reduction along `axis` back over the input's shape
+fn unreduce<T: Float>(
+    grad: &Tensor<T>,
+    shape: &[usize],
+    axis: usize,
+    keepdim: bool,
+) -> Result<Tensor<T>> {
+    let mut kept = shape.to_vec();
+    kept[axis] = 1;
+    let grad = if keepdim {
+        grad.clone()
+    } else {
+        grad.clone().reshape(&kept)?
+    };
+    grad.broadcast_to(shape)
+}
+
+// The gradient of a slice, scattered back into zeros of the input's shape
+fn unslice<T: Float>(
+    grad: &Tensor<T>,
+    shape: &[usize],
+    dim: usize,
+    start: usize,
+    step: usize,
+) -> Tensor<T> {
+    let mut input_grad = Tensor::zeros(shape);
+    let input_strides = contiguous_strides(shape);
+    let grad_strides = contiguous_strides(grad.shape());
+    let data = input_grad.as_mut_slice();
+    for (i, value) in grad.iter().enumerate() {
+        let mut position = 0;
+        for (d, (grad_stride, input_stride)) in grad_strides.iter().zip(&input_strides).enumerate()
+        {
+            let mut index = (i / grad_stride) % grad.shape()[d];
+            if d == dim {
+                index = start + index * step;
+            }
+            position += index * input_stride;
+        }
+        data[position] = *value;
+    }
+    input_grad
+}
+
+impl<T: Float> Var<T> {
+    // An elementwise op of two broadcast inputs. `grads` maps the output gradient and the input
+    // values to gradients with the output's shape.
+    fn binary(
+        &self,
+        other: &Var<T>,
+        value: Tensor<T>,
+        grads: impl Fn(&Tensor<T>, &Tensor<T>, &Tensor<T>) -> Result<[Tensor<T>; 2]> + 'static,
+    ) -> Var<T> {
+        let (a, b) = (self.clone(), other.clone());
+        let backward = move |grad: &Tensor<T>| {
+            let (a, b) = (a.value(), b.value());
+            let [a_grad, b_grad] = grads(grad, &a, &b)?;
+            Ok(vec![
+                sum_to_shape(a_grad, a.shape())?,
+                sum_to_shape(b_grad, b.shape())?,
+            ])
+        };
+        Var::from_op(value, &[self, other], Box::new(backward))
+    }
+
+    // An op of one input, with `grad` mapping the output gradient and the input to its gradient
+    fn unary(
+        &self,
+        value: Tensor<T>,
+        grad: impl Fn(&Tensor<T>, &Tensor<T>) -> Result<Tensor<T>> + 'static,
+    ) -> Var<T> {
+        let input = self.clone();
+        let backward = move |output_grad: &Tensor<T>| Ok(vec![grad(output_grad, &input.value())?]);
+        Var::from_op(value, &[self], Box::new(backward))
+    }
+
+    pub fn add(&self, other: &Var<T>) -> Result<Var<T>> {
+        let value = self.value().add(&other.value())?;
+        Ok(self.binary(other, value, |grad, _, _| Ok([grad.clone(), grad.clone()])))
+    }
+
+    pub fn sub(&self, other: &Var<T>) -> Result<Var<T>> {
+        let value = self.value().sub(&other.value())?;
+        Ok(self.binary(other, value, |grad, _, _| {
+            Ok([grad.clone(), grad.map(|x| -x)])
+        }))
+    }
+
+    pub fn mul(&self, other: &Var<T>) -> Result<Var<T>> {
+        let value = self.value().mul(&other.value())?;
+        Ok(self.binary(other, value, |grad, a, b| Ok([grad.mul(b)?, grad.mul(a)?])))
+    }
+
+    pub fn div(&self, other: &Var<T>) -> Result<Var<T>> {
+        let value = self.value().div(&other.value())?;
+        Ok(self.binary(other, value, |grad, a, b| {
+            // d(a / b)/db = -a / b²
+            let b_grad = a.broadcast_zip(b, |x, y| -x / (y * y))?.mul(grad)?;
+            Ok([grad.div(b)?, b_grad])
+        }))
+    }
+
+    /// The elementwise maximum. Where the inputs are equal, each gets half the gradient.
+    pub fn maximum(&self, other: &Var<T>) -> Result<Var<T>> {
+        let value = self.value().maximum(&other.value())?;
+        Ok(self.binary(other, value, |grad, a, b| {
+            split_between(grad, a, b, |x, y| x > y || (y.is_nan() && !x.is_nan()))
+        }))
+    }
+
+    /// The elementwise minimum. Where the inputs are equal, each gets half the gradient.
+    pub fn minimum(&self, other: &Var<T>) -> Result<Var<T>> {
+        let value = self.value().minimum(&other.value())?;
+        Ok(self.binary(other, value, |grad, a, b| {
+            split_between(grad, a, b, |x, y| x < y || (y.is_nan() && !x.is_nan()))
+        }))
+    }
+
+    pub fn pow(&self, exponent: &Var<T>) -> Result<Var<T>> {
+        let value = self.value().pow(&exponent.value())?;
+        Ok(self.binary(exponent, value, |grad, a, b| {
+            let a_grad = a.broadcast_zip(b, |x, y| y * x.powf(y - T::ONE))?;
+            // d(aᵇ)/db = aᵇ ln(a), which is taken to be zero where aᵇ is
+            let b_grad = a.broadcast_zip(b, |x, y| {
+                let power = x.powf(y);
+                if power == T::ZERO {
+                    T::ZERO
+                } else {
+                    power * x.ln()
+                }
+            })?;
+            Ok([a_grad.mul(grad)?, b_grad.mul(grad)?])
+        }))
+    }
+
+    /// See `Tensor::matmul`. A two-dimensional operand used for every batch gets the sum of
+    /// the gradients from each.
+    pub fn matmul(&self, other: &Var<T>) -> Result<Var<T>> {
+        let value = self.value().matmul(&other.value())?;
+        Ok(self.binary(other, value, |grad, a, b| {
+            Ok([grad.matmul_nt(b)?, a.matmul_tn(grad)?])
+        }))
+    }
+
+    /// See `Tensor::matmul_nt`.
+    pub fn matmul_nt(&self, other: &Var<T>) -> Result<Var<T>> {
+        let value = self.value().matmul_nt(&other.value())?;
+        Ok(self.binary(other, value, |grad, a, b| {
+            Ok([grad.matmul(b)?, grad.matmul_tn(a)?])
+        }))
+    }
+
+    /// See `Tensor::matmul_tn`.
+    pub fn matmul_tn(&self, other: &Var<T>) -> Result<Var<T>> {
+        let value = self.value().matmul_tn(&other.value())?;
+        Ok(self.binary(other, value, |grad, a, b| {
+            Ok([b.matmul_nt(grad)?, a.matmul(grad)?])
+        }))
+    }
+
+    pub fn neg(&self) -> Var<T> {
+        let value = self.value().map(|x| -x);
+        self.unary(value, |grad, _| Ok(grad.map(|x| -x)))
+    }
+
+    /// Multiply every element by a constant.
+    pub fn scale(&self, factor: T) -> Var<T> {
+        let value = self.value().map(|x| x * factor);
+        self.unary(value, move |grad, _| Ok(grad.map(|x| x * factor)))
+    }
+
+    pub fn exp(&self) -> Var<T> {
+        let value = self.value().map(T::exp);
+        let output = value.clone();
+        self.unary(value, move |grad, _| grad.mul(&output))
+    }
+
+    pub fn ln(&self) -> Var<T> {
+        let value = self.value().map(T::ln);
+        self.unary(value, |grad, input| grad.div(input))
+    }
+
+    pub fn sqrt(&self) -> Var<T> {
+        let value = self.value().map(T::sqrt);
+        let output = value.clone();
+        self.unary(value, move |grad, _| {
+            grad.zip_map(&output, |g, y| g / (y + y))
+        })
+    }
+
+    /// The absolute value, whose gradient is taken to be zero at zero.
+    pub fn abs(&self) -> Var<T> {
+        let value = self.value().map(T::abs);
+        self.unary(value, |grad, input| {
+            grad.zip_map(input, |g, x| {
+                if x > T::ZERO {
+                    g
+                } else if x < T::ZERO {
+                    -g
+                } else {
+                    T::ZERO
+                }
+            })
+        })
+    }
+
+    pub fn powi(&self, exponent: i32) -> Var<T> {
+        let value = self.value().map(|x| x.powi(exponent));
+        self.unary(value, move |grad, input| {
+            let n = T::from_f64(exponent as f64);
+            grad.zip_map(input, |g, x| g * n * x.powi(exponent - 1))
+        })
+    }
+
+    /// See `ops::activation::relu`. The gradient is taken to be zero at zero.
+    pub fn relu(&self) -> Var<T> {
+        let value = self.value().map(relu);
+        self.unary(value, |grad, input| {
+            grad.zip_map(input, |g, x| if x > T::ZERO { g } else { T::ZERO })
+        })
+    }
+
+    pub fn tanh(&self) -> Var<T> {
+        let value = self.value().map(T::tanh);
+        let output = value.clone();
+        self.unary(value, move |grad, _| {
+            grad.zip_map(&output, |g, y| g * (T::ONE - y * y))
+        })
+    }
+
+    /// See `ops::activation::sigmoid`.
+    pub fn sigmoid(&self) -> Var<T> {
+        let value = self.value().map(sigmoid);
+        self.unary(value, |grad, input| {
+            grad.zip_map(input, |g, x| g * sigmoid_grad(x))
+        })
+    }
+
+    /// See `ops::activation::silu`.
+    pub fn silu(&self) -> Var<T> {
+        let value = self.value().map(silu);
+        self.unary(value, |grad, input| {
+            grad.zip_map(input, |g, x| g * silu_grad(x))
+        })
+    }
+
+    /// See `ops::activation::gelu`.
+    pub fn gelu(&self) -> Var<T> {
+        let value = self.value().map(gelu);
+        self.unary(value, |grad, input| {
+            grad.zip_map(input, |g, x| g * gelu_grad(x))
+        })
+    }
+
+    /// See `ops::softmax::softmax_axis`.
+    pub fn softmax_axis(&self, axis: usize) -> Result<Var<T>> {
+        let value = softmax_axis(&self.value(), axis)?;
+        let output = value.clone();
+        Ok(self.unary(value, move |grad, _| {
+            // y ⊙ (g - Σ g ⊙ y)
+            let weighted = grad.mul(&output)?.sum_axis(axis, true)?;
+            grad.sub(&weighted)?.mul(&output)
+        }))
+    }
+
+    /// See `ops::softmax::log_softmax_axis`.
+    pub fn log_softmax_axis(&self, axis: usize) -> Result<Var<T>> {
+        let value = log_softmax_axis(&self.value(), axis)?;
+        let output = value.clone();
+        Ok(self.unary(value, move |grad, _| {
+            // g - softmax ⊙ Σ g
+            let total = grad.sum_axis(axis, true)?;
+            grad.sub(&output.map(T::exp).mul(&total)?)
+        }))
+    }
+
+    /// The sum of every element, as a scalar.
+    pub fn sum(&self) -> Var<T> {
+        let value = Tensor::scalar(self.value().sum());
+        self.unary(value, |grad, input| grad.broadcast_to(input.shape()))
+    }
+
+    /// The mean of every element, as a scalar.
+    pub fn mean(&self) -> Var<T> {
+        let value = Tensor::scalar(self.value().mean());
+        self.unary(value, |grad, input| {
+            let n = T::from_usize(input.numel());
+            Ok(grad.broadcast_to(input.shape())?.map(|g| g / n))
+        })
+    }
+
+    pub fn sum_axis(&self, axis: usize, keepdim: bool) -> Result<Var<T>> {
+        let value = self.value().sum_axis(axis, keepdim)?;
+        Ok(self.unary(value, move |grad, input| {
+            unreduce(grad, input.shape(), axis, keepdim)
+        }))
+    }
+
+    pub fn mean_axis(&self, axis: usize, keepdim: bool) -> Result<Var<T>> {
+        let value = self.value().mean_axis(axis, keepdim)?;
+        Ok(self.unary(value, move |grad, input| {
+            let n = T::from_usize(input.shape()[axis]);
+            Ok(unreduce(grad, input.shape(), axis, keepdim)?.map(|g| g / n))
+        }))
+    }
+
+    /// See `Tensor::max_axis`. The gradient goes to the first largest element of each lane.
+    pub fn max_axis(&self, axis: usize, keepdim: bool) -> Result<Var<T>> {
+        let value = self.value().max_axis(axis, keepdim)?;
+        Ok(self.unary(value, move |grad, input| {
+            let mask = lane_mask(input.shape(), axis, &input.argmax_axis(axis)?);
+            unreduce(grad, input.shape(), axis, keepdim)?.mul(&mask)
+        }))
+    }
+
+    /// See `Tensor::gather_axis`. The gradient goes to the gathered elements.
+    pub fn gather_axis(&self, axis: usize, indices: &Tensor<usize>) -> Result<Var<T>> {
+        let value = self.value().gather_axis(axis, indices)?;
+        let indices = indices.clone();
+        Ok(self.unary(value, move |grad, input| {
+            let mask = lane_mask(input.shape(), axis, &indices);
+            unreduce(grad, input.shape(), axis, false)?.mul(&mask)
+        }))
+    }
+
+    /// See `Tensor::masked_fill`. The replaced elements get no gradient.
+    pub fn masked_fill<M: Element>(&self, mask: &Tensor<M>, value: T) -> Result<Var<T>> {
+        let filled = self.value().masked_fill(mask, value)?;
+        let mask = mask.clone();
+        Ok(self.unary(filled, move |grad, _| grad.masked_fill(&mask, T::ZERO)))
+    }
+
+    pub fn var_axis(&self, axis: usize, keepdim: bool) -> Result<Var<T>> {
+        let value = self.value().var_axis(axis, keepdim)?;
+        Ok(self.unary(value, move |grad, input| {
+            // d(Σ (x - μ)² / n)/dx = 2 (x - μ) / n, as the deviations sum to zero
+            let n = T::from_usize(input.shape()[axis]);
+            let deviations = input.sub(&input.mean_axis(axis, true)?)?;
+            let scaled = deviations.map(|d| (d + d) / n);
+            unreduce(grad, input.shape(), axis, keepdim)?.mul(&scaled)
+        }))
+    }
+
+    pub fn logsumexp_axis(&self, axis: usize, keepdim: bool) -> Result<Var<T>> {
+        let value = self.value().logsumexp_axis(axis, keepdim)?;
+        Ok(self.unary(value, move |grad, input| {
+            let mut softmax = input.sub(&input.logsumexp_axis(axis, true)?)?;
+            softmax.map_inplace(T::exp);
+            unreduce(grad, input.shape(), axis, keepdim)?.mul(&softmax)
+        }))
+    }
+
+    pub fn reshape(&self, shape: &[usize]) -> Result<Var<T>> {
+        let value = self.value().clone().reshape(shape)?;
+        Ok(self.unary(value, |grad, input| grad.clone().reshape(input.shape())))
+    }
+
+    /// Insert a dimension of size 1 at `dim`.
+    pub fn unsqueeze(&self, dim: usize) -> Result<Var<T>> {
+        let value = self.value().view().unsqueeze(dim)?.contiguous();
+        Ok(self.unary(value, |grad, input| grad.clone().reshape(input.shape())))
+    }
+
+    pub fn broadcast_to(&self, shape: &[usize]) -> Result<Var<T>> {
+        let value = self.value().broadcast_to(shape)?;
+        Ok(self.unary(value, |grad, input| {
+            sum_to_shape(grad.clone(), input.shape())
+        }))
+    }
+
+    pub fn transpose(&self, dim0: usize, dim1: usize) -> Result<Var<T>> {
+        let value = self.value().transpose(dim0, dim1)?;
+        Ok(self.unary(value, move |grad, _| grad.transpose(dim0, dim1)))
+    }
+
+    /// See `TensorView::permute`. The result is copied into a new tensor.
+    pub fn permute(&self, dims: &[usize]) -> Result<Var<T>> {
+        let value = self.value().permute(dims)?.contiguous();
+        let mut inverse = vec![0; dims.len()];
+        for (i, dim) in dims.iter().enumerate() {
+            inverse[*dim] = i;
+        }
+        Ok(self.unary(value, move |grad, _| {
+            Ok(grad.permute(&inverse)?.contiguous())
+        }))
+    }
+
+    /// See `TensorView::slice`. The result is copied into a new tensor.
+    pub fn slice(&self, dim: usize, range: Range<usize>, step: usize) -> Result<Var<T>> {
+        let value = self.value().slice(dim, range.clone(), step)?.contiguous();
+        Ok(self.unary(value, move |grad, input| {
+            Ok(unslice(grad, input.shape(), dim, range.start, step))
+        }))
+    }
+
+    /// See `TensorView::narrow`. The result is copied into a new tensor.
+    pub fn narrow(&self, dim: usize, start: usize, length: usize) -> Result<Var<T>> {
+        self.slice(dim, start..start + length, 1)
+    }
+
+    /// See `TensorView::split`.
+    pub fn split(&self, dim: usize, size: usize) -> Result<Vec<Var<T>>> {
+        let value = self.value();
+        let pieces = value.split(dim, size)?;
+        let mut start = 0;
+        let mut vars = Vec::with_capacity(pieces.len());
+        for piece in pieces {
+            let length = piece.shape()[dim];
+            vars.push(self.narrow(dim, start, length)?);
+            start += length;
+        }
+        Ok(vars)
+    }
+
+    /// See `Tensor::cat`.
+    pub fn cat(parts: &[&Var<T>], dim: usize) -> Result<Var<T>> {
+        let values: Vec<_> = parts.iter().map(|part| part.value()).collect();
+        let views: Vec<_> = values.iter().map(|value| value.view()).collect();
+        let value = Tensor::cat(&views.iter().collect::<Vec<_>>(), dim)?;
+        let lengths: Vec<usize> = values.iter().map(|value| value.shape()[dim]).collect();
+        let backward = move |grad: &Tensor<T>| {
+            let mut start = 0;
+            let mut grads = Vec::with_capacity(lengths.len());
+            for length in &lengths {
+                grads.push(grad.narrow(dim, start, *length)?.contiguous());
+                start += length;
+            }
+            Ok(grads)
+        };
+        Ok(Var::from_op(value, parts, Box::new(backward)))
+    }
+
+    /// See `Tensor::stack`.
+    pub fn stack(parts: &[&Var<T>], dim: usize) -> Result<Var<T>> {
+        let unsqueezed = parts
+            .iter()
+            .map(|part| part.unsqueeze(dim))
+            .collect::<Result<Vec<Var<T>>>>()?;
+        Var::cat(&unsqueezed.iter().collect::<Vec<_>>(), dim)
+    }
+}
+
+// Give the gradient to `a` where `pick_a` holds, to `b` where it doesn't, and half to each
+// where they're equal
+fn split_between<T: Float>(
+    grad: &Tensor<T>,
+    a: &Tensor<T>,
+    b: &Tensor<T>,
+    pick_a: impl Fn(T, T) -> bool + Sync + Send,
+) -> Result<[Tensor<T>; 2]> {
+    let half = T::from_f64(0.5);
+    let a_share = a.broadcast_zip(b, |x, y| {
+        if x == y {
+            half
+        } else if pick_a(x, y) {
+            T::ONE
+        } else {
+            T::ZERO
+        }
+    })?;
+    let b_share = a_share.map(|share| T::ONE - share);
+    Ok([grad.mul(&a_share)?, grad.mul(&b_share)?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_close;
+
+    fn tensor(data: &[f32], shape: &[usize]) -> Tensor {
+        Tensor::from_slice(data, shape).unwrap()
+    }
+
+    #[test]
+    fn test_broadcasting() {
+        let a = Var::parameter(tensor(&[1., 2., 3., 4., 5., 6.], &[2, 3]));
+        let b = Var::parameter(tensor(&[10., 20., 30.], &[3]));
+        let c = Var::parameter(tensor(&[2., 3.], &[2, 1]));
+        let loss = a.add(&b).unwrap().mul(&c).unwrap().sum();
+        loss.backward().unwrap();
+        assert_eq!(
+            a.grad().unwrap(),
+            tensor(&[2., 2., 2., 3., 3., 3.], &[2, 3])
+        );
+        assert_eq!(b.grad().unwrap(), tensor(&[5., 5., 5.], &[3]));
+        assert_eq!(c.grad().unwrap(), tensor(&[66., 75.], &[2, 1]));
+    }
+
+    #[test]
+    fn test_matmul() {
+        let a = Var::parameter(tensor(&[1., 2., 3., 4., 5., 6.], &[2, 3]));
+        let b = Var::parameter(tensor(&[1., 0., -1., 2., 0.5, 1.], &[3, 2]));
+        a.matmul(&b).unwrap().sum().backward().unwrap();
+        // The gradient of Σ AB is 1Bᵀ for A and Aᵀ1 for B
+        assert_eq!(
+            a.grad().unwrap(),
+            tensor(&[1., 1., 1.5, 1., 1., 1.5], &[2, 3])
+        );
+        assert_eq!(
+            b.grad().unwrap(),
+            tensor(&[5., 5., 7., 7., 9., 9.], &[3, 2])
+        );
+
+        // The same products written with the transposing variants, batched over a shared B
+        let batched = Var::parameter(Tensor::stack(&[&a.value().view(); 2], 0).unwrap());
+        let bt = Var::parameter(b.value().transpose(0, 1).unwrap());
+        batched.matmul_nt(&bt).unwrap().sum().backward().unwrap();
+        assert_eq!(
+            bt.grad().unwrap(),
+            b.grad().unwrap().transpose(0, 1).unwrap().map(|x| 2. * x)
+        );
+        let at = Var::parameter(a.value().transpose(0, 1).unwrap());
+        at.matmul_tn(&b).unwrap().sum().backward().unwrap();
+        assert_eq!(
+            at.grad().unwrap(),
+            a.grad().unwrap().transpose(0, 1).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_reductions() {
+        let x = Var::parameter(tensor(&[1., 5., 3., 5., 2., 0.], &[2, 3]));
+        x.max_axis(1, false).unwrap().sum().backward().unwrap();
+        assert_eq!(
+            x.grad().unwrap(),
+            tensor(&[0., 1., 0., 1., 0., 0.], &[2, 3])
+        );
+
+        x.zero_grad();
+        x.mean_axis(0, true).unwrap().sum().backward().unwrap();
+        assert_eq!(x.grad().unwrap(), Tensor::full(&[2, 3], 0.5));
+
+        // Softmax outputs sum to one, so the gradient of their sum is zero
+        x.zero_grad();
+        x.softmax_axis(1).unwrap().sum().backward().unwrap();
+        assert_close!(x.grad().unwrap(), Tensor::zeros(&[2, 3]), atol = 1e-6);
+    }
+
+    #[test]
+    fn test_shapes() {
+        let x = Var::parameter(tensor(&[1., 2., 3., 4., 5., 6.], &[2, 3]));
+        let weights = Var::constant(tensor(&[1., 2., 3., 4., 5., 6.], &[3, 2]));
+        let parts = x.split(1, 2).unwrap();
+        assert_eq!(parts[1].shape(), [2, 1]);
+        let rejoined = Var::cat(&[&parts[1], &parts[0]], 1).unwrap();
+        let moved = rejoined.transpose(0, 1).unwrap().mul(&weights).unwrap();
+        moved.slice(0, 0..3, 2).unwrap().sum().backward().unwrap();
+        // Row 0 of the transpose is column 2 of x and row 2 is column 1
+        assert_eq!(
+            x.grad().unwrap(),
+            tensor(&[0., 5., 1., 0., 6., 2.], &[2, 3])
+        );
+
+        let stacked = Var::stack(&[&x, &x], 0).unwrap();
+        assert_eq!(stacked.shape(), [2, 2, 3]);
+        x.zero_grad();
+        stacked
+            .permute(&[2, 0, 1])
+            .unwrap()
+            .reshape(&[12])
+            .unwrap()
+            .sum()
+            .backward()
+            .unwrap();
+        assert_eq!(x.grad().unwrap(), Tensor::full(&[2, 3], 2.));
+    }
+
+    #[test]
+    fn test_ties() {
+        let a = Var::parameter(tensor(&[1., 2., 3.], &[3]));
+        let b = Var::parameter(tensor(&[2., 2., 2.], &[3]));
+        a.maximum(&b).unwrap().sum().backward().unwrap();
+        assert_eq!(a.grad().unwrap(), tensor(&[0., 0.5, 1.], &[3]));
+        assert_eq!(b.grad().unwrap(), tensor(&[1., 0.5, 0.], &[3]));
+    }
+
+    #[test]
+    fn test_train_linear() {
+        use crate::layers::linear::LinearLayer;
+        use crate::matrix::init::Init;
+        use crate::matrix::random::Rng;
+
+        // Recover y = Wx + b from noiseless samples with plain gradient descent
+        let mut rng = Rng::new(0);
+        let true_weights: Tensor = Init::Normal { mean: 0., std: 1. }.tensor(&[2, 3], &mut rng);
+        let true_bias = tensor(&[0.5, -1.], &[2]);
+        let inputs = Var::constant(Init::Normal { mean: 0., std: 1. }.tensor(&[64, 3], &mut rng));
+        let targets = Var::constant(
+            inputs
+                .value()
+                .matmul_nt(&true_weights)
+                .unwrap()
+                .add(&true_bias)
+                .unwrap(),
+        );
+
+        let weights = Var::parameter(Tensor::zeros(&[2, 3]));
+        let bias = Var::parameter(Tensor::zeros(&[2]));
+        for _ in 0..200 {
+            let outputs = inputs.matmul_nt(&weights).unwrap().add(&bias).unwrap();
+            let loss = outputs.sub(&targets).unwrap().powi(2).mean();
+            loss.backward().unwrap();
+            for param in [&weights, &bias] {
+                let grad = param.grad().unwrap();
+                param.update_value(|value| value.sub_inplace(&grad.map(|g| 0.2 * g)).unwrap());
+                param.zero_grad();
+            }
+        }
+        let layer =
+            LinearLayer::<3, 2>::try_from_tensors(&weights.value(), Some(&bias.value())).unwrap();
+        assert_close!(layer.weights(), true_weights, atol = 1e-4);
+        assert_close!(layer.bias().unwrap(), true_bias, atol = 1e-4);
+    }
+}
@@ -0,0 +1,300 @@
+// Variables: tensors which remember how they were computed, so gradients can flow back to them.
+//
+// Each operation on variables records its inputs and a function mapping the gradient of its
+// output to the gradients of its inputs. `backward` walks this graph from a scalar loss in
+// reverse topological order, so each node's gradient is complete before it's passed on, and
+// adds the results into the `grad` of every leaf which requires one. Gradients accumulate over
+// backward passes until `zero_grad` is called.
+use std::cell::{Ref, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+use anyhow::Result;
+
+use crate::autograd::grad_mode::is_grad_enabled;
+use crate::exceptions::AutogradError;
+use crate::matrix::element::Float;
+use crate::matrix::tensor::Tensor;
+
+/// Maps the gradient of an operation's output to the gradients of each of its inputs.
+pub(crate) type BackwardFn<T> = Box<dyn Fn(&Tensor<T>) -> Result<Vec<Tensor<T>>>>;
+
+struct Node<T: Float> {
+    value: RefCell<Tensor<T>>,
+    // Only kept for leaves: gradients of intermediate results are dropped once passed on
+    grad: RefCell<Option<Tensor<T>>>,
+    requires_grad: bool,
+    parents: Vec<Var<T>>,
+    backward: Option<BackwardFn<T>>,
+}
+
+impl<T: Float> Drop for Node<T> {
+    fn drop(&mut self) {
+        // Dropping a long chain of operations recursively would overflow the stack, so unlink
+        // the nodes nothing else refers to one at a time
+        drop(self.backward.take());
+        let mut stack = std::mem::take(&mut self.parents);
+        while let Some(var) = stack.pop() {
+            if let Ok(mut node) = Rc::try_unwrap(var.0) {
+                drop(node.backward.take());
+                stack.append(&mut node.parents);
+            }
+        }
+    }
+}
+
+/// A tensor in the autograd graph. Cloning a variable gives another handle to the same node.
+#[derive(Clone)]
+pub struct Var<T: Float = f32>(Rc<Node<T>>);
+
+impl<T: Float> Var<T> {
+    fn with_node(value: Tensor<T>, requires_grad: bool) -> Self {
+        Var(Rc::new(Node {
+            value: RefCell::new(value),
+            grad: RefCell::new(None),
+            requires_grad,
+            parents: vec![],
+            backward: None,
+        }))
+    }
+
+    /// A variable which never needs a gradient, such as an input or a target.
+    pub fn constant(value: Tensor<T>) -> Self {
+        Var::with_node(value, false)
+    }
+
+    /// A leaf which gradients are computed for, such as a weight.
+    pub fn parameter(value: Tensor<T>) -> Self {
+        Var::with_node(value, true)
+    }
+
+    /// The result of an operation. Its history is only recorded when gradient tracking is on
+    /// and some input requires a gradient.
+    pub(crate) fn from_op(value: Tensor<T>, parents: &[&Var<T>], backward: BackwardFn<T>) -> Self {
+        if !is_grad_enabled() || !parents.iter().any(|parent| parent.requires_grad()) {
+            return Var::constant(value);
+        }
+        Var(Rc::new(Node {
+            value: RefCell::new(value),
+            grad: RefCell::new(None),
+            requires_grad: true,
+            parents: parents.iter().map(|parent| (*parent).clone()).collect(),
+            backward: Some(backward),
+        }))
+    }
+
+    pub fn value(&self) -> Ref<'_, Tensor<T>> {
+        self.0.value.borrow()
+    }
+
+    /// Replace the value, as an optimizer does. Results already computed from the old value are
+    /// unaffected, but their gradients are computed with the new one, so update parameters
+    /// between backward passes rather than during one.
+    pub fn set_value(&self, value: Tensor<T>) {
+        *self.0.value.borrow_mut() = value;
+    }
+
+    /// Change the value in place. See `set_value`.
+    pub fn update_value<R>(&self, f: impl FnOnce(&mut Tensor<T>) -> R) -> R {
+        f(&mut self.0.value.borrow_mut())
+    }
+
+    pub fn shape(&self) -> Vec<usize> {
+        self.value().shape().to_vec()
+    }
+
+    pub fn requires_grad(&self) -> bool {
+        self.0.requires_grad
+    }
+
+    /// Whether the variable was created directly rather than computed by an operation.
+    pub fn is_leaf(&self) -> bool {
+        self.0.backward.is_none()
+    }
+
+    /// The gradient accumulated by backward passes so far, if there has been one.
+    pub fn grad(&self) -> Option<Tensor<T>> {
+        self.0.grad.borrow().clone()
+    }
+
+    /// Replace the accumulated gradient, e.g. after clipping it.
+    pub fn set_grad(&self, grad: Option<Tensor<T>>) {
+        *self.0.grad.borrow_mut() = grad;
+    }
+
+    pub fn zero_grad(&self) {
+        self.set_grad(None);
+    }
+
+    /// A constant with the same value, cut off from the graph.
+    pub fn detach(&self) -> Var<T> {
+        Var::constant(self.value().clone())
+    }
+
+    /// Whether two handles refer to the same variable.
+    pub fn same(&self, other: &Var<T>) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Backpropagate from this variable, which must be a scalar such as a loss.
+    pub fn backward(&self) -> Result<()> {
+        let shape = self.shape();
+        if shape.iter().product::<usize>() != 1 {
+            Err(AutogradError::NonScalarOutput(shape.clone()))?;
+        }
+        self.backward_with(Tensor::ones(&shape))
+    }
+
+    /// Backpropagate from this variable, given the gradient of some scalar with respect to it,
+    /// which is broadcast to the variable's shape.
+    pub fn backward_with(&self, grad: Tensor<T>) -> Result<()> {
+        if !self.requires_grad() {
+            Err(AutogradError::NoGradient)?;
+        }
+        let grad = grad.broadcast_to(&self.shape())?;
+        let mut grads: HashMap<*const Node<T>, Tensor<T>> = HashMap::new();
+        grads.insert(Rc::as_ptr(&self.0), grad);
+        for var in self.topological_order() {
+            let Some(grad) = grads.remove(&Rc::as_ptr(&var.0)) else {
+                continue;
+            };
+            let Some(backward) = &var.0.backward else {
+                let mut accumulated = var.0.grad.borrow_mut();
+                match accumulated.as_mut() {
+                    Some(total) => total.add_inplace(&grad)?,
+                    None => *accumulated = Some(grad),
+                }
+                continue;
+            };
+            for (parent, parent_grad) in var.0.parents.iter().zip(backward(&grad)?) {
+                if !parent.requires_grad() {
+                    continue;
+                }
+                match grads.get_mut(&Rc::as_ptr(&parent.0)) {
+                    Some(total) => total.add_inplace(&parent_grad)?,
+                    None => {
+                        grads.insert(Rc::as_ptr(&parent.0), parent_grad);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Every variable this one depends on which requires a gradient, each after everything
+    // computed from it
+    fn topological_order(&self) -> Vec<Var<T>> {
+        // An iterative depth-first search, as graphs can be deeper than the stack
+        let mut visited = HashSet::new();
+        let mut post_order = Vec::new();
+        let mut stack = vec![(self.clone(), false)];
+        while let Some((var, expanded)) = stack.pop() {
+            if expanded {
+                post_order.push(var);
+                continue;
+            }
+            if !visited.insert(Rc::as_ptr(&var.0)) {
+                continue;
+            }
+            stack.push((var.clone(), true));
+            for parent in &var.0.parents {
+                if parent.requires_grad() && !visited.contains(&Rc::as_ptr(&parent.0)) {
+                    stack.push((parent.clone(), false));
+                }
+            }
+        }
+        post_order.reverse();
+        post_order
+    }
+}
+
+impl<T: Float> fmt::Debug for Var<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Var")
+            .field("value", &*self.value())
+            .field("requires_grad", &self.requires_grad())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::autograd::grad_mode::no_grad;
+
+    fn scalar(value: f32) -> Tensor {
+        Tensor::scalar(value)
+    }
+
+    #[test]
+    fn test_backward() {
+        // f(x, y) = (x + y) * y, so df/dx = y and df/dy = x + 2y
+        let x = Var::parameter(scalar(2.));
+        let y = Var::parameter(scalar(3.));
+        let f = x.add(&y).unwrap().mul(&y).unwrap();
+        assert_eq!(f.value().as_slice(), &[15.]);
+        assert!(!f.is_leaf() && x.is_leaf());
+        f.backward().unwrap();
+        assert_eq!(x.grad().unwrap().as_slice(), &[3.]);
+        assert_eq!(y.grad().unwrap().as_slice(), &[8.]);
+        // Only leaves keep their gradients
+        assert!(f.grad().is_none());
+    }
+
+    #[test]
+    fn test_accumulation() {
+        let x = Var::parameter(Tensor::from_vec(vec![1., 2.], &[2]).unwrap());
+        let constant = Var::constant(Tensor::from_vec(vec![3., 4.], &[2]).unwrap());
+        let loss = x.mul(&constant).unwrap().sum();
+        loss.backward().unwrap();
+        loss.backward().unwrap();
+        assert_eq!(x.grad().unwrap().as_slice(), &[6., 8.]);
+        assert!(constant.grad().is_none());
+        x.zero_grad();
+        assert!(x.grad().is_none());
+
+        // A variable used in several places gets the sum of the gradients through each
+        let loss = x.mul(&x).unwrap().add(&x).unwrap().sum();
+        loss.backward().unwrap();
+        assert_eq!(x.grad().unwrap().as_slice(), &[3., 5.]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let x = Var::parameter(Tensor::from_vec(vec![1., 2.], &[2]).unwrap());
+        assert!(x.exp().backward().is_err());
+        x.exp()
+            .backward_with(Tensor::from_vec(vec![1., 0.], &[2]).unwrap())
+            .unwrap();
+        assert_eq!(x.grad().unwrap().as_slice(), &[1f32.exp(), 0.]);
+        assert!(x.exp().backward_with(Tensor::ones(&[3])).is_err());
+        assert!(Var::constant(scalar(1.)).backward().is_err());
+    }
+
+    #[test]
+    fn test_no_grad() {
+        let x = Var::parameter(scalar(2.));
+        let y = no_grad(|| x.mul(&x).unwrap());
+        assert!(!y.requires_grad());
+        assert!(y.backward().is_err());
+        assert!(!x.detach().requires_grad());
+        assert!(!x.detach().same(&x) && x.clone().same(&x));
+    }
+
+    #[test]
+    fn test_deep_graph() {
+        // Walking or dropping a long chain recursively would overflow this thread's small stack
+        let thread = std::thread::Builder::new().stack_size(128 * 1024);
+        let handle = thread.spawn(|| {
+            let x = Var::parameter(scalar(1.));
+            let mut y = x.clone();
+            for _ in 0..10_000 {
+                y = y.add(&x).unwrap();
+            }
+            y.backward().unwrap();
+            x.grad().unwrap().as_slice()[0]
+        });
+        assert_eq!(handle.unwrap().join().unwrap(), 10_001.);
+    }
+}
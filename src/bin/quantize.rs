@@ -0,0 +1,174 @@
+// Quantize the linear layers of a saved model and report the error this introduces.
+//
+// Usage: quantize <checkpoint> [--scheme int8|int8-asym|int4] [--group-size N] [--out <file>]
+//
+// The checkpoint is either the crate's own format or, with a `.safetensors` extension, a
+// safetensors file, whose floating point tensors are converted to f32.
+//
+// Every 2D tensor named `<layer>.weight` is quantized, and compared with the original on a
+// batch of inputs, adding `<layer>.bias` if there is one. Without a scheme, all are compared.
+//
+// With `--out`, which needs a single scheme, the quantized weights are saved along with every
+// other tensor in f32, to be read back with `io::quantized::load`.
+use std::env;
+
+use anyhow::{bail, Result};
+use transformer_oxide::io::safetensors::{DType, SafeTensors};
+use transformer_oxide::io::{checkpoint, quantized};
+use transformer_oxide::matrix::init::Init;
+use transformer_oxide::matrix::quantize::{Quantization, QuantizedMatrix};
+use transformer_oxide::matrix::random::Rng;
+use transformer_oxide::matrix::tensor::Tensor;
+
+const N_INPUTS: usize = 64;
+const DEFAULT_GROUP_SIZE: usize = 32;
+
+struct Args {
+    path: String,
+    schemes: Vec<Quantization>,
+    out: Option<String>,
+}
+
+fn parse_args() -> Result<Args> {
+    let mut path = None;
+    let mut scheme = None;
+    let mut group_size = DEFAULT_GROUP_SIZE;
+    let mut out = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scheme" => match args.next() {
+                Some(name) => scheme = Some(name),
+                None => bail!("--scheme needs int8, int8-asym or int4"),
+            },
+            "--out" => match args.next() {
+                Some(path) => out = Some(path),
+                None => bail!("--out needs a file"),
+            },
+            "--group-size" => match args.next().map(|size| size.parse()) {
+                Some(Ok(size)) => group_size = size,
+                _ => bail!("--group-size needs a number"),
+            },
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => bail!("Unexpected argument {arg}"),
+        }
+    }
+    let Some(path) = path else {
+        bail!(
+            "Usage: quantize <checkpoint> [--scheme int8|int8-asym|int4] [--group-size N] \
+             [--out <file>]"
+        );
+    };
+    let schemes = match scheme.as_deref() {
+        None if out.is_some() => bail!("--out needs a --scheme"),
+        None => vec![
+            Quantization::Int8Symmetric,
+            Quantization::Int8Asymmetric,
+            Quantization::Int4 { group_size },
+        ],
+        Some(name) => vec![Quantization::from_name(name, group_size)?],
+    };
+    Ok(Args { path, schemes, out })
+}
+
+// Normally distributed inputs, the same on every run
+fn inputs(n_inputs: usize) -> Tensor {
+    let init = Init::Normal { mean: 0., std: 1. };
+    init.tensor(&[N_INPUTS, n_inputs], &mut Rng::new(0))
+}
+
+fn norm(tensor: &Tensor) -> f32 {
+    tensor.iter().map(|x| x * x).sum::<f32>().sqrt()
+}
+
+fn relative_error(actual: &Tensor, expected: &Tensor) -> Result<f32> {
+    let difference = actual.sub(expected)?;
+    Ok(norm(&difference) / norm(expected).max(f32::MIN_POSITIVE))
+}
+
+fn load_model(path: &str) -> Result<Vec<(String, Tensor)>> {
+    if !path.ends_with(".safetensors") {
+        let checkpoint = checkpoint::load(path)?;
+        return Ok(checkpoint
+            .iter()
+            .map(|(name, tensor)| (name.to_owned(), tensor.clone()))
+            .collect());
+    }
+    let file = SafeTensors::load(path)?;
+    let floats = [DType::F16, DType::BF16, DType::F32, DType::F64];
+    file.names()
+        .filter(|name| floats.contains(&file.info(name).unwrap().dtype))
+        .map(|name| Ok((name.to_owned(), file.get_cast(name)?)))
+        .collect()
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let args = parse_args()?;
+    let model = load_model(&args.path)?;
+
+    for scheme in args.schemes {
+        println!("{scheme:?}");
+        println!(
+            "{:<24} {:>12} {:>14} {:>14} {:>12}",
+            "layer", "max error", "weight error", "output error", "size"
+        );
+        let (mut full_size, mut quantized_size) = (0, 0);
+        let mut matrices = Vec::new();
+        for (name, weights) in model.iter() {
+            let Some(layer) = name.strip_suffix(".weight") else {
+                continue;
+            };
+            if weights.ndim() != 2 {
+                continue;
+            }
+            let quantized = QuantizedMatrix::quantize(weights, scheme)?;
+            let dequantized = quantized.dequantize();
+            let max_error = dequantized
+                .zip_map(weights, |x, y| (x - y).abs())?
+                .iter()
+                .fold(0f32, |max, error| max.max(*error));
+
+            let inputs = inputs(weights.shape()[1]);
+            let mut expected = inputs.matmul_nt(weights)?;
+            let mut outputs = inputs.matmul_nt_quantized(&quantized)?;
+            let bias_name = format!("{layer}.bias");
+            if let Some((_, bias)) = model.iter().find(|(name, _)| *name == bias_name) {
+                expected.add_inplace(bias)?;
+                outputs.add_inplace(bias)?;
+            }
+
+            full_size += weights.numel() * size_of::<f32>();
+            quantized_size += quantized.size_in_bytes();
+            println!(
+                "{layer:<24} {max_error:>12.3e} {:>14.3e} {:>14.3e} {:>11.1}x",
+                relative_error(&dequantized, weights)?,
+                relative_error(&outputs, &expected)?,
+                (weights.numel() * size_of::<f32>()) as f32 / quantized.size_in_bytes() as f32
+            );
+            matrices.push((name.as_str(), quantized));
+        }
+        if quantized_size == 0 {
+            bail!("No 2D `.weight` tensors found in {}", args.path);
+        }
+        println!(
+            "Weights take {quantized_size} bytes, down from {full_size} ({:.1}x smaller)\n",
+            full_size as f32 / quantized_size as f32
+        );
+
+        if let Some(out) = &args.out {
+            let matrices: Vec<(&str, &QuantizedMatrix)> = matrices
+                .iter()
+                .map(|(name, matrix)| (*name, matrix))
+                .collect();
+            let tensors: Vec<(&str, &Tensor)> = model
+                .iter()
+                .filter(|(name, _)| !matrices.iter().any(|(quantized, _)| quantized == name))
+                .map(|(name, tensor)| (name.as_str(), tensor))
+                .collect();
+            quantized::save(out, &matrices, &tensors)?;
+            println!("Saved the quantized model to {out}");
+        }
+    }
+    Ok(())
+}
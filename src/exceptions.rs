@@ -4,6 +4,12 @@ use thiserror::Error;
 pub enum TokenizerError {
     #[error("token {0} not found in the vocab.")]
     UnrecognizedToken(u16),
+    #[error("special token {0} has no id assigned.")]
+    UnknownSpecialToken(String),
+    #[error("invalid chat template: {0}")]
+    InvalidTemplate(String),
+    #[error("no room for {0} special tokens above the vocab.")]
+    VocabOverflow(usize),
     #[error("unknown tokenizer error")]
     Unknown,
 }
@@ -12,6 +18,60 @@ pub enum TokenizerError {
 pub enum MatrixError {
     #[error("{0}")]
     MatrixError(String),
+    #[error("shape mismatch: expected {expected:?}, got {actual:?}")]
+    ShapeMismatch {
+        expected: Vec<usize>,
+        actual: Vec<usize>,
+    },
+    #[error("shapes {lhs:?} and {rhs:?} cannot be broadcast together")]
+    BroadcastMismatch { lhs: Vec<usize>, rhs: Vec<usize> },
+    #[error("index {index:?} is out of bounds for shape {shape:?}")]
+    IndexOutOfBounds {
+        index: Vec<usize>,
+        shape: Vec<usize>,
+    },
     #[error("unknown tokenizer error")]
     Unknown,
 }
+
+#[derive(Error, Debug)]
+pub enum FormatError {
+    #[error("invalid file: {0}")]
+    InvalidFile(String),
+    #[error("tensor {0} not found in the file")]
+    MissingTensor(String),
+    #[error("unsupported dtype {0}")]
+    UnsupportedDType(String),
+    #[error("dtype mismatch: expected {expected}, got {actual}")]
+    DTypeMismatch { expected: String, actual: String },
+}
+
+#[derive(Error, Debug)]
+pub enum AutogradError {
+    #[error("backward needs a scalar output or an explicit gradient, got shape {0:?}")]
+    NonScalarOutput(Vec<usize>),
+    #[error("the output doesn't depend on anything which requires a gradient")]
+    NoGradient,
+}
+
+#[derive(Error, Debug)]
+pub enum LossError {
+    #[error("target {target} is out of range for {classes} classes")]
+    TargetOutOfRange { target: usize, classes: usize },
+    #[error("label smoothing must be between 0 and 1, got {0}")]
+    InvalidLabelSmoothing(f64),
+}
+
+#[derive(Error, Debug)]
+pub enum OptimizerError {
+    #[error("parameter {0} is registered more than once")]
+    DuplicateParameter(String),
+    #[error("invalid hyperparameter: {0}")]
+    InvalidHyperparameter(String),
+    #[error("state {0} doesn't match any parameter buffer")]
+    UnknownBuffer(String),
+    #[error("state {0} is missing")]
+    MissingBuffer(String),
+    #[error("expected state for {expected} parameter groups, got {actual}")]
+    GroupCount { expected: usize, actual: usize },
+}
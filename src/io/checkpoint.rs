@@ -0,0 +1,167 @@
+// A simple file format for saving named f32 tensors, such as the weights of a model.
+//
+// Everything is little-endian: the magic bytes and a tensor count, then for each tensor its
+// name, its shape and its elements in row-major order.
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::exceptions::FormatError;
+use crate::matrix::tensor::Tensor;
+
+const MAGIC: &[u8; 8] = b"OXCKPT01";
+
+/// Named tensors loaded from a checkpoint, in the order they were saved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    tensors: Vec<(String, Tensor)>,
+}
+
+impl Checkpoint {
+    pub fn get(&self, name: &str) -> Result<&Tensor> {
+        match self.tensors.iter().find(|(key, _)| key == name) {
+            Some((_, tensor)) => Ok(tensor),
+            None => Err(FormatError::MissingTensor(name.to_owned()))?,
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.tensors.iter().any(|(key, _)| key == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Tensor)> {
+        self.tensors
+            .iter()
+            .map(|(name, tensor)| (name.as_str(), tensor))
+    }
+
+    pub fn len(&self) -> usize {
+        self.tensors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tensors.is_empty()
+    }
+}
+
+pub fn to_bytes(tensors: &[(&str, &Tensor)]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend((tensors.len() as u64).to_le_bytes());
+    for (name, tensor) in tensors {
+        bytes.extend((name.len() as u64).to_le_bytes());
+        bytes.extend(name.as_bytes());
+        bytes.extend((tensor.ndim() as u64).to_le_bytes());
+        for dim in tensor.shape() {
+            bytes.extend((*dim as u64).to_le_bytes());
+        }
+        for value in tensor.iter() {
+            bytes.extend(value.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+pub fn from_bytes(bytes: &[u8]) -> Result<Checkpoint> {
+    let mut reader = Reader { bytes };
+    if reader.take(MAGIC.len())? != MAGIC {
+        Err(FormatError::InvalidFile("not a checkpoint".to_owned()))?;
+    }
+    let n_tensors = reader.read_len()?;
+    let mut tensors = Vec::new();
+    for _ in 0..n_tensors {
+        let name_len = reader.read_len()?;
+        let name = String::from_utf8(reader.take(name_len)?.to_vec())
+            .map_err(|_| FormatError::InvalidFile("tensor name isn't UTF-8".to_owned()))?;
+        let ndim = reader.read_len()?;
+        let shape = (0..ndim)
+            .map(|_| reader.read_len())
+            .collect::<Result<Vec<usize>>>()?;
+        let numel = shape
+            .iter()
+            .try_fold(1usize, |total, dim| total.checked_mul(*dim))
+            .ok_or_else(|| FormatError::InvalidFile(format!("{name} has too many elements")))?;
+        let data = reader
+            .take(numel.saturating_mul(4))?
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        tensors.push((name, Tensor::from_vec(data, &shape)?));
+    }
+    if !reader.bytes.is_empty() {
+        Err(FormatError::InvalidFile(
+            "unexpected data after the last tensor".to_owned(),
+        ))?;
+    }
+    Ok(Checkpoint { tensors })
+}
+
+pub fn save(path: impl AsRef<Path>, tensors: &[(&str, &Tensor)]) -> Result<()> {
+    fs::write(path, to_bytes(tensors))?;
+    Ok(())
+}
+
+pub fn load(path: impl AsRef<Path>) -> Result<Checkpoint> {
+    from_bytes(&fs::read(path)?)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.bytes.len() {
+            Err(FormatError::InvalidFile(
+                "unexpected end of file".to_owned(),
+            ))?;
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn read_len(&mut self) -> Result<usize> {
+        let value = u64::from_le_bytes(self.take(8)?.try_into().unwrap());
+        usize::try_from(value)
+            .map_err(|_| FormatError::InvalidFile(format!("length {value} is too large")).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let weight = Tensor::from_vec(vec![1., -2.5, 3., 0.125, 5., 6.], &[2, 3]).unwrap();
+        let bias = Tensor::from_slice(&[0.5, f32::MIN_POSITIVE], &[2]).unwrap();
+        let scalar = Tensor::scalar(7f32);
+        let bytes = to_bytes(&[
+            ("fc.weight", &weight),
+            ("fc.bias", &bias),
+            ("scale", &scalar),
+        ]);
+
+        let checkpoint = from_bytes(&bytes).unwrap();
+        assert_eq!(checkpoint.len(), 3);
+        assert_eq!(checkpoint.get("fc.weight").unwrap(), &weight);
+        assert_eq!(checkpoint.get("fc.bias").unwrap(), &bias);
+        assert_eq!(checkpoint.get("scale").unwrap(), &scalar);
+        assert_eq!(
+            checkpoint.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+            ["fc.weight", "fc.bias", "scale"]
+        );
+        assert!(checkpoint.get("fc2.weight").is_err());
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let weight = Tensor::<f32>::zeros(&[2, 2]);
+        let bytes = to_bytes(&[("weight", &weight)]);
+        assert!(from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(from_bytes(&[bytes.as_slice(), &[0]].concat()).is_err());
+        assert!(from_bytes(b"not a checkpoint").is_err());
+        assert!(from_bytes(&[]).is_err());
+    }
+}
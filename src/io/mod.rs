@@ -0,0 +1,6 @@
+pub mod checkpoint;
+pub mod npy;
+pub mod npz;
+pub mod quantized;
+pub mod safetensors;
+mod zip;
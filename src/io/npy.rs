@@ -0,0 +1,647 @@
+// Reading and writing NumPy's `.npy` format, for moving arrays to and from Python.
+//
+// A file is a magic string and format version, then a header giving the dtype, memory order
+// and shape as a Python dict literal, padded with spaces so the data starts 64-byte aligned. The
+// elements follow as raw bytes. The format is described at
+// https://numpy.org/doc/stable/reference/generated/numpy.lib.format.html
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::exceptions::FormatError;
+use crate::matrix::element::Element;
+use crate::matrix::half::F16;
+use crate::matrix::matrix::Matrix2;
+use crate::matrix::tensor::Tensor;
+use crate::matrix::vector::FloatVector;
+
+const MAGIC: &[u8; 6] = b"\x93NUMPY";
+const ALIGNMENT: usize = 64;
+
+/// The element types which can be read and written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DType {
+    F16,
+    F32,
+    F64,
+    I32,
+    I64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    Little,
+    Big,
+}
+
+/// The order elements are stored in: row-major for C, column-major for Fortran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    C,
+    Fortran,
+}
+
+impl DType {
+    pub fn size(self) -> usize {
+        match self {
+            DType::F16 => 2,
+            DType::F32 | DType::I32 => 4,
+            DType::F64 | DType::I64 => 8,
+        }
+    }
+
+    /// The NumPy type string, such as `<f4`.
+    pub fn descr(self, byte_order: ByteOrder) -> String {
+        let kind = match self {
+            DType::F16 | DType::F32 | DType::F64 => 'f',
+            DType::I32 | DType::I64 => 'i',
+        };
+        let byte_order = match byte_order {
+            ByteOrder::Little => '<',
+            ByteOrder::Big => '>',
+        };
+        format!("{byte_order}{kind}{}", self.size())
+    }
+
+    fn parse(descr: &str) -> Result<(DType, ByteOrder)> {
+        let unsupported = || FormatError::UnsupportedDType(descr.to_owned());
+        let mut chars = descr.chars();
+        let byte_order = match chars.next() {
+            Some('<') => ByteOrder::Little,
+            Some('>') => ByteOrder::Big,
+            Some('=') if cfg!(target_endian = "little") => ByteOrder::Little,
+            Some('=') => ByteOrder::Big,
+            // Single bytes have no order
+            Some('|') => ByteOrder::Little,
+            _ => Err(unsupported())?,
+        };
+        let dtype = match chars.as_str() {
+            "f2" => DType::F16,
+            "f4" => DType::F32,
+            "f8" => DType::F64,
+            "i4" => DType::I32,
+            "i8" => DType::I64,
+            _ => Err(unsupported())?,
+        };
+        Ok((dtype, byte_order))
+    }
+}
+
+/// Element types which can be stored in `.npy` files.
+pub trait NpyElement: Element {
+    const DTYPE: DType;
+
+    fn read(bytes: &[u8], byte_order: ByteOrder) -> Self;
+    fn write(self, byte_order: ByteOrder, out: &mut Vec<u8>);
+    fn into_array(tensor: Tensor<Self>) -> NpyArray;
+    /// The tensor, if the array holds this type, and the array back if not.
+    fn from_array(array: NpyArray) -> Result<Tensor<Self>, NpyArray>;
+    fn as_tensor(array: &NpyArray) -> Option<&Tensor<Self>>;
+}
+
+/// An array of any of the supported dtypes, as read from a file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NpyArray {
+    F16(Tensor<F16>),
+    F32(Tensor<f32>),
+    F64(Tensor<f64>),
+    I32(Tensor<i32>),
+    I64(Tensor<i64>),
+}
+
+// Run the same expression on whichever tensor an array holds
+macro_rules! with_tensor {
+    ($array:expr, $tensor:ident => $body:expr) => {
+        match $array {
+            NpyArray::F16($tensor) => $body,
+            NpyArray::F32($tensor) => $body,
+            NpyArray::F64($tensor) => $body,
+            NpyArray::I32($tensor) => $body,
+            NpyArray::I64($tensor) => $body,
+        }
+    };
+}
+
+impl NpyArray {
+    pub fn dtype(&self) -> DType {
+        match self {
+            NpyArray::F16(_) => DType::F16,
+            NpyArray::F32(_) => DType::F32,
+            NpyArray::F64(_) => DType::F64,
+            NpyArray::I32(_) => DType::I32,
+            NpyArray::I64(_) => DType::I64,
+        }
+    }
+
+    pub fn shape(&self) -> &[usize] {
+        with_tensor!(self, tensor => tensor.shape())
+    }
+
+    /// The tensor, which must have exactly the type `T`.
+    pub fn into_tensor<T: NpyElement>(self) -> Result<Tensor<T>> {
+        let actual = self.dtype();
+        T::from_array(self).map_err(|_| {
+            FormatError::DTypeMismatch {
+                expected: T::DTYPE.descr(ByteOrder::Little),
+                actual: actual.descr(ByteOrder::Little),
+            }
+            .into()
+        })
+    }
+
+    /// The elements converted to any element type.
+    pub fn cast<T: Element>(&self) -> Tensor<T> {
+        with_tensor!(self, tensor => tensor.cast())
+    }
+
+    /// Encode as a `.npy` file.
+    pub fn to_bytes(&self, byte_order: ByteOrder, order: Order) -> Vec<u8> {
+        with_tensor!(self, tensor => to_bytes_with(tensor, byte_order, order))
+    }
+}
+
+impl<T: NpyElement> From<Tensor<T>> for NpyArray {
+    fn from(tensor: Tensor<T>) -> Self {
+        T::into_array(tensor)
+    }
+}
+
+macro_rules! impl_npy_element {
+    ($($type:ty: $dtype:ident),*) => {
+        $(
+            impl NpyElement for $type {
+                const DTYPE: DType = DType::$dtype;
+
+                fn read(bytes: &[u8], byte_order: ByteOrder) -> Self {
+                    let bytes = bytes.try_into().unwrap();
+                    match byte_order {
+                        ByteOrder::Little => <$type>::from_le_bytes(bytes),
+                        ByteOrder::Big => <$type>::from_be_bytes(bytes),
+                    }
+                }
+
+                fn write(self, byte_order: ByteOrder, out: &mut Vec<u8>) {
+                    match byte_order {
+                        ByteOrder::Little => out.extend(self.to_le_bytes()),
+                        ByteOrder::Big => out.extend(self.to_be_bytes()),
+                    }
+                }
+
+                fn into_array(tensor: Tensor<Self>) -> NpyArray {
+                    NpyArray::$dtype(tensor)
+                }
+
+                fn from_array(array: NpyArray) -> Result<Tensor<Self>, NpyArray> {
+                    match array {
+                        NpyArray::$dtype(tensor) => Ok(tensor),
+                        other => Err(other),
+                    }
+                }
+
+                fn as_tensor(array: &NpyArray) -> Option<&Tensor<Self>> {
+                    match array {
+                        NpyArray::$dtype(tensor) => Some(tensor),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_npy_element!(F16: F16, f32: F32, f64: F64, i32: I32, i64: I64);
+
+struct Header {
+    dtype: DType,
+    byte_order: ByteOrder,
+    order: Order,
+    shape: Vec<usize>,
+}
+
+/// Read a `.npy` file of any supported dtype.
+pub fn read_array(bytes: &[u8]) -> Result<NpyArray> {
+    let (header, data) = parse_header(bytes)?;
+    Ok(match header.dtype {
+        DType::F16 => NpyArray::F16(read_data(&header, data)?),
+        DType::F32 => NpyArray::F32(read_data(&header, data)?),
+        DType::F64 => NpyArray::F64(read_data(&header, data)?),
+        DType::I32 => NpyArray::I32(read_data(&header, data)?),
+        DType::I64 => NpyArray::I64(read_data(&header, data)?),
+    })
+}
+
+/// Read a `.npy` file whose dtype is `T`, in either byte order or memory order.
+pub fn from_bytes<T: NpyElement>(bytes: &[u8]) -> Result<Tensor<T>> {
+    read_array(bytes)?.into_tensor()
+}
+
+/// Encode as a little-endian, row-major `.npy` file, as NumPy writes by default.
+pub fn to_bytes<T: NpyElement>(tensor: &Tensor<T>) -> Vec<u8> {
+    to_bytes_with(tensor, ByteOrder::Little, Order::C)
+}
+
+pub fn to_bytes_with<T: NpyElement>(
+    tensor: &Tensor<T>,
+    byte_order: ByteOrder,
+    order: Order,
+) -> Vec<u8> {
+    let shape = match tensor.shape() {
+        [dim] => format!("({dim},)"),
+        shape => format!(
+            "({})",
+            shape
+                .iter()
+                .map(|dim| dim.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': {}, 'shape': {shape}, }}",
+        T::DTYPE.descr(byte_order),
+        if order == Order::Fortran {
+            "True"
+        } else {
+            "False"
+        },
+    );
+    // Version 1.0 stores the header length in 2 bytes, and 2.0 in 4
+    let (version, len_size) = if header.len() + ALIGNMENT < u16::MAX as usize {
+        (1, 2)
+    } else {
+        (2, 4)
+    };
+    let prefix_len = MAGIC.len() + 2 + len_size;
+    let padded_len = (prefix_len + header.len() + 1).next_multiple_of(ALIGNMENT) - prefix_len;
+    header.extend(std::iter::repeat_n(' ', padded_len - header.len() - 1));
+    header.push('\n');
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend([version, 0]);
+    if version == 1 {
+        bytes.extend((header.len() as u16).to_le_bytes());
+    } else {
+        bytes.extend((header.len() as u32).to_le_bytes());
+    }
+    bytes.extend(header.as_bytes());
+
+    let reversed: Vec<usize> = (0..tensor.ndim()).rev().collect();
+    let fortran;
+    let elements = match order {
+        Order::C => tensor.as_slice(),
+        Order::Fortran => {
+            fortran = tensor.permute(&reversed).unwrap().contiguous();
+            fortran.as_slice()
+        }
+    };
+    bytes.reserve(elements.len() * T::DTYPE.size());
+    for element in elements {
+        element.write(byte_order, &mut bytes);
+    }
+    bytes
+}
+
+pub fn save<T: NpyElement>(path: impl AsRef<Path>, tensor: &Tensor<T>) -> Result<()> {
+    fs::write(path, to_bytes(tensor))?;
+    Ok(())
+}
+
+pub fn load<T: NpyElement>(path: impl AsRef<Path>) -> Result<Tensor<T>> {
+    from_bytes(&fs::read(path)?)
+}
+
+pub fn load_array(path: impl AsRef<Path>) -> Result<NpyArray> {
+    read_array(&fs::read(path)?)
+}
+
+/// Load a one-dimensional array of exactly `SIZE` elements.
+pub fn load_vector<const SIZE: usize, T: NpyElement>(
+    path: impl AsRef<Path>,
+) -> Result<FloatVector<SIZE, T>> {
+    Ok(load(path)?.try_into()?)
+}
+
+/// Load a two-dimensional array of exactly the shape (N_ROWS, N_COLS).
+pub fn load_matrix<const N_ROWS: usize, const N_COLS: usize, T: NpyElement>(
+    path: impl AsRef<Path>,
+) -> Result<Matrix2<N_ROWS, N_COLS, T>> {
+    Ok(load(path)?.try_into()?)
+}
+
+fn invalid(message: &str) -> FormatError {
+    FormatError::InvalidFile(message.to_owned())
+}
+
+// Split a file into its header and its data
+fn parse_header(bytes: &[u8]) -> Result<(Header, &[u8])> {
+    if bytes.len() < MAGIC.len() + 4 || &bytes[..MAGIC.len()] != MAGIC {
+        Err(invalid("not a .npy file"))?;
+    }
+    let (header_len, header_start) = match bytes[MAGIC.len()] {
+        1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+        2 | 3 if bytes.len() >= 12 => (
+            u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize,
+            12,
+        ),
+        version => Err(FormatError::InvalidFile(format!(
+            "unsupported .npy version {version}"
+        )))?,
+    };
+    let header_end = header_start + header_len;
+    if bytes.len() < header_end {
+        Err(invalid("unexpected end of file"))?;
+    }
+    let text = std::str::from_utf8(&bytes[header_start..header_end])
+        .map_err(|_| invalid("header isn't UTF-8"))?;
+    let fields = parse_dict(text)?;
+
+    let field = |key: &str| {
+        fields
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value)
+            .ok_or_else(|| FormatError::InvalidFile(format!("header is missing '{key}'")))
+    };
+    let (dtype, byte_order) = match field("descr")? {
+        Value::Str(descr) => DType::parse(descr)?,
+        _ => Err(invalid("'descr' isn't a string"))?,
+    };
+    let order = match field("fortran_order")? {
+        Value::Bool(true) => Order::Fortran,
+        Value::Bool(false) => Order::C,
+        _ => Err(invalid("'fortran_order' isn't a bool"))?,
+    };
+    let shape = match field("shape")? {
+        Value::Tuple(shape) => shape.clone(),
+        _ => Err(invalid("'shape' isn't a tuple"))?,
+    };
+    let header = Header {
+        dtype,
+        byte_order,
+        order,
+        shape,
+    };
+    Ok((header, &bytes[header_end..]))
+}
+
+fn read_data<T: NpyElement>(header: &Header, data: &[u8]) -> Result<Tensor<T>> {
+    let numel = header
+        .shape
+        .iter()
+        .try_fold(1usize, |total, dim| total.checked_mul(*dim))
+        .ok_or_else(|| invalid("the shape has too many elements"))?;
+    if Some(data.len()) != numel.checked_mul(T::DTYPE.size()) {
+        Err(FormatError::InvalidFile(format!(
+            "expected {numel} elements of {} bytes, got {} bytes",
+            T::DTYPE.size(),
+            data.len()
+        )))?;
+    }
+    let elements = data
+        .chunks_exact(T::DTYPE.size())
+        .map(|bytes| T::read(bytes, header.byte_order))
+        .collect();
+    match header.order {
+        Order::C => Tensor::from_vec(elements, &header.shape),
+        Order::Fortran => {
+            // Column-major data is the row-major data of the reversed shape
+            let reversed_shape: Vec<usize> = header.shape.iter().rev().copied().collect();
+            let reversed: Vec<usize> = (0..header.shape.len()).rev().collect();
+            let tensor = Tensor::from_vec(elements, &reversed_shape)?;
+            let tensor = tensor.permute(&reversed)?.contiguous();
+            Ok(tensor)
+        }
+    }
+}
+
+// The values which appear in headers
+#[derive(Debug, PartialEq)]
+enum Value {
+    Str(String),
+    Bool(bool),
+    Tuple(Vec<usize>),
+}
+
+// Parse the header's dict literal, such as `{'descr': '<f4', 'shape': (2, 3), }`
+fn parse_dict(text: &str) -> Result<Vec<(String, Value)>> {
+    let body = text
+        .trim()
+        .strip_prefix('{')
+        .and_then(|text| text.strip_suffix('}'))
+        .ok_or_else(|| invalid("header isn't a dict"))?;
+    let mut rest = body.trim_start();
+    let mut fields = Vec::new();
+    while !rest.is_empty() {
+        let (key, after_key) = parse_string(rest)?;
+        let after_colon = after_key
+            .trim_start()
+            .strip_prefix(':')
+            .ok_or_else(|| invalid("expected ':' after a key"))?
+            .trim_start();
+        let (value, after_value) = if after_colon.starts_with(['\'', '"']) {
+            let (value, after) = parse_string(after_colon)?;
+            (Value::Str(value), after)
+        } else if let Some(after) = after_colon.strip_prefix("True") {
+            (Value::Bool(true), after)
+        } else if let Some(after) = after_colon.strip_prefix("False") {
+            (Value::Bool(false), after)
+        } else if let Some(after) = after_colon.strip_prefix('(') {
+            let end = after.find(')').ok_or_else(|| invalid("unclosed tuple"))?;
+            let dims = after[..end]
+                .split(',')
+                .map(str::trim)
+                .filter(|dim| !dim.is_empty())
+                .map(|dim| dim.trim_end_matches('L').parse::<usize>())
+                .collect::<Result<Vec<usize>, _>>()
+                .map_err(|_| invalid("shape isn't a tuple of integers"))?;
+            (Value::Tuple(dims), &after[end + 1..])
+        } else {
+            Err(invalid("unexpected value in header"))?
+        };
+        fields.push((key, value));
+        let after_value = after_value.trim_start();
+        rest = match after_value.strip_prefix(',') {
+            Some(after_comma) => after_comma.trim_start(),
+            None if after_value.is_empty() => after_value,
+            None => Err(invalid("expected ',' between fields"))?,
+        };
+    }
+    Ok(fields)
+}
+
+fn parse_string(text: &str) -> Result<(String, &str)> {
+    let quote = text
+        .chars()
+        .next()
+        .filter(|quote| *quote == '\'' || *quote == '"')
+        .ok_or_else(|| invalid("expected a string"))?;
+    let end = text[1..]
+        .find(quote)
+        .ok_or_else(|| invalid("unclosed string"))?;
+    Ok((text[1..end + 1].to_owned(), &text[end + 2..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A file as NumPy writes it, with the given header and data
+    fn npy_file(header: &str, data: &[u8]) -> Vec<u8> {
+        let mut header = header.to_owned();
+        while !(10 + header.len() + 1).is_multiple_of(ALIGNMENT) {
+            header.push(' ');
+        }
+        header.push('\n');
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend((header.len() as u16).to_le_bytes());
+        bytes.extend(header.as_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn test_matches_numpy() {
+        // np.save of np.arange(6, dtype=np.float32).reshape(2, 3)
+        let data: Vec<u8> = (0..6).flat_map(|x| (x as f32).to_le_bytes()).collect();
+        let expected = npy_file(
+            "{'descr': '<f4', 'fortran_order': False, 'shape': (2, 3), }",
+            &data,
+        );
+        let tensor = Tensor::from_vec((0..6).map(|x| x as f32).collect(), &[2, 3]).unwrap();
+        assert_eq!(to_bytes(&tensor), expected);
+        assert!((expected.len() - data.len()).is_multiple_of(ALIGNMENT));
+        assert_eq!(from_bytes::<f32>(&expected).unwrap(), tensor);
+
+        // One-dimensional and scalar shapes
+        let expected = npy_file(
+            "{'descr': '<i8', 'fortran_order': False, 'shape': (3,), }",
+            &[1i64, -2, 3].map(i64::to_le_bytes).concat(),
+        );
+        let tensor = Tensor::from_vec(vec![1i64, -2, 3], &[3]).unwrap();
+        assert_eq!(to_bytes(&tensor), expected);
+        let expected = npy_file(
+            "{'descr': '<f8', 'fortran_order': False, 'shape': (), }",
+            &2.5f64.to_le_bytes(),
+        );
+        assert_eq!(to_bytes(&Tensor::scalar(2.5f64)), expected);
+        assert_eq!(from_bytes::<f64>(&expected).unwrap().get(&[]).unwrap(), 2.5);
+    }
+
+    #[test]
+    fn test_byte_order_and_layout() {
+        // [[1, 2, 3], [4, 5, 6]] stored big-endian in column-major order
+        let data: Vec<u8> = [1, 4, 2, 5, 3, 6]
+            .iter()
+            .flat_map(|x: &i32| x.to_be_bytes())
+            .collect();
+        let bytes = npy_file(
+            "{'descr': '>i4', 'fortran_order': True, 'shape': (2, 3), }",
+            &data,
+        );
+        let expected = Tensor::from_vec(vec![1, 2, 3, 4, 5, 6], &[2, 3]).unwrap();
+        assert_eq!(from_bytes::<i32>(&bytes).unwrap(), expected);
+        assert_eq!(
+            to_bytes_with(&expected, ByteOrder::Big, Order::Fortran),
+            bytes
+        );
+
+        // Every combination round trips, for every dtype
+        let tensor =
+            Tensor::from_vec((0..24).map(|x| x as f32 / 4.).collect(), &[2, 3, 4]).unwrap();
+        for byte_order in [ByteOrder::Little, ByteOrder::Big] {
+            for order in [Order::C, Order::Fortran] {
+                let arrays: [NpyArray; 5] = [
+                    tensor.cast::<F16>().into(),
+                    tensor.clone().into(),
+                    tensor.cast::<f64>().into(),
+                    tensor.cast::<i32>().into(),
+                    tensor.cast::<i64>().into(),
+                ];
+                for array in arrays {
+                    let bytes = array.to_bytes(byte_order, order);
+                    assert_eq!(read_array(&bytes).unwrap(), array);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_half_precision() {
+        // np.array([1, -2, 65504], dtype='<f2')
+        let bytes = npy_file(
+            "{'descr': '<f2', 'fortran_order': False, 'shape': (3,), }",
+            &[0x00, 0x3c, 0x00, 0xc0, 0xff, 0x7b],
+        );
+        let array = read_array(&bytes).unwrap();
+        assert_eq!(array.dtype(), DType::F16);
+        assert_eq!(array.cast::<f32>().as_slice(), &[1., -2., 65504.]);
+    }
+
+    #[test]
+    fn test_dtype_checks() {
+        let bytes = to_bytes(&Tensor::from_vec(vec![1f32, 2.], &[2]).unwrap());
+        assert!(from_bytes::<f64>(&bytes).is_err());
+        assert_eq!(
+            read_array(&bytes).unwrap().cast::<f64>().as_slice(),
+            &[1., 2.]
+        );
+        assert_eq!(read_array(&bytes).unwrap().shape(), &[2]);
+
+        for descr in ["<u4", "<c8", "|b1", "<f16", "f4"] {
+            let bytes = npy_file(
+                &format!("{{'descr': '{descr}', 'fortran_order': False, 'shape': (0,), }}"),
+                &[],
+            );
+            assert!(read_array(&bytes).is_err(), "{descr}");
+        }
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let valid = to_bytes(&Tensor::from_vec(vec![1., 2.], &[2]).unwrap());
+        assert!(read_array(&valid[..valid.len() - 1]).is_err());
+        assert!(read_array(&[valid.as_slice(), &[0]].concat()).is_err());
+        assert!(read_array(&valid[..20]).is_err());
+        assert!(read_array(b"\x93NUMPY").is_err());
+        for header in [
+            "{'descr': '<f4', 'fortran_order': False, }",
+            "{'descr': '<f4', 'fortran_order': 0, 'shape': (2,), }",
+            "{'descr': '<f4', 'fortran_order': False, 'shape': (2, x), }",
+            "['descr', '<f4']",
+        ] {
+            assert!(read_array(&npy_file(header, &[0; 8])).is_err(), "{header}");
+        }
+    }
+
+    #[test]
+    fn test_header_variants() {
+        // Keys in any order, double quotes, no trailing comma and version 2.0
+        let header = "{\"shape\": (2,), \"fortran_order\": False, \"descr\": \"<f4\"}\n";
+        let mut bytes = b"\x93NUMPY\x02\x00".to_vec();
+        bytes.extend((header.len() as u32).to_le_bytes());
+        bytes.extend(header.as_bytes());
+        bytes.extend([1f32, 2.].map(f32::to_le_bytes).concat());
+        assert_eq!(from_bytes::<f32>(&bytes).unwrap().as_slice(), &[1., 2.]);
+    }
+
+    #[test]
+    fn test_files() {
+        let dir = std::env::temp_dir().join(format!("npy_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("matrix.npy");
+        let matrix = Matrix2::from_elements([[1., 2., 3.], [4., 5., 6.]]);
+        save(&path, &Tensor::from(&matrix)).unwrap();
+
+        assert_eq!(load_matrix::<2, 3, f32>(&path).unwrap(), matrix);
+        assert!(load_matrix::<3, 2, f32>(&path).is_err());
+        assert!(load_vector::<6, f32>(&path).is_err());
+        assert_eq!(load_array(&path).unwrap().shape(), &[2, 3]);
+
+        let vector = FloatVector::from_elements([1i64, 2]);
+        save(&path, &Tensor::from(&vector)).unwrap();
+        assert_eq!(load_vector::<2, i64>(&path).unwrap(), vector);
+        assert!(load_vector::<3, i64>(&path).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
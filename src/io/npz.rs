@@ -0,0 +1,148 @@
+// Reading and writing uncompressed `.npz` archives, as written by `np.savez`.
+//
+// An archive is a zip file with an `.npy` file for each array, named after the array.
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::exceptions::FormatError;
+use crate::io::npy::{self, ByteOrder, NpyArray, NpyElement, Order};
+use crate::io::zip;
+use crate::matrix::tensor::Tensor;
+
+/// Named arrays loaded from an archive, in the order they were stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Npz {
+    arrays: Vec<(String, NpyArray)>,
+}
+
+impl Npz {
+    pub fn get_array(&self, name: &str) -> Result<&NpyArray> {
+        match self.arrays.iter().find(|(key, _)| key == name) {
+            Some((_, array)) => Ok(array),
+            None => Err(FormatError::MissingTensor(name.to_owned()))?,
+        }
+    }
+
+    /// The array called `name`, which must have exactly the type `T`.
+    pub fn get<T: NpyElement>(&self, name: &str) -> Result<&Tensor<T>> {
+        let array = self.get_array(name)?;
+        T::as_tensor(array).ok_or_else(|| {
+            FormatError::DTypeMismatch {
+                expected: T::DTYPE.descr(ByteOrder::Little),
+                actual: array.dtype().descr(ByteOrder::Little),
+            }
+            .into()
+        })
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.arrays.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &NpyArray)> {
+        self.arrays
+            .iter()
+            .map(|(name, array)| (name.as_str(), array))
+    }
+
+    pub fn len(&self) -> usize {
+        self.arrays.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.arrays.is_empty()
+    }
+}
+
+pub fn from_bytes(bytes: &[u8]) -> Result<Npz> {
+    let arrays = zip::read_stored(bytes)?
+        .into_iter()
+        .map(|(name, data)| {
+            let name = name.strip_suffix(".npy").unwrap_or(&name).to_owned();
+            Ok((name, npy::read_array(data)?))
+        })
+        .collect::<Result<Vec<(String, NpyArray)>>>()?;
+    Ok(Npz { arrays })
+}
+
+/// Encode named arrays as an archive, which `np.load` reads back as a dict of arrays.
+pub fn to_bytes(arrays: &[(&str, NpyArray)]) -> Result<Vec<u8>> {
+    let entries: Vec<(String, Vec<u8>)> = arrays
+        .iter()
+        .map(|(name, array)| {
+            (
+                format!("{name}.npy"),
+                array.to_bytes(ByteOrder::Little, Order::C),
+            )
+        })
+        .collect();
+    zip::write_stored(&entries)
+}
+
+pub fn save(path: impl AsRef<Path>, arrays: &[(&str, NpyArray)]) -> Result<()> {
+    fs::write(path, to_bytes(arrays)?)?;
+    Ok(())
+}
+
+pub fn load(path: impl AsRef<Path>) -> Result<Npz> {
+    from_bytes(&fs::read(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matrix::half::F16;
+
+    #[test]
+    fn test_round_trip() {
+        let weights = Tensor::from_vec(vec![1., 2., 3., 4., 5., 6.], &[2, 3]).unwrap();
+        let ids = Tensor::from_vec(vec![3i64, 1, 4], &[3]).unwrap();
+        let bytes = to_bytes(&[
+            ("weights", weights.clone().into()),
+            ("ids", ids.clone().into()),
+            ("half", weights.cast::<F16>().into()),
+        ])
+        .unwrap();
+
+        let archive = from_bytes(&bytes).unwrap();
+        assert_eq!(
+            archive.names().collect::<Vec<_>>(),
+            ["weights", "ids", "half"]
+        );
+        assert_eq!(archive.get::<f32>("weights").unwrap(), &weights);
+        assert_eq!(archive.get::<i64>("ids").unwrap(), &ids);
+        assert_eq!(archive.get_array("half").unwrap().cast::<f32>(), weights);
+        assert!(archive.get::<f64>("weights").is_err());
+        assert!(archive.get::<f32>("bias").is_err());
+    }
+
+    #[test]
+    fn test_numpy_archives() {
+        // Written the way np.savez writes them, with zip64 extra fields in each local header:
+        // np.savez(path, x=np.array([[1, 2], [3, 4]], dtype=np.float32), y=np.arange(3))
+        let archive = from_bytes(include_bytes!("test_data/savez.npz")).unwrap();
+        assert_eq!(archive.len(), 2);
+        assert_eq!(
+            archive.get::<f32>("x").unwrap(),
+            &Tensor::from_vec(vec![1., 2., 3., 4.], &[2, 2]).unwrap()
+        );
+        assert_eq!(archive.get::<i64>("y").unwrap().as_slice(), &[0, 1, 2]);
+
+        // The same arrays with np.savez_compressed, which isn't supported
+        let error = from_bytes(include_bytes!("test_data/savez_compressed.npz")).unwrap_err();
+        assert!(error.to_string().contains("compressed"), "{error}");
+    }
+
+    #[test]
+    fn test_files() {
+        let dir = std::env::temp_dir().join(format!("npz_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("arrays.npz");
+        let scalar = Tensor::scalar(1.5f64);
+        save(&path, &[("scalar", scalar.clone().into())]).unwrap();
+        assert_eq!(load(&path).unwrap().get::<f64>("scalar").unwrap(), &scalar);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
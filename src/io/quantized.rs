@@ -0,0 +1,225 @@
+// Saving quantized weights, so a model only has to be quantized once.
+//
+// Files are safetensors. A quantized matrix called `name` is stored as three tensors:
+// `name.qdata` with its packed values, `name.scales` and `name.zero_points`. The `quantization`
+// metadata entry maps each matrix's name to its scheme, group size and shape, as JSON. Any other
+// tensors, such as biases, are stored as they are, in f32.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+
+use crate::exceptions::FormatError;
+use crate::io::safetensors::{self, SafeTensors, TensorData};
+use crate::matrix::quantize::{Quantization, QuantizedMatrix};
+use crate::matrix::tensor::Tensor;
+
+const QUANTIZATION_KEY: &str = "quantization";
+const SUFFIXES: [&str; 3] = [".qdata", ".scales", ".zero_points"];
+
+/// Quantized matrices and full precision tensors loaded from a file, each in the order they
+/// were saved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantizedCheckpoint {
+    pub matrices: Vec<(String, QuantizedMatrix)>,
+    pub tensors: Vec<(String, Tensor)>,
+}
+
+impl QuantizedCheckpoint {
+    pub fn matrix(&self, name: &str) -> Result<&QuantizedMatrix> {
+        match self.matrices.iter().find(|(key, _)| key == name) {
+            Some((_, matrix)) => Ok(matrix),
+            None => Err(FormatError::MissingTensor(name.to_owned()))?,
+        }
+    }
+
+    pub fn tensor(&self, name: &str) -> Result<&Tensor> {
+        match self.tensors.iter().find(|(key, _)| key == name) {
+            Some((_, tensor)) => Ok(tensor),
+            None => Err(FormatError::MissingTensor(name.to_owned()))?,
+        }
+    }
+}
+
+pub fn to_bytes(
+    matrices: &[(&str, &QuantizedMatrix)],
+    tensors: &[(&str, &Tensor)],
+) -> Result<Vec<u8>> {
+    let mut entries: Vec<(String, TensorData)> = Vec::new();
+    let mut schemes = Map::new();
+    for (name, matrix) in matrices {
+        let [rows, cols] = matrix.shape();
+        let mut scheme = json!({"scheme": matrix.scheme().name(), "shape": [rows, cols]});
+        if let Quantization::Int4 { group_size } = matrix.scheme() {
+            scheme["group_size"] = json!(group_size);
+        }
+        schemes.insert(name.to_string(), scheme);
+
+        let n_groups = matrix.scales().len();
+        let [data, scales, zero_points] = SUFFIXES.map(|suffix| format!("{name}{suffix}"));
+        entries.push((data, bytes_data(matrix.data())?));
+        entries.push((
+            scales,
+            (&Tensor::from_slice(matrix.scales(), &[n_groups])?).into(),
+        ));
+        entries.push((zero_points, bytes_data(matrix.zero_points())?));
+    }
+    for (name, tensor) in tensors {
+        entries.push((name.to_string(), (*tensor).into()));
+    }
+
+    let metadata = BTreeMap::from([(
+        QUANTIZATION_KEY.to_owned(),
+        Value::from(schemes).to_string(),
+    )]);
+    let (names, data): (Vec<String>, Vec<TensorData>) = entries.into_iter().unzip();
+    let entries: Vec<(&str, TensorData)> = names.iter().map(String::as_str).zip(data).collect();
+    safetensors::to_bytes(&entries, &metadata)
+}
+
+pub fn from_bytes(bytes: Vec<u8>) -> Result<QuantizedCheckpoint> {
+    let file = SafeTensors::from_bytes(bytes)?;
+    let schemes = file
+        .metadata()
+        .get(QUANTIZATION_KEY)
+        .ok_or_else(|| invalid("the file has no quantization metadata"))?;
+    let Ok(Value::Object(schemes)) = serde_json::from_str(schemes) else {
+        Err(invalid("the quantization metadata isn't a JSON object"))?
+    };
+
+    let mut matrices = Vec::new();
+    for (name, scheme) in &schemes {
+        let bad_scheme = || invalid(&format!("bad quantization metadata for {name}"));
+        let shape = scheme["shape"]
+            .as_array()
+            .filter(|shape| shape.len() == 2)
+            .and_then(|shape| {
+                shape
+                    .iter()
+                    .map(|dim| dim.as_u64().map(|dim| dim as usize))
+                    .collect::<Option<Vec<usize>>>()
+            })
+            .ok_or_else(bad_scheme)?;
+        let group_size = scheme["group_size"].as_u64().unwrap_or(0) as usize;
+        let scheme = Quantization::from_name(
+            scheme["scheme"].as_str().ok_or_else(bad_scheme)?,
+            group_size,
+        )?;
+        let [data, scales, zero_points] = SUFFIXES.map(|suffix| format!("{name}{suffix}"));
+        let matrix = QuantizedMatrix::from_parts(
+            scheme,
+            [shape[0], shape[1]],
+            file.get::<u8>(&data)?.into_vec(),
+            file.get::<f32>(&scales)?.into_vec(),
+            file.get::<u8>(&zero_points)?.into_vec(),
+        )?;
+        matrices.push((name.clone(), matrix));
+    }
+    // The metadata is a JSON object, so put the matrices back in the order they were stored
+    let position = |name: &str| file.names().position(|stored| stored == name);
+    matrices.sort_by_key(|(name, _)| position(&format!("{name}{}", SUFFIXES[0])));
+
+    let is_part = |name: &str| {
+        SUFFIXES.iter().any(|suffix| {
+            name.strip_suffix(suffix)
+                .is_some_and(|matrix| schemes.contains_key(matrix))
+        })
+    };
+    let tensors = file
+        .names()
+        .filter(|name| !is_part(name))
+        .map(|name| Ok((name.to_owned(), file.get_cast(name)?)))
+        .collect::<Result<_>>()?;
+    Ok(QuantizedCheckpoint { matrices, tensors })
+}
+
+pub fn save(
+    path: impl AsRef<Path>,
+    matrices: &[(&str, &QuantizedMatrix)],
+    tensors: &[(&str, &Tensor)],
+) -> Result<()> {
+    fs::write(path, to_bytes(matrices, tensors)?)?;
+    Ok(())
+}
+
+pub fn load(path: impl AsRef<Path>) -> Result<QuantizedCheckpoint> {
+    from_bytes(fs::read(path)?)
+}
+
+fn bytes_data(bytes: &[u8]) -> Result<TensorData> {
+    Ok((&Tensor::from_slice(bytes, &[bytes.len()])?).into())
+}
+
+fn invalid(message: &str) -> FormatError {
+    FormatError::InvalidFile(message.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matrix::init::Init;
+    use crate::matrix::random::Rng;
+
+    #[test]
+    fn test_round_trip() {
+        let mut rng = Rng::new(0);
+        let weights: Tensor = Init::Normal { mean: 0., std: 1. }.tensor(&[6, 10], &mut rng);
+        let int4 =
+            QuantizedMatrix::quantize(&weights, Quantization::Int4 { group_size: 4 }).unwrap();
+        let int8 = QuantizedMatrix::quantize(&weights, Quantization::Int8Asymmetric).unwrap();
+        let bias = Tensor::from_slice(&[0.5, -1., 0., 2., 3., 4.], &[6]).unwrap();
+
+        let bytes = to_bytes(
+            &[("fc2.weight", &int4), ("fc1.weight", &int8)],
+            &[("fc1.bias", &bias)],
+        )
+        .unwrap();
+        let checkpoint = from_bytes(bytes.clone()).unwrap();
+        assert_eq!(
+            checkpoint.matrices,
+            [
+                ("fc2.weight".to_owned(), int4.clone()),
+                ("fc1.weight".to_owned(), int8)
+            ]
+        );
+        assert_eq!(checkpoint.tensors, [("fc1.bias".to_owned(), bias)]);
+        assert_eq!(
+            checkpoint.matrix("fc2.weight").unwrap().dequantize(),
+            int4.dequantize()
+        );
+        assert!(checkpoint.matrix("fc1.bias").is_err());
+
+        // It's an ordinary safetensors file
+        let file = SafeTensors::from_bytes(bytes).unwrap();
+        assert_eq!(file.info("fc2.weight.qdata").unwrap().shape, [30]);
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let weights = Tensor::from_vec(vec![1., -2., 3., 4.], &[2, 2]).unwrap();
+        let matrix = QuantizedMatrix::quantize(&weights, Quantization::Int8Symmetric).unwrap();
+
+        // Plain safetensors files have no quantization metadata
+        let plain = safetensors::to_bytes(&[("w", (&weights).into())], &BTreeMap::new()).unwrap();
+        assert!(from_bytes(plain).is_err());
+
+        // Parts which don't match the recorded shape
+        let scheme = json!({"fc.weight": {"scheme": "int8", "shape": [4, 2]}}).to_string();
+        let metadata = BTreeMap::from([(QUANTIZATION_KEY.to_owned(), scheme)]);
+        let tensors = [
+            ("fc.weight.qdata", bytes_data(matrix.data()).unwrap()),
+            (
+                "fc.weight.scales",
+                (&Tensor::from_slice(matrix.scales(), &[2]).unwrap()).into(),
+            ),
+            (
+                "fc.weight.zero_points",
+                bytes_data(matrix.zero_points()).unwrap(),
+            ),
+        ];
+        let bytes = safetensors::to_bytes(&tensors, &metadata).unwrap();
+        assert!(from_bytes(bytes).is_err());
+    }
+}
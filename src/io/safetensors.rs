@@ -0,0 +1,540 @@
+// Reading and writing the safetensors format, used for most pretrained checkpoints.
+//
+// A file is an 8-byte little-endian header length, a JSON header, and then a buffer holding
+// every tensor's little-endian, row-major data back to back. The header maps each tensor's
+// name to its dtype, shape and the byte range of its data in the buffer, with an optional
+// `__metadata__` map of strings. See https://github.com/huggingface/safetensors
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use serde_json::{json, Value};
+
+use crate::exceptions::FormatError;
+use crate::matrix::element::Element;
+use crate::matrix::half::{Bf16, F16};
+use crate::matrix::tensor::Tensor;
+
+const METADATA_KEY: &str = "__metadata__";
+// The header is padded so the data starts 8-byte aligned
+const ALIGNMENT: usize = 8;
+
+/// The dtypes a safetensors file can hold. Only those with an element type can be loaded, but
+/// files with the others can still be opened to read the rest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DType {
+    Bool,
+    U8,
+    I8,
+    F8E5M2,
+    F8E4M3,
+    F8E8M0,
+    U16,
+    I16,
+    F16,
+    BF16,
+    U32,
+    I32,
+    F32,
+    U64,
+    I64,
+    F64,
+}
+
+const DTYPES: [(DType, &str, usize); 16] = [
+    (DType::Bool, "BOOL", 1),
+    (DType::U8, "U8", 1),
+    (DType::I8, "I8", 1),
+    (DType::F8E5M2, "F8_E5M2", 1),
+    (DType::F8E4M3, "F8_E4M3", 1),
+    (DType::F8E8M0, "F8_E8M0", 1),
+    (DType::U16, "U16", 2),
+    (DType::I16, "I16", 2),
+    (DType::F16, "F16", 2),
+    (DType::BF16, "BF16", 2),
+    (DType::U32, "U32", 4),
+    (DType::I32, "I32", 4),
+    (DType::F32, "F32", 4),
+    (DType::U64, "U64", 8),
+    (DType::I64, "I64", 8),
+    (DType::F64, "F64", 8),
+];
+
+impl DType {
+    pub fn size(self) -> usize {
+        DTYPES.iter().find(|(dtype, ..)| *dtype == self).unwrap().2
+    }
+
+    pub fn name(self) -> &'static str {
+        DTYPES.iter().find(|(dtype, ..)| *dtype == self).unwrap().1
+    }
+
+    fn parse(name: &str) -> Result<DType> {
+        match DTYPES.iter().find(|(_, dtype_name, _)| *dtype_name == name) {
+            Some((dtype, ..)) => Ok(*dtype),
+            None => Err(FormatError::UnsupportedDType(name.to_owned()))?,
+        }
+    }
+}
+
+/// Element types which can be stored in safetensors files.
+pub trait SafeElement: Element {
+    const DTYPE: DType;
+
+    fn read(bytes: &[u8]) -> Self;
+    fn write(self, out: &mut Vec<u8>);
+}
+
+macro_rules! impl_safe_element {
+    ($($type:ty: $dtype:ident),*) => {
+        $(
+            impl SafeElement for $type {
+                const DTYPE: DType = DType::$dtype;
+
+                fn read(bytes: &[u8]) -> Self {
+                    <$type>::from_le_bytes(bytes.try_into().unwrap())
+                }
+
+                fn write(self, out: &mut Vec<u8>) {
+                    out.extend(self.to_le_bytes())
+                }
+            }
+        )*
+    };
+}
+
+impl_safe_element!(
+    u8: U8,
+    u16: U16,
+    F16: F16,
+    Bf16: BF16,
+    u32: U32,
+    i32: I32,
+    f32: F32,
+    i64: I64,
+    f64: F64
+);
+
+/// The dtype, shape and data of a tensor, ready to be written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TensorData {
+    dtype: DType,
+    shape: Vec<usize>,
+    bytes: Vec<u8>,
+}
+
+impl<T: SafeElement> From<&Tensor<T>> for TensorData {
+    fn from(tensor: &Tensor<T>) -> Self {
+        let mut bytes = Vec::with_capacity(tensor.numel() * T::DTYPE.size());
+        for element in tensor.iter() {
+            element.write(&mut bytes);
+        }
+        TensorData {
+            dtype: T::DTYPE,
+            shape: tensor.shape().to_vec(),
+            bytes,
+        }
+    }
+}
+
+/// Where a tensor is in the file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TensorInfo {
+    pub dtype: DType,
+    pub shape: Vec<usize>,
+    // The byte range in the data buffer
+    start: usize,
+    end: usize,
+}
+
+/// A loaded safetensors file. Tensors are decoded from the buffer as they're requested.
+#[derive(Debug, Clone)]
+pub struct SafeTensors {
+    bytes: Vec<u8>,
+    data_start: usize,
+    tensors: Vec<(String, TensorInfo)>,
+    metadata: BTreeMap<String, String>,
+}
+
+impl SafeTensors {
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<SafeTensors> {
+        let (tensors, metadata, data_start) = parse_header(&bytes)?;
+        Ok(SafeTensors {
+            bytes,
+            data_start,
+            tensors,
+            metadata,
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<SafeTensors> {
+        SafeTensors::from_bytes(fs::read(path)?)
+    }
+
+    /// The tensor names, in the order their data is stored.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.tensors.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.tensors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tensors.is_empty()
+    }
+
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    pub fn info(&self, name: &str) -> Result<&TensorInfo> {
+        match self.tensors.iter().find(|(key, _)| key == name) {
+            Some((_, info)) => Ok(info),
+            None => Err(FormatError::MissingTensor(name.to_owned()))?,
+        }
+    }
+
+    /// The tensor called `name`, which must be stored with exactly the type `T`.
+    pub fn get<T: SafeElement>(&self, name: &str) -> Result<Tensor<T>> {
+        let info = self.info(name)?;
+        if info.dtype != T::DTYPE {
+            Err(FormatError::DTypeMismatch {
+                expected: T::DTYPE.name().to_owned(),
+                actual: info.dtype.name().to_owned(),
+            })?;
+        }
+        let data = &self.bytes[self.data_start + info.start..self.data_start + info.end];
+        let elements = data.chunks_exact(T::DTYPE.size()).map(T::read).collect();
+        Tensor::from_vec(elements, &info.shape)
+    }
+
+    /// The tensor called `name` converted to `T`, from any dtype with an element type.
+    pub fn get_cast<T: Element>(&self, name: &str) -> Result<Tensor<T>> {
+        Ok(match self.info(name)?.dtype {
+            DType::U8 => self.get::<u8>(name)?.cast(),
+            DType::U16 => self.get::<u16>(name)?.cast(),
+            DType::F16 => self.get::<F16>(name)?.cast(),
+            DType::BF16 => self.get::<Bf16>(name)?.cast(),
+            DType::U32 => self.get::<u32>(name)?.cast(),
+            DType::I32 => self.get::<i32>(name)?.cast(),
+            DType::F32 => self.get::<f32>(name)?.cast(),
+            DType::I64 => self.get::<i64>(name)?.cast(),
+            DType::F64 => self.get::<f64>(name)?.cast(),
+            dtype => Err(FormatError::UnsupportedDType(dtype.name().to_owned()))?,
+        })
+    }
+}
+
+/// Encode named tensors, with optional string metadata.
+pub fn to_bytes(
+    tensors: &[(&str, TensorData)],
+    metadata: &BTreeMap<String, String>,
+) -> Result<Vec<u8>> {
+    // Entries are written by hand to keep their fields in the order the reference writer uses
+    let mut entries = Vec::with_capacity(tensors.len() + 1);
+    if !metadata.is_empty() {
+        entries.push(format!("\"{METADATA_KEY}\":{}", json!(metadata)));
+    }
+    let mut names = BTreeSet::new();
+    let mut offset = 0;
+    for (name, tensor) in tensors {
+        if *name == METADATA_KEY || !names.insert(*name) {
+            Err(FormatError::InvalidFile(format!(
+                "tensor name {name} is reserved or repeated"
+            )))?;
+        }
+        let end = offset + tensor.bytes.len();
+        entries.push(format!(
+            "{}:{{\"dtype\":\"{}\",\"shape\":{},\"data_offsets\":[{offset},{end}]}}",
+            json!(name),
+            tensor.dtype.name(),
+            json!(tensor.shape),
+        ));
+        offset = end;
+    }
+    let mut header = format!("{{{}}}", entries.join(","));
+    let padded_len = header.len().next_multiple_of(ALIGNMENT);
+    header.extend(std::iter::repeat_n(' ', padded_len - header.len()));
+
+    let mut bytes = Vec::with_capacity(8 + header.len() + offset);
+    bytes.extend((header.len() as u64).to_le_bytes());
+    bytes.extend(header.as_bytes());
+    for (_, tensor) in tensors {
+        bytes.extend(&tensor.bytes);
+    }
+    Ok(bytes)
+}
+
+pub fn save(
+    path: impl AsRef<Path>,
+    tensors: &[(&str, TensorData)],
+    metadata: &BTreeMap<String, String>,
+) -> Result<()> {
+    fs::write(path, to_bytes(tensors, metadata)?)?;
+    Ok(())
+}
+
+fn invalid(message: &str) -> FormatError {
+    FormatError::InvalidFile(message.to_owned())
+}
+
+type Header = (Vec<(String, TensorInfo)>, BTreeMap<String, String>, usize);
+
+// Parse and check the header, returning the tensors in data order and where the data starts
+fn parse_header(bytes: &[u8]) -> Result<Header> {
+    let header_len = bytes
+        .get(..8)
+        .map(|len| u64::from_le_bytes(len.try_into().unwrap()))
+        .ok_or_else(|| invalid("unexpected end of file"))?;
+    let data_start = usize::try_from(header_len)
+        .ok()
+        .and_then(|len| len.checked_add(8))
+        .filter(|start| *start <= bytes.len())
+        .ok_or_else(|| invalid("the header is longer than the file"))?;
+    let header: Value = serde_json::from_slice(&bytes[8..data_start])
+        .map_err(|error| FormatError::InvalidFile(format!("invalid header: {error}")))?;
+    let Value::Object(header) = header else {
+        Err(invalid("the header isn't a JSON object"))?
+    };
+
+    let mut tensors = Vec::new();
+    let mut metadata = BTreeMap::new();
+    for (name, value) in header {
+        if name == METADATA_KEY {
+            let Value::Object(entries) = value else {
+                Err(invalid("the metadata isn't an object"))?
+            };
+            for (key, value) in entries {
+                let Value::String(value) = value else {
+                    Err(invalid("metadata values must be strings"))?
+                };
+                metadata.insert(key, value);
+            }
+            continue;
+        }
+        let info = parse_info(&value)
+            .map_err(|error| FormatError::InvalidFile(format!("tensor {name}: {error}")))?;
+        tensors.push((name, info));
+    }
+
+    // The tensors must exactly cover the data, without gaps or overlaps
+    tensors.sort_by_key(|(_, info)| (info.start, info.end));
+    let mut expected_start = 0;
+    for (name, info) in &tensors {
+        if info.start != expected_start {
+            Err(FormatError::InvalidFile(format!(
+                "tensor {name} doesn't start where the previous one ends"
+            )))?;
+        }
+        expected_start = info.end;
+    }
+    if data_start + expected_start != bytes.len() {
+        Err(invalid("the data doesn't match the size of the tensors"))?;
+    }
+    Ok((tensors, metadata, data_start))
+}
+
+fn parse_info(value: &Value) -> Result<TensorInfo, String> {
+    let field = |key: &str| value.get(key).ok_or(format!("missing {key}"));
+    let dtype = field("dtype")?.as_str().ok_or("the dtype isn't a string")?;
+    let dtype = DType::parse(dtype).map_err(|error| error.to_string())?;
+    let as_usizes = |value: &Value| -> Option<Vec<usize>> {
+        value
+            .as_array()?
+            .iter()
+            .map(|dim| dim.as_u64().and_then(|dim| usize::try_from(dim).ok()))
+            .collect()
+    };
+    let shape = as_usizes(field("shape")?).ok_or("the shape isn't a list of sizes")?;
+    let offsets = as_usizes(field("data_offsets")?).ok_or("the offsets aren't sizes")?;
+    let &[start, end] = offsets.as_slice() else {
+        Err("there must be two offsets")?
+    };
+    let size = shape
+        .iter()
+        .try_fold(dtype.size(), |total, dim| total.checked_mul(*dim));
+    if start > end || size != Some(end - start) {
+        Err("the offsets don't match the shape")?;
+    }
+    Ok(TensorInfo {
+        dtype,
+        shape,
+        start,
+        end,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::linear::LinearLayer;
+    use crate::matrix::random::Rng;
+
+    // A file with the given header and data, padded as the reference implementation does
+    fn file(header: &str, data: &[u8]) -> Vec<u8> {
+        let mut header = header.to_owned();
+        while !header.len().is_multiple_of(ALIGNMENT) {
+            header.push(' ');
+        }
+        let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+        bytes.extend(header.as_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn test_reference_file() {
+        // As written by safetensors.torch.save_file({"a": torch.tensor([[1., 2.]]),
+        // "b": torch.tensor([3], dtype=torch.int64)}, metadata={"format": "pt"})
+        let header = r#"{"__metadata__":{"format":"pt"},"a":{"dtype":"F32","shape":[1,2],"data_offsets":[0,8]},"b":{"dtype":"I64","shape":[1],"data_offsets":[8,16]}}"#;
+        let data = [
+            1f32.to_le_bytes().as_slice(),
+            &2f32.to_le_bytes(),
+            &3i64.to_le_bytes(),
+        ]
+        .concat();
+        let bytes = file(header, &data);
+        let tensors = SafeTensors::from_bytes(bytes.clone()).unwrap();
+
+        assert_eq!(tensors.names().collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(tensors.metadata()["format"], "pt");
+        assert_eq!(
+            tensors.get::<f32>("a").unwrap(),
+            Tensor::from_vec(vec![1., 2.], &[1, 2]).unwrap()
+        );
+        assert_eq!(tensors.get::<i64>("b").unwrap().as_slice(), &[3]);
+        assert_eq!(tensors.get_cast::<f32>("b").unwrap().as_slice(), &[3.]);
+        assert_eq!(tensors.info("a").unwrap().dtype, DType::F32);
+        assert!(tensors.get::<f64>("a").is_err());
+        assert!(tensors.get::<f32>("c").is_err());
+
+        // Writing the same tensors gives the same file
+        let metadata = BTreeMap::from([("format".to_owned(), "pt".to_owned())]);
+        let a = Tensor::from_vec(vec![1f32, 2.], &[1, 2]).unwrap();
+        let b = Tensor::from_vec(vec![3i64], &[1]).unwrap();
+        let written = to_bytes(&[("a", (&a).into()), ("b", (&b).into())], &metadata).unwrap();
+        assert_eq!(written, bytes);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let weights = Tensor::from_vec((0..12).map(|x| x as f32 / 8.).collect(), &[3, 4]).unwrap();
+        let tensors = [
+            ("weights", (&weights).into()),
+            ("half", (&weights.cast::<F16>()).into()),
+            ("brain", (&weights.cast::<Bf16>()).into()),
+            ("double", (&weights.cast::<f64>()).into()),
+            ("ids", (&weights.cast::<i32>()).into()),
+            ("mask", (&weights.cast::<u8>()).into()),
+            ("scalar", (&Tensor::scalar(2.5f32)).into()),
+            ("empty", (&Tensor::<f32>::zeros(&[0, 3])).into()),
+        ];
+        let bytes = to_bytes(&tensors, &BTreeMap::new()).unwrap();
+        let loaded = SafeTensors::from_bytes(bytes).unwrap();
+        assert_eq!(loaded.len(), tensors.len());
+        assert!(loaded.metadata().is_empty());
+        assert_eq!(loaded.get::<f32>("weights").unwrap(), weights);
+        for name in ["half", "brain", "double"] {
+            assert_eq!(loaded.get_cast::<f32>(name).unwrap(), weights, "{name}");
+        }
+        assert_eq!(loaded.get::<Bf16>("brain").unwrap(), weights.cast::<Bf16>());
+        assert_eq!(loaded.get::<i32>("ids").unwrap(), weights.cast::<i32>());
+        assert_eq!(loaded.get::<u8>("mask").unwrap(), weights.cast::<u8>());
+        assert_eq!(loaded.get::<f32>("scalar").unwrap().get(&[]).unwrap(), 2.5);
+        assert_eq!(loaded.get::<f32>("empty").unwrap().shape(), &[0, 3]);
+
+        let repeated = [("a", (&weights).into()), ("a", (&weights).into())];
+        assert!(to_bytes(&repeated, &BTreeMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_load_layer() {
+        let dir = std::env::temp_dir().join(format!("safetensors_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("model.safetensors");
+        let layer = LinearLayer::<3, 2>::new(&mut Rng::new(0));
+        let weights = Tensor::from(layer.weights());
+        let bias = Tensor::from(layer.bias().unwrap());
+        // Pretrained weights are often stored in half precision
+        let tensors = [
+            ("fc.weight", (&weights.cast::<Bf16>()).into()),
+            ("fc.bias", (&bias).into()),
+        ];
+        save(&path, &tensors, &BTreeMap::new()).unwrap();
+
+        let file = SafeTensors::load(&path).unwrap();
+        let loaded = LinearLayer::<3, 2>::try_from_tensors(
+            &file.get_cast("fc.weight").unwrap(),
+            Some(&file.get_cast("fc.bias").unwrap()),
+        )
+        .unwrap();
+        assert_eq!(loaded.bias(), layer.bias());
+        let error = Tensor::from(loaded.weights())
+            .zip_map(&weights, |x, y| (x - y).abs())
+            .unwrap();
+        assert!(error.iter().all(|error| *error < 1e-2));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_unsupported_dtypes() {
+        // Files with other dtypes still load, but those tensors can't be decoded
+        let header = r#"{"mask":{"dtype":"BOOL","shape":[2],"data_offsets":[0,2]}}"#;
+        let tensors = SafeTensors::from_bytes(file(header, &[1, 0])).unwrap();
+        assert_eq!(tensors.info("mask").unwrap().dtype, DType::Bool);
+        assert!(tensors.get_cast::<f32>("mask").is_err());
+
+        // Including FP8 checkpoints, whose other tensors are still readable
+        let header = concat!(
+            r#"{"w":{"dtype":"F8_E4M3","shape":[2],"data_offsets":[0,2]},"#,
+            r#""v":{"dtype":"F8_E5M2","shape":[2],"data_offsets":[2,4]},"#,
+            r#""scale":{"dtype":"F32","shape":[1],"data_offsets":[4,8]}}"#
+        );
+        let data = [[0x38, 0x40, 0x3c, 0x40].as_slice(), &2f32.to_le_bytes()].concat();
+        let tensors = SafeTensors::from_bytes(file(header, &data)).unwrap();
+        assert_eq!(tensors.info("w").unwrap().dtype, DType::F8E4M3);
+        assert!(tensors.get_cast::<f32>("w").is_err());
+        assert!(tensors.get_cast::<f32>("v").is_err());
+        assert_eq!(tensors.get::<f32>("scale").unwrap().as_slice(), &[2.]);
+
+        let header = r#"{"x":{"dtype":"F7","shape":[1],"data_offsets":[0,1]}}"#;
+        assert!(SafeTensors::from_bytes(file(header, &[0])).is_err());
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let tensor = r#""dtype":"F32","shape":[2]"#;
+        for (header, data_len) in [
+            // Offsets which don't match the shape, leave a gap, overlap or miss the end
+            (format!(r#"{{"a":{{{tensor},"data_offsets":[0,4]}}}}"#), 4),
+            (format!(r#"{{"a":{{{tensor},"data_offsets":[4,12]}}}}"#), 12),
+            (
+                format!(
+                    r#"{{"a":{{{tensor},"data_offsets":[0,8]}},"b":{{{tensor},"data_offsets":[4,12]}}}}"#
+                ),
+                12,
+            ),
+            (format!(r#"{{"a":{{{tensor},"data_offsets":[0,8]}}}}"#), 12),
+            (format!(r#"{{"a":{{{tensor},"data_offsets":[0,8]}}}}"#), 4),
+            // Malformed entries
+            (
+                r#"{"a":{"dtype":"F32","data_offsets":[0,8]}}"#.to_owned(),
+                8,
+            ),
+            (
+                r#"{"a":{"dtype":"F32","shape":[-2],"data_offsets":[0,8]}}"#.to_owned(),
+                8,
+            ),
+            (r#"{"__metadata__":{"version":1}}"#.to_owned(), 0),
+            (r#"["a"]"#.to_owned(), 0),
+            ("{".to_owned(), 0),
+        ] {
+            let bytes = file(&header, &vec![0; data_len]);
+            assert!(SafeTensors::from_bytes(bytes).is_err(), "{header}");
+        }
+        assert!(SafeTensors::from_bytes(vec![0; 4]).is_err());
+        assert!(SafeTensors::from_bytes(u64::MAX.to_le_bytes().to_vec()).is_err());
+    }
+}
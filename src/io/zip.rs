@@ -0,0 +1,291 @@
+// Just enough of the zip format for `.npz` archives: uncompressed ("stored") entries, with the
+// zip64 extensions NumPy uses when writing.
+//
+// An archive is each entry's local header and data, then a central directory listing every
+// entry and where its local header is, then an end record saying where the directory is.
+use anyhow::Result;
+
+use crate::exceptions::FormatError;
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_DIRECTORY: u32 = 0x0605_4b50;
+const ZIP64_END_OF_DIRECTORY: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR: u32 = 0x0706_4b50;
+const ZIP64_EXTRA: u16 = 0x0001;
+// 1980-01-01, the earliest date a zip can hold
+const DOS_DATE: u16 = (1 << 5) | 1;
+const VERSION: u16 = 20;
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+/// Write entries into an archive without compression.
+pub(crate) fn write_stored(entries: &[(String, Vec<u8>)]) -> Result<Vec<u8>> {
+    let too_large = || FormatError::InvalidFile("archive is too large for a zip".to_owned());
+    let mut bytes = Vec::new();
+    let mut directory = Vec::new();
+    for (name, data) in entries {
+        let offset = u32::try_from(bytes.len()).map_err(|_| too_large())?;
+        let size = u32::try_from(data.len()).map_err(|_| too_large())?;
+        let name_len = u16::try_from(name.len()).map_err(|_| too_large())?;
+        let crc = crc32(data);
+
+        write_u32(&mut bytes, LOCAL_HEADER);
+        write_entry_fields(&mut bytes, crc, size, name_len);
+        bytes.extend(name.as_bytes());
+        bytes.extend(data);
+
+        write_u32(&mut directory, CENTRAL_HEADER);
+        write_u16(&mut directory, VERSION);
+        write_entry_fields(&mut directory, crc, size, name_len);
+        // Comment length, disk number, internal and external attributes
+        directory.extend([0; 10]);
+        write_u32(&mut directory, offset);
+        directory.extend(name.as_bytes());
+    }
+
+    let n_entries = u16::try_from(entries.len()).map_err(|_| too_large())?;
+    let directory_offset = u32::try_from(bytes.len()).map_err(|_| too_large())?;
+    let directory_size = directory.len() as u32;
+    bytes.extend(directory);
+    write_u32(&mut bytes, END_OF_DIRECTORY);
+    // This disk and the disk the directory starts on
+    bytes.extend([0; 4]);
+    write_u16(&mut bytes, n_entries);
+    write_u16(&mut bytes, n_entries);
+    write_u32(&mut bytes, directory_size);
+    write_u32(&mut bytes, directory_offset);
+    // Comment length
+    write_u16(&mut bytes, 0);
+    Ok(bytes)
+}
+
+// The fields shared by local and central headers, from the version needed to the extra length
+fn write_entry_fields(bytes: &mut Vec<u8>, crc: u32, size: u32, name_len: u16) {
+    for field in [VERSION, 0, STORED, 0, DOS_DATE] {
+        write_u16(bytes, field);
+    }
+    for field in [crc, size, size] {
+        write_u32(bytes, field);
+    }
+    write_u16(bytes, name_len);
+    write_u16(bytes, 0);
+}
+
+/// The names and data of the entries in an archive, which must all be stored uncompressed.
+pub(crate) fn read_stored(bytes: &[u8]) -> Result<Vec<(String, &[u8])>> {
+    // The end record is the last thing in the file, apart from a comment of up to 64KiB
+    let end = (0..=bytes.len().saturating_sub(22))
+        .rev()
+        .take(u16::MAX as usize + 1)
+        .find(|start| read_u32(bytes, *start).ok() == Some(END_OF_DIRECTORY))
+        .ok_or_else(|| invalid("not a zip file"))?;
+    let mut n_entries = read_u16(bytes, end + 10)? as u64;
+    let mut directory_offset = read_u32(bytes, end + 16)? as u64;
+    if n_entries == u16::MAX as u64 || directory_offset == u32::MAX as u64 {
+        // The real values are in the zip64 end record, which the locator before this one finds
+        let locator = end
+            .checked_sub(20)
+            .filter(|start| read_u32(bytes, *start).ok() == Some(ZIP64_LOCATOR))
+            .ok_or_else(|| invalid("missing zip64 end record"))?;
+        let zip64_end = to_usize(read_u64(bytes, locator + 8)?)?;
+        if read_u32(bytes, zip64_end)? != ZIP64_END_OF_DIRECTORY {
+            Err(invalid("missing zip64 end record"))?;
+        }
+        n_entries = read_u64(bytes, zip64_end + 32)?;
+        directory_offset = read_u64(bytes, zip64_end + 48)?;
+    }
+
+    let mut entries = Vec::new();
+    let mut position = to_usize(directory_offset)?;
+    for _ in 0..n_entries {
+        if read_u32(bytes, position)? != CENTRAL_HEADER {
+            Err(invalid("corrupt central directory"))?;
+        }
+        let flags = read_u16(bytes, position + 8)?;
+        let method = read_u16(bytes, position + 10)?;
+        let crc = read_u32(bytes, position + 16)?;
+        let mut compressed_size = read_u32(bytes, position + 20)? as u64;
+        let mut size = read_u32(bytes, position + 24)? as u64;
+        let mut local_offset = read_u32(bytes, position + 42)? as u64;
+        let name_len = read_u16(bytes, position + 28)? as usize;
+        let extra_len = read_u16(bytes, position + 30)? as usize;
+        let comment_len = read_u16(bytes, position + 32)? as usize;
+        let name = slice(bytes, position + 46, name_len)?;
+        let name = String::from_utf8(name.to_vec()).map_err(|_| invalid("name isn't UTF-8"))?;
+        let extra = slice(bytes, position + 46 + name_len, extra_len)?;
+        position += 46 + name_len + extra_len + comment_len;
+
+        if flags & 1 != 0 {
+            Err(FormatError::InvalidFile(format!("{name} is encrypted")))?;
+        }
+        match method {
+            STORED => {}
+            DEFLATED => Err(FormatError::InvalidFile(format!(
+                "{name} is compressed, which isn't supported: save with np.savez rather than np.savez_compressed"
+            )))?,
+            _ => Err(FormatError::InvalidFile(format!(
+                "{name} uses unsupported compression method {method}"
+            )))?,
+        }
+
+        // Sizes and offsets too large for the header are in a zip64 extra field, in order
+        let mut values = zip64_extra(extra).into_iter();
+        let mut next = |value: &mut u64| {
+            if *value == u32::MAX as u64 {
+                *value = values
+                    .next()
+                    .ok_or_else(|| invalid("missing zip64 extra field"))?;
+            }
+            Ok::<(), FormatError>(())
+        };
+        next(&mut size)?;
+        next(&mut compressed_size)?;
+        next(&mut local_offset)?;
+        if compressed_size != size {
+            Err(invalid("stored entry sizes don't match"))?;
+        }
+
+        let local_offset = to_usize(local_offset)?;
+        if read_u32(bytes, local_offset)? != LOCAL_HEADER {
+            Err(invalid("corrupt local header"))?;
+        }
+        let local_name_len = read_u16(bytes, local_offset + 26)? as usize;
+        let local_extra_len = read_u16(bytes, local_offset + 28)? as usize;
+        let data_start = local_offset + 30 + local_name_len + local_extra_len;
+        let data = slice(bytes, data_start, to_usize(size)?)?;
+        if crc32(data) != crc {
+            Err(FormatError::InvalidFile(format!(
+                "{name} fails its checksum"
+            )))?;
+        }
+        entries.push((name, data));
+    }
+    Ok(entries)
+}
+
+// The 8-byte values of the zip64 extra field, if there is one
+fn zip64_extra(mut extra: &[u8]) -> Vec<u64> {
+    while extra.len() >= 4 {
+        let id = u16::from_le_bytes([extra[0], extra[1]]);
+        let len = (u16::from_le_bytes([extra[2], extra[3]]) as usize).min(extra.len() - 4);
+        if id == ZIP64_EXTRA {
+            return extra[4..4 + len]
+                .chunks_exact(8)
+                .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+                .collect();
+        }
+        extra = &extra[4 + len..];
+    }
+    vec![]
+}
+
+fn invalid(message: &str) -> FormatError {
+    FormatError::InvalidFile(message.to_owned())
+}
+
+fn slice(bytes: &[u8], start: usize, len: usize) -> Result<&[u8]> {
+    start
+        .checked_add(len)
+        .and_then(|end| bytes.get(start..end))
+        .ok_or_else(|| invalid("unexpected end of file").into())
+}
+
+fn read_u16(bytes: &[u8], start: usize) -> Result<u16> {
+    Ok(u16::from_le_bytes(
+        slice(bytes, start, 2)?.try_into().unwrap(),
+    ))
+}
+
+fn read_u32(bytes: &[u8], start: usize) -> Result<u32> {
+    Ok(u32::from_le_bytes(
+        slice(bytes, start, 4)?.try_into().unwrap(),
+    ))
+}
+
+fn read_u64(bytes: &[u8], start: usize) -> Result<u64> {
+    Ok(u64::from_le_bytes(
+        slice(bytes, start, 8)?.try_into().unwrap(),
+    ))
+}
+
+fn to_usize(value: u64) -> Result<usize> {
+    usize::try_from(value).map_err(|_| invalid("offset is too large").into())
+}
+
+fn write_u16(bytes: &mut Vec<u8>, value: u16) {
+    bytes.extend(value.to_le_bytes());
+}
+
+fn write_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend(value.to_le_bytes());
+}
+
+// The CRC-32 used by zip (polynomial 0xedb88320, reflected)
+fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xedb8_8320
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+    !data.iter().fold(!0, |crc, byte| {
+        TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414f_a339
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let entries = vec![
+            ("a.npy".to_owned(), b"first".to_vec()),
+            ("dir/b.npy".to_owned(), vec![]),
+            ("c.npy".to_owned(), (0..=255).collect()),
+        ];
+        let bytes = write_stored(&entries).unwrap();
+        let read = read_stored(&bytes).unwrap();
+        assert_eq!(read.len(), 3);
+        for ((name, data), (read_name, read_data)) in entries.iter().zip(&read) {
+            assert_eq!(name, read_name);
+            assert_eq!(data, read_data);
+        }
+    }
+
+    #[test]
+    fn test_corruption() {
+        let bytes = write_stored(&[("a".to_owned(), b"data".to_vec())]).unwrap();
+        let mut corrupted = bytes.clone();
+        // The data follows the 30-byte local header and the name
+        corrupted[31] ^= 1;
+        assert!(read_stored(&corrupted).is_err());
+        assert!(read_stored(&bytes[..bytes.len() - 1]).is_err());
+        assert!(read_stored(&bytes[1..]).is_err());
+        assert!(read_stored(b"").is_err());
+    }
+}
@@ -0,0 +1,113 @@
+// The activations to choose from between layers.
+//
+// `Activation` applies one to a variable or a tensor, and `ActivationLayer` wraps it as a module
+// with no parameters, so it can go in a `Sequential` between linear layers.
+use anyhow::Result;
+
+use crate::autograd::variable::Var;
+use crate::layers::module::Module;
+use crate::matrix::element::Float;
+use crate::matrix::tensor::Tensor;
+use crate::ops::activation::{gelu, relu, silu};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Activation {
+    #[default]
+    Relu,
+    /// The tanh approximation, as in GPT-2.
+    Gelu,
+    /// x σ(x), as in LLaMA's feed-forward layers.
+    Silu,
+    Tanh,
+    Identity,
+}
+
+impl Activation {
+    pub fn forward<T: Float>(&self, input: &Var<T>) -> Var<T> {
+        match self {
+            Activation::Relu => input.relu(),
+            Activation::Gelu => input.gelu(),
+            Activation::Silu => input.silu(),
+            Activation::Tanh => input.tanh(),
+            Activation::Identity => input.clone(),
+        }
+    }
+
+    /// The same as `forward`, without recording anything for a backward pass.
+    pub fn apply<T: Float>(&self, input: &Tensor<T>) -> Tensor<T> {
+        match self {
+            Activation::Relu => input.map(relu),
+            Activation::Gelu => input.map(gelu),
+            Activation::Silu => input.map(silu),
+            Activation::Tanh => input.map(T::tanh),
+            Activation::Identity => input.clone(),
+        }
+    }
+}
+
+/// An activation as a module.
+pub struct ActivationLayer {
+    activation: Activation,
+    training: bool,
+}
+
+impl ActivationLayer {
+    pub fn new(activation: Activation) -> Self {
+        ActivationLayer {
+            activation,
+            training: true,
+        }
+    }
+
+    pub fn activation(&self) -> Activation {
+        self.activation
+    }
+}
+
+impl<T: Float> Module<T> for ActivationLayer {
+    fn forward(&self, input: &Var<T>) -> Result<Var<T>> {
+        Ok(self.activation.forward(input))
+    }
+
+    fn is_training(&self) -> bool {
+        self.training
+    }
+
+    fn set_training(&mut self, training: bool) {
+        self.training = training;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_activations() {
+        let input = Tensor::from_slice(&[-2., -0.5, 0., 1., 3.], &[5]).unwrap();
+        let variable = Var::constant(input.clone());
+        for activation in [
+            Activation::Relu,
+            Activation::Gelu,
+            Activation::Silu,
+            Activation::Tanh,
+            Activation::Identity,
+        ] {
+            assert_eq!(
+                *activation.forward(&variable).value(),
+                activation.apply(&input)
+            );
+        }
+        assert_eq!(
+            Activation::Relu.apply(&input).as_slice(),
+            &[0., 0., 0., 1., 3.]
+        );
+        assert_eq!(Activation::Identity.apply(&input), input);
+        assert_eq!(Activation::Silu.apply(&input).as_slice()[3], silu(1f32));
+
+        let layer = ActivationLayer::new(Activation::Tanh);
+        let output = Module::<f32>::forward(&layer, &variable).unwrap();
+        assert_eq!(*output.value(), input.map(f32::tanh));
+        assert_eq!(Module::<f32>::num_parameters(&layer), 0);
+    }
+}
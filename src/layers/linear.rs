@@ -1,44 +1,150 @@
+use anyhow::Result;
+
+use crate::autograd::variable::Var;
+use crate::layers::module::Module;
+use crate::matrix::init::Init;
 use crate::matrix::matrix::Matrix2;
+use crate::matrix::random::Rng;
+use crate::matrix::tensor::Tensor;
 use crate::matrix::vector::FloatVector;
-use crate::ops::relu::relu;
 
-struct LinearLayer<const INPUT_SIZE: usize, const OUTPUT_SIZE: usize> {
-    weights: Matrix2<OUTPUT_SIZE, INPUT_SIZE>,
-    bias: FloatVector<OUTPUT_SIZE>,
+/// An affine map x Wᵀ + b, with no activation. Follow it with an `Activation` where one is needed.
+pub struct LinearLayer<const INPUT_SIZE: usize, const OUTPUT_SIZE: usize> {
+    // Variables so the layer can be trained, always of shapes (OUTPUT_SIZE, INPUT_SIZE) and
+    // (OUTPUT_SIZE)
+    weights: Var,
+    bias: Option<Var>,
+    training: bool,
 }
 
 impl<const INPUT_SIZE: usize, const OUTPUT_SIZE: usize> LinearLayer<INPUT_SIZE, OUTPUT_SIZE> {
+    /// A randomly initialised layer: Kaiming uniform weights, which suit the ReLU, and a zero
+    /// bias.
+    pub fn new(rng: &mut Rng) -> Self {
+        LinearLayer::init(Init::KaimingUniform, Init::Zeros, rng)
+    }
+
+    pub fn init(weight_init: Init, bias_init: Init, rng: &mut Rng) -> Self {
+        LinearLayer::from_wb(weight_init.matrix(rng), bias_init.vector(rng))
+    }
+
     pub fn from_elements(
         weight_elements: [[f32; INPUT_SIZE]; OUTPUT_SIZE],
         bias_elements: [f32; OUTPUT_SIZE],
     ) -> Self {
-        LinearLayer {
-            weights: Matrix2::from_elements(weight_elements),
-            bias: FloatVector::from_elements(bias_elements),
-        }
+        LinearLayer::from_wb(
+            Matrix2::from_elements(weight_elements),
+            FloatVector::from_elements(bias_elements),
+        )
     }
 
     pub fn from_wb(
         weights: Matrix2<OUTPUT_SIZE, INPUT_SIZE>,
         bias: FloatVector<OUTPUT_SIZE>,
     ) -> Self {
-        LinearLayer { weights, bias }
+        LinearLayer {
+            weights: Var::parameter(Tensor::from(weights)),
+            bias: Some(Var::parameter(Tensor::from(bias))),
+            training: true,
+        }
+    }
+
+    /// The same layer without a bias, as for attention projections.
+    pub fn without_bias(mut self) -> Self {
+        self.bias = None;
+        self
     }
 
     pub fn from(
         matrix_elements: [[f32; INPUT_SIZE]; OUTPUT_SIZE],
         bias_elements: [f32; OUTPUT_SIZE],
     ) -> Self {
-        LinearLayer {
-            weights: Matrix2::from_elements(matrix_elements),
-            bias: FloatVector::from_elements(bias_elements),
-        }
+        LinearLayer::from_elements(matrix_elements, bias_elements)
+    }
+
+    /// Build a layer from a weight tensor of shape (OUTPUT_SIZE, INPUT_SIZE) and, if it has one, a
+    /// bias of shape (OUTPUT_SIZE), as loaded from a checkpoint.
+    pub fn try_from_tensors(weights: &Tensor, bias: Option<&Tensor>) -> Result<Self> {
+        let weights = weights.try_into()?;
+        Ok(match bias {
+            Some(bias) => LinearLayer::from_wb(weights, bias.try_into()?),
+            None => LinearLayer::from_wb(weights, FloatVector::from_elements([0.; OUTPUT_SIZE]))
+                .without_bias(),
+        })
+    }
+
+    /// A copy of the weights.
+    pub fn weights(&self) -> Matrix2<OUTPUT_SIZE, INPUT_SIZE> {
+        (&*self.weights.value())
+            .try_into()
+            .expect("The weights keep their shape")
+    }
+
+    /// A copy of the bias, if the layer has one.
+    pub fn bias(&self) -> Option<FloatVector<OUTPUT_SIZE>> {
+        self.bias.as_ref().map(|bias| {
+            (&*bias.value())
+                .try_into()
+                .expect("The bias keeps its shape")
+        })
     }
 
     pub fn forward(&self, state: FloatVector<INPUT_SIZE>) -> FloatVector<OUTPUT_SIZE> {
-        let mut ret_vector = self.weights.dot(&state);
-        ret_vector += &self.bias;
-        relu(ret_vector)
+        // As a batch of one
+        let inputs = Tensor::from(state).reshape(&[1, INPUT_SIZE]).unwrap();
+        self.affine(&inputs)
+            .reshape(&[OUTPUT_SIZE])
+            .and_then(|outputs| Ok(outputs.try_into()?))
+            .expect("The output has OUTPUT_SIZE elements")
+    }
+
+    /// Apply the layer to each row of a batch of inputs.
+    pub fn forward_batch<const BATCH_SIZE: usize>(
+        &self,
+        states: &Matrix2<BATCH_SIZE, INPUT_SIZE>,
+    ) -> Matrix2<BATCH_SIZE, OUTPUT_SIZE> {
+        self.affine(&Tensor::from(states))
+            .try_into()
+            .expect("The output has a row for each input")
+    }
+
+    fn affine(&self, inputs: &Tensor) -> Tensor {
+        let outputs = inputs
+            .matmul_nt(&self.weights.value())
+            .expect("The inputs have INPUT_SIZE columns");
+        match &self.bias {
+            Some(bias) => outputs.add(&bias.value()).unwrap(),
+            None => outputs,
+        }
+    }
+}
+
+impl<const INPUT_SIZE: usize, const OUTPUT_SIZE: usize> Module
+    for LinearLayer<INPUT_SIZE, OUTPUT_SIZE>
+{
+    /// Apply the layer to inputs of shape (..., INPUT_SIZE), with at least one batch dimension.
+    fn forward(&self, input: &Var) -> Result<Var> {
+        let outputs = input.matmul_nt(&self.weights)?;
+        match &self.bias {
+            Some(bias) => outputs.add(bias),
+            None => Ok(outputs),
+        }
+    }
+
+    fn own_parameters(&self) -> Vec<(String, Var)> {
+        let mut params = vec![("weight".to_owned(), self.weights.clone())];
+        if let Some(bias) = &self.bias {
+            params.push(("bias".to_owned(), bias.clone()));
+        }
+        params
+    }
+
+    fn is_training(&self) -> bool {
+        self.training
+    }
+
+    fn set_training(&mut self, training: bool) {
+        self.training = training;
     }
 }
 
@@ -53,8 +159,82 @@ mod tests {
 
         let layer = LinearLayer::from_elements([[1., 0.], [0., -1.], [0., 0.]], [0., 0., 1.]);
         let input: FloatVector<INPUT_SIZE> = FloatVector::from_elements([5., 3.]);
-        let label: FloatVector<OUTPUT_SIZE> = FloatVector::from_elements([5., 0., 1.]);
+        let label: FloatVector<OUTPUT_SIZE> = FloatVector::from_elements([5., -3., 1.]);
+
+        assert_eq!(layer.forward(input.clone()), label);
+
+        let layer = LinearLayer::from_elements([[1., 0.], [0., -1.], [0., 0.]], [0., 0., 1.]);
+        let label: FloatVector<OUTPUT_SIZE> = FloatVector::from_elements([5., -3., 0.]);
+        assert_eq!(layer.without_bias().forward(input), label);
+    }
+
+    #[test]
+    fn test_forward_batch() {
+        let layer = LinearLayer::from_elements([[1., 0.], [0., -1.], [1., 1.]], [0., 0., 1.]);
+        let states = Matrix2::from_elements([[5., 3.], [-1., -2.], [0., 0.]]);
+
+        let outputs = layer.forward_batch(&states);
+        for (state, output) in states.rows.iter().zip(outputs.rows.iter()) {
+            assert_eq!(&layer.forward(state.clone()), output);
+        }
+    }
+
+    #[test]
+    fn test_from_tensors() {
+        let layer = LinearLayer::from_elements([[1., 0.], [0., -1.], [1., 1.]], [0., 0., 1.]);
+        let weights = Tensor::from(layer.weights());
+        let bias = Tensor::from(layer.bias().unwrap());
+        let loaded = LinearLayer::<2, 3>::try_from_tensors(&weights, Some(&bias)).unwrap();
+        assert_eq!(loaded.weights(), layer.weights());
+        assert_eq!(loaded.bias(), layer.bias());
+        assert!(LinearLayer::<3, 2>::try_from_tensors(&weights, Some(&bias)).is_err());
+
+        let loaded = LinearLayer::<2, 3>::try_from_tensors(&weights, None).unwrap();
+        assert_eq!(loaded.bias(), None);
+        assert_eq!(loaded.named_parameters().len(), 1);
+    }
+
+    #[test]
+    fn test_module() {
+        let layer = LinearLayer::from_elements([[1., 0.], [0., -1.], [1., 1.]], [0., 0., 1.]);
+        let states = Matrix2::from_elements([[5., 3.], [-1., -2.]]);
+        let inputs = Var::constant(Tensor::from(&states));
+        let outputs = Module::forward(&layer, &inputs).unwrap();
+        assert_eq!(*outputs.value(), Tensor::from(layer.forward_batch(&states)));
+
+        // Training the variables changes the layer
+        outputs.sum().backward().unwrap();
+        let params = layer.named_parameters();
+        assert_eq!(params[0].0, "weight");
+        assert_eq!(params[1].1.grad().unwrap().as_slice(), &[2., 2., 2.]);
+        params[1].1.set_value(Tensor::zeros(&[3]));
+        assert_eq!(layer.bias(), Some(FloatVector::from_elements([0.; 3])));
+        assert_eq!(layer.num_parameters(), 9);
+
+        let layer = layer.without_bias();
+        let outputs = Module::forward(&layer, &inputs).unwrap();
+        assert_eq!(*outputs.value(), Tensor::from(layer.forward_batch(&states)));
+        assert_eq!(layer.num_parameters(), 6);
+    }
+
+    #[test]
+    fn test_init() {
+        let layer = LinearLayer::<20, 10>::new(&mut Rng::new(0));
+        let limit = (6f32 / 20.).sqrt();
+        assert!(layer
+            .weights()
+            .rows
+            .iter()
+            .flat_map(|row| row.iter())
+            .all(|x| x.abs() <= limit));
+        assert!(layer.bias().unwrap().iter().all(|x| *x == 0.));
+        assert_eq!(
+            layer.weights(),
+            LinearLayer::<20, 10>::new(&mut Rng::new(0)).weights()
+        );
 
-        assert_eq!(layer.forward(input), label);
+        let layer = LinearLayer::<2, 3>::init(Init::Ones, Init::Constant(0.5), &mut Rng::new(0));
+        let outputs = layer.forward(FloatVector::from_elements([1., 2.]));
+        assert_eq!(outputs, FloatVector::from_elements([3.5; 3]));
     }
 }
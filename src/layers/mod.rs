@@ -1 +1,4 @@
+pub mod activation;
 pub mod linear;
+pub mod module;
+pub mod quantized;
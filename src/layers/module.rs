@@ -0,0 +1,331 @@
+// The interface shared by layers and models built from them.
+//
+// A module holds some parameters directly and others through child modules, each under a name,
+// so every parameter in a model has a path such as `blocks.0.mlp.weight`. Optimizers take the
+// parameters from `named_parameters`, and checkpoints store and restore them by these paths.
+//
+// Modules start in training mode. `eval` switches a module and all its children to evaluation
+// mode, for layers such as dropout which behave differently at inference.
+use std::fmt::Write;
+
+use anyhow::Result;
+
+use crate::autograd::variable::Var;
+use crate::exceptions::MatrixError;
+use crate::matrix::element::Float;
+use crate::matrix::tensor::Tensor;
+
+pub trait Module<T: Float = f32> {
+    fn forward(&self, input: &Var<T>) -> Result<Var<T>>;
+
+    /// The parameters held by this module itself rather than through a child.
+    fn own_parameters(&self) -> Vec<(String, Var<T>)> {
+        Vec::new()
+    }
+
+    fn children(&self) -> Vec<(String, &dyn Module<T>)> {
+        Vec::new()
+    }
+
+    fn children_mut(&mut self) -> Vec<(String, &mut dyn Module<T>)> {
+        Vec::new()
+    }
+
+    fn is_training(&self) -> bool;
+
+    /// Set the mode of this module alone. See `train` and `eval`.
+    fn set_training(&mut self, training: bool);
+
+    /// Put this module and its children in training mode.
+    fn train(&mut self) {
+        set_mode(self, true);
+    }
+
+    /// Put this module and its children in evaluation mode.
+    fn eval(&mut self) {
+        set_mode(self, false);
+    }
+
+    /// Every parameter, each named by its path from this module.
+    fn named_parameters(&self) -> Vec<(String, Var<T>)> {
+        let mut params = self.own_parameters();
+        for (child_name, child) in self.children() {
+            params.extend(
+                child
+                    .named_parameters()
+                    .into_iter()
+                    .map(|(name, param)| (format!("{child_name}.{name}"), param)),
+            );
+        }
+        params
+    }
+
+    fn parameters(&self) -> Vec<Var<T>> {
+        self.named_parameters()
+            .into_iter()
+            .map(|(_, param)| param)
+            .collect()
+    }
+
+    /// The number of elements in every parameter.
+    fn num_parameters(&self) -> usize {
+        self.named_parameters()
+            .iter()
+            .map(|(_, param)| param.value().numel())
+            .sum()
+    }
+
+    /// A copy of every parameter's value, e.g. to save as a checkpoint.
+    fn state_dict(&self) -> Vec<(String, Tensor<T>)> {
+        self.named_parameters()
+            .into_iter()
+            .map(|(name, param)| (name, param.value().clone()))
+            .collect()
+    }
+
+    /// Set every parameter's value, looking each up by name, e.g. with `SafeTensors::get_cast`.
+    fn load_state_dict(&self, mut get: impl FnMut(&str) -> Result<Tensor<T>>) -> Result<()>
+    where
+        Self: Sized,
+    {
+        for (name, param) in self.named_parameters() {
+            let value = get(&name)?;
+            if value.shape() != param.shape() {
+                Err(MatrixError::ShapeMismatch {
+                    expected: param.shape(),
+                    actual: value.shape().to_vec(),
+                })?;
+            }
+            param.set_value(value);
+        }
+        Ok(())
+    }
+
+    /// A table of every parameter's name and shape, with the total number of parameters.
+    fn summary(&self) -> String {
+        let params = self.named_parameters();
+        let width = params.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        let mut summary = String::new();
+        for (name, param) in &params {
+            let shape = param.shape();
+            let count: usize = shape.iter().product();
+            writeln!(summary, "{name:<width$}  {shape:?}  {count}").unwrap();
+        }
+        write!(summary, "{} parameters", self.num_parameters()).unwrap();
+        summary
+    }
+}
+
+fn set_mode<T: Float, M: Module<T> + ?Sized>(module: &mut M, training: bool) {
+    module.set_training(training);
+    for (_, child) in module.children_mut() {
+        set_mode(child, training);
+    }
+}
+
+/// Modules applied one after another, named by their positions.
+pub struct Sequential<T: Float = f32> {
+    modules: Vec<Box<dyn Module<T>>>,
+    training: bool,
+}
+
+impl<T: Float> Sequential<T> {
+    pub fn new(modules: Vec<Box<dyn Module<T>>>) -> Self {
+        Sequential {
+            modules,
+            training: true,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.modules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.modules.is_empty()
+    }
+}
+
+impl<T: Float> Module<T> for Sequential<T> {
+    fn forward(&self, input: &Var<T>) -> Result<Var<T>> {
+        let mut output = input.clone();
+        for module in &self.modules {
+            output = module.forward(&output)?;
+        }
+        Ok(output)
+    }
+
+    fn children(&self) -> Vec<(String, &dyn Module<T>)> {
+        self.modules
+            .iter()
+            .enumerate()
+            .map(|(i, module)| (i.to_string(), module.as_ref()))
+            .collect()
+    }
+
+    fn children_mut(&mut self) -> Vec<(String, &mut dyn Module<T>)> {
+        self.modules
+            .iter_mut()
+            .enumerate()
+            .map(|(i, module)| (i.to_string(), module.as_mut() as &mut dyn Module<T>))
+            .collect()
+    }
+
+    fn is_training(&self) -> bool {
+        self.training
+    }
+
+    fn set_training(&mut self, training: bool) {
+        self.training = training;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::activation::{Activation, ActivationLayer};
+    use crate::layers::linear::LinearLayer;
+    use crate::matrix::random::Rng;
+    use crate::ops::loss::{mse_loss, Reduction};
+    use crate::optim::optimizer::{Optimizer, ParamGroup};
+    use crate::optim::sgd::{Sgd, SgdConfig};
+
+    // A model with a parameter of its own as well as children
+    struct Scaled {
+        scale: Var,
+        body: Sequential,
+        training: bool,
+    }
+
+    impl Module for Scaled {
+        fn forward(&self, input: &Var) -> Result<Var> {
+            self.body.forward(input)?.mul(&self.scale)
+        }
+
+        fn own_parameters(&self) -> Vec<(String, Var)> {
+            vec![("scale".to_owned(), self.scale.clone())]
+        }
+
+        fn children(&self) -> Vec<(String, &dyn Module)> {
+            vec![("body".to_owned(), &self.body)]
+        }
+
+        fn children_mut(&mut self) -> Vec<(String, &mut dyn Module)> {
+            vec![("body".to_owned(), &mut self.body)]
+        }
+
+        fn is_training(&self) -> bool {
+            self.training
+        }
+
+        fn set_training(&mut self, training: bool) {
+            self.training = training;
+        }
+    }
+
+    fn model(seed: u64) -> Scaled {
+        let mut rng = Rng::new(seed);
+        Scaled {
+            scale: Var::parameter(Tensor::ones(&[1])),
+            body: Sequential::new(vec![
+                Box::new(LinearLayer::<3, 4>::new(&mut rng)),
+                Box::new(ActivationLayer::new(Activation::Relu)),
+                Box::new(LinearLayer::<4, 2>::new(&mut rng)),
+            ]),
+            training: true,
+        }
+    }
+
+    #[test]
+    fn test_parameters() {
+        let model = model(0);
+        let names: Vec<String> = model
+            .named_parameters()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "scale",
+                "body.0.weight",
+                "body.0.bias",
+                "body.2.weight",
+                "body.2.bias"
+            ]
+        );
+        assert_eq!(model.num_parameters(), 1 + 3 * 4 + 4 + 4 * 2 + 2);
+        assert!(model.parameters()[1].same(&model.body.children()[0].1.parameters()[0]));
+        assert_eq!(
+            model.summary().lines().collect::<Vec<_>>(),
+            [
+                "scale          [1]  1",
+                "body.0.weight  [4, 3]  12",
+                "body.0.bias    [4]  4",
+                "body.2.weight  [2, 4]  8",
+                "body.2.bias    [2]  2",
+                "27 parameters"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_modes() {
+        let mut model = model(0);
+        assert!(model.is_training() && model.body.children()[1].1.is_training());
+        model.eval();
+        assert!(!model.is_training());
+        assert!(model
+            .body
+            .children()
+            .iter()
+            .all(|(_, child)| !child.is_training()));
+        model.body.train();
+        assert!(!model.is_training() && model.body.children()[0].1.is_training());
+    }
+
+    #[test]
+    fn test_state_dict() {
+        let (model, other) = (model(0), model(1));
+        let input = Var::constant(Tensor::ones(&[5, 3]));
+        assert_ne!(
+            *model.forward(&input).unwrap().value(),
+            *other.forward(&input).unwrap().value()
+        );
+
+        let state: std::collections::HashMap<String, Tensor> =
+            model.state_dict().into_iter().collect();
+        other
+            .load_state_dict(|name| Ok(state[name].clone()))
+            .unwrap();
+        assert_eq!(
+            *model.forward(&input).unwrap().value(),
+            *other.forward(&input).unwrap().value()
+        );
+
+        assert!(other.load_state_dict(|_| Ok(Tensor::zeros(&[7]))).is_err());
+    }
+
+    #[test]
+    fn test_optimize() {
+        // Any module's parameters can be handed to an optimizer
+        let model = model(0);
+        let inputs = Var::constant(Tensor::ones(&[5, 3]));
+        let targets = Var::constant(Tensor::full(&[5, 2], 0.5));
+        let mut sgd = Sgd::new(
+            vec![ParamGroup::new(model.named_parameters(), 0.05)],
+            SgdConfig::default(),
+        )
+        .unwrap();
+        let loss =
+            || mse_loss(&model.forward(&inputs).unwrap(), &targets, Reduction::Mean).unwrap();
+        let initial = loss().value().as_slice()[0];
+        for _ in 0..50 {
+            sgd.zero_grad();
+            loss().backward().unwrap();
+            sgd.step().unwrap();
+        }
+        let last = loss().value().as_slice()[0];
+        assert!(last < 0.01 * initial, "loss went from {initial} to {last}");
+    }
+}
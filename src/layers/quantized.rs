@@ -0,0 +1,98 @@
+use anyhow::Result;
+
+use crate::layers::linear::LinearLayer;
+use crate::matrix::matrix::Matrix2;
+use crate::matrix::quantize::{Quantization, QuantizedMatrix};
+use crate::matrix::tensor::Tensor;
+use crate::matrix::vector::FloatVector;
+
+/// A `LinearLayer` with quantized weights, which are dequantized as they're used. The bias, if
+/// there is one, is kept in full precision.
+pub struct QuantizedLinearLayer<const INPUT_SIZE: usize, const OUTPUT_SIZE: usize> {
+    weights: QuantizedMatrix,
+    bias: Option<FloatVector<OUTPUT_SIZE>>,
+}
+
+impl<const INPUT_SIZE: usize, const OUTPUT_SIZE: usize>
+    QuantizedLinearLayer<INPUT_SIZE, OUTPUT_SIZE>
+{
+    pub fn quantize(
+        layer: &LinearLayer<INPUT_SIZE, OUTPUT_SIZE>,
+        scheme: Quantization,
+    ) -> Result<Self> {
+        Ok(QuantizedLinearLayer {
+            weights: QuantizedMatrix::quantize(&Tensor::from(layer.weights()), scheme)?,
+            bias: layer.bias(),
+        })
+    }
+
+    pub fn weights(&self) -> &QuantizedMatrix {
+        &self.weights
+    }
+
+    pub fn forward(&self, state: FloatVector<INPUT_SIZE>) -> FloatVector<OUTPUT_SIZE> {
+        let outputs = Tensor::from(state)
+            .matmul_nt_quantized(&self.weights)
+            .unwrap();
+        let mut ret_vector = FloatVector::try_from(outputs).unwrap();
+        if let Some(bias) = &self.bias {
+            ret_vector += bias;
+        }
+        ret_vector
+    }
+
+    /// Apply the layer to each row of a batch of inputs.
+    pub fn forward_batch<const BATCH_SIZE: usize>(
+        &self,
+        states: &Matrix2<BATCH_SIZE, INPUT_SIZE>,
+    ) -> Matrix2<BATCH_SIZE, OUTPUT_SIZE> {
+        let outputs = Tensor::from(states)
+            .matmul_nt_quantized(&self.weights)
+            .unwrap();
+        let outputs = Matrix2::try_from(outputs).unwrap();
+        match &self.bias {
+            Some(bias) => &outputs + bias,
+            None => outputs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_close;
+
+    #[test]
+    fn test_forward() {
+        let layer = LinearLayer::from_elements(
+            [[0.5, -1., 0.25], [2., 0.125, -0.75], [-0.5, 1.5, 1.]],
+            [0.1, -0.2, 0.3],
+        );
+        let states = Matrix2::from_elements([[1., 2., 3.], [-1., 0.5, 0.], [0., 0., 0.]]);
+        for scheme in [
+            Quantization::Int8Symmetric,
+            Quantization::Int8Asymmetric,
+            Quantization::Int4 { group_size: 2 },
+        ] {
+            let quantized = QuantizedLinearLayer::quantize(&layer, scheme).unwrap();
+            let outputs = quantized.forward_batch(&states);
+            let tolerance = match scheme {
+                Quantization::Int4 { .. } => 0.5,
+                _ => 0.05,
+            };
+            for (state, output) in states.rows.iter().zip(outputs.rows.iter()) {
+                assert_eq!(&quantized.forward(state.clone()), output);
+                assert_close!(output, layer.forward(state.clone()), atol = tolerance);
+            }
+        }
+
+        let layer = layer.without_bias();
+        let quantized =
+            QuantizedLinearLayer::quantize(&layer, Quantization::Int8Symmetric).unwrap();
+        assert_close!(
+            quantized.forward_batch(&states),
+            layer.forward_batch(&states),
+            atol = 0.05
+        );
+    }
+}
@@ -1,5 +1,8 @@
-mod exceptions;
+pub mod autograd;
+pub mod exceptions;
+pub mod io;
 pub mod layers;
 pub mod matrix;
-mod ops;
+pub mod ops;
+pub mod optim;
 pub mod tokenizer;
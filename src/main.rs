@@ -1,10 +1,8 @@
 use core::str;
-use std::collections::HashSet;
 // This is going to by my tokenizer
-use env_logger;
 
 use log::info;
-use transformer_oxide::tokenizer::tokenizer::{bpe_on_file, bpe_on_str};
+use transformer_oxide::tokenizer::tokenizer::bpe_on_file;
 
 fn main() {
     env_logger::init();
@@ -13,7 +11,7 @@ fn main() {
 
     let mut vocab_words: Vec<String> = vocab
         .values()
-        .flat_map(|vocab_bytes| str::from_utf8(&vocab_bytes).ok())
+        .flat_map(|vocab_bytes| str::from_utf8(vocab_bytes).ok())
         .map(|val| val.to_owned())
         .collect();
     vocab_words.sort_by(|slf, other| slf.len().partial_cmp(&other.len()).unwrap());
@@ -0,0 +1,160 @@
+// NumPy-style broadcasting rules.
+//
+// Shapes are aligned from their last dimension. Each pair of dimensions must be equal, or one of
+// them must be 1, in which case that operand is repeated along the dimension. A missing leading
+// dimension counts as 1.
+use crate::exceptions::MatrixError;
+
+/// The shape two operands broadcast to.
+pub fn broadcast_shapes(lhs: &[usize], rhs: &[usize]) -> Result<Vec<usize>, MatrixError> {
+    let ndim = lhs.len().max(rhs.len());
+    let mut shape = vec![0; ndim];
+    for (i, dim) in shape.iter_mut().enumerate() {
+        let lhs_dim = aligned_dim(lhs, ndim, i);
+        let rhs_dim = aligned_dim(rhs, ndim, i);
+        *dim = if lhs_dim == rhs_dim || rhs_dim == 1 {
+            lhs_dim
+        } else if lhs_dim == 1 {
+            rhs_dim
+        } else {
+            return Err(MatrixError::BroadcastMismatch {
+                lhs: lhs.to_vec(),
+                rhs: rhs.to_vec(),
+            });
+        };
+    }
+    Ok(shape)
+}
+
+/// The strides to read an operand with as if it had the broadcast shape: zero along every
+/// dimension it is repeated over.
+///
+/// The shape must broadcast to `out_shape`.
+pub(crate) fn broadcast_strides(
+    shape: &[usize],
+    strides: &[usize],
+    out_shape: &[usize],
+) -> Vec<usize> {
+    let missing = out_shape.len() - shape.len();
+    (0..out_shape.len())
+        .map(|i| {
+            if i < missing || shape[i - missing] != out_shape[i] {
+                0
+            } else {
+                strides[i - missing]
+            }
+        })
+        .collect()
+}
+
+// The size of dimension `i` once the shape is padded with leading ones to `ndim` dimensions
+fn aligned_dim(shape: &[usize], ndim: usize, i: usize) -> usize {
+    let missing = ndim - shape.len();
+    if i < missing {
+        1
+    } else {
+        shape[i - missing]
+    }
+}
+
+/// The positions in a buffer of consecutive elements of a strided layout, in row-major order.
+pub(crate) struct StridedPositions<'a> {
+    shape: &'a [usize],
+    strides: &'a [usize],
+    index: Vec<usize>,
+    position: usize,
+    remaining: usize,
+}
+
+impl<'a> StridedPositions<'a> {
+    /// Start at the `start`th element in row-major order, for a layout beginning at `offset`.
+    pub(crate) fn new(
+        shape: &'a [usize],
+        strides: &'a [usize],
+        offset: usize,
+        start: usize,
+    ) -> Self {
+        let numel: usize = shape.iter().product();
+        let mut index = vec![0; shape.len()];
+        let mut rest = start;
+        for dim in (0..shape.len()).rev() {
+            if shape[dim] > 0 {
+                index[dim] = rest % shape[dim];
+                rest /= shape[dim];
+            }
+        }
+        let position = offset + index.iter().zip(strides).map(|(i, s)| i * s).sum::<usize>();
+        StridedPositions {
+            shape,
+            strides,
+            index,
+            position,
+            remaining: numel.saturating_sub(start),
+        }
+    }
+}
+
+impl Iterator for StridedPositions<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let current = self.position;
+        // Increment the index like an odometer, keeping track of the position
+        for dim in (0..self.shape.len()).rev() {
+            self.index[dim] += 1;
+            self.position += self.strides[dim];
+            if self.index[dim] < self.shape[dim] {
+                break;
+            }
+            self.position -= self.strides[dim] * self.shape[dim];
+            self.index[dim] = 0;
+        }
+        Some(current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_broadcast_shapes() {
+        assert_eq!(broadcast_shapes(&[2, 3], &[2, 3]).unwrap(), vec![2, 3]);
+        assert_eq!(broadcast_shapes(&[4, 2, 3], &[3]).unwrap(), vec![4, 2, 3]);
+        assert_eq!(
+            broadcast_shapes(&[4, 1, 3], &[2, 1]).unwrap(),
+            vec![4, 2, 3]
+        );
+        assert_eq!(broadcast_shapes(&[], &[5]).unwrap(), vec![5]);
+        assert_eq!(broadcast_shapes(&[0, 1], &[3]).unwrap(), vec![0, 3]);
+
+        match broadcast_shapes(&[2, 3], &[2]) {
+            Err(MatrixError::BroadcastMismatch { lhs, rhs }) => {
+                assert_eq!(lhs, vec![2, 3]);
+                assert_eq!(rhs, vec![2]);
+            }
+            other => panic!("Expected a broadcast error, got {other:?}"),
+        }
+        assert!(broadcast_shapes(&[3, 2], &[2, 3]).is_err());
+    }
+
+    #[test]
+    fn test_strided_positions() {
+        // A [3] operand read as [2, 3] repeats itself
+        let strides = broadcast_strides(&[3], &[1], &[2, 3]);
+        assert_eq!(strides, vec![0, 1]);
+        let positions: Vec<usize> = StridedPositions::new(&[2, 3], &strides, 0, 0).collect();
+        assert_eq!(positions, vec![0, 1, 2, 0, 1, 2]);
+
+        // Starting part of the way through, as a thread working on one chunk does
+        let strides = broadcast_strides(&[2, 1], &[1, 1], &[2, 3]);
+        let positions: Vec<usize> = StridedPositions::new(&[2, 3], &strides, 0, 2).collect();
+        assert_eq!(positions, vec![0, 1, 1, 1]);
+
+        assert_eq!(StridedPositions::new(&[2, 0], &[0, 1], 0, 0).count(), 0);
+    }
+}
@@ -0,0 +1,192 @@
+// Approximate comparison of vectors, matrices and tensors, mainly for tests.
+//
+// As with NumPy's `allclose`, an element is close to the expected one when
+// `|actual - expected| <= atol + rtol * |expected|`. Infinities are only close to the same
+// infinity, and NaN is close to NaN, so outputs which should be NaN can be checked too.
+use crate::matrix::element::Element;
+use crate::matrix::matrix::Matrix2;
+use crate::matrix::tensor::{unravel_index, Tensor};
+use crate::matrix::vector::FloatVector;
+use crate::matrix::view::TensorView;
+
+pub const DEFAULT_RTOL: f64 = 1e-5;
+pub const DEFAULT_ATOL: f64 = 1e-8;
+
+/// Anything `assert_close!` can compare.
+pub trait ToTensor<T: Element> {
+    fn to_tensor(&self) -> Tensor<T>;
+}
+
+impl<T: Element, A: ToTensor<T>> ToTensor<T> for &A {
+    fn to_tensor(&self) -> Tensor<T> {
+        (*self).to_tensor()
+    }
+}
+
+impl<T: Element> ToTensor<T> for Tensor<T> {
+    fn to_tensor(&self) -> Tensor<T> {
+        self.clone()
+    }
+}
+
+impl<T: Element> ToTensor<T> for TensorView<'_, T> {
+    fn to_tensor(&self) -> Tensor<T> {
+        self.contiguous()
+    }
+}
+
+impl<const SIZE: usize, T: Element> ToTensor<T> for FloatVector<SIZE, T> {
+    fn to_tensor(&self) -> Tensor<T> {
+        self.into()
+    }
+}
+
+impl<const N_ROWS: usize, const N_COLS: usize, T: Element> ToTensor<T>
+    for Matrix2<N_ROWS, N_COLS, T>
+{
+    fn to_tensor(&self) -> Tensor<T> {
+        self.into()
+    }
+}
+
+fn is_close(actual: f64, expected: f64, rtol: f64, atol: f64) -> bool {
+    if actual.is_nan() || expected.is_nan() {
+        return actual.is_nan() && expected.is_nan();
+    }
+    if actual.is_infinite() || expected.is_infinite() {
+        return actual == expected;
+    }
+    (actual - expected).abs() <= atol + rtol * expected.abs()
+}
+
+/// Check `actual` is elementwise close to `expected`, which must have the same shape,
+/// describing the differences if not.
+pub fn check_close<T: Element>(
+    actual: &impl ToTensor<T>,
+    expected: &impl ToTensor<T>,
+    rtol: f64,
+    atol: f64,
+) -> Result<(), String> {
+    let (actual, expected) = (actual.to_tensor(), expected.to_tensor());
+    if actual.shape() != expected.shape() {
+        return Err(format!(
+            "shapes differ: {:?} and {:?}",
+            actual.shape(),
+            expected.shape()
+        ));
+    }
+    let mismatches: Vec<(usize, f64)> = actual
+        .iter()
+        .zip(expected.iter())
+        .map(|(x, y)| (x.to_f64(), y.to_f64()))
+        .enumerate()
+        .filter(|(_, (x, y))| !is_close(*x, *y, rtol, atol))
+        .map(|(position, (x, y))| (position, (x - y).abs()))
+        .collect();
+    let Some((worst, difference)) = mismatches
+        .iter()
+        .copied()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+    else {
+        return Ok(());
+    };
+
+    let index = unravel_index(worst, actual.shape());
+    Err(format!(
+        "{} of {} elements differ (rtol={rtol:e}, atol={atol:e}), the largest by {difference:e} \
+         at {index:?}, where {:?} was expected but got {:?}\nactual: {actual}\nexpected: {expected}",
+        mismatches.len(),
+        actual.numel(),
+        expected.as_slice()[worst],
+        actual.as_slice()[worst],
+    ))
+}
+
+/// Whether `actual` is elementwise close to `expected`. See `check_close`.
+pub fn all_close<T: Element>(
+    actual: &impl ToTensor<T>,
+    expected: &impl ToTensor<T>,
+    rtol: f64,
+    atol: f64,
+) -> bool {
+    check_close(actual, expected, rtol, atol).is_ok()
+}
+
+/// Assert two vectors, matrices, tensors or views are elementwise close, with optional
+/// tolerances: `assert_close!(actual, expected, rtol = 1e-4, atol = 1e-6)`. Either tolerance
+/// can be left out, defaulting to `DEFAULT_RTOL` and `DEFAULT_ATOL`.
+#[macro_export]
+macro_rules! assert_close {
+    ($actual:expr, $expected:expr $(, rtol = $rtol:expr)? $(, atol = $atol:expr)? $(,)?) => {{
+        #[allow(unused_variables)]
+        let rtol = $crate::matrix::compare::DEFAULT_RTOL;
+        $(let rtol = $rtol;)?
+        #[allow(unused_variables)]
+        let atol = $crate::matrix::compare::DEFAULT_ATOL;
+        $(let atol = $atol;)?
+        if let Err(message) = $crate::matrix::compare::check_close(&$actual, &$expected, rtol, atol)
+        {
+            panic!("assertion failed: {} is not close to {}: {message}", stringify!($actual), stringify!($expected));
+        }
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_close() {
+        let expected = Tensor::from_vec(vec![1f32, 100., 0., f32::NAN], &[2, 2]).unwrap();
+        let actual =
+            Tensor::from_vec(vec![1.000001f32, 100.0001, 1e-9, f32::NAN], &[2, 2]).unwrap();
+        assert_close!(actual, expected);
+        assert_close!(actual.view(), expected);
+        assert!(!all_close(&actual, &expected, 0., 0.));
+        assert!(all_close(&actual, &expected, 0., 1e-4));
+        assert!(!all_close(&actual, &expected, 1e-7, 1e-8));
+        let infinity = Tensor::scalar(f32::INFINITY);
+        assert!(all_close(&infinity, &infinity, 0., 0.));
+        assert!(!all_close(&infinity, &Tensor::scalar(f32::MAX), 1., 1.));
+
+        let vector = FloatVector::from_elements([1f64, 2.]);
+        assert_close!(
+            vector,
+            FloatVector::from_elements([1.01, 1.99]),
+            atol = 0.02
+        );
+        assert_close!(
+            vector,
+            FloatVector::from_elements([1.01, 1.99]),
+            rtol = 0.01
+        );
+        assert_close!(
+            Matrix2::from_elements([[1f32, 2.]]),
+            Matrix2::from_elements([[1.1, 2.]]),
+            rtol = 0.,
+            atol = 0.2,
+        );
+    }
+
+    #[test]
+    fn test_messages() {
+        let expected = Tensor::from_vec(vec![1f32, 2., 3., 4.], &[2, 2]).unwrap();
+        let actual = Tensor::from_vec(vec![1f32, 2.5, 3., 3.], &[2, 2]).unwrap();
+        let message = check_close(&actual, &expected, 0., 0.1).unwrap_err();
+        assert!(
+            message.starts_with("2 of 4 elements differ (rtol=0e0, atol=1e-1), the largest by 1e0 at [1, 1], where 4.0 was expected but got 3.0\nactual: Tensor"),
+            "{message}"
+        );
+        let message =
+            check_close(&actual, &actual.clone().reshape(&[4]).unwrap(), 0., 0.).unwrap_err();
+        assert_eq!(message, "shapes differ: [2, 2] and [4]");
+    }
+
+    #[test]
+    #[should_panic(expected = "assertion failed: actual is not close to expected")]
+    fn test_assert_close_fails() {
+        let actual = FloatVector::from_elements([1f32]);
+        let expected = FloatVector::from_elements([2f32]);
+        assert_close!(actual, expected);
+    }
+}
@@ -0,0 +1,256 @@
+// NumPy-style printing for vectors, matrices, tensors and views.
+//
+// The output is a header with the shape and element type, then the elements nested in brackets
+// and right-aligned to a common width. Floats are printed to 4 decimal places, or the
+// formatter's precision as in `{:.2}`, switching to scientific notation when the values span
+// too many orders of magnitude. Anything with more than SUMMARY_THRESHOLD elements only shows
+// the first and last EDGE_ITEMS along each dimension, unless printed with `{:#}`.
+use std::fmt::{self, Display, Formatter};
+
+use crate::matrix::element::Element;
+use crate::matrix::matrix::Matrix2;
+use crate::matrix::tensor::Tensor;
+use crate::matrix::vector::FloatVector;
+use crate::matrix::view::TensorView;
+
+const DEFAULT_PRECISION: usize = 4;
+pub const SUMMARY_THRESHOLD: usize = 1000;
+pub const EDGE_ITEMS: usize = 3;
+
+/// Write `view` with a header naming its type, e.g. `Tensor(shape=[2, 3], dtype=f32)`.
+pub(crate) fn write_elements<T: Element>(
+    f: &mut Formatter,
+    name: &str,
+    view: &TensorView<T>,
+) -> fmt::Result {
+    writeln!(f, "{name}(shape={:?}, dtype={})", view.shape(), T::NAME)?;
+    let summarise = !f.alternate() && view.numel() > SUMMARY_THRESHOLD;
+    let mut values = Vec::new();
+    visit_shown(view, summarise, &mut Vec::new(), &mut values);
+    let cells = format_cells(&values, f.precision().unwrap_or(DEFAULT_PRECISION));
+    let width = cells.iter().map(String::len).max().unwrap_or(0);
+
+    let mut out = String::new();
+    if view.ndim() == 0 {
+        out.push_str(&cells[0]);
+    } else {
+        write_nested(
+            &mut out,
+            view.shape(),
+            summarise,
+            &mut cells.iter(),
+            width,
+            0,
+        );
+    }
+    f.write_str(&out)
+}
+
+// The indices to show along a dimension, with None where the ellipsis goes
+fn shown_indices(len: usize, summarise: bool) -> Vec<Option<usize>> {
+    if summarise && len > 2 * EDGE_ITEMS {
+        (0..EDGE_ITEMS)
+            .map(Some)
+            .chain([None])
+            .chain((len - EDGE_ITEMS..len).map(Some))
+            .collect()
+    } else {
+        (0..len).map(Some).collect()
+    }
+}
+
+// Collect the elements which will be shown, in the order they're printed
+fn visit_shown<T: Element>(
+    view: &TensorView<T>,
+    summarise: bool,
+    index: &mut Vec<usize>,
+    values: &mut Vec<T>,
+) {
+    let dim = index.len();
+    if dim == view.ndim() {
+        values.push(view.get(index).unwrap());
+        return;
+    }
+    for i in shown_indices(view.shape()[dim], summarise)
+        .into_iter()
+        .flatten()
+    {
+        index.push(i);
+        visit_shown(view, summarise, index, values);
+        index.pop();
+    }
+}
+
+fn format_cells<T: Element>(values: &[T], precision: usize) -> Vec<String> {
+    if !T::IS_FLOAT {
+        return values.iter().map(|value| format!("{value:?}")).collect();
+    }
+    let magnitudes = values
+        .iter()
+        .map(|value| value.to_f64().abs())
+        .filter(|value| value.is_finite() && *value != 0.);
+    let (min, max) = magnitudes.fold((f64::INFINITY, 0f64), |(min, max), value| {
+        (min.min(value), max.max(value))
+    });
+    let scientific = max >= 1e8 || min < 10f64.powi(-(precision as i32)) || max / min >= 1e8;
+    values
+        .iter()
+        .map(|value| {
+            let value = value.to_f64();
+            if value.is_nan() {
+                "nan".to_owned()
+            } else if value.is_infinite() {
+                if value > 0. { "inf" } else { "-inf" }.to_owned()
+            } else if scientific {
+                format!("{value:.precision$e}")
+            } else {
+                format!("{value:.precision$}")
+            }
+        })
+        .collect()
+}
+
+fn write_nested<'a>(
+    out: &mut String,
+    shape: &[usize],
+    summarise: bool,
+    cells: &mut impl Iterator<Item = &'a String>,
+    width: usize,
+    depth: usize,
+) {
+    out.push('[');
+    let innermost = shape.len() == 1;
+    for (position, index) in shown_indices(shape[0], summarise).into_iter().enumerate() {
+        if position > 0 {
+            out.push(',');
+            if innermost {
+                out.push(' ');
+            } else {
+                // Each level up gets another blank line between its blocks
+                out.push_str(&"\n".repeat(shape.len() - 1));
+                out.push_str(&" ".repeat(depth + 1));
+            }
+        }
+        match index {
+            None => out.push_str("..."),
+            Some(_) if innermost => {
+                let cell = cells.next().unwrap();
+                out.push_str(&format!("{cell:>width$}"));
+            }
+            Some(_) => write_nested(out, &shape[1..], summarise, cells, width, depth + 1),
+        }
+    }
+    out.push(']');
+}
+
+impl<T: Element> Display for Tensor<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write_elements(f, "Tensor", &self.view())
+    }
+}
+
+impl<T: Element> Display for TensorView<'_, T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write_elements(f, "TensorView", self)
+    }
+}
+
+impl<const SIZE: usize, T: Element> Display for FloatVector<SIZE, T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let view = TensorView::new(self.as_slice(), &[SIZE], &[1]);
+        write_elements(f, "FloatVector", &view)
+    }
+}
+
+impl<const N_ROWS: usize, const N_COLS: usize, T: Element> Display for Matrix2<N_ROWS, N_COLS, T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write_elements(f, "Matrix2", &Tensor::from(self).view())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matrix::half::Bf16;
+
+    #[test]
+    fn test_small() {
+        let tensor = Tensor::from_vec(vec![1f32, -2.5, 3., 40.], &[2, 2]).unwrap();
+        assert_eq!(
+            tensor.to_string(),
+            "Tensor(shape=[2, 2], dtype=f32)\n\
+             [[ 1.0000, -2.5000],\n \
+              [ 3.0000, 40.0000]]"
+        );
+        assert_eq!(
+            format!("{tensor:.1}"),
+            "Tensor(shape=[2, 2], dtype=f32)\n[[ 1.0, -2.5],\n [ 3.0, 40.0]]"
+        );
+
+        let ids = Tensor::from_vec((0..8i64).collect(), &[2, 2, 2]).unwrap();
+        assert_eq!(
+            ids.to_string(),
+            "Tensor(shape=[2, 2, 2], dtype=i64)\n[[[0, 1],\n  [2, 3]],\n\n [[4, 5],\n  [6, 7]]]"
+        );
+
+        assert_eq!(
+            format!("{:.2}", FloatVector::from_elements([0.5f64, 1.])),
+            "FloatVector(shape=[2], dtype=f64)\n[0.50, 1.00]"
+        );
+        assert_eq!(
+            Matrix2::from_elements([[1u8, 20]]).to_string(),
+            "Matrix2(shape=[1, 2], dtype=u8)\n[[ 1, 20]]"
+        );
+        assert_eq!(
+            Tensor::scalar(Bf16::from_f32(2.)).to_string(),
+            "Tensor(shape=[], dtype=bf16)\n2.0000"
+        );
+        assert_eq!(
+            Tensor::<f32>::zeros(&[0, 3]).to_string(),
+            "Tensor(shape=[0, 3], dtype=f32)\n[]"
+        );
+    }
+
+    #[test]
+    fn test_special_values() {
+        let tensor = Tensor::from_vec(vec![f32::NAN, f32::NEG_INFINITY, 0., 1.], &[4]).unwrap();
+        assert_eq!(
+            format!("{tensor:.1}"),
+            "Tensor(shape=[4], dtype=f32)\n[ nan, -inf,  0.0,  1.0]"
+        );
+        let tensor = Tensor::from_vec(vec![1e-6f64, 1.], &[2]).unwrap();
+        assert_eq!(
+            format!("{tensor:.2}"),
+            "Tensor(shape=[2], dtype=f64)\n[1.00e-6,  1.00e0]"
+        );
+    }
+
+    #[test]
+    fn test_summarised() {
+        let tensor = Tensor::from_vec((0..2000i32).collect(), &[2, 1000]).unwrap();
+        assert_eq!(
+            tensor.to_string(),
+            "Tensor(shape=[2, 1000], dtype=i32)\n\
+             [[   0,    1,    2, ...,  997,  998,  999],\n \
+              [1000, 1001, 1002, ..., 1997, 1998, 1999]]"
+        );
+        let tensor = Tensor::from_vec((0..1001i32).collect(), &[1001, 1]).unwrap();
+        let lines: Vec<String> = tensor.to_string().lines().map(str::to_owned).collect();
+        assert_eq!(
+            lines[1..],
+            ["[[   0],", " [   1],", " [   2],", " ...,", " [ 998],", " [ 999],", " [1000]]"]
+        );
+        // The alternate flag prints everything
+        assert_eq!(format!("{tensor:#}").lines().count(), 1002);
+    }
+
+    #[test]
+    fn test_views() {
+        let tensor = Tensor::from_vec(vec![1i32, 2, 3, 4, 5, 6], &[2, 3]).unwrap();
+        let view = tensor.view().transpose(0, 1).unwrap();
+        assert_eq!(
+            view.to_string(),
+            "TensorView(shape=[3, 2], dtype=i32)\n[[1, 4],\n [2, 5],\n [3, 6]]"
+        );
+    }
+}
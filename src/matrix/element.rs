@@ -0,0 +1,237 @@
+// The element types vectors, matrices and tensors can hold.
+//
+// `Element` is all a type needs to be stored and converted, `Number` adds arithmetic, and `Float`
+// adds the functions reductions and activations need. Half precision types only implement
+// `Element`: they are converted to `f32` to do arithmetic.
+use std::fmt::Debug;
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub};
+
+use crate::matrix::gemm::{self, MatRef};
+
+pub trait Element: Copy + Debug + PartialEq + Send + Sync + 'static {
+    const ZERO: Self;
+    const ONE: Self;
+    /// The name of the type, as printed in tensor headers.
+    const NAME: &'static str;
+    /// Whether the type holds fractional values, which are printed to a fixed precision.
+    const IS_FLOAT: bool;
+
+    /// Convert to an `f64`, which holds every value of the other types exactly, apart from
+    /// integers beyond ±2⁵³.
+    fn to_f64(self) -> f64;
+
+    /// Convert from an `f64`, rounding to the nearest value for floats. Integers are rounded
+    /// towards zero and saturate at their bounds, with NaN becoming zero.
+    fn from_f64(value: f64) -> Self;
+
+    fn to_f32(self) -> f32 {
+        self.to_f64() as f32
+    }
+
+    fn from_f32(value: f32) -> Self {
+        Self::from_f64(value as f64)
+    }
+
+    /// Convert to another element type.
+    fn cast<U: Element>(self) -> U {
+        U::from_f64(self.to_f64())
+    }
+}
+
+pub trait Number:
+    Element
+    + PartialOrd
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + AddAssign
+{
+    /// The sum of products of the elements of two slices of the same length.
+    fn dot(a: &[Self], b: &[Self]) -> Self {
+        assert_eq!(a.len(), b.len());
+        a.iter()
+            .zip(b)
+            .fold(Self::ZERO, |total, (x, y)| total + *x * *y)
+    }
+
+    /// Accumulate the product `a · b` into the row-major matrix `c`.
+    fn gemm(a: MatRef<Self>, b: MatRef<Self>, c: &mut [Self]) {
+        gemm::naive_gemm(a, b, c)
+    }
+}
+
+pub trait Float: Number + Neg<Output = Self> {
+    const INFINITY: Self;
+    const NEG_INFINITY: Self;
+    const NAN: Self;
+    /// The difference between 1 and the next larger number.
+    const EPSILON: Self;
+
+    fn exp(self) -> Self;
+    fn ln(self) -> Self;
+    fn sqrt(self) -> Self;
+    fn powf(self, exponent: Self) -> Self;
+    fn powi(self, exponent: i32) -> Self;
+    fn abs(self) -> Self;
+    fn tanh(self) -> Self;
+    /// The larger of the two, ignoring NaN.
+    fn max(self, other: Self) -> Self;
+    /// The smaller of the two, ignoring NaN.
+    fn min(self, other: Self) -> Self;
+    fn is_nan(self) -> bool;
+    fn is_finite(self) -> bool;
+    fn is_infinite(self) -> bool;
+
+    fn from_usize(value: usize) -> Self {
+        Self::from_f64(value as f64)
+    }
+}
+
+macro_rules! impl_element {
+    ($($type:ty: $zero:literal, $one:literal, $is_float:literal);* $(;)?) => {
+        $(
+            impl Element for $type {
+                const ZERO: Self = $zero;
+                const ONE: Self = $one;
+                const NAME: &'static str = stringify!($type);
+                const IS_FLOAT: bool = $is_float;
+
+                fn to_f64(self) -> f64 {
+                    self as f64
+                }
+
+                fn from_f64(value: f64) -> Self {
+                    value as $type
+                }
+
+                fn to_f32(self) -> f32 {
+                    self as f32
+                }
+
+                fn from_f32(value: f32) -> Self {
+                    value as $type
+                }
+            }
+        )*
+    };
+}
+
+impl_element!(
+    f32: 0., 1., true;
+    f64: 0., 1., true;
+    u8: 0, 1, false;
+    u16: 0, 1, false;
+    u32: 0, 1, false;
+    i32: 0, 1, false;
+    i64: 0, 1, false;
+    usize: 0, 1, false;
+);
+
+impl Number for f32 {
+    fn dot(a: &[f32], b: &[f32]) -> f32 {
+        gemm::dot(a, b)
+    }
+
+    fn gemm(a: MatRef<f32>, b: MatRef<f32>, c: &mut [f32]) {
+        gemm::gemm(a, b, c)
+    }
+}
+
+impl Number for f64 {}
+impl Number for u8 {}
+impl Number for u16 {}
+impl Number for u32 {}
+impl Number for i32 {}
+impl Number for i64 {}
+impl Number for usize {}
+
+macro_rules! impl_float {
+    ($($type:ident),*) => {
+        $(
+            impl Float for $type {
+                const INFINITY: Self = $type::INFINITY;
+                const NEG_INFINITY: Self = $type::NEG_INFINITY;
+                const NAN: Self = $type::NAN;
+                const EPSILON: Self = $type::EPSILON;
+
+                fn exp(self) -> Self {
+                    $type::exp(self)
+                }
+
+                fn ln(self) -> Self {
+                    $type::ln(self)
+                }
+
+                fn sqrt(self) -> Self {
+                    $type::sqrt(self)
+                }
+
+                fn powf(self, exponent: Self) -> Self {
+                    $type::powf(self, exponent)
+                }
+
+                fn powi(self, exponent: i32) -> Self {
+                    $type::powi(self, exponent)
+                }
+
+                fn abs(self) -> Self {
+                    $type::abs(self)
+                }
+
+                fn tanh(self) -> Self {
+                    $type::tanh(self)
+                }
+
+                fn max(self, other: Self) -> Self {
+                    $type::max(self, other)
+                }
+
+                fn min(self, other: Self) -> Self {
+                    $type::min(self, other)
+                }
+
+                fn is_nan(self) -> bool {
+                    $type::is_nan(self)
+                }
+
+                fn is_finite(self) -> bool {
+                    $type::is_finite(self)
+                }
+
+                fn is_infinite(self) -> bool {
+                    $type::is_infinite(self)
+                }
+            }
+        )*
+    };
+}
+
+impl_float!(f32, f64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cast() {
+        assert_eq!(1.5f32.cast::<f64>(), 1.5);
+        assert_eq!(0.1f64.cast::<f32>(), 0.1f32);
+        assert_eq!(3.9f32.cast::<i32>(), 3);
+        assert_eq!((-3.9f32).cast::<i64>(), -3);
+        assert_eq!(300f32.cast::<u8>(), 255);
+        assert_eq!((-1f32).cast::<usize>(), 0);
+        assert_eq!(f32::NAN.cast::<u16>(), 0);
+        assert_eq!(7u16.cast::<f32>(), 7.);
+        assert_eq!(i64::MIN.cast::<f64>(), -(2f64.powi(63)));
+    }
+
+    #[test]
+    fn test_dot() {
+        let a = [1., 2., 3., 4., 5., 6., 7., 8., 9.];
+        assert_eq!(Number::dot(&a, &a), 285f32);
+        let a: Vec<f64> = a.iter().map(|x| *x as f64).collect();
+        assert_eq!(Number::dot(&a, &a), 285f64);
+        assert_eq!(Number::dot(&[1i64, -2], &[3, 4]), -5);
+    }
+}
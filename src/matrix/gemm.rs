@@ -0,0 +1,474 @@
+// A cache-blocked matrix multiplication, following the usual BLIS-style structure.
+//
+// The operands are split into blocks which fit in cache (KC x NC of B, MC x KC of A) and each
+// block is packed into a contiguous buffer of thin panels, MR rows of A or NR columns of B at a
+// time. A micro-kernel then computes one MR x NR tile of the output from a pair of panels,
+// keeping the whole tile in registers.
+//
+// Packing converts the operands to f32, so half precision operands are only widened as they're
+// loaded, and the products are always accumulated in f32.
+use rayon::prelude::*;
+
+use crate::matrix::element::{Element, Number};
+use crate::matrix::parallel;
+
+/// Rows of A in each packed panel, and of the micro-kernel's output tile.
+const MR: usize = 6;
+/// Columns of B in each packed panel, and of the micro-kernel's output tile.
+const NR: usize = 16;
+/// The depth of each block.
+const KC: usize = 256;
+/// Rows of A in each block. A multiple of MR.
+const MC: usize = 96;
+/// Columns of B in each block. A multiple of NR.
+const NC: usize = 1024;
+
+/// A read-only view of a matrix inside a slice, with arbitrary strides.
+#[derive(Debug, Clone, Copy)]
+pub struct MatRef<'a, T = f32> {
+    data: &'a [T],
+    rows: usize,
+    cols: usize,
+    row_stride: usize,
+    col_stride: usize,
+}
+
+impl<'a, T: Copy> MatRef<'a, T> {
+    /// Panics if an element would lie outside the data.
+    pub fn new(
+        data: &'a [T],
+        rows: usize,
+        cols: usize,
+        row_stride: usize,
+        col_stride: usize,
+    ) -> Self {
+        if rows > 0 && cols > 0 {
+            let last = (rows - 1) * row_stride + (cols - 1) * col_stride;
+            assert!(
+                last < data.len(),
+                "A {rows}x{cols} matrix with strides ({row_stride}, {col_stride}) doesn't fit in {} elements",
+                data.len()
+            );
+        }
+        MatRef {
+            data,
+            rows,
+            cols,
+            row_stride,
+            col_stride,
+        }
+    }
+
+    /// A contiguous row-major matrix.
+    pub fn row_major(data: &'a [T], rows: usize, cols: usize) -> Self {
+        MatRef::new(data, rows, cols, cols, 1)
+    }
+
+    /// The transpose, which only swaps the strides.
+    pub fn t(self) -> Self {
+        MatRef {
+            data: self.data,
+            rows: self.cols,
+            cols: self.rows,
+            row_stride: self.col_stride,
+            col_stride: self.row_stride,
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    fn at(&self, row: usize, col: usize) -> T {
+        self.data[row * self.row_stride + col * self.col_stride]
+    }
+}
+
+/// The micro-kernels available to `gemm`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kernel {
+    /// Plain Rust written so the compiler can vectorise it for any target.
+    Generic,
+    /// Explicit AVX2 and FMA intrinsics.
+    #[cfg(target_arch = "x86_64")]
+    Avx2Fma,
+}
+
+impl Kernel {
+    /// The fastest kernel supported by the running CPU.
+    pub fn detect() -> Kernel {
+        #[cfg(target_arch = "x86_64")]
+        if Kernel::Avx2Fma.is_supported() {
+            return Kernel::Avx2Fma;
+        }
+        Kernel::Generic
+    }
+
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Kernel::Generic => true,
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2Fma => is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma"),
+        }
+    }
+
+    // Compute the product of an A panel and a B panel of depth kc into `tile`
+    fn run(&self, kc: usize, a_panel: &[f32], b_panel: &[f32], tile: &mut [[f32; NR]; MR]) {
+        assert!(a_panel.len() >= kc * MR && b_panel.len() >= kc * NR);
+        match self {
+            Kernel::Generic => kernel_generic(kc, a_panel, b_panel, tile),
+            // SAFETY: the kernel is only selected once the CPU features have been checked
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2Fma => unsafe { kernel_avx2_fma(kc, a_panel, b_panel, tile) },
+        }
+    }
+}
+
+/// Accumulate `a · b` into `c`, a row-major matrix of shape (a.rows(), b.cols()). The operands
+/// can be of any element type, and are converted to f32 as they are packed.
+pub fn gemm<A: Element, B: Element>(a: MatRef<A>, b: MatRef<B>, c: &mut [f32]) {
+    gemm_with_kernel(Kernel::detect(), a, b, c)
+}
+
+/// As `gemm`, with a particular micro-kernel. Panics if the CPU doesn't support the kernel.
+pub fn gemm_with_kernel<A: Element, B: Element>(
+    kernel: Kernel,
+    a: MatRef<A>,
+    b: MatRef<B>,
+    c: &mut [f32],
+) {
+    assert!(
+        kernel.is_supported(),
+        "{kernel:?} isn't supported by this CPU"
+    );
+    assert_eq!(a.cols, b.rows, "The inner dimensions of a gemm must match");
+    assert_eq!(c.len(), a.rows * b.cols, "The output has the wrong size");
+    let (m, n, k) = (a.rows, b.cols, a.cols);
+    if m == 0 || n == 0 || k == 0 {
+        return;
+    }
+
+    // Blocks of MC rows of the output are independent, so with enough work they are shared
+    // between threads, each packing its own blocks of A. Every element is computed in the same
+    // order either way.
+    let packed_a_len = MC.min(m.next_multiple_of(MR)) * KC.min(k);
+    let parallel = m > MC && parallel::should_parallelize(m * n * k);
+    let mut packed_a = vec![0.; if parallel { 0 } else { packed_a_len }];
+    let mut packed_b = vec![0.; NC.min(n.next_multiple_of(NR)) * KC.min(k)];
+    for jc in (0..n).step_by(NC) {
+        let nc = NC.min(n - jc);
+        for pc in (0..k).step_by(KC) {
+            let kc = KC.min(k - pc);
+            pack_b(&b, (pc, jc), (kc, nc), &mut packed_b);
+
+            let packed_b = &packed_b;
+            let row_block = |block_idx: usize, c_block: &mut [f32], packed_a: &mut [f32]| {
+                let ic = block_idx * MC;
+                let mc = MC.min(m - ic);
+                pack_a(&a, (ic, pc), (mc, kc), packed_a);
+                macro_kernel(kernel, packed_a, packed_b, (mc, nc, kc), jc, c_block, n);
+            };
+            if parallel {
+                parallel::install(|| {
+                    c.par_chunks_mut(MC * n).enumerate().for_each_init(
+                        || vec![0.; packed_a_len],
+                        |packed_a, (block_idx, c_block)| row_block(block_idx, c_block, packed_a),
+                    )
+                });
+            } else {
+                for (block_idx, c_block) in c.chunks_mut(MC * n).enumerate() {
+                    row_block(block_idx, c_block, &mut packed_a);
+                }
+            }
+        }
+    }
+}
+
+/// Accumulate `a · b` into `c` with the textbook triple loop. Used as a reference.
+pub fn naive_gemm<T: Number>(a: MatRef<T>, b: MatRef<T>, c: &mut [T]) {
+    assert_eq!(a.cols, b.rows, "The inner dimensions of a gemm must match");
+    assert_eq!(c.len(), a.rows * b.cols, "The output has the wrong size");
+    let n = b.cols;
+    for i in 0..a.rows {
+        for p in 0..a.cols {
+            let a_ip = a.at(i, p);
+            for (j, element) in c[i * n..(i + 1) * n].iter_mut().enumerate() {
+                *element += a_ip * b.at(p, j);
+            }
+        }
+    }
+}
+
+/// The dot product of two slices, with independent accumulators so that it vectorises.
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+    const LANES: usize = 8;
+    assert_eq!(a.len(), b.len());
+    let mut partial_sums = [0.; LANES];
+    let a_chunks = a.chunks_exact(LANES);
+    let b_chunks = b.chunks_exact(LANES);
+    let remainder: f32 = a_chunks
+        .remainder()
+        .iter()
+        .zip(b_chunks.remainder())
+        .map(|(x, y)| x * y)
+        .sum();
+    for (a_chunk, b_chunk) in a_chunks.zip(b_chunks) {
+        for ((sum, x), y) in partial_sums.iter_mut().zip(a_chunk).zip(b_chunk) {
+            *sum += x * y;
+        }
+    }
+    partial_sums.iter().sum::<f32>() + remainder
+}
+
+// Pack a (kc, nc) block of B starting at `start` into panels of NR columns. Each panel is
+// stored depth-first, so the kernel reads NR contiguous values per step. The last panel is
+// padded with zeros.
+fn pack_b<T: Element>(
+    b: &MatRef<T>,
+    (row_start, col_start): (usize, usize),
+    (kc, nc): (usize, usize),
+    packed: &mut [f32],
+) {
+    for (panel_idx, panel) in packed
+        .chunks_exact_mut(NR * kc)
+        .take(nc.div_ceil(NR))
+        .enumerate()
+    {
+        let jr = panel_idx * NR;
+        for (p, values) in panel.chunks_exact_mut(NR).enumerate() {
+            for (c, value) in values.iter_mut().enumerate() {
+                *value = if jr + c < nc {
+                    b.at(row_start + p, col_start + jr + c).to_f32()
+                } else {
+                    0.
+                };
+            }
+        }
+    }
+}
+
+// Pack an (mc, kc) block of A into panels of MR rows, as for `pack_b`
+fn pack_a<T: Element>(
+    a: &MatRef<T>,
+    (row_start, col_start): (usize, usize),
+    (mc, kc): (usize, usize),
+    packed: &mut [f32],
+) {
+    for (panel_idx, panel) in packed
+        .chunks_exact_mut(MR * kc)
+        .take(mc.div_ceil(MR))
+        .enumerate()
+    {
+        let ir = panel_idx * MR;
+        for (p, values) in panel.chunks_exact_mut(MR).enumerate() {
+            for (r, value) in values.iter_mut().enumerate() {
+                *value = if ir + r < mc {
+                    a.at(row_start + ir + r, col_start + p).to_f32()
+                } else {
+                    0.
+                };
+            }
+        }
+    }
+}
+
+// Multiply the packed blocks, adding the result into the rows of `c` starting at `col_start`
+fn macro_kernel(
+    kernel: Kernel,
+    packed_a: &[f32],
+    packed_b: &[f32],
+    (mc, nc, kc): (usize, usize, usize),
+    col_start: usize,
+    c: &mut [f32],
+    ldc: usize,
+) {
+    let mut tile = [[0.; NR]; MR];
+    for jr in (0..nc).step_by(NR) {
+        let b_panel = &packed_b[(jr / NR) * NR * kc..][..NR * kc];
+        for ir in (0..mc).step_by(MR) {
+            let a_panel = &packed_a[(ir / MR) * MR * kc..][..MR * kc];
+            kernel.run(kc, a_panel, b_panel, &mut tile);
+
+            for (r, tile_row) in tile.iter().take(mc - ir).enumerate() {
+                let c_start = (ir + r) * ldc + col_start + jr;
+                let c_row = &mut c[c_start..c_start + NR.min(nc - jr)];
+                for (element, value) in c_row.iter_mut().zip(tile_row) {
+                    *element += value;
+                }
+            }
+        }
+    }
+}
+
+fn kernel_generic(kc: usize, a_panel: &[f32], b_panel: &[f32], tile: &mut [[f32; NR]; MR]) {
+    *tile = [[0.; NR]; MR];
+    for (a_values, b_values) in a_panel
+        .chunks_exact(MR)
+        .zip(b_panel.chunks_exact(NR))
+        .take(kc)
+    {
+        for (tile_row, a_value) in tile.iter_mut().zip(a_values) {
+            for (element, b_value) in tile_row.iter_mut().zip(b_values) {
+                *element += a_value * b_value;
+            }
+        }
+    }
+}
+
+// SAFETY: the caller must check the CPU supports AVX2 and FMA, and that the panels hold at least
+// kc steps.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
+unsafe fn kernel_avx2_fma(kc: usize, a_panel: &[f32], b_panel: &[f32], tile: &mut [[f32; NR]; MR]) {
+    use std::arch::x86_64::{
+        __m256, _mm256_fmadd_ps, _mm256_loadu_ps, _mm256_set1_ps, _mm256_setzero_ps,
+        _mm256_storeu_ps,
+    };
+    const LANES: usize = 8;
+
+    // Two registers per row of the tile
+    let mut acc: [[__m256; 2]; MR] = [[_mm256_setzero_ps(); 2]; MR];
+    let a_ptr = a_panel.as_ptr();
+    let b_ptr = b_panel.as_ptr();
+    for p in 0..kc {
+        let b_low = _mm256_loadu_ps(b_ptr.add(p * NR));
+        let b_high = _mm256_loadu_ps(b_ptr.add(p * NR + LANES));
+        for (r, acc_row) in acc.iter_mut().enumerate() {
+            let a_value = _mm256_set1_ps(*a_ptr.add(p * MR + r));
+            acc_row[0] = _mm256_fmadd_ps(a_value, b_low, acc_row[0]);
+            acc_row[1] = _mm256_fmadd_ps(a_value, b_high, acc_row[1]);
+        }
+    }
+    for (tile_row, acc_row) in tile.iter_mut().zip(acc.iter()) {
+        _mm256_storeu_ps(tile_row.as_mut_ptr(), acc_row[0]);
+        _mm256_storeu_ps(tile_row.as_mut_ptr().add(LANES), acc_row[1]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(len: usize, seed: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| ((i * 7 + seed * 13) % 17) as f32 / 4. - 2.)
+            .collect()
+    }
+
+    fn assert_all_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (i, (x, y)) in actual.iter().zip(expected).enumerate() {
+            assert!(
+                (x - y).abs() <= 1e-4 * (1. + y.abs()),
+                "Element {i} differs: {x} vs {y}"
+            );
+        }
+    }
+
+    fn check(m: usize, n: usize, k: usize, kernel: Kernel) {
+        let a = sample(m * k, 1);
+        let b = sample(k * n, 2);
+        let a_t: Vec<f32> = (0..k * m).map(|i| a[(i % m) * k + i / m]).collect();
+
+        let mut expected = vec![0.; m * n];
+        naive_gemm(
+            MatRef::row_major(&a, m, k),
+            MatRef::row_major(&b, k, n),
+            &mut expected,
+        );
+
+        let mut c = vec![0.; m * n];
+        gemm_with_kernel(
+            kernel,
+            MatRef::row_major(&a, m, k),
+            MatRef::row_major(&b, k, n),
+            &mut c,
+        );
+        assert_all_close(&c, &expected);
+
+        // A transposed operand, read through its strides
+        let mut c = vec![0.; m * n];
+        gemm_with_kernel(
+            kernel,
+            MatRef::row_major(&a_t, k, m).t(),
+            MatRef::row_major(&b, k, n),
+            &mut c,
+        );
+        assert_all_close(&c, &expected);
+    }
+
+    #[test]
+    fn test_gemm_small() {
+        for kernel in [Kernel::Generic, Kernel::detect()] {
+            for (m, n, k) in [(1, 1, 1), (3, 5, 7), (6, 16, 4), (7, 17, 5), (13, 33, 2)] {
+                check(m, n, k, kernel);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_gemm_blocks() {
+        // Sizes which span several blocks in every dimension, with ragged edges
+        for kernel in [Kernel::Generic, Kernel::detect()] {
+            check(MC + 5, NR * 3 + 1, KC + 3, kernel);
+            check(2, NC + 7, 3, kernel);
+        }
+    }
+
+    #[test]
+    fn test_gemm_accumulates() {
+        let a = sample(4, 1);
+        let b = sample(4, 2);
+        let mut c = vec![1.; 4];
+        let mut expected = vec![1.; 4];
+        gemm(
+            MatRef::row_major(&a, 2, 2),
+            MatRef::row_major(&b, 2, 2),
+            &mut c,
+        );
+        naive_gemm(
+            MatRef::row_major(&a, 2, 2),
+            MatRef::row_major(&b, 2, 2),
+            &mut expected,
+        );
+        assert_all_close(&c, &expected);
+    }
+
+    #[test]
+    fn test_gemm_empty() {
+        let mut c: Vec<f32> = vec![];
+        gemm(
+            MatRef::<f32>::row_major(&[], 0, 3),
+            MatRef::row_major(&[0.; 6], 3, 2),
+            &mut c,
+        );
+        let mut c = vec![2.; 4];
+        gemm(
+            MatRef::<f32>::row_major(&[], 2, 0),
+            MatRef::<f32>::row_major(&[], 0, 2),
+            &mut c,
+        );
+        assert_eq!(c, vec![2.; 4]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_matref_bounds() {
+        MatRef::new(&[0.; 5], 2, 3, 3, 1);
+    }
+
+    #[test]
+    fn test_dot() {
+        for len in [0, 1, 7, 8, 9, 33] {
+            let a = sample(len, 1);
+            let b = sample(len, 2);
+            let expected: f32 = a.iter().zip(&b).map(|(x, y)| x * y).sum();
+            assert!((dot(&a, &b) - expected).abs() < 1e-4);
+        }
+    }
+}
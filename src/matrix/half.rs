@@ -0,0 +1,394 @@
+// Half precision floats, for storing weights in half the memory.
+//
+// Both types only store values: arithmetic is done by converting to `f32`, which holds every
+// value of either type exactly. Conversions to half precision round to the nearest
+// representable value, with ties going to the value with an even last bit.
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::matrix::element::Element;
+
+/// An IEEE 754 half precision float: 1 sign bit, 5 exponent bits and 10 mantissa bits.
+#[derive(Clone, Copy, Default)]
+pub struct F16(u16);
+
+/// A bfloat16: the top half of an `f32`, so the same range with 7 mantissa bits.
+#[derive(Clone, Copy, Default)]
+pub struct Bf16(u16);
+
+impl F16 {
+    pub const ZERO: F16 = F16(0);
+    pub const ONE: F16 = F16(0x3c00);
+    /// The largest finite value, 65504.
+    pub const MAX: F16 = F16(0x7bff);
+    pub const INFINITY: F16 = F16(0x7c00);
+    pub const NEG_INFINITY: F16 = F16(0xfc00);
+    pub const NAN: F16 = F16(0x7e00);
+
+    pub const fn from_bits(bits: u16) -> F16 {
+        F16(bits)
+    }
+
+    pub const fn to_bits(self) -> u16 {
+        self.0
+    }
+
+    pub fn from_f32(value: f32) -> F16 {
+        let bits = value.to_bits();
+        let sign = ((bits >> 16) & 0x8000) as u16;
+        let exponent = ((bits >> 23) & 0xff) as i32;
+        let mantissa = bits & 0x7f_ffff;
+
+        if exponent == 0xff {
+            // Infinity keeps a zero mantissa, and NaNs stay NaNs with the top of their payload
+            let nan_bits = if mantissa == 0 {
+                0
+            } else {
+                0x200 | (mantissa >> 13) as u16
+            };
+            return F16(sign | 0x7c00 | nan_bits);
+        }
+        let half_exponent = exponent - 127 + 15;
+        if half_exponent >= 0x1f {
+            return F16(sign | 0x7c00);
+        }
+        if half_exponent <= 0 {
+            // A subnormal, counting multiples of 2⁻²⁴. Anything under half the smallest one
+            // rounds to zero
+            let shift = (14 - half_exponent) as u32;
+            if shift > 24 {
+                return F16(sign);
+            }
+            let rounded = round_shift(mantissa | 0x80_0000, shift);
+            return F16(sign | rounded as u16);
+        }
+        // A carry out of the mantissa correctly bumps the exponent, up to infinity
+        let rounded = round_shift(((half_exponent as u32) << 23) | mantissa, 13);
+        F16(sign | rounded as u16)
+    }
+
+    pub fn to_f32(self) -> f32 {
+        let bits = self.0 as u32;
+        let sign = (bits & 0x8000) << 16;
+        let exponent = (bits >> 10) & 0x1f;
+        let mantissa = bits & 0x3ff;
+        match exponent {
+            0 => {
+                // Subnormals are exact multiples of 2⁻²⁴
+                let magnitude = mantissa as f32 * (1. / (1 << 24) as f32);
+                f32::from_bits(sign | magnitude.to_bits())
+            }
+            0x1f => f32::from_bits(sign | 0x7f80_0000 | (mantissa << 13)),
+            _ => f32::from_bits(sign | ((exponent + 127 - 15) << 23) | (mantissa << 13)),
+        }
+    }
+
+    pub fn is_nan(self) -> bool {
+        self.0 & 0x7c00 == 0x7c00 && self.0 & 0x3ff != 0
+    }
+}
+
+impl Bf16 {
+    pub const ZERO: Bf16 = Bf16(0);
+    pub const ONE: Bf16 = Bf16(0x3f80);
+    pub const MAX: Bf16 = Bf16(0x7f7f);
+    pub const INFINITY: Bf16 = Bf16(0x7f80);
+    pub const NEG_INFINITY: Bf16 = Bf16(0xff80);
+    pub const NAN: Bf16 = Bf16(0x7fc0);
+
+    pub const fn from_bits(bits: u16) -> Bf16 {
+        Bf16(bits)
+    }
+
+    pub const fn to_bits(self) -> u16 {
+        self.0
+    }
+
+    pub fn from_f32(value: f32) -> Bf16 {
+        let bits = value.to_bits();
+        if value.is_nan() {
+            // Rounding could carry a NaN's payload into infinity, so just keep it quiet
+            return Bf16((bits >> 16) as u16 | 0x40);
+        }
+        Bf16(round_shift(bits, 16) as u16)
+    }
+
+    pub fn to_f32(self) -> f32 {
+        f32::from_bits((self.0 as u32) << 16)
+    }
+
+    pub fn is_nan(self) -> bool {
+        self.to_f32().is_nan()
+    }
+}
+
+// Shift right, rounding to nearest with ties to even
+fn round_shift(value: u32, shift: u32) -> u32 {
+    let truncated = value >> shift;
+    let remainder = value & ((1 << shift) - 1);
+    let halfway = 1 << (shift - 1);
+    if remainder > halfway || (remainder == halfway && truncated & 1 == 1) {
+        truncated + 1
+    } else {
+        truncated
+    }
+}
+
+// Rounding an f64 to f32 and then to half precision can round twice in the same direction, so
+// first round to f32 towards zero and set the last bit if anything was lost. f32 has enough
+// extra bits that the second rounding then gives the correctly rounded result.
+fn f64_to_f32_round_to_odd(value: f64) -> f32 {
+    let rounded = value as f32;
+    if rounded as f64 == value || !value.is_finite() || rounded.is_infinite() {
+        return rounded;
+    }
+    let mut bits = rounded.to_bits();
+    if (rounded as f64).abs() > value.abs() {
+        bits -= 1;
+    }
+    f32::from_bits(bits | 1)
+}
+
+macro_rules! impl_half {
+    ($type:ident, $name:literal) => {
+        impl $type {
+            pub const fn to_le_bytes(self) -> [u8; 2] {
+                self.0.to_le_bytes()
+            }
+
+            pub const fn to_be_bytes(self) -> [u8; 2] {
+                self.0.to_be_bytes()
+            }
+
+            pub const fn from_le_bytes(bytes: [u8; 2]) -> Self {
+                $type(u16::from_le_bytes(bytes))
+            }
+
+            pub const fn from_be_bytes(bytes: [u8; 2]) -> Self {
+                $type(u16::from_be_bytes(bytes))
+            }
+        }
+
+        impl Element for $type {
+            const ZERO: Self = $type::ZERO;
+            const ONE: Self = $type::ONE;
+            const NAME: &'static str = $name;
+            const IS_FLOAT: bool = true;
+
+            fn to_f64(self) -> f64 {
+                self.to_f32() as f64
+            }
+
+            fn from_f64(value: f64) -> Self {
+                $type::from_f32(f64_to_f32_round_to_odd(value))
+            }
+
+            fn to_f32(self) -> f32 {
+                $type::to_f32(self)
+            }
+
+            fn from_f32(value: f32) -> Self {
+                $type::from_f32(value)
+            }
+        }
+
+        impl From<f32> for $type {
+            fn from(value: f32) -> Self {
+                $type::from_f32(value)
+            }
+        }
+
+        impl From<$type> for f32 {
+            fn from(value: $type) -> Self {
+                value.to_f32()
+            }
+        }
+
+        // Compare as numbers, so that 0 == -0 and NaN != NaN
+        impl PartialEq for $type {
+            fn eq(&self, other: &Self) -> bool {
+                self.to_f32() == other.to_f32()
+            }
+        }
+
+        impl PartialOrd for $type {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                self.to_f32().partial_cmp(&other.to_f32())
+            }
+        }
+
+        impl fmt::Debug for $type {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Debug::fmt(&self.to_f32(), f)
+            }
+        }
+
+        impl fmt::Display for $type {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Display::fmt(&self.to_f32(), f)
+            }
+        }
+    };
+}
+
+impl_half!(F16, "f16");
+impl_half!(Bf16, "bf16");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_f16_reference_values() {
+        // Values which f16 holds exactly
+        for (value, bits) in [
+            (0., 0x0000),
+            (-0., 0x8000),
+            (1., 0x3c00),
+            (-2., 0xc000),
+            (0.5, 0x3800),
+            (65504., 0x7bff),
+            // The smallest normal and subnormal values
+            (2f32.powi(-14), 0x0400),
+            (2f32.powi(-24), 0x0001),
+            (f32::INFINITY, 0x7c00),
+            (f32::NEG_INFINITY, 0xfc00),
+        ] {
+            assert_eq!(F16::from_f32(value).to_bits(), bits, "{value}");
+            assert_eq!(F16::from_bits(bits).to_f32(), value, "{value}");
+        }
+        for (value, bits, rounded) in [
+            (0.1, 0x2e66, 0.099975586),
+            (1. / 3., 0x3555, 0.33325195),
+            (std::f32::consts::PI, 0x4248, 3.140625),
+        ] {
+            assert_eq!(F16::from_f32(value).to_bits(), bits, "{value}");
+            assert_eq!(F16::from_bits(bits).to_f32(), rounded, "{value}");
+        }
+    }
+
+    #[test]
+    fn test_f16_rounding() {
+        for (value, bits) in [
+            // Ties go to the even neighbour, either up or down
+            (1. + 2f32.powi(-11), 0x3c00),
+            (1. + 3. * 2f32.powi(-11), 0x3c02),
+            // Just either side of a tie
+            (1. + 2f32.powi(-11) + 2f32.powi(-20), 0x3c01),
+            (1. + 2f32.powi(-11) - 2f32.powi(-20), 0x3c00),
+            // Overflow, where 65520 is the tie between the largest value and infinity
+            (65519., 0x7bff),
+            (65520., 0x7c00),
+            (1e10, 0x7c00),
+            (-1e10, 0xfc00),
+            // Underflow into the subnormals and to zero
+            (2f32.powi(-25), 0x0000),
+            (1.5 * 2f32.powi(-25), 0x0001),
+            (3. * 2f32.powi(-25), 0x0002),
+            (1e-10, 0x0000),
+            (-1e-10, 0x8000),
+            // Rounding up out of the subnormals
+            (2f32.powi(-14) - 2f32.powi(-26), 0x0400),
+        ] {
+            assert_eq!(F16::from_f32(value).to_bits(), bits, "{value:e}");
+        }
+        assert!(F16::from_f32(f32::NAN).is_nan());
+        assert!(F16::from_f32(f32::NAN).to_f32().is_nan());
+    }
+
+    #[test]
+    fn test_bf16_reference_values() {
+        for (value, bits) in [
+            (0., 0x0000),
+            (1., 0x3f80),
+            (-2., 0xc000),
+            (0.1, 0x3dcd),
+            (std::f32::consts::PI, 0x4049),
+            (1. + 2f32.powi(-8), 0x3f80),
+            (1. + 3. * 2f32.powi(-8), 0x3f82),
+            (1. + 2f32.powi(-8) + 2f32.powi(-20), 0x3f81),
+            (f32::MAX, 0x7f80),
+            (f32::INFINITY, 0x7f80),
+            (1e-40, 0x0001),
+        ] {
+            assert_eq!(Bf16::from_f32(value).to_bits(), bits, "{value:e}");
+        }
+        assert_eq!(Bf16::from_bits(0x4049).to_f32(), 3.140625);
+        assert!(Bf16::from_f32(f32::NAN).is_nan());
+        // A NaN whose payload is all in the low bits must not become infinity
+        assert!(Bf16::from_f32(f32::from_bits(0x7f80_0001)).is_nan());
+    }
+
+    #[test]
+    fn test_round_trips() {
+        // Every half value converts to f32 and back unchanged
+        for bits in 0..=u16::MAX {
+            let half = F16::from_bits(bits);
+            if !half.is_nan() {
+                assert_eq!(F16::from_f32(half.to_f32()).to_bits(), bits);
+            }
+            let half = Bf16::from_bits(bits);
+            if !half.is_nan() {
+                assert_eq!(Bf16::from_f32(half.to_f32()).to_bits(), bits);
+            }
+        }
+    }
+
+    // Whether `rounded` is as close to `value` as either of its neighbours
+    fn is_nearest(value: f32, rounded: f32, below: f32, above: f32) -> bool {
+        let error = (value as f64 - rounded as f64).abs();
+        error <= (value as f64 - below as f64).abs() && error <= (value as f64 - above as f64).abs()
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_nearest() {
+        // A spread of f32 values across the whole range of finite f16s, up to the tie between the
+        // largest and infinity
+        for bits in (0..0x477f_f000u32).step_by(4099) {
+            let value = f32::from_bits(bits);
+            let rounded = F16::from_f32(value);
+            let (below, above) = (
+                F16::from_bits(rounded.to_bits().saturating_sub(1)),
+                F16::from_bits(rounded.to_bits() + 1),
+            );
+            assert!(
+                is_nearest(value, rounded.to_f32(), below.to_f32(), above.to_f32()),
+                "{value:e} rounded to {rounded:?}"
+            );
+
+            let rounded = Bf16::from_f32(value);
+            let (below, above) = (
+                Bf16::from_bits(rounded.to_bits().saturating_sub(1)),
+                Bf16::from_bits(rounded.to_bits() + 1),
+            );
+            assert!(
+                is_nearest(value, rounded.to_f32(), below.to_f32(), above.to_f32()),
+                "{value:e} rounded to {rounded:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_f64() {
+        // 1 + 2⁻¹¹ + 2⁻⁴⁰ is just above a tie for f16, but rounds to the tie as an f32, which
+        // would then round down to 1
+        let value = 1. + 2f64.powi(-11) + 2f64.powi(-40);
+        assert_eq!((value as f32), 1. + 2f32.powi(-11));
+        assert_eq!(F16::from_f64(value).to_bits(), 0x3c01);
+        let value = 1. + 2f64.powi(-8) + 2f64.powi(-40);
+        assert_eq!(Bf16::from_f64(value).to_bits(), 0x3f81);
+        assert_eq!(F16::from_f64(0.1), F16::from_f32(0.1));
+        assert_eq!(0.5f64.cast::<Bf16>().cast::<f64>(), 0.5);
+    }
+
+    #[test]
+    fn test_comparisons() {
+        assert_eq!(F16::from_f32(0.), F16::from_f32(-0.));
+        assert_ne!(F16::NAN, F16::NAN);
+        assert!(Bf16::from_f32(-1.) < Bf16::ONE);
+        assert_eq!(format!("{:?}", F16::from_f32(1.5)), "1.5");
+        assert_eq!(std::mem::size_of::<F16>(), 2);
+        assert_eq!(std::mem::size_of::<Bf16>(), 2);
+    }
+}
@@ -0,0 +1,197 @@
+// Initialisers for weights, following the usual conventions for fan-in and fan-out.
+//
+// For a weight of shape (outputs, inputs, ...) the fan-in is the number of inputs and the
+// fan-out the number of outputs, both multiplied by the size of any further dimensions. A
+// vector's fan-in and fan-out are both its length.
+use crate::matrix::element::Element;
+use crate::matrix::matrix::Matrix2;
+use crate::matrix::random::Rng;
+use crate::matrix::tensor::Tensor;
+use crate::matrix::vector::FloatVector;
+
+/// How to fill a new tensor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Init {
+    Zeros,
+    Ones,
+    Constant(f64),
+    /// Uniform over [low, high).
+    Uniform {
+        low: f64,
+        high: f64,
+    },
+    Normal {
+        mean: f64,
+        std: f64,
+    },
+    /// Normal, with samples more than `bound` standard deviations from the mean redrawn.
+    TruncatedNormal {
+        mean: f64,
+        std: f64,
+        bound: f64,
+    },
+    /// Glorot & Bengio (2010): uniform with variance `2 / (fan_in + fan_out)`.
+    XavierUniform,
+    /// Glorot & Bengio (2010): normal with variance `2 / (fan_in + fan_out)`.
+    XavierNormal,
+    /// He et al. (2015): uniform with variance `2 / fan_in`, for layers followed by a ReLU.
+    KaimingUniform,
+    /// He et al. (2015): normal with variance `2 / fan_in`, for layers followed by a ReLU.
+    KaimingNormal,
+}
+
+/// The fan-in and fan-out of a weight of the given shape.
+pub fn fans(shape: &[usize]) -> (usize, usize) {
+    match shape {
+        [] => (1, 1),
+        [len] => (*len, *len),
+        [outputs, inputs, rest @ ..] => {
+            let receptive_field: usize = rest.iter().product();
+            (inputs * receptive_field, outputs * receptive_field)
+        }
+    }
+}
+
+impl Init {
+    /// A tensor of the given shape, with values drawn from `rng`.
+    pub fn tensor<T: Element>(&self, shape: &[usize], rng: &mut Rng) -> Tensor<T> {
+        let numel = shape.iter().product();
+        let (fan_in, fan_out) = fans(shape);
+        let data = (0..numel)
+            .map(|_| T::from_f64(self.sample(fan_in, fan_out, rng)))
+            .collect();
+        Tensor::from_vec(data, shape).unwrap()
+    }
+
+    pub fn matrix<const N_ROWS: usize, const N_COLS: usize>(
+        &self,
+        rng: &mut Rng,
+    ) -> Matrix2<N_ROWS, N_COLS> {
+        self.tensor(&[N_ROWS, N_COLS], rng).try_into().unwrap()
+    }
+
+    pub fn vector<const SIZE: usize>(&self, rng: &mut Rng) -> FloatVector<SIZE> {
+        self.tensor(&[SIZE], rng).try_into().unwrap()
+    }
+
+    fn sample(&self, fan_in: usize, fan_out: usize, rng: &mut Rng) -> f64 {
+        // A uniform distribution over [-a, a) has variance a² / 3
+        let xavier_std = (2. / (fan_in + fan_out).max(1) as f64).sqrt();
+        let kaiming_std = (2. / fan_in.max(1) as f64).sqrt();
+        match *self {
+            Init::Zeros => 0.,
+            Init::Ones => 1.,
+            Init::Constant(value) => value,
+            Init::Uniform { low, high } => rng.uniform(low, high),
+            Init::Normal { mean, std } => mean + std * rng.normal(),
+            Init::TruncatedNormal { mean, std, bound } => {
+                assert!(bound > 0., "The truncation bound must be positive");
+                loop {
+                    let sample = rng.normal();
+                    if sample.abs() <= bound {
+                        return mean + std * sample;
+                    }
+                }
+            }
+            Init::XavierUniform => {
+                let limit = 3f64.sqrt() * xavier_std;
+                rng.uniform(-limit, limit)
+            }
+            Init::XavierNormal => xavier_std * rng.normal(),
+            Init::KaimingUniform => {
+                let limit = 3f64.sqrt() * kaiming_std;
+                rng.uniform(-limit, limit)
+            }
+            Init::KaimingNormal => kaiming_std * rng.normal(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mean_and_std(tensor: &Tensor) -> (f32, f32) {
+        let mean = tensor.mean();
+        let variance =
+            tensor.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / tensor.numel() as f32;
+        (mean, variance.sqrt())
+    }
+
+    #[test]
+    fn test_fans() {
+        assert_eq!(fans(&[]), (1, 1));
+        assert_eq!(fans(&[10]), (10, 10));
+        assert_eq!(fans(&[30, 20]), (20, 30));
+        assert_eq!(fans(&[8, 4, 3, 3]), (36, 72));
+    }
+
+    #[test]
+    fn test_reproducible() {
+        let a: Tensor = Init::KaimingNormal.tensor(&[16, 8], &mut Rng::new(3));
+        let b: Tensor = Init::KaimingNormal.tensor(&[16, 8], &mut Rng::new(3));
+        let c: Tensor = Init::KaimingNormal.tensor(&[16, 8], &mut Rng::new(4));
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn test_constants() {
+        let mut rng = Rng::new(0);
+        let zeros: Matrix2<2, 3> = Init::Zeros.matrix(&mut rng);
+        assert_eq!(zeros, Matrix2::from_elements([[0.; 3]; 2]));
+        let ones: FloatVector<4> = Init::Ones.vector(&mut rng);
+        assert_eq!(ones, FloatVector::from_elements([1.; 4]));
+        let halves: Tensor<f64> = Init::Constant(0.5).tensor(&[3], &mut rng);
+        assert_eq!(halves.as_slice(), &[0.5; 3]);
+        // Constants don't use the generator
+        assert_eq!(rng, Rng::new(0));
+    }
+
+    #[test]
+    fn test_distributions() {
+        let mut rng = Rng::new(11);
+        let shape = [400, 250];
+
+        let tensor: Tensor = Init::Uniform { low: -1., high: 3. }.tensor(&shape, &mut rng);
+        assert!(tensor.iter().all(|x| (-1. ..3.).contains(x)));
+        let (mean, _) = mean_and_std(&tensor);
+        assert!((mean - 1.).abs() < 0.02, "{mean}");
+
+        let tensor: Tensor = Init::Normal { mean: 2., std: 0.5 }.tensor(&shape, &mut rng);
+        let (mean, std) = mean_and_std(&tensor);
+        assert!(
+            (mean - 2.).abs() < 0.01 && (std - 0.5).abs() < 0.01,
+            "{mean} {std}"
+        );
+
+        let init = Init::TruncatedNormal {
+            mean: 0.,
+            std: 0.02,
+            bound: 2.,
+        };
+        let tensor: Tensor = init.tensor(&shape, &mut rng);
+        assert!(tensor.iter().all(|x| x.abs() <= 0.04));
+        // Truncating at two standard deviations leaves a standard deviation of about 0.88
+        let (_, std) = mean_and_std(&tensor);
+        assert!((std / 0.02 - 0.88).abs() < 0.01, "{std}");
+
+        let xavier_limit = (6f32 / 650.).sqrt();
+        let tensor: Tensor = Init::XavierUniform.tensor(&shape, &mut rng);
+        assert!(tensor.iter().all(|x| x.abs() <= xavier_limit));
+        let (_, std) = mean_and_std(&tensor);
+        assert!((std / (2f32 / 650.).sqrt() - 1.).abs() < 0.01, "{std}");
+        let tensor: Tensor = Init::XavierNormal.tensor(&shape, &mut rng);
+        let (_, std) = mean_and_std(&tensor);
+        assert!((std / (2f32 / 650.).sqrt() - 1.).abs() < 0.01, "{std}");
+
+        let kaiming_limit = (6f32 / 250.).sqrt();
+        let tensor: Tensor = Init::KaimingUniform.tensor(&shape, &mut rng);
+        assert!(tensor.iter().all(|x| x.abs() <= kaiming_limit));
+        let (_, std) = mean_and_std(&tensor);
+        assert!((std / (2f32 / 250.).sqrt() - 1.).abs() < 0.01, "{std}");
+        let tensor: Tensor = Init::KaimingNormal.tensor(&shape, &mut rng);
+        let (_, std) = mean_and_std(&tensor);
+        assert!((std / (2f32 / 250.).sqrt() - 1.).abs() < 0.01, "{std}");
+    }
+}
@@ -1,34 +1,162 @@
-use std::collections::HashSet;
+use std::ops::{Add, Div, Mul, Sub};
 
-use anyhow::Result;
+use crate::matrix::element::{Element, Number};
+use crate::matrix::gemm::MatRef;
+use crate::matrix::parallel;
+use crate::matrix::vector::FloatVector;
 
-use crate::{exceptions::MatrixError, matrix::vector::FloatVector};
+// The number of rows each thread works on at a time in a matrix-vector product
+const ROWS_PER_TASK: usize = 64;
 
-pub struct Matrix2<const N_ROWS: usize, const N_COLS: usize> {
-    pub rows: [FloatVector<N_COLS>; N_ROWS],
+/// A fixed-size matrix, stored as an array of rows. The elements are `f32` unless another
+/// element type is given.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matrix2<const N_ROWS: usize, const N_COLS: usize, T: Element = f32> {
+    pub rows: [FloatVector<N_COLS, T>; N_ROWS],
 }
 
-impl<const N_COLS: usize, const N_ROWS: usize> Matrix2<N_ROWS, N_COLS> {
-    pub fn from_rows(rows: [FloatVector<N_COLS>; N_ROWS]) -> Self {
+impl<const N_COLS: usize, const N_ROWS: usize, T: Element> Matrix2<N_ROWS, N_COLS, T> {
+    pub fn from_rows(rows: [FloatVector<N_COLS, T>; N_ROWS]) -> Self {
         Matrix2 { rows }
     }
 
-    pub fn from_elements(elements: [[f32; N_COLS]; N_ROWS]) -> Self {
-        let mut rows: [FloatVector<N_COLS>; N_ROWS] =
-            unsafe { std::mem::MaybeUninit::uninit().assume_init() };
-        for i in 0..N_COLS {
-            rows[i] = FloatVector::from_elements(elements[i]);
+    pub fn from_elements(elements: [[T; N_COLS]; N_ROWS]) -> Self {
+        Matrix2 {
+            rows: elements.map(FloatVector::from_elements),
         }
-        Matrix2 { rows }
     }
 
-    pub fn dot(&self, other: &FloatVector<N_COLS>) -> FloatVector<N_ROWS> {
-        let mut ret_elements: [f32; N_ROWS] =
-            unsafe { std::mem::MaybeUninit::uninit().assume_init() };
-        for i in 0..N_ROWS {
-            ret_elements[i] = self.rows[i].dot(other);
+    pub fn transpose(&self) -> Matrix2<N_COLS, N_ROWS, T> {
+        Matrix2::from_rows(std::array::from_fn(|j| {
+            FloatVector::from_elements(std::array::from_fn(|i| self.rows[i][j]))
+        }))
+    }
+
+    /// Convert the elements to another type. See `Element::from_f64` for how values which
+    /// don't fit are handled.
+    pub fn cast<U: Element>(&self) -> Matrix2<N_ROWS, N_COLS, U> {
+        Matrix2::from_rows(std::array::from_fn(|i| self.rows[i].cast()))
+    }
+
+    // Copy the rows into one contiguous buffer, which is what the gemm kernel reads from
+    fn to_row_major(&self) -> Vec<T> {
+        self.rows
+            .iter()
+            .flat_map(|row| row.as_slice().iter().copied())
+            .collect()
+    }
+}
+
+impl<const N_COLS: usize, const N_ROWS: usize, T: Number> Matrix2<N_ROWS, N_COLS, T> {
+    pub fn dot(&self, other: &FloatVector<N_COLS, T>) -> FloatVector<N_ROWS, T> {
+        let mut elements = [T::ZERO; N_ROWS];
+        parallel::for_each_chunk_mut(
+            &mut elements,
+            ROWS_PER_TASK,
+            N_ROWS * N_COLS,
+            |idx, chunk| {
+                for (i, element) in chunk.iter_mut().enumerate() {
+                    *element = self.rows[idx * ROWS_PER_TASK + i].dot(other);
+                }
+            },
+        );
+        FloatVector::from_elements(elements)
+    }
+
+    /// The matrix product `self · other`.
+    pub fn matmul<const N_OUT: usize>(
+        &self,
+        other: &Matrix2<N_COLS, N_OUT, T>,
+    ) -> Matrix2<N_ROWS, N_OUT, T> {
+        let (lhs, rhs) = (self.to_row_major(), other.to_row_major());
+        Matrix2::product(
+            MatRef::row_major(&lhs, N_ROWS, N_COLS),
+            MatRef::row_major(&rhs, N_COLS, N_OUT),
+        )
+    }
+
+    /// The matrix product `self · otherᵀ`, without materialising the transpose.
+    pub fn matmul_nt<const N_OUT: usize>(
+        &self,
+        other: &Matrix2<N_OUT, N_COLS, T>,
+    ) -> Matrix2<N_ROWS, N_OUT, T> {
+        let (lhs, rhs) = (self.to_row_major(), other.to_row_major());
+        Matrix2::product(
+            MatRef::row_major(&lhs, N_ROWS, N_COLS),
+            MatRef::row_major(&rhs, N_OUT, N_COLS).t(),
+        )
+    }
+
+    /// The matrix product `selfᵀ · other`, without materialising the transpose.
+    pub fn matmul_tn<const N_OUT: usize>(
+        &self,
+        other: &Matrix2<N_ROWS, N_OUT, T>,
+    ) -> Matrix2<N_COLS, N_OUT, T> {
+        let (lhs, rhs) = (self.to_row_major(), other.to_row_major());
+        Matrix2::product(
+            MatRef::row_major(&lhs, N_ROWS, N_COLS).t(),
+            MatRef::row_major(&rhs, N_ROWS, N_OUT),
+        )
+    }
+
+    fn product(lhs: MatRef<T>, rhs: MatRef<T>) -> Self {
+        let mut elements = vec![T::ZERO; N_ROWS * N_COLS];
+        T::gemm(lhs, rhs, &mut elements);
+        Matrix2::from_rows(std::array::from_fn(|i| {
+            FloatVector::from_slice(&elements[i * N_COLS..(i + 1) * N_COLS])
+                .expect("Rows have N_COLS elements")
+        }))
+    }
+}
+
+impl<const N_ROWS: usize, const N_INNER: usize, const N_COLS: usize, T: Number>
+    Mul<&Matrix2<N_INNER, N_COLS, T>> for &Matrix2<N_ROWS, N_INNER, T>
+{
+    type Output = Matrix2<N_ROWS, N_COLS, T>;
+
+    fn mul(self, other: &Matrix2<N_INNER, N_COLS, T>) -> Matrix2<N_ROWS, N_COLS, T> {
+        self.matmul(other)
+    }
+}
+
+// Elementwise operators between matrices of the same shape, and between a matrix and a row
+// vector, which is broadcast over every row
+macro_rules! impl_elementwise {
+    ($trait:ident, $method:ident, $op:tt) => {
+        impl<'b, const N_ROWS: usize, const N_COLS: usize, T: Number>
+            $trait<&'b Matrix2<N_ROWS, N_COLS, T>> for &Matrix2<N_ROWS, N_COLS, T>
+        {
+            type Output = Matrix2<N_ROWS, N_COLS, T>;
+
+            fn $method(self, other: &'b Matrix2<N_ROWS, N_COLS, T>) -> Matrix2<N_ROWS, N_COLS, T> {
+                Matrix2::from_rows(std::array::from_fn(|i| &self.rows[i] $op &other.rows[i]))
+            }
+        }
+
+        impl<'b, const N_ROWS: usize, const N_COLS: usize, T: Number>
+            $trait<&'b FloatVector<N_COLS, T>> for &Matrix2<N_ROWS, N_COLS, T>
+        {
+            type Output = Matrix2<N_ROWS, N_COLS, T>;
+
+            fn $method(self, row: &'b FloatVector<N_COLS, T>) -> Matrix2<N_ROWS, N_COLS, T> {
+                Matrix2::from_rows(std::array::from_fn(|i| &self.rows[i] $op row))
+            }
         }
-        FloatVector::from_elements(ret_elements)
+    };
+}
+
+impl_elementwise!(Add, add, +);
+impl_elementwise!(Sub, sub, -);
+impl_elementwise!(Div, div, /);
+
+// `*` between matrices is the matrix product, so only the row broadcast is elementwise
+impl<'b, const N_ROWS: usize, const N_COLS: usize, T: Number> Mul<&'b FloatVector<N_COLS, T>>
+    for &Matrix2<N_ROWS, N_COLS, T>
+{
+    type Output = Matrix2<N_ROWS, N_COLS, T>;
+
+    fn mul(self, row: &'b FloatVector<N_COLS, T>) -> Matrix2<N_ROWS, N_COLS, T> {
+        Matrix2::from_rows(std::array::from_fn(|i| &self.rows[i] * row))
     }
 }
 
@@ -38,16 +166,139 @@ mod tests {
 
     #[test]
     fn test_dot() {
-        let A_iden = Matrix2::from_elements([[1., 0.], [0., 1.]]);
+        let a_iden = Matrix2::from_elements([[1., 0.], [0., 1.]]);
         let b = FloatVector::from_elements([5., 4.]);
 
-        let c = A_iden.dot(&b);
+        let c = a_iden.dot(&b);
 
         assert_eq!(c, b);
 
-        let A_rot = Matrix2::from_elements([[0., -1.], [1., 0.]]);
-        let c = A_rot.dot(&b);
+        let a_rot = Matrix2::from_elements([[0., -1.], [1., 0.]]);
+        let c = a_rot.dot(&b);
 
         assert_eq!(c, FloatVector::from_elements([-4., 5.]));
     }
+
+    #[test]
+    fn test_non_square() {
+        // Every row must be filled in, whichever dimension is larger
+        let wide = Matrix2::from_elements([[1., 2., 3.], [4., 5., 6.]]);
+        assert_eq!(wide.rows[1], FloatVector::from_elements([4., 5., 6.]));
+        assert_eq!(
+            wide.dot(&FloatVector::from_elements([1., 0., -1.])),
+            FloatVector::from_elements([-2., -2.])
+        );
+
+        let tall = Matrix2::from_elements([[1., 2.], [3., 4.], [5., 6.]]);
+        assert_eq!(tall.rows[2], FloatVector::from_elements([5., 6.]));
+        assert_eq!(
+            tall.dot(&FloatVector::from_elements([1., 1.])),
+            FloatVector::from_elements([3., 7., 11.])
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        let empty: Matrix2<0, 3> = Matrix2::from_elements([]);
+        assert_eq!(
+            empty.dot(&FloatVector::from_elements([1., 2., 3.])).len(),
+            0
+        );
+
+        let no_cols: Matrix2<2, 0> = Matrix2::from_elements([[], []]);
+        assert_eq!(
+            no_cols.dot(&FloatVector::from_elements([])),
+            FloatVector::from_elements([0., 0.])
+        );
+    }
+
+    // The textbook triple loop, to check against
+    fn naive_matmul<const N: usize, const K: usize, const M: usize>(
+        a: &Matrix2<N, K>,
+        b: &Matrix2<K, M>,
+    ) -> Matrix2<N, M> {
+        let mut elements = [[0.; M]; N];
+        for (i, row) in elements.iter_mut().enumerate() {
+            for (j, element) in row.iter_mut().enumerate() {
+                for k in 0..K {
+                    *element += a.rows[i][k] * b.rows[k][j];
+                }
+            }
+        }
+        Matrix2::from_elements(elements)
+    }
+
+    fn sample<const N: usize, const M: usize>(seed: usize) -> Matrix2<N, M> {
+        Matrix2::from_elements(std::array::from_fn(|i| {
+            std::array::from_fn(|j| ((i * 7 + j * 13 + seed) % 11) as f32 - 5.)
+        }))
+    }
+
+    #[test]
+    fn test_transpose() {
+        let a = Matrix2::from_elements([[1., 2., 3.], [4., 5., 6.]]);
+        assert_eq!(
+            a.transpose(),
+            Matrix2::from_elements([[1., 4.], [2., 5.], [3., 6.]])
+        );
+        assert_eq!(a.transpose().transpose(), a);
+    }
+
+    #[test]
+    fn test_matmul() {
+        let a: Matrix2<3, 4> = sample(1);
+        let b: Matrix2<4, 5> = sample(2);
+        let expected = naive_matmul(&a, &b);
+
+        assert_eq!(a.matmul(&b), expected);
+        assert_eq!(&a * &b, expected);
+        assert_eq!(a.matmul_nt(&b.transpose()), expected);
+        assert_eq!(a.transpose().matmul_tn(&b), expected);
+
+        // Matrix-vector products are the single column case
+        let v = FloatVector::from_elements([1., -2., 3., 0.5]);
+        let column: Matrix2<4, 1> = Matrix2::from_elements(std::array::from_fn(|k| [v[k]]));
+        assert_eq!(a.matmul(&column).transpose().rows[0], a.dot(&v));
+    }
+
+    #[test]
+    fn test_elementwise() {
+        let a = Matrix2::from_elements([[1., 2., 3.], [4., 5., 6.]]);
+        let row = FloatVector::from_elements([1., 0., -1.]);
+
+        assert_eq!(
+            &a + &row,
+            Matrix2::from_elements([[2., 2., 2.], [5., 5., 5.]])
+        );
+        assert_eq!(&(&a + &row) - &row, a);
+        assert_eq!(
+            &a * &row,
+            Matrix2::from_elements([[1., 0., -3.], [4., 0., -6.]])
+        );
+        assert_eq!(
+            &a / &FloatVector::from_elements([1., 2., 3.]),
+            Matrix2::from_elements([[1., 1., 1.], [4., 2.5, 2.]])
+        );
+        assert_eq!(
+            &a + &a,
+            Matrix2::from_elements([[2., 4., 6.], [8., 10., 12.]])
+        );
+        assert_eq!(&a - &a, Matrix2::from_elements([[0.; 3]; 2]));
+        assert_eq!(&a / &a, Matrix2::from_elements([[1.; 3]; 2]));
+    }
+
+    #[test]
+    fn test_element_types() {
+        let a: Matrix2<2, 3, i64> = Matrix2::from_elements([[1, 2, 3], [4, 5, 6]]);
+        let b = a.transpose();
+        assert_eq!(a.matmul(&b), Matrix2::from_elements([[14, 32], [32, 77]]));
+        assert_eq!(
+            a.dot(&FloatVector::from_elements([1, 0, -1])),
+            FloatVector::from_elements([-2, -2])
+        );
+
+        let (a, b): (Matrix2<3, 4>, Matrix2<4, 5>) = (sample(1), sample(2));
+        let (a_f64, b_f64) = (a.cast::<f64>(), b.cast::<f64>());
+        assert_eq!(a_f64.matmul(&b_f64), naive_matmul(&a, &b).cast());
+    }
 }
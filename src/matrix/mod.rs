@@ -1,2 +1,16 @@
+pub mod broadcast;
+pub mod compare;
+pub mod display;
+pub mod element;
+pub mod gemm;
+pub mod half;
+pub mod init;
+#[allow(clippy::module_inception)]
 pub mod matrix;
+pub mod parallel;
+pub mod quantize;
+pub mod random;
+mod reduce;
+pub mod tensor;
 pub mod vector;
+pub mod view;
@@ -0,0 +1,192 @@
+// Settings for running tensor operations on several threads.
+//
+// Operations check `should_parallelize` with the amount of work they are about to do, and only
+// hand it to a thread pool above the threshold, since small operations are faster on one core.
+// Work is always split the same way regardless of the thread count, so results don't depend on
+// how many threads are used.
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+/// The default amount of work (roughly, scalar operations) below which ops stay on one thread.
+pub const DEFAULT_PARALLEL_THRESHOLD: usize = 1 << 16;
+
+/// The size of the chunks reductions are split into. Partial results are combined in order, so
+/// reductions give identical results on any number of threads.
+pub(crate) const REDUCTION_CHUNK: usize = 1 << 12;
+
+// Zero means one thread per core
+static NUM_THREADS: AtomicUsize = AtomicUsize::new(0);
+static PARALLEL_THRESHOLD: AtomicUsize = AtomicUsize::new(DEFAULT_PARALLEL_THRESHOLD);
+static POOLS: OnceLock<Mutex<HashMap<usize, Arc<ThreadPool>>>> = OnceLock::new();
+
+thread_local! {
+    static NUM_THREADS_OVERRIDE: Cell<Option<usize>> = const { Cell::new(None) };
+    static THRESHOLD_OVERRIDE: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Set the number of threads used by every thread. Zero means one thread per core.
+pub fn set_num_threads(n_threads: usize) {
+    NUM_THREADS.store(n_threads, Ordering::Relaxed);
+}
+
+/// Set the amount of work below which ops stay on the calling thread.
+pub fn set_parallel_threshold(threshold: usize) {
+    PARALLEL_THRESHOLD.store(threshold, Ordering::Relaxed);
+}
+
+/// The number of threads ops on this thread will use.
+pub fn num_threads() -> usize {
+    let n_threads = NUM_THREADS_OVERRIDE
+        .get()
+        .unwrap_or_else(|| NUM_THREADS.load(Ordering::Relaxed));
+    if n_threads == 0 {
+        std::thread::available_parallelism().map_or(1, |n| n.get())
+    } else {
+        n_threads
+    }
+}
+
+pub fn parallel_threshold() -> usize {
+    THRESHOLD_OVERRIDE
+        .get()
+        .unwrap_or_else(|| PARALLEL_THRESHOLD.load(Ordering::Relaxed))
+}
+
+/// Run `f` with a different number of threads, for ops called from this thread only.
+pub fn with_num_threads<R>(n_threads: usize, f: impl FnOnce() -> R) -> R {
+    let previous = NUM_THREADS_OVERRIDE.replace(Some(n_threads));
+    let _reset = Reset(|| NUM_THREADS_OVERRIDE.set(previous));
+    f()
+}
+
+/// Run `f` with a different parallel threshold, for ops called from this thread only.
+pub fn with_parallel_threshold<R>(threshold: usize, f: impl FnOnce() -> R) -> R {
+    let previous = THRESHOLD_OVERRIDE.replace(Some(threshold));
+    let _reset = Reset(|| THRESHOLD_OVERRIDE.set(previous));
+    f()
+}
+
+// Restores the previous setting when dropped, including when `f` panics
+struct Reset<F: FnMut()>(F);
+
+impl<F: FnMut()> Drop for Reset<F> {
+    fn drop(&mut self) {
+        (self.0)()
+    }
+}
+
+/// Whether an op doing this much work should be split across threads.
+pub(crate) fn should_parallelize(work: usize) -> bool {
+    num_threads() > 1 && work >= parallel_threshold()
+}
+
+/// Run `f` inside the pool for the current thread count, so rayon iterators in it use the pool.
+pub(crate) fn install<R: Send>(f: impl FnOnce() -> R + Send) -> R {
+    let n_threads = num_threads();
+    let pool = {
+        let mut pools = POOLS
+            .get_or_init(|| Mutex::new(HashMap::new()))
+            .lock()
+            .expect("No thread panics while holding the pool lock");
+        pools
+            .entry(n_threads)
+            .or_insert_with(|| {
+                Arc::new(
+                    ThreadPoolBuilder::new()
+                        .num_threads(n_threads)
+                        .build()
+                        .expect("Failed to build a thread pool"),
+                )
+            })
+            .clone()
+    };
+    pool.install(f)
+}
+
+/// Call `f` with the index and contents of each chunk of `data`, on several threads if the
+/// work is large enough.
+pub(crate) fn for_each_chunk_mut<T: Send>(
+    data: &mut [T],
+    chunk_len: usize,
+    work: usize,
+    f: impl Fn(usize, &mut [T]) + Sync + Send,
+) {
+    let chunk_len = chunk_len.max(1);
+    if should_parallelize(work) {
+        install(|| {
+            data.par_chunks_mut(chunk_len)
+                .enumerate()
+                .for_each(|(idx, chunk)| f(idx, chunk))
+        });
+    } else {
+        data.chunks_mut(chunk_len)
+            .enumerate()
+            .for_each(|(idx, chunk)| f(idx, chunk));
+    }
+}
+
+/// Map each `REDUCTION_CHUNK` of `data` to a partial result, returned in order.
+pub(crate) fn map_chunks<T: Sync, R: Send>(
+    data: &[T],
+    f: impl Fn(&[T]) -> R + Sync + Send,
+) -> Vec<R> {
+    if should_parallelize(data.len()) {
+        install(|| data.par_chunks(REDUCTION_CHUNK).map(f).collect())
+    } else {
+        data.chunks(REDUCTION_CHUNK).map(f).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides() {
+        with_num_threads(3, || {
+            assert_eq!(num_threads(), 3);
+            with_num_threads(1, || {
+                assert_eq!(num_threads(), 1);
+                assert!(!should_parallelize(usize::MAX));
+            });
+            assert_eq!(num_threads(), 3);
+
+            with_parallel_threshold(10, || {
+                assert!(should_parallelize(10));
+                assert!(!should_parallelize(9));
+            });
+        });
+
+        // Settings are restored if the op panics
+        let result = std::panic::catch_unwind(|| with_num_threads(5, || panic!("Failed")));
+        assert!(result.is_err());
+        assert_ne!(NUM_THREADS_OVERRIDE.get(), Some(5));
+    }
+
+    #[test]
+    // Miri reports stacked borrows violations inside rayon's pool internals
+    #[cfg_attr(miri, ignore)]
+    fn test_chunks() {
+        let mut data: Vec<usize> = vec![0; 1000];
+        with_num_threads(4, || {
+            with_parallel_threshold(0, || {
+                for_each_chunk_mut(&mut data, 7, 1000, |idx, chunk| {
+                    chunk.iter_mut().for_each(|x| *x = idx)
+                });
+            })
+        });
+        assert!(data.iter().enumerate().all(|(i, x)| *x == i / 7));
+
+        let data: Vec<usize> = (0..REDUCTION_CHUNK * 3 + 1).collect();
+        let partials = with_parallel_threshold(0, || map_chunks(&data, |chunk| chunk[0]));
+        assert_eq!(
+            partials,
+            vec![0, REDUCTION_CHUNK, 2 * REDUCTION_CHUNK, 3 * REDUCTION_CHUNK]
+        );
+    }
+}
@@ -0,0 +1,463 @@
+// Weight quantization, storing matrices in 8 or 4 bits per value.
+//
+// The values are split into groups which each get a scale, and for asymmetric schemes a zero
+// point, chosen from the group's range: a value `w` is stored as `round(w / scale) + zero_point`
+// and read back as `(q - zero_point) * scale`. The int8 schemes use a group per row, so each
+// output channel of a linear layer gets its own scale. Products dequantize a block of rows at a
+// time, so the full precision matrix is never stored.
+use std::ops::Range;
+
+use anyhow::Result;
+
+use crate::exceptions::MatrixError;
+use crate::matrix::gemm::{self, MatRef};
+use crate::matrix::tensor::Tensor;
+
+// Rows dequantized at a time by products
+const BLOCK_ROWS: usize = 64;
+
+/// How the values of a matrix are quantized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantization {
+    /// int8 with a scale per row, mapping the largest magnitude in the row to ±127.
+    Int8Symmetric,
+    /// uint8 with a scale and zero point per row, mapping the row's range onto 0..=255.
+    Int8Asymmetric,
+    /// 4-bit values with a scale and zero point for each run of `group_size` values in a row,
+    /// packed two to a byte.
+    Int4 { group_size: usize },
+}
+
+/// A quantized matrix, as the weights of a linear layer with shape (outputs, inputs).
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantizedMatrix {
+    scheme: Quantization,
+    rows: usize,
+    cols: usize,
+    data: Vec<u8>,
+    scales: Vec<f32>,
+    zero_points: Vec<u8>,
+}
+
+impl Quantization {
+    /// The scheme's name as the `quantize` tool and saved files write it: `int8`, `int8-asym` or
+    /// `int4`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Quantization::Int8Symmetric => "int8",
+            Quantization::Int8Asymmetric => "int8-asym",
+            Quantization::Int4 { .. } => "int4",
+        }
+    }
+
+    /// The scheme with the given name. The group size is only used by `int4`.
+    pub fn from_name(name: &str, group_size: usize) -> Result<Quantization> {
+        Ok(match name {
+            "int8" => Quantization::Int8Symmetric,
+            "int8-asym" => Quantization::Int8Asymmetric,
+            "int4" => Quantization::Int4 { group_size },
+            _ => Err(MatrixError::MatrixError(format!(
+                "Unknown quantization scheme {name}"
+            )))?,
+        })
+    }
+
+    // The number of values in each group
+    fn group_size(&self, cols: usize) -> usize {
+        match self {
+            Quantization::Int8Symmetric | Quantization::Int8Asymmetric => cols,
+            Quantization::Int4 { group_size } => *group_size,
+        }
+    }
+
+    // The scale and zero point for a group of values
+    fn group_params(&self, values: &[f32]) -> (f32, u8) {
+        let (min, max) = values.iter().fold((0f32, 0f32), |(min, max), value| {
+            (min.min(*value), max.max(*value))
+        });
+        let (scale, zero_point) = match self {
+            Quantization::Int8Symmetric => (max.max(-min) / 127., 0.),
+            Quantization::Int8Asymmetric => Self::asymmetric_params(min, max, 255.),
+            Quantization::Int4 { .. } => Self::asymmetric_params(min, max, 15.),
+        };
+        // An all-zero group can use any scale
+        let scale = if scale > 0. { scale } else { 1. };
+        (scale, zero_point as u8)
+    }
+
+    // The range always includes zero, so zero is stored exactly
+    fn asymmetric_params(min: f32, max: f32, max_level: f32) -> (f32, f32) {
+        let scale = (max - min) / max_level;
+        if scale > 0. {
+            (scale, (-min / scale).round().clamp(0., max_level))
+        } else {
+            (scale, 0.)
+        }
+    }
+
+    fn quantize_value(&self, value: f32, scale: f32, zero_point: u8) -> u8 {
+        let scaled = (value / scale).round();
+        match self {
+            Quantization::Int8Symmetric => scaled.clamp(-127., 127.) as i8 as u8,
+            Quantization::Int8Asymmetric => (scaled + zero_point as f32).clamp(0., 255.) as u8,
+            Quantization::Int4 { .. } => (scaled + zero_point as f32).clamp(0., 15.) as u8,
+        }
+    }
+}
+
+impl QuantizedMatrix {
+    /// Quantize a two-dimensional tensor of finite values.
+    pub fn quantize(weights: &Tensor, scheme: Quantization) -> Result<QuantizedMatrix> {
+        let &[rows, cols] = weights.shape() else {
+            Err(MatrixError::MatrixError(format!(
+                "Expected a matrix to quantize, got shape {:?}",
+                weights.shape()
+            )))?
+        };
+        if scheme.group_size(cols) == 0 && cols > 0 {
+            Err(MatrixError::MatrixError(
+                "The quantization group size must be positive".to_owned(),
+            ))?;
+        }
+        if weights.iter().any(|value| !value.is_finite()) {
+            Err(MatrixError::MatrixError(
+                "Only finite values can be quantized".to_owned(),
+            ))?;
+        }
+
+        let mut matrix = QuantizedMatrix {
+            scheme,
+            rows,
+            cols,
+            data: vec![0; Self::data_len(scheme, rows * cols)],
+            scales: vec![],
+            zero_points: vec![],
+        };
+        let group_size = scheme.group_size(cols).max(1);
+        for (row, values) in weights.as_slice().chunks(cols.max(1)).enumerate() {
+            for (group, group_values) in values.chunks(group_size).enumerate() {
+                let (scale, zero_point) = scheme.group_params(group_values);
+                matrix.scales.push(scale);
+                matrix.zero_points.push(zero_point);
+                let start = row * cols + group * group_size;
+                for (idx, value) in group_values.iter().enumerate() {
+                    matrix.set(
+                        start + idx,
+                        scheme.quantize_value(*value, scale, zero_point),
+                    );
+                }
+            }
+        }
+        Ok(matrix)
+    }
+
+    /// Rebuild a quantized matrix from its stored values, scales and zero points, as saved by
+    /// `io::quantized`.
+    pub fn from_parts(
+        scheme: Quantization,
+        shape: [usize; 2],
+        data: Vec<u8>,
+        scales: Vec<f32>,
+        zero_points: Vec<u8>,
+    ) -> Result<QuantizedMatrix> {
+        let [rows, cols] = shape;
+        let group_size = scheme.group_size(cols);
+        if group_size == 0 && cols > 0 {
+            Err(MatrixError::MatrixError(
+                "The quantization group size must be positive".to_owned(),
+            ))?;
+        }
+        let n_groups = rows * cols.div_ceil(group_size.max(1));
+        if data.len() != Self::data_len(scheme, rows * cols)
+            || scales.len() != n_groups
+            || zero_points.len() != n_groups
+        {
+            Err(MatrixError::MatrixError(format!(
+                "A {rows}x{cols} matrix quantized as {scheme:?} needs {} bytes of values and {n_groups} \
+                 scales and zero points, got {}, {} and {}",
+                Self::data_len(scheme, rows * cols),
+                data.len(),
+                scales.len(),
+                zero_points.len()
+            )))?;
+        }
+        Ok(QuantizedMatrix {
+            scheme,
+            rows,
+            cols,
+            data,
+            scales,
+            zero_points,
+        })
+    }
+
+    pub fn scheme(&self) -> Quantization {
+        self.scheme
+    }
+
+    /// The stored values, with 4-bit values packed two to a byte, low nibble first.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The scale of each group, row by row.
+    pub fn scales(&self) -> &[f32] {
+        &self.scales
+    }
+
+    /// The zero point of each group, row by row. These are zero for symmetric schemes.
+    pub fn zero_points(&self) -> &[u8] {
+        &self.zero_points
+    }
+
+    pub fn shape(&self) -> [usize; 2] {
+        [self.rows, self.cols]
+    }
+
+    /// The memory used by the values, scales and zero points.
+    pub fn size_in_bytes(&self) -> usize {
+        self.data.len() + self.scales.len() * size_of::<f32>() + self.zero_points.len()
+    }
+
+    /// The full precision matrix the quantized values represent.
+    pub fn dequantize(&self) -> Tensor {
+        let mut data = vec![0.; self.rows * self.cols];
+        self.dequantize_rows(0..self.rows, &mut data);
+        Tensor::from_vec(data, &[self.rows, self.cols]).unwrap()
+    }
+
+    // Dequantize a range of rows into a row-major buffer
+    fn dequantize_rows(&self, rows: Range<usize>, out: &mut [f32]) {
+        let group_size = self.scheme.group_size(self.cols).max(1);
+        let groups_per_row = self.cols.div_ceil(group_size);
+        for (row, values) in rows.zip(out.chunks_exact_mut(self.cols.max(1))) {
+            for (col, value) in values.iter_mut().enumerate() {
+                let group = row * groups_per_row + col / group_size;
+                let stored = self.get(row * self.cols + col);
+                let level = match self.scheme {
+                    Quantization::Int8Symmetric => stored as i8 as f32,
+                    _ => stored as f32 - self.zero_points[group] as f32,
+                };
+                *value = level * self.scales[group];
+            }
+        }
+    }
+
+    fn data_len(scheme: Quantization, numel: usize) -> usize {
+        match scheme {
+            Quantization::Int4 { .. } => numel.div_ceil(2),
+            _ => numel,
+        }
+    }
+
+    // The stored value at a flat index. 4-bit values are packed low nibble first
+    fn get(&self, idx: usize) -> u8 {
+        match self.scheme {
+            Quantization::Int4 { .. } => (self.data[idx / 2] >> (4 * (idx % 2))) & 0xf,
+            _ => self.data[idx],
+        }
+    }
+
+    fn set(&mut self, idx: usize, value: u8) {
+        match self.scheme {
+            Quantization::Int4 { .. } => self.data[idx / 2] |= value << (4 * (idx % 2)),
+            _ => self.data[idx] = value,
+        }
+    }
+}
+
+impl Tensor {
+    /// The product `self · weightsᵀ` over the last dimension, dequantizing the weights a block
+    /// of rows at a time. Any leading dimensions are kept.
+    pub fn matmul_nt_quantized(&self, weights: &QuantizedMatrix) -> Result<Tensor> {
+        let [n_outputs, n_inputs] = weights.shape();
+        if self.ndim() == 0 || self.shape()[self.ndim() - 1] != n_inputs {
+            let mut expected = self.shape().to_vec();
+            match expected.last_mut() {
+                Some(last) => *last = n_inputs,
+                None => expected.push(n_inputs),
+            }
+            Err(MatrixError::ShapeMismatch {
+                expected,
+                actual: self.shape().to_vec(),
+            })?;
+        }
+        let n_rows = self.numel() / n_inputs.max(1);
+        let mut out_shape = self.shape().to_vec();
+        *out_shape.last_mut().unwrap() = n_outputs;
+
+        let mut output = vec![0.; n_rows * n_outputs];
+        let mut block = vec![0.; BLOCK_ROWS.min(n_outputs) * n_inputs];
+        let mut block_output = vec![0.; n_rows * BLOCK_ROWS.min(n_outputs)];
+        for start in (0..n_outputs).step_by(BLOCK_ROWS) {
+            let block_rows = BLOCK_ROWS.min(n_outputs - start);
+            let block = &mut block[..block_rows * n_inputs];
+            weights.dequantize_rows(start..start + block_rows, block);
+            let block_output = &mut block_output[..n_rows * block_rows];
+            block_output.fill(0.);
+            gemm::gemm(
+                MatRef::row_major(self.as_slice(), n_rows, n_inputs),
+                MatRef::row_major(block, block_rows, n_inputs).t(),
+                block_output,
+            );
+            for (row, values) in block_output.chunks_exact(block_rows).enumerate() {
+                output[row * n_outputs + start..][..block_rows].copy_from_slice(values);
+            }
+        }
+        Tensor::from_vec(output, &out_shape)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(shape: &[usize], seed: usize) -> Tensor {
+        let numel = shape.iter().product();
+        Tensor::from_vec(
+            (0..numel)
+                .map(|i| (((i * 7919 + seed * 104729) % 2003) as f32 / 1001. - 1.).powi(3))
+                .collect(),
+            shape,
+        )
+        .unwrap()
+    }
+
+    fn max_error(a: &Tensor, b: &Tensor) -> f32 {
+        a.zip_map(b, |x, y| (x - y).abs())
+            .unwrap()
+            .iter()
+            .fold(0., |max, error| max.max(*error))
+    }
+
+    #[test]
+    fn test_exact_values() {
+        // Values on the quantization grid come back exactly, including zero
+        let weights = Tensor::from_vec(vec![-127., 0., 1., 127., 0., 0., 0., 0.], &[2, 4]).unwrap();
+        let quantized = QuantizedMatrix::quantize(&weights, Quantization::Int8Symmetric).unwrap();
+        assert_eq!(quantized.dequantize(), weights);
+        assert_eq!(quantized.data, [129, 0, 1, 127, 0, 0, 0, 0]);
+
+        let weights = Tensor::from_vec(vec![-5., 0., 5., 10.], &[1, 4]).unwrap();
+        let quantized =
+            QuantizedMatrix::quantize(&weights, Quantization::Int4 { group_size: 4 }).unwrap();
+        assert_eq!(quantized.scales, [1.]);
+        assert_eq!(quantized.zero_points, [5]);
+        assert_eq!(quantized.data, [0x50, 0xfa]);
+        assert_eq!(quantized.dequantize(), weights);
+    }
+
+    #[test]
+    fn test_errors_are_bounded() {
+        // Rows with very different ranges, which per-row scales handle
+        let weights = sample(&[24, 40], 1)
+            .mul(&Tensor::from_vec((1..=24).map(|i| i as f32).collect(), &[24, 1]).unwrap())
+            .unwrap();
+        for (scheme, levels) in [
+            (Quantization::Int8Symmetric, 254.),
+            (Quantization::Int8Asymmetric, 255.),
+            (Quantization::Int4 { group_size: 8 }, 15.),
+            (Quantization::Int4 { group_size: 7 }, 15.),
+        ] {
+            let quantized = QuantizedMatrix::quantize(&weights, scheme).unwrap();
+            let dequantized = quantized.dequantize();
+            // Each value is within half a step of its row's range
+            for (row, (original, restored)) in weights
+                .as_slice()
+                .chunks(40)
+                .zip(dequantized.as_slice().chunks(40))
+                .enumerate()
+            {
+                let range = original.iter().fold(0f32, |max, x| max.max(x.abs())) * 2.;
+                for (x, y) in original.iter().zip(restored) {
+                    assert!(
+                        (x - y).abs() <= range / levels / 2. * 1.001,
+                        "{scheme:?} row {row}: {x} became {y}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_sizes() {
+        let weights = sample(&[16, 64], 2);
+        let size = |scheme| {
+            QuantizedMatrix::quantize(&weights, scheme)
+                .unwrap()
+                .size_in_bytes()
+        };
+        assert_eq!(size(Quantization::Int8Symmetric), 16 * 64 + 16 * 5);
+        assert_eq!(size(Quantization::Int8Asymmetric), 16 * 64 + 16 * 5);
+        assert_eq!(
+            size(Quantization::Int4 { group_size: 32 }),
+            16 * 32 + 16 * 2 * 5
+        );
+    }
+
+    #[test]
+    fn test_matmul() {
+        // Enough outputs for several blocks, with a partial last one
+        let weights = sample(&[150, 37], 3);
+        let inputs = sample(&[2, 5, 37], 4);
+        for scheme in [
+            Quantization::Int8Symmetric,
+            Quantization::Int8Asymmetric,
+            Quantization::Int4 { group_size: 16 },
+        ] {
+            let quantized = QuantizedMatrix::quantize(&weights, scheme).unwrap();
+            let product = inputs.matmul_nt_quantized(&quantized).unwrap();
+            assert_eq!(product.shape(), &[2, 5, 150]);
+            let expected = inputs.matmul_nt(&quantized.dequantize()).unwrap();
+            assert!(max_error(&product, &expected) < 1e-5);
+        }
+
+        let quantized = QuantizedMatrix::quantize(&weights, Quantization::Int8Symmetric).unwrap();
+        assert!(sample(&[5, 36], 0).matmul_nt_quantized(&quantized).is_err());
+        assert!(Tensor::scalar(1f32)
+            .matmul_nt_quantized(&quantized)
+            .is_err());
+    }
+
+    #[test]
+    fn test_from_parts() {
+        let weights = sample(&[3, 5], 5);
+        let scheme = Quantization::Int4 { group_size: 2 };
+        let quantized = QuantizedMatrix::quantize(&weights, scheme).unwrap();
+        let parts = || {
+            (
+                quantized.data().to_vec(),
+                quantized.scales().to_vec(),
+                quantized.zero_points().to_vec(),
+            )
+        };
+        let (data, scales, zero_points) = parts();
+        assert_eq!((data.len(), scales.len()), (8, 9));
+        let rebuilt = QuantizedMatrix::from_parts(scheme, [3, 5], data, scales, zero_points);
+        assert_eq!(rebuilt.unwrap(), quantized);
+
+        let (data, scales, zero_points) = parts();
+        assert!(QuantizedMatrix::from_parts(scheme, [5, 3], data, scales, zero_points).is_err());
+        let (data, scales, _) = parts();
+        assert!(QuantizedMatrix::from_parts(scheme, [3, 5], data, scales, vec![0; 8]).is_err());
+
+        for scheme in [
+            Quantization::Int8Symmetric,
+            Quantization::Int8Asymmetric,
+            Quantization::Int4 { group_size: 32 },
+        ] {
+            assert_eq!(Quantization::from_name(scheme.name(), 32).unwrap(), scheme);
+        }
+        assert!(Quantization::from_name("int2", 32).is_err());
+    }
+
+    #[test]
+    fn test_invalid_weights() {
+        let scheme = Quantization::Int8Symmetric;
+        assert!(QuantizedMatrix::quantize(&sample(&[4], 0), scheme).is_err());
+        let weights = Tensor::from_vec(vec![1., f32::NAN], &[1, 2]).unwrap();
+        assert!(QuantizedMatrix::quantize(&weights, scheme).is_err());
+        let scheme = Quantization::Int4 { group_size: 0 };
+        assert!(QuantizedMatrix::quantize(&sample(&[2, 2], 0), scheme).is_err());
+    }
+}
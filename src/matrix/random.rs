@@ -0,0 +1,150 @@
+// A small seedable random number generator, so that random weights are reproducible.
+//
+// This is xoshiro256** (Blackman and Vigna), with its state filled from the seed by splitmix64
+// as its authors recommend. It is fast and statistically strong, but not cryptographically
+// secure.
+
+/// A seedable pseudo-random number generator. The same seed always gives the same sequence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rng {
+    state: [u64; 4],
+    // The second normal sample from the last Box-Muller transform
+    spare_normal: Option<f64>,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        let mut seed = seed;
+        let mut splitmix = || {
+            seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = seed;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        Rng {
+            state: [splitmix(), splitmix(), splitmix(), splitmix()],
+            spare_normal: None,
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let [s0, s1, s2, s3] = &mut self.state;
+        let result = s1.wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = *s1 << 17;
+        *s2 ^= *s0;
+        *s3 ^= *s1;
+        *s1 ^= *s2;
+        *s0 ^= *s3;
+        *s2 ^= t;
+        *s3 = s3.rotate_left(45);
+        result
+    }
+
+    /// A uniform sample from [0, 1), with 53 random bits.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 * (1. / (1u64 << 53) as f64)
+    }
+
+    /// A uniform sample from [0, 1), with 24 random bits.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 * (1. / (1u32 << 24) as f32)
+    }
+
+    /// A uniform integer below `bound`, without modulo bias.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        assert!(bound > 0, "The bound must be positive");
+        // Reject the top partial copy of [0, bound)
+        let zone = u64::MAX - (u64::MAX - bound + 1) % bound;
+        loop {
+            let value = self.next_u64();
+            if value <= zone {
+                return value % bound;
+            }
+        }
+    }
+
+    /// A uniform sample from [low, high).
+    pub fn uniform(&mut self, low: f64, high: f64) -> f64 {
+        low + (high - low) * self.next_f64()
+    }
+
+    /// A sample from the standard normal distribution, by the Box-Muller transform.
+    pub fn normal(&mut self) -> f64 {
+        if let Some(spare) = self.spare_normal.take() {
+            return spare;
+        }
+        // 1 - u is in (0, 1], so the log is finite
+        let radius = (-2. * (1. - self.next_f64()).ln()).sqrt();
+        let angle = 2. * std::f64::consts::PI * self.next_f64();
+        self.spare_normal = Some(radius * angle.sin());
+        radius * angle.cos()
+    }
+
+    /// A new generator seeded from this one, for an independent stream of numbers.
+    pub fn fork(&mut self) -> Rng {
+        Rng::new(self.next_u64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reference_values() {
+        // The first outputs of the reference implementations for seed 0
+        let mut rng = Rng::new(0);
+        assert_eq!(
+            rng.state,
+            [
+                0xe220_a839_7b1d_cdaf,
+                0x6e78_9e6a_a1b9_65f4,
+                0x06c4_5d18_8009_454f,
+                0xf88b_b8a8_724c_81ec
+            ]
+        );
+        assert_eq!(rng.next_u64(), 0x99ec_5f36_cb75_f2b4);
+        assert_eq!(rng.next_u64(), 0xbf6e_1f78_4956_452a);
+
+        let first: Vec<u64> = (0..4).map(|_| Rng::new(42).next_u64()).collect();
+        assert!(first.iter().all(|value| *value == first[0]));
+        assert_ne!(Rng::new(42).next_u64(), Rng::new(43).next_u64());
+    }
+
+    #[test]
+    fn test_distributions() {
+        let mut rng = Rng::new(7);
+        let n = 100_000;
+
+        let samples: Vec<f64> = (0..n).map(|_| rng.uniform(-2., 3.)).collect();
+        assert!(samples.iter().all(|x| (-2. ..3.).contains(x)));
+        let mean = samples.iter().sum::<f64>() / n as f64;
+        assert!((mean - 0.5).abs() < 0.02, "{mean}");
+        assert!((0..n).all(|_| (0. ..1.).contains(&rng.next_f32())));
+
+        let samples: Vec<f64> = (0..n).map(|_| rng.normal()).collect();
+        let mean = samples.iter().sum::<f64>() / n as f64;
+        let variance = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n as f64;
+        assert!(mean.abs() < 0.01, "{mean}");
+        assert!((variance - 1.).abs() < 0.02, "{variance}");
+        // About 4.55% of samples are more than two standard deviations out
+        let tails = samples.iter().filter(|x| x.abs() > 2.).count() as f64 / n as f64;
+        assert!((tails - 0.0455).abs() < 0.003, "{tails}");
+
+        let mut counts = [0; 3];
+        for _ in 0..30_000 {
+            counts[rng.below(3) as usize] += 1;
+        }
+        assert!(counts.iter().all(|count| (9_700..10_300).contains(count)));
+    }
+
+    #[test]
+    fn test_fork() {
+        let mut rng = Rng::new(1);
+        let mut forked = rng.fork();
+        assert_ne!(forked.next_u64(), rng.next_u64());
+        let (mut a, mut b) = (Rng::new(1), Rng::new(1));
+        assert_eq!(a.fork().next_u64(), b.fork().next_u64());
+    }
+}
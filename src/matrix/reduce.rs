@@ -0,0 +1,51 @@
+// Reductions over a single lane of values, shared by the tensor types.
+//
+// Each takes an iterator which can be cloned, so the reductions needing two passes over their
+// input (to subtract the max, or the mean) don't have to collect it first.
+use crate::matrix::element::{Float, Number};
+
+pub(crate) fn sum<T: Number>(values: impl Iterator<Item = T>) -> T {
+    values.fold(T::ZERO, |total, value| total + value)
+}
+
+pub(crate) fn mean<T: Float>(values: impl Iterator<Item = T> + Clone) -> T {
+    let count = values.clone().count();
+    sum(values) / T::from_usize(count)
+}
+
+/// The largest value, ignoring NaNs. Negative infinity if there are no values.
+pub(crate) fn max<T: Float>(values: impl Iterator<Item = T>) -> T {
+    values.fold(T::NEG_INFINITY, T::max)
+}
+
+/// The position of the first largest value, ignoring NaNs.
+pub(crate) fn argmax<T: Float>(values: impl Iterator<Item = T>) -> Option<usize> {
+    let mut best: Option<(usize, T)> = None;
+    for (idx, value) in values.enumerate() {
+        if value.is_nan() {
+            continue;
+        }
+        match best {
+            Some((_, best_value)) if value <= best_value => {}
+            _ => best = Some((idx, value)),
+        }
+    }
+    best.map(|(idx, _)| idx)
+}
+
+/// The population variance, computed about the mean so large offsets don't lose precision.
+pub(crate) fn variance<T: Float>(values: impl Iterator<Item = T> + Clone) -> T {
+    let mean = mean(values.clone());
+    let count = values.clone().count();
+    sum(values.map(|value| (value - mean).powi(2))) / T::from_usize(count)
+}
+
+/// `ln(Σ exp(x))`, computed as `max + ln(Σ exp(x - max))` so no term can overflow.
+pub(crate) fn logsumexp<T: Float>(values: impl Iterator<Item = T> + Clone) -> T {
+    let max = max(values.clone());
+    if max.is_infinite() {
+        // All the values are -inf, or one is +inf, and subtracting would give NaN
+        return max;
+    }
+    max + sum(values.map(|value| (value - max).exp())).ln()
+}
@@ -0,0 +1,1287 @@
+use std::ops::Range;
+
+use anyhow::Result;
+
+use crate::exceptions::MatrixError;
+use crate::matrix::broadcast::{broadcast_shapes, broadcast_strides, StridedPositions};
+use crate::matrix::element::{Element, Float, Number};
+use crate::matrix::gemm::{self, MatRef};
+use crate::matrix::matrix::Matrix2;
+use crate::matrix::parallel;
+use crate::matrix::reduce;
+use crate::matrix::vector::FloatVector;
+use crate::matrix::view::TensorView;
+
+/// A heap-backed n-dimensional array, with its shape known at runtime.
+///
+/// Elements are stored contiguously in row-major order, and `strides` gives the distance in
+/// elements between consecutive indices along each dimension. The elements are `f32` unless
+/// another element type is given.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tensor<T: Element = f32> {
+    data: Vec<T>,
+    shape: Vec<usize>,
+    strides: Vec<usize>,
+}
+
+// The number of elements each thread works on at a time in elementwise ops
+const ELEMENTWISE_CHUNK: usize = 1 << 12;
+
+// The values along one axis of a tensor
+type Lane<'a, T> = std::iter::Copied<std::iter::Take<std::iter::StepBy<std::slice::Iter<'a, T>>>>;
+
+/// The row-major strides for a contiguous tensor of the given shape.
+pub fn contiguous_strides(shape: &[usize]) -> Vec<usize> {
+    let mut strides = vec![1; shape.len()];
+    for i in (0..shape.len().saturating_sub(1)).rev() {
+        strides[i] = strides[i + 1] * shape[i + 1];
+    }
+    strides
+}
+
+/// The index of the element at `position` in row-major order.
+pub fn unravel_index(mut position: usize, shape: &[usize]) -> Vec<usize> {
+    let mut index = vec![0; shape.len()];
+    for (i, dim) in shape.iter().enumerate().rev() {
+        index[i] = position % dim;
+        position /= dim;
+    }
+    index
+}
+
+impl<T: Element> Tensor<T> {
+    pub fn from_vec(data: Vec<T>, shape: &[usize]) -> Result<Tensor<T>> {
+        if data.len() != shape.iter().product::<usize>() {
+            Err(MatrixError::ShapeMismatch {
+                expected: shape.to_vec(),
+                actual: vec![data.len()],
+            })?;
+        }
+        Ok(Tensor {
+            data,
+            shape: shape.to_vec(),
+            strides: contiguous_strides(shape),
+        })
+    }
+
+    pub fn from_slice(data: &[T], shape: &[usize]) -> Result<Tensor<T>> {
+        Tensor::from_vec(data.to_vec(), shape)
+    }
+
+    pub fn full(shape: &[usize], value: T) -> Tensor<T> {
+        Tensor {
+            data: vec![value; shape.iter().product()],
+            shape: shape.to_vec(),
+            strides: contiguous_strides(shape),
+        }
+    }
+
+    pub fn zeros(shape: &[usize]) -> Tensor<T> {
+        Tensor::full(shape, T::ZERO)
+    }
+
+    pub fn ones(shape: &[usize]) -> Tensor<T> {
+        Tensor::full(shape, T::ONE)
+    }
+
+    /// A zero-dimensional tensor holding a single value.
+    pub fn scalar(value: T) -> Tensor<T> {
+        Tensor::full(&[], value)
+    }
+
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    pub fn strides(&self) -> &[usize] {
+        &self.strides
+    }
+
+    pub fn ndim(&self) -> usize {
+        self.shape.len()
+    }
+
+    /// The total number of elements.
+    pub fn numel(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.data
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.data
+    }
+
+    pub fn into_vec(self) -> Vec<T> {
+        self.data
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.data.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, T> {
+        self.data.iter_mut()
+    }
+
+    pub fn get(&self, index: &[usize]) -> Result<T> {
+        Ok(self.data[self.offset(index)?])
+    }
+
+    pub fn set(&mut self, index: &[usize], value: T) -> Result<()> {
+        let offset = self.offset(index)?;
+        self.data[offset] = value;
+        Ok(())
+    }
+
+    // The position in `data` of the element at the index
+    fn offset(&self, index: &[usize]) -> Result<usize> {
+        if index.len() != self.ndim() || index.iter().zip(&self.shape).any(|(i, dim)| i >= dim) {
+            Err(MatrixError::IndexOutOfBounds {
+                index: index.to_vec(),
+                shape: self.shape.clone(),
+            })?;
+        }
+        Ok(index.iter().zip(&self.strides).map(|(i, s)| i * s).sum())
+    }
+
+    /// Apply `f` to every element.
+    pub fn map(&self, f: impl Fn(T) -> T + Sync + Send) -> Tensor<T> {
+        let mut mapped = self.clone();
+        mapped.map_inplace(f);
+        mapped
+    }
+
+    pub fn map_inplace(&mut self, f: impl Fn(T) -> T + Sync + Send) {
+        let numel = self.numel();
+        parallel::for_each_chunk_mut(&mut self.data, ELEMENTWISE_CHUNK, numel, |_, chunk| {
+            chunk.iter_mut().for_each(|element| *element = f(*element))
+        });
+    }
+
+    /// Convert the elements to another type. See `Element::from_f64` for how values which
+    /// don't fit are handled.
+    pub fn cast<U: Element>(&self) -> Tensor<U> {
+        Tensor {
+            data: self.data.iter().map(|element| element.cast()).collect(),
+            shape: self.shape.clone(),
+            strides: self.strides.clone(),
+        }
+    }
+
+    /// Combine the elements of two tensors of the same shape with `f`.
+    pub fn zip_map(
+        &self,
+        other: &Tensor<T>,
+        f: impl Fn(T, T) -> T + Sync + Send,
+    ) -> Result<Tensor<T>> {
+        other.expect_shape(&self.shape)?;
+        let mut zipped = self.clone();
+        let numel = self.numel();
+        parallel::for_each_chunk_mut(&mut zipped.data, ELEMENTWISE_CHUNK, numel, |idx, chunk| {
+            let other_chunk = &other.data[idx * ELEMENTWISE_CHUNK..];
+            for (element, other_element) in chunk.iter_mut().zip(other_chunk) {
+                *element = f(*element, *other_element);
+            }
+        });
+        Ok(zipped)
+    }
+
+    /// The element of each lane along `axis` at the index for that lane. `indices` has the
+    /// shape with `axis` removed, which is also the shape of the result.
+    pub fn gather_axis(&self, axis: usize, indices: &Tensor<usize>) -> Result<Tensor<T>> {
+        self.check_dim(axis)?;
+        let mut shape = self.shape.clone();
+        let axis_len = shape.remove(axis);
+        indices.expect_shape(&shape)?;
+        if let Some(index) = indices.iter().find(|index| **index >= axis_len) {
+            Err(MatrixError::IndexOutOfBounds {
+                index: vec![*index],
+                shape: vec![axis_len],
+            })?;
+        }
+        let inner: usize = self.shape[axis + 1..].iter().product();
+        let data = indices
+            .iter()
+            .enumerate()
+            .map(|(lane, index)| {
+                self.data[(lane / inner) * axis_len * inner + lane % inner + index * inner]
+            })
+            .collect();
+        Tensor::from_vec(data, &shape)
+    }
+
+    /// Replace the elements where `mask`, of the same shape, is nonzero with `value`. Unlike
+    /// multiplying by a mask, this also replaces infinities and NaNs.
+    pub fn masked_fill<M: Element>(&self, mask: &Tensor<M>, value: T) -> Result<Tensor<T>> {
+        mask.expect_shape(&self.shape)?;
+        let data = self
+            .data
+            .iter()
+            .zip(mask.iter())
+            .map(|(element, masked)| if *masked == M::ZERO { *element } else { value })
+            .collect();
+        Tensor::from_vec(data, &self.shape)
+    }
+
+    /// Repeat the tensor along new leading dimensions and dimensions of size 1, to the given
+    /// shape.
+    pub fn broadcast_to(&self, shape: &[usize]) -> Result<Tensor<T>> {
+        if broadcast_shapes(&self.shape, shape)? != shape {
+            Err(MatrixError::BroadcastMismatch {
+                lhs: self.shape.clone(),
+                rhs: shape.to_vec(),
+            })?;
+        }
+        if self.shape == shape {
+            return Ok(self.clone());
+        }
+        let strides = broadcast_strides(&self.shape, &self.strides, shape);
+        Tensor::from_vec(strided_copy(&self.data, 0, shape, &strides), shape)
+    }
+
+    /// Combine the elements of two tensors with `f`, broadcasting them to a common shape.
+    pub fn broadcast_zip(
+        &self,
+        other: &Tensor<T>,
+        f: impl Fn(T, T) -> T + Sync + Send,
+    ) -> Result<Tensor<T>> {
+        let shape = broadcast_shapes(&self.shape, &other.shape)?;
+        let mut zipped = self.broadcast_to(&shape)?;
+        zipped.broadcast_zip_inplace(other, f)?;
+        Ok(zipped)
+    }
+
+    /// Combine `other` into this tensor with `f`. `other` is broadcast to this tensor's shape,
+    /// which must not change.
+    pub fn broadcast_zip_inplace(
+        &mut self,
+        other: &Tensor<T>,
+        f: impl Fn(T, T) -> T + Sync + Send,
+    ) -> Result<()> {
+        if broadcast_shapes(&self.shape, &other.shape)? != self.shape {
+            Err(MatrixError::BroadcastMismatch {
+                lhs: self.shape.clone(),
+                rhs: other.shape.clone(),
+            })?;
+        }
+        let other_strides = broadcast_strides(&other.shape, &other.strides, &self.shape);
+        let (numel, shape) = (self.numel(), &self.shape);
+        parallel::for_each_chunk_mut(&mut self.data, ELEMENTWISE_CHUNK, numel, |idx, chunk| {
+            let positions =
+                StridedPositions::new(shape, &other_strides, 0, idx * ELEMENTWISE_CHUNK);
+            for (element, position) in chunk.iter_mut().zip(positions) {
+                *element = f(*element, other.data[position]);
+            }
+        });
+        Ok(())
+    }
+
+    fn reduce_axis<'a>(
+        &'a self,
+        axis: usize,
+        keepdim: bool,
+        f: impl Fn(Lane<'a, T>) -> T + Sync + Send,
+    ) -> Result<Tensor<T>> {
+        let reduced = self.reduce_lanes(axis, f)?;
+        let mut shape = self.shape.clone();
+        if keepdim {
+            shape[axis] = 1;
+        } else {
+            shape.remove(axis);
+        }
+        Tensor::from_vec(reduced, &shape)
+    }
+
+    // Apply `f` to the values along `axis` for every index into the other dimensions
+    fn reduce_lanes<'a, R: Element>(
+        &'a self,
+        axis: usize,
+        f: impl Fn(Lane<'a, T>) -> R + Sync + Send,
+    ) -> Result<Vec<R>> {
+        self.check_dim(axis)?;
+        let axis_len = self.shape[axis];
+        let inner: usize = self.shape[axis + 1..].iter().product();
+        let n_lanes: usize = self.shape[..axis].iter().product::<usize>() * inner;
+
+        let mut reduced = vec![R::ZERO; n_lanes];
+        // Give each thread enough whole lanes to be worth sending over
+        let lanes_per_chunk = (ELEMENTWISE_CHUNK / axis_len.max(1)).max(1);
+        parallel::for_each_chunk_mut(&mut reduced, lanes_per_chunk, self.numel(), |idx, chunk| {
+            for (j, value) in chunk.iter_mut().enumerate() {
+                let lane = idx * lanes_per_chunk + j;
+                let start = (lane / inner) * axis_len * inner + lane % inner;
+                let data = if axis_len == 0 {
+                    &[]
+                } else {
+                    &self.data[start..]
+                };
+                *value = f(data.iter().step_by(inner).take(axis_len).copied());
+            }
+        });
+        Ok(reduced)
+    }
+
+    /// Swap two dimensions, copying the data into the new layout.
+    pub fn transpose(&self, dim0: usize, dim1: usize) -> Result<Tensor<T>> {
+        Ok(self.view().transpose(dim0, dim1)?.contiguous())
+    }
+
+    /// A view of the whole tensor, which can be sliced and permuted without copying.
+    pub fn view(&self) -> TensorView<'_, T> {
+        TensorView::new(&self.data, &self.shape, &self.strides)
+    }
+
+    /// Give the elements a new shape with the same number of elements, without copying.
+    pub fn reshape(mut self, shape: &[usize]) -> Result<Tensor<T>> {
+        if shape.iter().product::<usize>() != self.numel() {
+            Err(MatrixError::ShapeMismatch {
+                expected: shape.to_vec(),
+                actual: self.shape.clone(),
+            })?;
+        }
+        self.shape = shape.to_vec();
+        self.strides = contiguous_strides(shape);
+        Ok(self)
+    }
+
+    /// See `TensorView::permute`.
+    pub fn permute(&self, dims: &[usize]) -> Result<TensorView<'_, T>> {
+        self.view().permute(dims)
+    }
+
+    /// See `TensorView::narrow`.
+    pub fn narrow(&self, dim: usize, start: usize, length: usize) -> Result<TensorView<'_, T>> {
+        self.view().narrow(dim, start, length)
+    }
+
+    /// See `TensorView::slice`.
+    pub fn slice(&self, dim: usize, range: Range<usize>, step: usize) -> Result<TensorView<'_, T>> {
+        self.view().slice(dim, range, step)
+    }
+
+    /// See `TensorView::split`.
+    pub fn split(&self, dim: usize, size: usize) -> Result<Vec<TensorView<'_, T>>> {
+        self.view().split(dim, size)
+    }
+
+    /// Join tensors along an existing dimension. Every other dimension must match.
+    pub fn cat(parts: &[&TensorView<T>], dim: usize) -> Result<Tensor<T>> {
+        let first = parts.first().ok_or_else(|| {
+            MatrixError::MatrixError(String::from("Can't concatenate an empty list of tensors"))
+        })?;
+        if dim >= first.ndim() {
+            Err(MatrixError::MatrixError(format!(
+                "Dimension {dim} is out of range for a tensor of shape {:?}",
+                first.shape()
+            )))?;
+        }
+        let mut shape = first.shape().to_vec();
+        shape[dim] = 0;
+        for part in parts {
+            let mut expected = shape.clone();
+            expected[dim] = part.shape().get(dim).copied().unwrap_or(0);
+            if part.shape() != expected {
+                Err(MatrixError::ShapeMismatch {
+                    expected,
+                    actual: part.shape().to_vec(),
+                })?;
+            }
+            shape[dim] += part.shape()[dim];
+        }
+
+        // Each part contributes a block of its own dimension times the inner dimensions, in turn
+        // for every index into the outer dimensions
+        let parts: Vec<Tensor<T>> = parts.iter().map(|part| part.contiguous()).collect();
+        let inner: usize = shape[dim + 1..].iter().product();
+        let n_outer: usize = shape[..dim].iter().product();
+        let mut data = Vec::with_capacity(shape.iter().product());
+        for outer in 0..n_outer {
+            for part in &parts {
+                let block = part.shape()[dim] * inner;
+                data.extend_from_slice(&part.data[outer * block..(outer + 1) * block]);
+            }
+        }
+        Tensor::from_vec(data, &shape)
+    }
+
+    /// Join tensors of the same shape along a new dimension.
+    pub fn stack(parts: &[&TensorView<T>], dim: usize) -> Result<Tensor<T>> {
+        let unsqueezed = parts
+            .iter()
+            .map(|part| part.unsqueeze(dim))
+            .collect::<Result<Vec<TensorView<T>>>>()?;
+        Tensor::cat(&unsqueezed.iter().collect::<Vec<&TensorView<T>>>(), dim)
+    }
+
+    /// The matrix product over the last two dimensions, for operands of any element types. The
+    /// elements are converted to f32 as they are loaded and the products accumulated in f32, so
+    /// half precision weights can be multiplied without a full precision copy.
+    pub fn matmul_f32<U: Element>(&self, other: &Tensor<U>) -> Result<Tensor<f32>> {
+        self.batched_matmul(other, false, false, gemm::gemm)
+    }
+
+    /// As `matmul_f32`, computing `self · otherᵀ`.
+    pub fn matmul_nt_f32<U: Element>(&self, other: &Tensor<U>) -> Result<Tensor<f32>> {
+        self.batched_matmul(other, false, true, gemm::gemm)
+    }
+
+    // The product over the last two dimensions, batched over the rest, with `gemm` computing
+    // each product
+    fn batched_matmul<U: Element, C: Element>(
+        &self,
+        other: &Tensor<U>,
+        transpose_lhs: bool,
+        transpose_rhs: bool,
+        gemm: impl Fn(MatRef<T>, MatRef<U>, &mut [C]) + Sync,
+    ) -> Result<Tensor<C>> {
+        let (lhs_batch, lhs_rows, lhs_cols) = self.split_matrix_shape()?;
+        let (rhs_batch, rhs_rows, rhs_cols) = other.split_matrix_shape()?;
+        let (m, k) = if transpose_lhs {
+            (lhs_cols, lhs_rows)
+        } else {
+            (lhs_rows, lhs_cols)
+        };
+        let (rhs_k, n) = if transpose_rhs {
+            (rhs_cols, rhs_rows)
+        } else {
+            (rhs_rows, rhs_cols)
+        };
+
+        let batch: &[usize] = if lhs_batch == rhs_batch || rhs_batch.is_empty() {
+            lhs_batch
+        } else if lhs_batch.is_empty() {
+            rhs_batch
+        } else {
+            Err(MatrixError::ShapeMismatch {
+                expected: [lhs_batch, &other.shape[other.ndim() - 2..]].concat(),
+                actual: other.shape.clone(),
+            })?
+        };
+        if rhs_k != k {
+            let mut expected = other.shape.clone();
+            let inner_dim = other.ndim() - if transpose_rhs { 1 } else { 2 };
+            expected[inner_dim] = k;
+            Err(MatrixError::ShapeMismatch {
+                expected,
+                actual: other.shape.clone(),
+            })?;
+        }
+
+        // Transposing an operand is just a matter of swapping its strides
+        let lhs_strides = if transpose_lhs {
+            (1, lhs_cols)
+        } else {
+            (lhs_cols, 1)
+        };
+        let rhs_strides = if transpose_rhs {
+            (1, rhs_cols)
+        } else {
+            (rhs_cols, 1)
+        };
+        let n_batches: usize = batch.iter().product();
+        let lhs_size = lhs_rows * lhs_cols;
+        let rhs_size = rhs_rows * rhs_cols;
+
+        let mut data = vec![C::ZERO; n_batches * m * n];
+        let batch_product = |b: usize, out: &mut [C]| {
+            let lhs_start = if lhs_batch.is_empty() {
+                0
+            } else {
+                b * lhs_size
+            };
+            let rhs_start = if rhs_batch.is_empty() {
+                0
+            } else {
+                b * rhs_size
+            };
+            gemm(
+                MatRef::new(
+                    &self.data[lhs_start..lhs_start + lhs_size],
+                    m,
+                    k,
+                    lhs_strides.0,
+                    lhs_strides.1,
+                ),
+                MatRef::new(
+                    &other.data[rhs_start..rhs_start + rhs_size],
+                    k,
+                    n,
+                    rhs_strides.0,
+                    rhs_strides.1,
+                ),
+                out,
+            );
+        };
+        if n_batches > 1 {
+            // Share the batches between threads, each computing whole products on its own
+            parallel::for_each_chunk_mut(&mut data, m * n, n_batches * m * n * k, |b, out| {
+                parallel::with_num_threads(1, || batch_product(b, out))
+            });
+        } else if n_batches == 1 {
+            batch_product(0, &mut data);
+        }
+        Tensor::from_vec(data, &[batch, &[m, n]].concat())
+    }
+
+    // Split the shape into the batch dimensions, rows and columns
+    fn split_matrix_shape(&self) -> Result<(&[usize], usize, usize)> {
+        if self.ndim() < 2 {
+            Err(MatrixError::MatrixError(format!(
+                "Expected a tensor with at least two dimensions for a matrix product, got shape {:?}",
+                self.shape
+            )))?;
+        }
+        let n = self.ndim();
+        Ok((&self.shape[..n - 2], self.shape[n - 2], self.shape[n - 1]))
+    }
+
+    fn check_dim(&self, dim: usize) -> Result<()> {
+        if dim >= self.ndim() {
+            Err(MatrixError::MatrixError(format!(
+                "Dimension {dim} is out of range for a tensor of shape {:?}",
+                self.shape
+            )))?;
+        }
+        Ok(())
+    }
+
+    // Check the shape matches exactly, for conversions into the fixed-size types
+    fn expect_shape(&self, expected: &[usize]) -> Result<(), MatrixError> {
+        if self.shape != expected {
+            return Err(MatrixError::ShapeMismatch {
+                expected: expected.to_vec(),
+                actual: self.shape.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<T: Number> Tensor<T> {
+    pub fn add(&self, other: &Tensor<T>) -> Result<Tensor<T>> {
+        self.broadcast_zip(other, |x, y| x + y)
+    }
+
+    pub fn sub(&self, other: &Tensor<T>) -> Result<Tensor<T>> {
+        self.broadcast_zip(other, |x, y| x - y)
+    }
+
+    pub fn mul(&self, other: &Tensor<T>) -> Result<Tensor<T>> {
+        self.broadcast_zip(other, |x, y| x * y)
+    }
+
+    pub fn div(&self, other: &Tensor<T>) -> Result<Tensor<T>> {
+        self.broadcast_zip(other, |x, y| x / y)
+    }
+
+    pub fn add_inplace(&mut self, other: &Tensor<T>) -> Result<()> {
+        self.broadcast_zip_inplace(other, |x, y| x + y)
+    }
+
+    pub fn sub_inplace(&mut self, other: &Tensor<T>) -> Result<()> {
+        self.broadcast_zip_inplace(other, |x, y| x - y)
+    }
+
+    pub fn mul_inplace(&mut self, other: &Tensor<T>) -> Result<()> {
+        self.broadcast_zip_inplace(other, |x, y| x * y)
+    }
+
+    pub fn div_inplace(&mut self, other: &Tensor<T>) -> Result<()> {
+        self.broadcast_zip_inplace(other, |x, y| x / y)
+    }
+
+    /// The sum of every element.
+    pub fn sum(&self) -> T {
+        let partial_sums =
+            parallel::map_chunks(&self.data, |chunk| reduce::sum(chunk.iter().copied()));
+        reduce::sum(partial_sums.into_iter())
+    }
+
+    /// Sum along `axis`. With `keepdim` the axis is kept with size 1, so the result broadcasts
+    /// against the input; otherwise it is removed.
+    pub fn sum_axis(&self, axis: usize, keepdim: bool) -> Result<Tensor<T>> {
+        self.reduce_axis(axis, keepdim, reduce::sum)
+    }
+
+    /// The matrix product over the last two dimensions.
+    ///
+    /// Any leading dimensions are batch dimensions: they must be equal for both operands, or one
+    /// of the operands must be two-dimensional, in which case it is used for every batch.
+    pub fn matmul(&self, other: &Tensor<T>) -> Result<Tensor<T>> {
+        self.batched_matmul(other, false, false, T::gemm)
+    }
+
+    /// The matrix product `self · otherᵀ` over the last two dimensions.
+    pub fn matmul_nt(&self, other: &Tensor<T>) -> Result<Tensor<T>> {
+        self.batched_matmul(other, false, true, T::gemm)
+    }
+
+    /// The matrix product `selfᵀ · other` over the last two dimensions.
+    pub fn matmul_tn(&self, other: &Tensor<T>) -> Result<Tensor<T>> {
+        self.batched_matmul(other, true, false, T::gemm)
+    }
+}
+
+impl<T: Float> Tensor<T> {
+    /// The mean of every element.
+    pub fn mean(&self) -> T {
+        self.sum() / T::from_usize(self.numel())
+    }
+
+    /// The elementwise maximum. NaNs are ignored unless both elements are NaN.
+    pub fn maximum(&self, other: &Tensor<T>) -> Result<Tensor<T>> {
+        self.broadcast_zip(other, T::max)
+    }
+
+    /// The elementwise minimum. NaNs are ignored unless both elements are NaN.
+    pub fn minimum(&self, other: &Tensor<T>) -> Result<Tensor<T>> {
+        self.broadcast_zip(other, T::min)
+    }
+
+    /// Raise each element to the power of the matching element of `exponent`.
+    pub fn pow(&self, exponent: &Tensor<T>) -> Result<Tensor<T>> {
+        self.broadcast_zip(exponent, T::powf)
+    }
+
+    pub fn maximum_inplace(&mut self, other: &Tensor<T>) -> Result<()> {
+        self.broadcast_zip_inplace(other, T::max)
+    }
+
+    pub fn minimum_inplace(&mut self, other: &Tensor<T>) -> Result<()> {
+        self.broadcast_zip_inplace(other, T::min)
+    }
+
+    pub fn pow_inplace(&mut self, exponent: &Tensor<T>) -> Result<()> {
+        self.broadcast_zip_inplace(exponent, T::powf)
+    }
+
+    pub fn mean_axis(&self, axis: usize, keepdim: bool) -> Result<Tensor<T>> {
+        self.reduce_axis(axis, keepdim, reduce::mean)
+    }
+
+    /// The largest element along `axis`, ignoring NaNs.
+    pub fn max_axis(&self, axis: usize, keepdim: bool) -> Result<Tensor<T>> {
+        self.check_non_empty_axis(axis)?;
+        self.reduce_axis(axis, keepdim, reduce::max)
+    }
+
+    /// The position along `axis` of the first largest element, ignoring NaNs. The axis is
+    /// removed from the shape.
+    pub fn argmax_axis(&self, axis: usize) -> Result<Tensor<usize>> {
+        self.check_non_empty_axis(axis)?;
+        // A lane of NaNs has no largest element, so fall back to the first
+        let indices = self.reduce_lanes(axis, |lane| reduce::argmax(lane).unwrap_or(0))?;
+        let mut shape = self.shape.clone();
+        shape.remove(axis);
+        Tensor::from_vec(indices, &shape)
+    }
+
+    /// The population variance along `axis`.
+    pub fn var_axis(&self, axis: usize, keepdim: bool) -> Result<Tensor<T>> {
+        self.reduce_axis(axis, keepdim, reduce::variance)
+    }
+
+    /// `ln(Σ exp(x))` along `axis`, which doesn't overflow for large inputs.
+    pub fn logsumexp_axis(&self, axis: usize, keepdim: bool) -> Result<Tensor<T>> {
+        self.reduce_axis(axis, keepdim, reduce::logsumexp)
+    }
+
+    fn check_non_empty_axis(&self, axis: usize) -> Result<()> {
+        self.check_dim(axis)?;
+        if self.shape[axis] == 0 {
+            Err(MatrixError::MatrixError(format!(
+                "Cannot take the maximum along empty dimension {axis} of a tensor of shape {:?}",
+                self.shape
+            )))?;
+        }
+        Ok(())
+    }
+}
+
+/// Copy the elements laid out with the given shape and strides into a contiguous vector.
+pub(crate) fn strided_copy<T: Copy>(
+    data: &[T],
+    offset: usize,
+    shape: &[usize],
+    strides: &[usize],
+) -> Vec<T> {
+    StridedPositions::new(shape, strides, offset, 0)
+        .map(|position| data[position])
+        .collect()
+}
+
+impl<const SIZE: usize, T: Element> From<&FloatVector<SIZE, T>> for Tensor<T> {
+    fn from(vector: &FloatVector<SIZE, T>) -> Self {
+        Tensor::from_vec(vector.iter().copied().collect(), &[SIZE])
+            .expect("The vector has SIZE elements")
+    }
+}
+
+impl<const SIZE: usize, T: Element> From<FloatVector<SIZE, T>> for Tensor<T> {
+    fn from(vector: FloatVector<SIZE, T>) -> Self {
+        Tensor::from(&vector)
+    }
+}
+
+impl<const N_ROWS: usize, const N_COLS: usize, T: Element> From<&Matrix2<N_ROWS, N_COLS, T>>
+    for Tensor<T>
+{
+    fn from(matrix: &Matrix2<N_ROWS, N_COLS, T>) -> Self {
+        Tensor::from_vec(
+            matrix
+                .rows
+                .iter()
+                .flat_map(|row| row.iter().copied())
+                .collect(),
+            &[N_ROWS, N_COLS],
+        )
+        .expect("The matrix has N_ROWS * N_COLS elements")
+    }
+}
+
+impl<const N_ROWS: usize, const N_COLS: usize, T: Element> From<Matrix2<N_ROWS, N_COLS, T>>
+    for Tensor<T>
+{
+    fn from(matrix: Matrix2<N_ROWS, N_COLS, T>) -> Self {
+        Tensor::from(&matrix)
+    }
+}
+
+impl<const SIZE: usize, T: Element> TryFrom<&Tensor<T>> for FloatVector<SIZE, T> {
+    type Error = MatrixError;
+
+    fn try_from(tensor: &Tensor<T>) -> Result<Self, Self::Error> {
+        tensor.expect_shape(&[SIZE])?;
+        Ok(FloatVector::from_elements(
+            tensor
+                .as_slice()
+                .try_into()
+                .expect("The shape has been checked"),
+        ))
+    }
+}
+
+impl<const SIZE: usize, T: Element> TryFrom<Tensor<T>> for FloatVector<SIZE, T> {
+    type Error = MatrixError;
+
+    fn try_from(tensor: Tensor<T>) -> Result<Self, Self::Error> {
+        FloatVector::try_from(&tensor)
+    }
+}
+
+impl<const N_ROWS: usize, const N_COLS: usize, T: Element> TryFrom<&Tensor<T>>
+    for Matrix2<N_ROWS, N_COLS, T>
+{
+    type Error = MatrixError;
+
+    fn try_from(tensor: &Tensor<T>) -> Result<Self, Self::Error> {
+        tensor.expect_shape(&[N_ROWS, N_COLS])?;
+        let data = tensor.as_slice();
+        let rows: Vec<FloatVector<N_COLS, T>> = (0..N_ROWS)
+            .map(|i| {
+                FloatVector::from_elements(
+                    data[i * N_COLS..(i + 1) * N_COLS]
+                        .try_into()
+                        .expect("Rows have N_COLS elements"),
+                )
+            })
+            .collect();
+        Ok(Matrix2::from_rows(rows.try_into().map_err(|_| {
+            MatrixError::ShapeMismatch {
+                expected: vec![N_ROWS, N_COLS],
+                actual: tensor.shape().to_vec(),
+            }
+        })?))
+    }
+}
+
+impl<const N_ROWS: usize, const N_COLS: usize, T: Element> TryFrom<Tensor<T>>
+    for Matrix2<N_ROWS, N_COLS, T>
+{
+    type Error = MatrixError;
+
+    fn try_from(tensor: Tensor<T>) -> Result<Self, Self::Error> {
+        Matrix2::try_from(&tensor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matrix::half::{Bf16, F16};
+
+    #[test]
+    fn test_create() {
+        let tensor = Tensor::from_vec((0..24).map(|i| i as f32).collect(), &[2, 3, 4]).unwrap();
+        assert_eq!(tensor.shape(), &[2, 3, 4]);
+        assert_eq!(tensor.strides(), &[12, 4, 1]);
+        assert_eq!(tensor.ndim(), 3);
+        assert_eq!(tensor.numel(), 24);
+        assert_eq!(tensor.get(&[1, 2, 3]).unwrap(), 23.);
+        assert_eq!(tensor.get(&[1, 0, 2]).unwrap(), 14.);
+
+        assert!(Tensor::from_vec(vec![0.; 5], &[2, 3]).is_err());
+        assert!(tensor.get(&[2, 0, 0]).is_err());
+        assert!(tensor.get(&[0, 0]).is_err());
+
+        let scalar = Tensor::scalar(3.);
+        assert_eq!(scalar.shape(), &[] as &[usize]);
+        assert_eq!(scalar.get(&[]).unwrap(), 3.);
+
+        let mut zeros = Tensor::zeros(&[4, 0]);
+        assert_eq!(zeros.numel(), 0);
+        assert!(zeros.set(&[0, 0], 1.).is_err());
+    }
+
+    #[test]
+    fn test_conversions() {
+        let vector = FloatVector::from_elements([1., 2., 3.]);
+        let tensor = Tensor::from(&vector);
+        assert_eq!(tensor.shape(), &[3]);
+        assert_eq!(FloatVector::<3>::try_from(&tensor).unwrap(), vector);
+
+        let matrix = Matrix2::from_rows([
+            FloatVector::from_elements([1., 2., 3.]),
+            FloatVector::from_elements([4., 5., 6.]),
+        ]);
+        let tensor = Tensor::from(&matrix);
+        assert_eq!(tensor.shape(), &[2, 3]);
+        assert_eq!(tensor.get(&[1, 0]).unwrap(), 4.);
+        assert_eq!(Matrix2::<2, 3>::try_from(tensor).unwrap(), matrix);
+    }
+
+    #[test]
+    fn test_conversion_errors() {
+        let tensor = Tensor::zeros(&[2, 3]);
+        match FloatVector::<6>::try_from(&tensor) {
+            Err(MatrixError::ShapeMismatch { expected, actual }) => {
+                assert_eq!(expected, vec![6]);
+                assert_eq!(actual, vec![2, 3]);
+            }
+            other => panic!("Expected a shape mismatch, got {other:?}"),
+        }
+        assert!(Matrix2::<3, 2>::try_from(&tensor).is_err());
+        assert!(Matrix2::<2, 3>::try_from(&tensor).is_ok());
+    }
+
+    fn sample(shape: &[usize], seed: usize) -> Tensor {
+        let numel = shape.iter().product();
+        Tensor::from_vec(
+            (0..numel)
+                .map(|i| ((i * 7 + seed) % 11) as f32 - 5.)
+                .collect(),
+            shape,
+        )
+        .unwrap()
+    }
+
+    // The textbook triple loop over every batch, to check against
+    fn naive_matmul(a: &Tensor, b: &Tensor) -> Tensor {
+        let (batch, m, k) = a.split_matrix_shape().unwrap();
+        let n = b.shape()[b.ndim() - 1];
+        let mut out = Tensor::zeros(&[batch, &[m, n]].concat());
+        let n_batches = batch.iter().product::<usize>();
+        for bi in 0..n_batches {
+            let batch_index: Vec<usize> = if batch.is_empty() { vec![] } else { vec![bi] };
+            let b_index: Vec<usize> = if b.ndim() == 2 { vec![] } else { vec![bi] };
+            for i in 0..m {
+                for j in 0..n {
+                    let mut total = 0.;
+                    for p in 0..k {
+                        total += a.get(&[&batch_index[..], &[i, p]].concat()).unwrap()
+                            * b.get(&[&b_index[..], &[p, j]].concat()).unwrap();
+                    }
+                    out.set(&[&batch_index[..], &[i, j]].concat(), total)
+                        .unwrap();
+                }
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_transpose() {
+        let tensor = sample(&[2, 3, 4], 0);
+        let transposed = tensor.transpose(0, 2).unwrap();
+        assert_eq!(transposed.shape(), &[4, 3, 2]);
+        for index in [[0, 0, 0], [1, 2, 3], [0, 1, 2], [1, 0, 3]] {
+            assert_eq!(
+                transposed.get(&[index[2], index[1], index[0]]).unwrap(),
+                tensor.get(&index).unwrap()
+            );
+        }
+        assert_eq!(transposed.transpose(2, 0).unwrap(), tensor);
+        assert!(tensor.transpose(0, 3).is_err());
+    }
+
+    #[test]
+    fn test_matmul() {
+        let a = sample(&[3, 4], 1);
+        let b = sample(&[4, 5], 2);
+        let expected = naive_matmul(&a, &b);
+        assert_eq!(a.matmul(&b).unwrap(), expected);
+        assert_eq!(a.matmul_nt(&b.transpose(0, 1).unwrap()).unwrap(), expected);
+        assert_eq!(a.transpose(0, 1).unwrap().matmul_tn(&b).unwrap(), expected);
+
+        // Agrees with the fixed-size matrices
+        let a_fixed = Matrix2::<3, 4>::try_from(&a).unwrap();
+        let b_fixed = Matrix2::<4, 5>::try_from(&b).unwrap();
+        assert_eq!(Tensor::from(a_fixed.matmul(&b_fixed)), expected);
+    }
+
+    #[test]
+    fn test_batched_matmul() {
+        let a = sample(&[6, 3, 4], 1);
+        let b = sample(&[6, 4, 2], 2);
+        assert_eq!(a.matmul(&b).unwrap(), naive_matmul(&a, &b));
+
+        // A two-dimensional right hand side is shared across the batch
+        let shared = sample(&[4, 2], 3);
+        assert_eq!(a.matmul(&shared).unwrap(), naive_matmul(&a, &shared));
+
+        let b_t = b.transpose(1, 2).unwrap();
+        assert_eq!(a.matmul_nt(&b_t).unwrap(), naive_matmul(&a, &b));
+        let a_t = a.transpose(1, 2).unwrap();
+        assert_eq!(a_t.matmul_tn(&b).unwrap(), naive_matmul(&a, &b));
+
+        // A two-dimensional left hand side is too
+        let shared = sample(&[3, 4], 3);
+        let out = shared.matmul(&b).unwrap();
+        assert_eq!(out.shape(), &[6, 3, 2]);
+        for (i, out_batch) in out.as_slice().chunks_exact(3 * 2).enumerate() {
+            let b_batch = Tensor::from_slice(&b.as_slice()[i * 8..(i + 1) * 8], &[4, 2]).unwrap();
+            assert_eq!(out_batch, naive_matmul(&shared, &b_batch).as_slice());
+        }
+    }
+
+    #[test]
+    fn test_matmul_errors() {
+        let a = sample(&[2, 3, 4], 1);
+        match a.matmul(&sample(&[2, 5, 2], 2)) {
+            Err(err) => match err.downcast_ref::<MatrixError>() {
+                Some(MatrixError::ShapeMismatch { expected, actual }) => {
+                    assert_eq!(expected, &vec![2, 4, 2]);
+                    assert_eq!(actual, &vec![2, 5, 2]);
+                }
+                other => panic!("Expected a shape mismatch, got {other:?}"),
+            },
+            Ok(_) => panic!("Mismatched inner dimensions should fail"),
+        }
+        assert!(a.matmul(&sample(&[3, 4, 2], 2)).is_err());
+        assert!(a.matmul(&sample(&[4], 2)).is_err());
+        assert!(sample(&[3, 4], 1).matmul_nt(&sample(&[2, 4], 2)).is_ok());
+        assert!(sample(&[3, 4], 1).matmul_tn(&sample(&[4, 2], 2)).is_err());
+    }
+
+    // Run `f` serially and on several threads, checking the results are bitwise identical
+    fn assert_same_in_parallel<T: PartialEq + std::fmt::Debug>(f: impl Fn() -> T) {
+        let serial = parallel::with_num_threads(1, &f);
+        let threaded = parallel::with_num_threads(4, || parallel::with_parallel_threshold(0, &f));
+        assert_eq!(serial, threaded);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_parallel_matches_serial() {
+        let a = sample(&[300, 70], 1).map(|x| x / 3.);
+        let b = sample(&[70, 50], 2).map(|x| x / 7.);
+        assert_same_in_parallel(|| a.matmul(&b).unwrap());
+        assert_same_in_parallel(|| a.matmul_nt(&b.transpose(0, 1).unwrap()).unwrap());
+
+        let batched = sample(&[8, 20, 70], 3).map(|x| x / 3.);
+        assert_same_in_parallel(|| batched.matmul(&b).unwrap());
+
+        let large = sample(&[50_000], 4).map(|x| x / 9.);
+        assert_same_in_parallel(|| large.map(|x| x.exp()));
+        assert_same_in_parallel(|| large.zip_map(&large, |x, y| x * y + 1.).unwrap());
+        assert_same_in_parallel(|| large.sum().to_bits());
+
+        let rows = sample(&[400, 300], 5);
+        let bias = sample(&[300], 6);
+        assert_same_in_parallel(|| rows.add(&bias).unwrap());
+        assert_same_in_parallel(|| bias.sub(&rows).unwrap());
+        assert_same_in_parallel(|| rows.sum_axis(0, false).unwrap());
+        assert_same_in_parallel(|| rows.logsumexp_axis(1, true).unwrap());
+    }
+
+    #[test]
+    fn test_elementwise() {
+        let a = sample(&[2, 3], 1);
+        let doubled = a.map(|x| 2. * x);
+        assert_eq!(doubled.shape(), a.shape());
+        assert_eq!(doubled.get(&[1, 2]).unwrap(), 2. * a.get(&[1, 2]).unwrap());
+        assert_eq!(
+            a.zip_map(&doubled, |x, y| y - x).unwrap(),
+            a,
+            "2x - x should be x"
+        );
+        assert!(a.zip_map(&sample(&[3, 2], 1), |x, _| x).is_err());
+        assert_eq!(a.sum(), a.iter().sum::<f32>());
+    }
+
+    #[test]
+    fn test_broadcasting() {
+        let a = sample(&[2, 3], 1);
+        let row = Tensor::from_slice(&[1., 2., 3.], &[3]).unwrap();
+        let column = Tensor::from_slice(&[10., 20.], &[2, 1]).unwrap();
+
+        let sum = a.add(&row).unwrap();
+        let difference = a.sub(&column).unwrap();
+        for i in 0..2 {
+            for j in 0..3 {
+                let x = a.get(&[i, j]).unwrap();
+                assert_eq!(sum.get(&[i, j]).unwrap(), x + row.get(&[j]).unwrap());
+                assert_eq!(
+                    difference.get(&[i, j]).unwrap(),
+                    x - column.get(&[i, 0]).unwrap()
+                );
+            }
+        }
+        // Broadcasting works on either side, and both operands can be expanded
+        assert_eq!(row.sub(&a).unwrap(), a.sub(&row).unwrap().map(|x| -x));
+        let outer = column.mul(&row).unwrap();
+        assert_eq!(outer.shape(), &[2, 3]);
+        assert_eq!(outer.as_slice(), &[10., 20., 30., 20., 40., 60.]);
+
+        // Scalars broadcast against anything
+        assert_eq!(a.div(&Tensor::scalar(2.)).unwrap(), a.map(|x| x / 2.));
+        assert_eq!(a.pow(&Tensor::scalar(2.)).unwrap(), a.mul(&a).unwrap());
+        assert_eq!(
+            a.maximum(&Tensor::scalar(0.)).unwrap(),
+            a.map(|x| x.max(0.))
+        );
+        assert_eq!(
+            a.minimum(&Tensor::scalar(0.)).unwrap(),
+            a.map(|x| x.min(0.))
+        );
+        let bases = Tensor::from_slice(&[2., 3.], &[2, 1]).unwrap();
+        assert_eq!(
+            bases.pow(&row).unwrap().as_slice(),
+            &[2., 4., 8., 3., 9., 27.]
+        );
+
+        let expanded = row.broadcast_to(&[4, 1, 3]).unwrap();
+        assert_eq!(expanded.shape(), &[4, 1, 3]);
+        assert_eq!(expanded.get(&[3, 0, 2]).unwrap(), 3.);
+        assert!(row.broadcast_to(&[3, 2]).is_err());
+        assert!(a.broadcast_to(&[3]).is_err());
+    }
+
+    #[test]
+    fn test_broadcast_inplace() {
+        let mut batch = sample(&[4, 2, 3], 2);
+        let original = batch.clone();
+        let bias = sample(&[3], 3);
+        batch.add_inplace(&bias).unwrap();
+        assert_eq!(batch, original.add(&bias).unwrap());
+        batch.sub_inplace(&bias).unwrap();
+        assert_eq!(batch, original);
+
+        batch.mul_inplace(&Tensor::full(&[2, 1], 2.)).unwrap();
+        batch.div_inplace(&Tensor::scalar(2.)).unwrap();
+        assert_eq!(batch, original);
+
+        batch.maximum_inplace(&Tensor::scalar(-1.)).unwrap();
+        batch.minimum_inplace(&Tensor::scalar(1.)).unwrap();
+        batch.pow_inplace(&Tensor::scalar(2.)).unwrap();
+        assert_eq!(batch, original.map(|x| x.clamp(-1., 1.).powi(2)));
+
+        // The result must keep the shape of the tensor being updated
+        let mut row = bias.clone();
+        match row.add_inplace(&batch) {
+            Err(err) => match err.downcast_ref::<MatrixError>() {
+                Some(MatrixError::BroadcastMismatch { lhs, rhs }) => {
+                    assert_eq!(lhs, &vec![3]);
+                    assert_eq!(rhs, &vec![4, 2, 3]);
+                }
+                other => panic!("Expected a broadcast error, got {other:?}"),
+            },
+            Ok(_) => panic!("Broadcasting the tensor being updated should fail"),
+        }
+        assert_eq!(row, bias, "A failed op leaves the tensor unchanged");
+        assert!(batch.add(&sample(&[2], 1)).is_err());
+    }
+
+    #[test]
+    fn test_reductions() {
+        let a = Tensor::from_slice(&[1., 5., 3., -2., 4., 4.], &[2, 3]).unwrap();
+        assert_eq!(a.sum(), 15.);
+        assert_eq!(a.mean(), 2.5);
+
+        let sums = a.sum_axis(0, false).unwrap();
+        assert_eq!(sums.shape(), &[3]);
+        assert_eq!(sums.as_slice(), &[-1., 9., 7.]);
+        let sums = a.sum_axis(1, true).unwrap();
+        assert_eq!(sums.shape(), &[2, 1]);
+        assert_eq!(sums.as_slice(), &[9., 6.]);
+
+        assert_eq!(a.mean_axis(1, false).unwrap().as_slice(), &[3., 2.]);
+        assert_eq!(a.max_axis(0, false).unwrap().as_slice(), &[1., 5., 4.]);
+        assert_eq!(a.max_axis(1, false).unwrap().as_slice(), &[5., 4.]);
+        // Ties go to the first largest element
+        assert_eq!(a.argmax_axis(1).unwrap().as_slice(), &[1, 1]);
+        assert_eq!(a.argmax_axis(0).unwrap().as_slice(), &[0, 0, 1]);
+        assert_eq!(
+            a.var_axis(0, false).unwrap().as_slice(),
+            &[2.25, 0.25, 0.25]
+        );
+
+        // The middle axis of a 3D tensor
+        let b = sample(&[2, 3, 4], 1);
+        let maxes = b.max_axis(1, true).unwrap();
+        assert_eq!(maxes.shape(), &[2, 1, 4]);
+        for i in 0..2 {
+            for k in 0..4 {
+                let lane: Vec<f32> = (0..3).map(|j| b.get(&[i, j, k]).unwrap()).collect();
+                let max = lane.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                assert_eq!(maxes.get(&[i, 0, k]).unwrap(), max);
+            }
+        }
+        // With keepdim the result broadcasts back against the input
+        assert!(b.sub(&b.mean_axis(2, true).unwrap()).is_ok());
+
+        assert!(a.sum_axis(2, false).is_err());
+        let empty = Tensor::<f32>::zeros(&[2, 0]);
+        assert_eq!(empty.sum_axis(1, false).unwrap().as_slice(), &[0., 0.]);
+        assert!(empty.max_axis(1, false).is_err());
+        assert!(empty.argmax_axis(1).is_err());
+        assert_eq!(empty.max_axis(0, false).unwrap().numel(), 0);
+    }
+
+    #[test]
+    fn test_gather() {
+        let a = Tensor::from_slice(&[1., 5., 3., -2., 4., 4.], &[2, 3]).unwrap();
+        let rows = Tensor::from_slice(&[2, 0], &[2]).unwrap();
+        assert_eq!(a.gather_axis(1, &rows).unwrap().as_slice(), &[3., -2.]);
+        let columns = Tensor::from_slice(&[1, 0, 1], &[3]).unwrap();
+        assert_eq!(
+            a.gather_axis(0, &columns).unwrap().as_slice(),
+            &[-2., 5., 4.]
+        );
+        // The middle axis of a 3D tensor
+        let b = sample(&[2, 3, 4], 2);
+        let indices = Tensor::from_slice(&[0, 1, 2, 1, 2, 2, 0, 0], &[2, 4]).unwrap();
+        let gathered = b.gather_axis(1, &indices).unwrap();
+        for i in 0..2 {
+            for k in 0..4 {
+                let j = indices.get(&[i, k]).unwrap();
+                assert_eq!(gathered.get(&[i, k]).unwrap(), b.get(&[i, j, k]).unwrap());
+            }
+        }
+        assert!(a.gather_axis(1, &columns).is_err());
+        assert!(a
+            .gather_axis(1, &Tensor::from_slice(&[0, 3], &[2]).unwrap())
+            .is_err());
+        assert!(a.gather_axis(2, &rows).is_err());
+
+        let infinite = Tensor::from_slice(&[f32::NEG_INFINITY, f32::NAN, 1.], &[3]).unwrap();
+        let mask = Tensor::from_slice(&[1u8, 1, 0], &[3]).unwrap();
+        assert_eq!(
+            infinite.masked_fill(&mask, 0.).unwrap().as_slice(),
+            &[0., 0., 1.]
+        );
+        assert!(a.masked_fill(&mask, 0.).is_err());
+    }
+
+    #[test]
+    fn test_large_magnitude_reductions() {
+        // exp(1000) overflows f32, so a naive logsumexp would give inf
+        let a = Tensor::from_slice(&[1000., 1000., -1000., 0.], &[2, 2]).unwrap();
+        let lse = a.logsumexp_axis(1, false).unwrap();
+        assert!((lse.get(&[0]).unwrap() - (1000. + 2f32.ln())).abs() < 1e-3);
+        assert_eq!(lse.get(&[1]).unwrap(), 0.);
+
+        let lse = Tensor::from_slice(&[f32::NEG_INFINITY; 2], &[2])
+            .unwrap()
+            .logsumexp_axis(0, false)
+            .unwrap();
+        assert_eq!(lse.get(&[]).unwrap(), f32::NEG_INFINITY);
+
+        // The variance is taken about the mean, so a large offset doesn't swamp it
+        let offset = Tensor::from_slice(&[1e4 + 1., 1e4 - 1.], &[2]).unwrap();
+        assert_eq!(offset.var_axis(0, false).unwrap().as_slice(), &[1.]);
+    }
+
+    #[test]
+    fn test_element_types() {
+        // Doubles for precise reference computations
+        let (a_f32, b_f32) = (sample(&[3, 4], 1), sample(&[4, 2], 2));
+        let a = a_f32.cast::<f64>().map(|x| x / 3.);
+        let product = a.matmul(&b_f32.cast()).unwrap();
+        let expected = naive_matmul(&a_f32, &b_f32).cast::<f64>().map(|x| x / 3.);
+        assert!(product
+            .zip_map(&expected, |x, y| (x - y).abs())
+            .unwrap()
+            .iter()
+            .all(|difference| *difference < 1e-12));
+        assert!(a
+            .logsumexp_axis(1, false)
+            .unwrap()
+            .iter()
+            .all(|x| x.is_finite()));
+
+        // Integers for token ids and masks
+        let ids: Tensor<i64> = Tensor::from_vec(vec![5, -2, 7, 1], &[2, 2]).unwrap();
+        assert_eq!(ids.sum(), 11);
+        assert_eq!(ids.sum_axis(0, false).unwrap().as_slice(), &[12, -1]);
+        assert_eq!(
+            ids.add(&Tensor::scalar(1)).unwrap().as_slice(),
+            &[6, -1, 8, 2]
+        );
+        assert_eq!(ids.matmul(&ids).unwrap().as_slice(), &[11, -12, 42, -13]);
+        let mask: Tensor<u8> = ids.map(|id| (id > 0) as i64).cast();
+        assert_eq!(mask.as_slice(), &[1, 0, 1, 1]);
+        assert_eq!(
+            mask.view().transpose(0, 1).unwrap().contiguous().as_slice(),
+            &[1, 1, 0, 1]
+        );
+
+        // Casting rounds floats towards zero and saturates
+        let floats = Tensor::from_slice(&[-1.5, 0.5, 2.7, 300.], &[4]).unwrap();
+        assert_eq!(floats.cast::<i32>().as_slice(), &[-1, 0, 2, 300]);
+        assert_eq!(floats.cast::<u8>().as_slice(), &[0, 0, 2, 255]);
+        assert_eq!(floats.cast::<f64>().cast::<f32>(), floats);
+
+        // And the fixed-size types convert to and from tensors of any element type
+        let matrix = Matrix2::<2, 2, i64>::try_from(&ids).unwrap();
+        assert_eq!(matrix.rows[1][0], 7);
+        assert_eq!(Tensor::from(matrix.cast::<f64>()), ids.cast::<f64>());
+    }
+
+    #[test]
+    fn test_half_precision() {
+        // Half precision tensors store rounded values, and products widen them as they load,
+        // giving exactly the product of the widened tensors
+        let activations = sample(&[2, 7, 40], 1).map(|x| x / 3.);
+        let weights = sample(&[24, 40], 2).map(|x| x / 7.);
+        fn check<H: Element>(activations: &Tensor, weights: &Tensor) {
+            let half_weights = weights.cast::<H>();
+            let widened = half_weights.cast::<f32>();
+            assert_ne!(&widened, weights);
+            let product = activations.matmul_nt_f32(&half_weights).unwrap();
+            assert_eq!(product.shape(), &[2, 7, 24]);
+            assert_eq!(product, activations.matmul_nt(&widened).unwrap());
+        }
+        check::<F16>(&activations, &weights);
+        check::<Bf16>(&activations, &weights);
+        let half_activations = activations.cast::<Bf16>();
+        assert_eq!(
+            half_activations
+                .matmul_f32(&weights.transpose(0, 1).unwrap().cast::<F16>())
+                .unwrap(),
+            half_activations
+                .cast::<f32>()
+                .matmul(&weights.transpose(0, 1).unwrap().cast::<F16>().cast())
+                .unwrap()
+        );
+
+        // The products are accumulated in f32, well beyond the largest f16
+        let large = Tensor::full(&[1, 300], F16::from_f32(256.));
+        let product = large.matmul_nt_f32(&large).unwrap();
+        assert_eq!(product.as_slice(), &[300. * 65536.]);
+        assert!(product.cast::<F16>().as_slice()[0].to_f32().is_infinite());
+    }
+}
@@ -1,16 +1,19 @@
-use std::ops::{Add, AddAssign, Mul, MulAssign, Sub, SubAssign};
+use std::ops::{Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Sub, SubAssign};
 
 use anyhow::Result;
 
 use crate::exceptions::MatrixError;
+use crate::matrix::element::{Element, Float, Number};
+use crate::matrix::reduce;
 
+/// A fixed-size vector. The elements are `f32` unless another element type is given.
 #[derive(Debug, Clone, PartialEq)]
-pub struct FloatVector<const SIZE: usize> {
-    elements: [f32; SIZE],
+pub struct FloatVector<const SIZE: usize, T: Element = f32> {
+    elements: [T; SIZE],
 }
 
-impl<const SIZE: usize> FloatVector<SIZE> {
-    pub fn from_vector(elements: Vec<f32>) -> Result<FloatVector<SIZE>> {
+impl<const SIZE: usize, T: Element> FloatVector<SIZE, T> {
+    pub fn from_vector(elements: Vec<T>) -> Result<FloatVector<SIZE, T>> {
         Ok(FloatVector {
             elements: elements.try_into().map_err(|_| {
                 MatrixError::MatrixError(String::from("Failed to convert Vec to array"))
@@ -18,11 +21,11 @@ impl<const SIZE: usize> FloatVector<SIZE> {
         })
     }
 
-    pub fn from_elements(elements: [f32; SIZE]) -> FloatVector<SIZE> {
+    pub fn from_elements(elements: [T; SIZE]) -> FloatVector<SIZE, T> {
         FloatVector { elements }
     }
 
-    pub fn from_slice(elements: &[f32]) -> Result<FloatVector<SIZE>> {
+    pub fn from_slice(elements: &[T]) -> Result<FloatVector<SIZE, T>> {
         Ok(FloatVector {
             elements: elements.try_into().map_err(|_| {
                 MatrixError::MatrixError(String::from("Failed to convert Vec to array"))
@@ -30,117 +33,198 @@ impl<const SIZE: usize> FloatVector<SIZE> {
         })
     }
 
+    /// Collect an iterator into a vector, failing unless it yields exactly SIZE items.
+    pub fn try_from_iter<I: IntoIterator<Item = T>>(iter: I) -> Result<FloatVector<SIZE, T>> {
+        let mut elements = [T::ZERO; SIZE];
+        let mut iter = iter.into_iter();
+        for (idx, element) in elements.iter_mut().enumerate() {
+            *element = iter.next().ok_or_else(|| {
+                MatrixError::MatrixError(format!(
+                    "The iterator contained {idx} items, which is too few to create a FloatVector of size {SIZE}."
+                ))
+            })?;
+        }
+        if iter.next().is_some() {
+            Err(MatrixError::MatrixError(format!(
+                "The iterator contained more than {SIZE} items, which is too many to create a FloatVector of size {SIZE}."
+            )))?;
+        }
+        Ok(FloatVector { elements })
+    }
+
     pub fn len(&self) -> usize {
         SIZE
     }
 
-    pub fn dot(&self, other: &FloatVector<SIZE>) -> f32 {
-        self.elements
-            .iter()
-            .zip(other.elements.iter())
-            .map(|(a, b)| a * b)
-            .sum()
+    pub fn is_empty(&self) -> bool {
+        SIZE == 0
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.elements
     }
 
-    pub fn iter(&self) -> std::slice::Iter<'_, f32> {
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
         self.elements.iter()
     }
 
-    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, f32> {
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, T> {
         self.elements.iter_mut()
     }
+
+    /// Apply `f` to every element.
+    pub fn map<U: Element>(&self, f: impl Fn(T) -> U) -> FloatVector<SIZE, U> {
+        FloatVector::from_elements(self.elements.map(f))
+    }
+
+    /// Combine the elements of two vectors with `f`.
+    pub fn zip_map(&self, other: &FloatVector<SIZE, T>, f: impl Fn(T, T) -> T) -> Self {
+        FloatVector::from_elements(std::array::from_fn(|i| {
+            f(self.elements[i], other.elements[i])
+        }))
+    }
+
+    /// Convert the elements to another type. See `Element::from_f64` for how values which
+    /// don't fit are handled.
+    pub fn cast<U: Element>(&self) -> FloatVector<SIZE, U> {
+        self.map(Element::cast)
+    }
 }
 
-impl<const SIZE: usize> IntoIterator for FloatVector<SIZE> {
-    type Item = f32;
-    type IntoIter = std::array::IntoIter<Self::Item, SIZE>;
+impl<const SIZE: usize, T: Number> FloatVector<SIZE, T> {
+    pub fn dot(&self, other: &FloatVector<SIZE, T>) -> T {
+        T::dot(&self.elements, &other.elements)
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.elements.into_iter()
+    pub fn sum(&self) -> T {
+        reduce::sum(self.iter().copied())
     }
 }
 
-impl<const SIZE: usize> FromIterator<f32> for FloatVector<SIZE> {
-    fn from_iter<I: IntoIterator<Item = f32>>(iter: I) -> Self {
-        let mut ret_elements: [f32; SIZE] =
-            unsafe { std::mem::MaybeUninit::uninit().assume_init() };
+impl<const SIZE: usize, T: Float> FloatVector<SIZE, T> {
+    pub fn mean(&self) -> T {
+        reduce::mean(self.iter().copied())
+    }
 
-        let mut idx: usize = 0;
-        for element in iter {
-            ret_elements[idx] = element;
-            idx += 1;
-        }
-        assert_eq!(idx, SIZE, "The iterator contained {} items, which is incorrect to create a FloatVector of size {}.", idx, SIZE);
-        Self::from_elements(ret_elements)
+    /// The largest element, ignoring NaNs. Negative infinity for an empty vector.
+    pub fn max(&self) -> T {
+        reduce::max(self.iter().copied())
+    }
+
+    /// The index of the first largest element, ignoring NaNs. None if there are no numbers.
+    pub fn argmax(&self) -> Option<usize> {
+        reduce::argmax(self.iter().copied())
+    }
+
+    /// The population variance.
+    pub fn variance(&self) -> T {
+        reduce::variance(self.iter().copied())
+    }
+
+    /// `ln(Σ exp(x))`, which doesn't overflow for large elements.
+    pub fn logsumexp(&self) -> T {
+        reduce::logsumexp(self.iter().copied())
+    }
+
+    /// The elementwise maximum. NaNs are ignored unless both elements are NaN.
+    pub fn maximum(&self, other: &FloatVector<SIZE, T>) -> Self {
+        self.zip_map(other, T::max)
+    }
+
+    /// The elementwise minimum. NaNs are ignored unless both elements are NaN.
+    pub fn minimum(&self, other: &FloatVector<SIZE, T>) -> Self {
+        self.zip_map(other, T::min)
+    }
+
+    /// Raise each element to the power of the matching element of `exponent`.
+    pub fn pow(&self, exponent: &FloatVector<SIZE, T>) -> Self {
+        self.zip_map(exponent, T::powf)
+    }
+
+    pub fn powf(&self, exponent: T) -> Self {
+        self.map(|element| element.powf(exponent))
     }
 }
 
-impl<'a, 'b, const SIZE: usize> Add<&'b FloatVector<SIZE>> for &'a FloatVector<SIZE> {
-    type Output = FloatVector<SIZE>;
+impl<const SIZE: usize, T: Element> Index<usize> for FloatVector<SIZE, T> {
+    type Output = T;
 
-    fn add(self, other: &'b FloatVector<SIZE>) -> FloatVector<SIZE> {
-        let mut ret_elements: [f32; SIZE] =
-            unsafe { std::mem::MaybeUninit::uninit().assume_init() };
-        for i in 0..SIZE {
-            ret_elements[i] = self.elements[i] + other.elements[i]
-        }
-        FloatVector::from_elements(ret_elements)
+    fn index(&self, index: usize) -> &T {
+        &self.elements[index]
     }
 }
 
-impl<const SIZE: usize> AddAssign<&Self> for FloatVector<SIZE> {
-    fn add_assign(&mut self, other: &Self) {
-        for i in 0..SIZE {
-            self.elements[i] = self.elements[i] + other.elements[i];
-        }
+impl<const SIZE: usize, T: Element> IndexMut<usize> for FloatVector<SIZE, T> {
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        &mut self.elements[index]
     }
 }
 
-impl<'a, 'b, const SIZE: usize> Sub<&'b FloatVector<SIZE>> for &'a FloatVector<SIZE> {
-    type Output = FloatVector<SIZE>;
+impl<const SIZE: usize, T: Element> IntoIterator for FloatVector<SIZE, T> {
+    type Item = T;
+    type IntoIter = std::array::IntoIter<Self::Item, SIZE>;
 
-    fn sub(self, other: &'b FloatVector<SIZE>) -> FloatVector<SIZE> {
-        let mut ret_elements: [f32; SIZE] =
-            unsafe { std::mem::MaybeUninit::uninit().assume_init() };
-        for i in 0..SIZE {
-            ret_elements[i] = self.elements[i] - other.elements[i]
-        }
-        FloatVector::from_elements(ret_elements)
+    fn into_iter(self) -> Self::IntoIter {
+        self.elements.into_iter()
     }
 }
 
-impl<const SIZE: usize> SubAssign<&Self> for FloatVector<SIZE> {
-    fn sub_assign(&mut self, other: &Self) {
-        for i in 0..SIZE {
-            self.elements[i] = self.elements[i] - other.elements[i];
-        }
+impl<const SIZE: usize, T: Element> FromIterator<T> for FloatVector<SIZE, T> {
+    /// Panics if the iterator does not yield exactly SIZE items. See `try_from_iter` for a
+    /// fallible version.
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self::try_from_iter(iter).unwrap_or_else(|err| panic!("{err}"))
     }
 }
 
-impl<const SIZE: usize> Mul<f32> for &FloatVector<SIZE> {
-    type Output = FloatVector<SIZE>;
+// Elementwise operators between vectors, and with a scalar on the right
+macro_rules! impl_elementwise {
+    ($trait:ident, $method:ident, $assign_trait:ident, $assign_method:ident, $op:tt) => {
+        impl<'b, const SIZE: usize, T: Number> $trait<&'b FloatVector<SIZE, T>>
+            for &FloatVector<SIZE, T>
+        {
+            type Output = FloatVector<SIZE, T>;
 
-    fn mul(self, other: f32) -> FloatVector<SIZE> {
-        let mut ret_elements: [f32; SIZE] =
-            unsafe { std::mem::MaybeUninit::uninit().assume_init() };
-        for i in 0..SIZE {
-            ret_elements[i] = self.elements[i] * other
+            fn $method(self, other: &'b FloatVector<SIZE, T>) -> FloatVector<SIZE, T> {
+                self.zip_map(other, |x, y| x $op y)
+            }
         }
-        FloatVector::from_elements(ret_elements)
-    }
-}
 
-impl<const SIZE: usize> MulAssign<f32> for FloatVector<SIZE> {
-    fn mul_assign(&mut self, other: f32) {
-        for i in 0..SIZE {
-            self.elements[i] *= other
+        impl<const SIZE: usize, T: Number> $assign_trait<&Self> for FloatVector<SIZE, T> {
+            fn $assign_method(&mut self, other: &Self) {
+                for i in 0..SIZE {
+                    self.elements[i] = self.elements[i] $op other.elements[i];
+                }
+            }
         }
-    }
+
+        impl<const SIZE: usize, T: Number> $trait<T> for &FloatVector<SIZE, T> {
+            type Output = FloatVector<SIZE, T>;
+
+            fn $method(self, other: T) -> FloatVector<SIZE, T> {
+                self.map(|element| element $op other)
+            }
+        }
+
+        impl<const SIZE: usize, T: Number> $assign_trait<T> for FloatVector<SIZE, T> {
+            fn $assign_method(&mut self, other: T) {
+                for i in 0..SIZE {
+                    self.elements[i] = self.elements[i] $op other;
+                }
+            }
+        }
+    };
 }
 
+impl_elementwise!(Add, add, AddAssign, add_assign, +);
+impl_elementwise!(Sub, sub, SubAssign, sub_assign, -);
+impl_elementwise!(Mul, mul, MulAssign, mul_assign, *);
+impl_elementwise!(Div, div, DivAssign, div_assign, /);
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::assert_close;
 
     #[test]
     fn test_ops() {
@@ -166,4 +250,92 @@ mod tests {
         let bb: FloatVector<SIZE_A> = aa.iter().map(|element| element * 2.0).collect();
         assert_eq!(bb, &aa * 2.0);
     }
+
+    #[test]
+    fn test_try_from_iter() {
+        let aa: FloatVector<3> = FloatVector::try_from_iter([1., 2., 3.]).unwrap();
+        assert_eq!(aa, FloatVector::from_elements([1., 2., 3.]));
+
+        assert!(FloatVector::<3>::try_from_iter([1., 2.]).is_err());
+        assert!(FloatVector::<3>::try_from_iter([1., 2., 3., 4.]).is_err());
+        // Only SIZE + 1 items are ever taken from the iterator
+        assert!(FloatVector::<3>::try_from_iter(std::iter::repeat(1.)).is_err());
+
+        let empty: FloatVector<0> = FloatVector::try_from_iter([]).unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "too few")]
+    fn test_collect_too_few() {
+        let _: FloatVector<3> = [1., 2.].into_iter().collect();
+    }
+
+    #[test]
+    fn test_scalar_mul() {
+        let mut aa: FloatVector<3> = FloatVector::from_elements([1., -2., 3.]);
+        assert_eq!(&aa * -2., FloatVector::from_elements([-2., 4., -6.]));
+        aa *= 0.5;
+        assert_eq!(aa, FloatVector::from_elements([0.5, -1., 1.5]));
+    }
+
+    #[test]
+    fn test_elementwise() {
+        let mut aa: FloatVector<3> = FloatVector::from_elements([1., -2., 4.]);
+        let ab: FloatVector<3> = FloatVector::from_elements([2., 2., -1.]);
+
+        assert_eq!(&aa * &ab, FloatVector::from_elements([2., -4., -4.]));
+        assert_eq!(&aa / &ab, FloatVector::from_elements([0.5, -1., -4.]));
+        assert_eq!(&aa / 2., FloatVector::from_elements([0.5, -1., 2.]));
+        assert_eq!(aa.maximum(&ab), FloatVector::from_elements([2., 2., 4.]));
+        assert_eq!(aa.minimum(&ab), FloatVector::from_elements([1., -2., -1.]));
+        // Powers aren't exact everywhere, e.g. under Miri
+        assert_close!(aa.pow(&ab), FloatVector::from_elements([1., 4., 0.25]));
+        assert_close!(aa.powf(2.), &aa * &aa);
+
+        aa *= &ab;
+        aa /= &ab;
+        assert_eq!(aa, FloatVector::from_elements([1., -2., 4.]));
+        aa /= 4.;
+        assert_eq!(aa, FloatVector::from_elements([0.25, -0.5, 1.]));
+    }
+
+    #[test]
+    fn test_reductions() {
+        let aa: FloatVector<4> = FloatVector::from_elements([1., 3., -2., 3.]);
+        assert_eq!(aa.sum(), 5.);
+        assert_eq!(aa.mean(), 1.25);
+        assert_eq!(aa.max(), 3.);
+        assert_eq!(aa.argmax(), Some(1));
+        // Miri perturbs powers and transcendental functions, so these are only close
+        let logsumexp = aa.iter().map(|x| x.exp()).sum::<f32>().ln();
+        assert_close!(
+            FloatVector::from_elements([aa.variance(), aa.logsumexp()]),
+            FloatVector::from_elements([4.1875, logsumexp]),
+            rtol = 1e-5
+        );
+
+        let with_nan: FloatVector<3> = FloatVector::from_elements([f32::NAN, -1., -3.]);
+        assert_eq!(with_nan.max(), -1.);
+        assert_eq!(with_nan.argmax(), Some(1));
+        assert_eq!(FloatVector::<0>::from_elements([]).argmax(), None);
+    }
+
+    #[test]
+    fn test_element_types() {
+        let aa: FloatVector<3, f64> = FloatVector::from_elements([1., 2., 1e-10]);
+        assert_eq!(aa.sum(), 3.0000000001);
+        assert_eq!(
+            aa.cast::<f32>(),
+            FloatVector::from_elements([1., 2., 1e-10])
+        );
+
+        let ids: FloatVector<4, i32> = FloatVector::from_elements([3, -1, 4, 1]);
+        assert_eq!(ids.dot(&ids), 27);
+        assert_eq!(&ids + 1, FloatVector::from_elements([4, 0, 5, 2]));
+        assert_eq!(&ids / 2, FloatVector::from_elements([1, 0, 2, 0]));
+        let collected: FloatVector<4, usize> =
+            ids.iter().map(|id| id.unsigned_abs() as usize).collect();
+        assert_eq!(collected.sum(), 9);
+    }
 }
@@ -0,0 +1,388 @@
+use std::ops::Range;
+
+use anyhow::Result;
+
+use crate::exceptions::MatrixError;
+use crate::matrix::broadcast::StridedPositions;
+use crate::matrix::element::Element;
+use crate::matrix::tensor::{contiguous_strides, strided_copy, Tensor};
+
+/// A borrowed window onto a tensor's data, with its own shape and strides.
+///
+/// Views are made without copying anything, by changing how the same elements are indexed.
+/// Call `contiguous` to copy the elements into a new tensor in row-major order.
+#[derive(Debug, Clone)]
+pub struct TensorView<'a, T: Element = f32> {
+    data: &'a [T],
+    offset: usize,
+    shape: Vec<usize>,
+    strides: Vec<usize>,
+}
+
+impl<'a, T: Element> TensorView<'a, T> {
+    pub(crate) fn new(data: &'a [T], shape: &[usize], strides: &[usize]) -> Self {
+        TensorView {
+            data,
+            offset: 0,
+            shape: shape.to_vec(),
+            strides: strides.to_vec(),
+        }
+    }
+
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    pub fn strides(&self) -> &[usize] {
+        &self.strides
+    }
+
+    pub fn ndim(&self) -> usize {
+        self.shape.len()
+    }
+
+    pub fn numel(&self) -> usize {
+        self.shape.iter().product()
+    }
+
+    /// Whether the elements are laid out in row-major order with no gaps.
+    pub fn is_contiguous(&self) -> bool {
+        // Strides along dimensions of size 1 never move, so they don't matter
+        self.shape
+            .iter()
+            .zip(self.strides.iter().zip(contiguous_strides(&self.shape)))
+            .all(|(dim, (stride, expected))| *dim == 1 || *stride == expected)
+    }
+
+    pub fn get(&self, index: &[usize]) -> Result<T> {
+        if index.len() != self.ndim() || index.iter().zip(&self.shape).any(|(i, dim)| i >= dim) {
+            Err(MatrixError::IndexOutOfBounds {
+                index: index.to_vec(),
+                shape: self.shape.clone(),
+            })?;
+        }
+        let position: usize = index.iter().zip(&self.strides).map(|(i, s)| i * s).sum();
+        Ok(self.data[self.offset + position])
+    }
+
+    /// The elements in row-major order.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        StridedPositions::new(&self.shape, &self.strides, self.offset, 0)
+            .map(|position| self.data[position])
+    }
+
+    /// Copy the elements into a new tensor, in row-major order.
+    pub fn contiguous(&self) -> Tensor<T> {
+        let data = if self.is_contiguous() {
+            self.data[self.offset..self.offset + self.numel()].to_vec()
+        } else {
+            strided_copy(self.data, self.offset, &self.shape, &self.strides)
+        };
+        Tensor::from_vec(data, &self.shape).expect("The data has an element for every index")
+    }
+
+    /// Reorder the dimensions, so that dimension `i` of the result is `dims[i]` of this view.
+    pub fn permute(&self, dims: &[usize]) -> Result<TensorView<'a, T>> {
+        let mut sorted = dims.to_vec();
+        sorted.sort_unstable();
+        if sorted != (0..self.ndim()).collect::<Vec<usize>>() {
+            Err(MatrixError::MatrixError(format!(
+                "{dims:?} is not a permutation of the dimensions of a tensor of shape {:?}",
+                self.shape
+            )))?;
+        }
+        Ok(TensorView {
+            data: self.data,
+            offset: self.offset,
+            shape: dims.iter().map(|dim| self.shape[*dim]).collect(),
+            strides: dims.iter().map(|dim| self.strides[*dim]).collect(),
+        })
+    }
+
+    pub fn transpose(&self, dim0: usize, dim1: usize) -> Result<TensorView<'a, T>> {
+        self.check_dim(dim0)?;
+        self.check_dim(dim1)?;
+        let mut dims: Vec<usize> = (0..self.ndim()).collect();
+        dims.swap(dim0, dim1);
+        self.permute(&dims)
+    }
+
+    /// Give the same elements a new shape with the same number of elements. Only contiguous
+    /// views can be reshaped without copying, so call `contiguous` first otherwise.
+    pub fn reshape(&self, shape: &[usize]) -> Result<TensorView<'a, T>> {
+        if shape.iter().product::<usize>() != self.numel() {
+            Err(MatrixError::ShapeMismatch {
+                expected: shape.to_vec(),
+                actual: self.shape.clone(),
+            })?;
+        }
+        if !self.is_contiguous() {
+            Err(MatrixError::MatrixError(format!(
+                "A view of shape {:?} with strides {:?} is not contiguous, so it can't be reshaped without copying",
+                self.shape, self.strides
+            )))?;
+        }
+        Ok(TensorView {
+            data: self.data,
+            offset: self.offset,
+            shape: shape.to_vec(),
+            strides: contiguous_strides(shape),
+        })
+    }
+
+    /// Add a dimension of size 1 at `dim`.
+    pub fn unsqueeze(&self, dim: usize) -> Result<TensorView<'a, T>> {
+        if dim > self.ndim() {
+            Err(MatrixError::MatrixError(format!(
+                "Can't insert dimension {dim} into a tensor of shape {:?}",
+                self.shape
+            )))?;
+        }
+        let mut view = self.clone();
+        view.shape.insert(dim, 1);
+        view.strides.insert(dim, 0);
+        Ok(view)
+    }
+
+    /// The `length` elements along `dim` starting at `start`.
+    pub fn narrow(&self, dim: usize, start: usize, length: usize) -> Result<TensorView<'a, T>> {
+        self.slice(dim, start..start + length, 1)
+    }
+
+    /// Every `step`th element along `dim` in the range.
+    pub fn slice(&self, dim: usize, range: Range<usize>, step: usize) -> Result<TensorView<'a, T>> {
+        self.check_dim(dim)?;
+        if range.start > range.end || range.end > self.shape[dim] || step == 0 {
+            Err(MatrixError::MatrixError(format!(
+                "Can't take the range {range:?} with step {step} of dimension {dim} of a tensor of shape {:?}",
+                self.shape
+            )))?;
+        }
+        let mut view = self.clone();
+        view.shape[dim] = range.len().div_ceil(step);
+        // An empty view has nothing to point at, so leave the offset where it is
+        if view.numel() > 0 {
+            view.offset += range.start * self.strides[dim];
+        }
+        view.strides[dim] *= step;
+        Ok(view)
+    }
+
+    /// Split `dim` into pieces of `size` elements. The last piece is smaller if `size` doesn't
+    /// divide the dimension.
+    pub fn split(&self, dim: usize, size: usize) -> Result<Vec<TensorView<'a, T>>> {
+        self.check_dim(dim)?;
+        if size == 0 {
+            Err(MatrixError::MatrixError(String::from(
+                "Can't split a tensor into pieces of size 0",
+            )))?;
+        }
+        (0..self.shape[dim])
+            .step_by(size)
+            .map(|start| self.narrow(dim, start, size.min(self.shape[dim] - start)))
+            .collect()
+    }
+
+    fn check_dim(&self, dim: usize) -> Result<()> {
+        if dim >= self.ndim() {
+            Err(MatrixError::MatrixError(format!(
+                "Dimension {dim} is out of range for a tensor of shape {:?}",
+                self.shape
+            )))?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, T: Element> From<&'a Tensor<T>> for TensorView<'a, T> {
+    fn from(tensor: &'a Tensor<T>) -> Self {
+        tensor.view()
+    }
+}
+
+impl<T: Element> PartialEq for TensorView<'_, T> {
+    /// Views are equal if they have the same shape and elements, however they are laid out.
+    fn eq(&self, other: &Self) -> bool {
+        self.shape == other.shape && self.iter().eq(other.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arange(shape: &[usize]) -> Tensor {
+        let numel = shape.iter().product::<usize>();
+        Tensor::from_vec((0..numel).map(|i| i as f32).collect(), shape).unwrap()
+    }
+
+    #[test]
+    fn test_permute() {
+        let tensor = arange(&[2, 3, 4]);
+        let view = tensor.view().permute(&[2, 0, 1]).unwrap();
+        assert_eq!(view.shape(), &[4, 2, 3]);
+        assert_eq!(view.strides(), &[1, 12, 4]);
+        assert!(!view.is_contiguous());
+        assert_eq!(
+            view.get(&[3, 1, 2]).unwrap(),
+            tensor.get(&[1, 2, 3]).unwrap()
+        );
+
+        let copied = view.contiguous();
+        assert_eq!(copied.shape(), &[4, 2, 3]);
+        assert_eq!(copied.view(), view);
+        assert_eq!(copied.view().permute(&[1, 2, 0]).unwrap(), tensor.view());
+
+        assert_eq!(
+            tensor.view().transpose(0, 2).unwrap().contiguous(),
+            tensor.transpose(0, 2).unwrap()
+        );
+        assert!(tensor.view().permute(&[0, 1]).is_err());
+        assert!(tensor.view().permute(&[0, 1, 1]).is_err());
+    }
+
+    #[test]
+    fn test_reshape() {
+        let tensor = arange(&[2, 3, 4]);
+        let view = tensor.view().reshape(&[6, 4]).unwrap();
+        assert!(view.is_contiguous());
+        assert_eq!(view.get(&[5, 3]).unwrap(), 23.);
+        assert_eq!(
+            tensor
+                .clone()
+                .reshape(&[4, 6])
+                .unwrap()
+                .get(&[3, 5])
+                .unwrap(),
+            23.
+        );
+
+        assert!(tensor.view().reshape(&[5, 5]).is_err());
+        assert!(tensor.clone().reshape(&[25]).is_err());
+        // A transposed view needs copying first
+        let transposed = tensor.view().transpose(1, 2).unwrap();
+        assert!(transposed.reshape(&[24]).is_err());
+        let flat = transposed.contiguous().reshape(&[24]).unwrap();
+        assert_eq!(flat.get(&[1]).unwrap(), 4.);
+
+        let unsqueezed = tensor.view().unsqueeze(1).unwrap();
+        assert_eq!(unsqueezed.shape(), &[2, 1, 3, 4]);
+        assert!(unsqueezed.is_contiguous());
+        assert!(tensor.view().unsqueeze(4).is_err());
+    }
+
+    #[test]
+    fn test_narrow_and_slice() {
+        let tensor = arange(&[4, 5]);
+        let columns = tensor.narrow(1, 1, 3).unwrap();
+        assert_eq!(columns.shape(), &[4, 3]);
+        assert!(!columns.is_contiguous());
+        assert_eq!(columns.get(&[2, 0]).unwrap(), 11.);
+        assert_eq!(
+            columns.contiguous().as_slice()[..6],
+            [1., 2., 3., 6., 7., 8.]
+        );
+
+        // Whole rows stay contiguous
+        let rows = tensor.narrow(0, 1, 2).unwrap();
+        assert!(rows.is_contiguous());
+        assert_eq!(rows.contiguous().as_slice()[0], 5.);
+
+        let every_other = tensor.slice(1, 0..5, 2).unwrap();
+        assert_eq!(every_other.shape(), &[4, 3]);
+        assert_eq!(every_other.get(&[1, 2]).unwrap(), 9.);
+        let odd_rows = tensor.slice(0, 1..4, 2).unwrap();
+        assert_eq!(odd_rows.shape(), &[2, 5]);
+        assert_eq!(odd_rows.get(&[1, 0]).unwrap(), 15.);
+
+        // Views of views compose
+        let corner = columns.narrow(0, 3, 1).unwrap().narrow(1, 2, 1).unwrap();
+        assert_eq!(corner.get(&[0, 0]).unwrap(), 18.);
+
+        let empty = tensor.narrow(0, 4, 0).unwrap();
+        assert_eq!(empty.numel(), 0);
+        assert_eq!(empty.contiguous().shape(), &[0, 5]);
+
+        assert!(tensor.narrow(0, 3, 2).is_err());
+        assert!(tensor.narrow(2, 0, 1).is_err());
+        assert!(tensor.slice(1, 0..2, 0).is_err());
+    }
+
+    #[test]
+    fn test_split() {
+        let tensor = arange(&[2, 7]);
+        let pieces = tensor.split(1, 3).unwrap();
+        assert_eq!(
+            pieces.iter().map(|p| p.shape()[1]).collect::<Vec<usize>>(),
+            vec![3, 3, 1]
+        );
+        assert_eq!(pieces[2].get(&[1, 0]).unwrap(), 13.);
+        let pieces: Vec<&TensorView> = pieces.iter().collect();
+        assert_eq!(Tensor::cat(&pieces, 1).unwrap(), tensor);
+        assert!(tensor.split(0, 0).is_err());
+    }
+
+    #[test]
+    fn test_cat_and_stack() {
+        let a = arange(&[2, 3]);
+        let b = arange(&[1, 3]).map(|x| -x);
+        let (a_view, b_view) = (a.view(), b.view());
+
+        let rows = Tensor::cat(&[&a_view, &b_view], 0).unwrap();
+        assert_eq!(rows.shape(), &[3, 3]);
+        assert_eq!(rows.as_slice(), &[0., 1., 2., 3., 4., 5., -0., -1., -2.]);
+
+        let c = arange(&[2, 2]);
+        let c_view = c.view();
+        let columns = Tensor::cat(&[&a_view, &c_view], 1).unwrap();
+        assert_eq!(columns.shape(), &[2, 5]);
+        assert_eq!(
+            columns.as_slice(),
+            &[0., 1., 2., 0., 1., 3., 4., 5., 2., 3.]
+        );
+
+        // Non-contiguous views can be joined too
+        let a_t = a.view().transpose(0, 1).unwrap();
+        let joined = Tensor::cat(&[&a_t, &a_t], 1).unwrap();
+        assert_eq!(joined.shape(), &[3, 4]);
+        assert_eq!(joined.narrow(1, 2, 2).unwrap(), a_t);
+
+        let stacked = Tensor::stack(&[&a_view, &a_view], 0).unwrap();
+        assert_eq!(stacked.shape(), &[2, 2, 3]);
+        let stacked = Tensor::stack(&[&a_view, &a_view], 2).unwrap();
+        assert_eq!(stacked.shape(), &[2, 3, 2]);
+        assert_eq!(stacked.get(&[1, 2, 1]).unwrap(), 5.);
+
+        assert!(Tensor::cat(&[&a_view, &b_view], 1).is_err());
+        assert!(Tensor::cat(&[&a_view, &c_view], 0).is_err());
+        assert!(Tensor::<f32>::cat(&[], 0).is_err());
+        assert!(Tensor::stack(&[&a_view, &b_view], 0).is_err());
+    }
+
+    #[test]
+    fn test_attention_heads() {
+        // Split [batch, sequence, heads * head_dim] into heads and back
+        let (batch, sequence, heads, head_dim) = (2, 3, 4, 5);
+        let tensor = arange(&[batch, sequence, heads * head_dim]);
+        let split = tensor
+            .view()
+            .reshape(&[batch, sequence, heads, head_dim])
+            .unwrap()
+            .permute(&[0, 2, 1, 3])
+            .unwrap()
+            .contiguous();
+        assert_eq!(split.shape(), &[batch, heads, sequence, head_dim]);
+        assert_eq!(
+            split.get(&[1, 2, 0, 4]).unwrap(),
+            tensor.get(&[1, 0, 2 * head_dim + 4]).unwrap()
+        );
+
+        let merged = split
+            .permute(&[0, 2, 1, 3])
+            .unwrap()
+            .contiguous()
+            .reshape(&[batch, sequence, heads * head_dim])
+            .unwrap();
+        assert_eq!(merged, tensor);
+    }
+}
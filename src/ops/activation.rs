@@ -0,0 +1,88 @@
+// Elementwise activation functions and their derivatives, for `Activation` and the variable
+// operations of the same names.
+//
+// GELU uses the tanh approximation from GPT-2, 0.5x(1 + tanh(√(2/π)(x + 0.044715x³))), rather
+// than the exact form with the error function, so checkpoints trained with it behave the same.
+use crate::matrix::element::Float;
+
+const SQRT_2_OVER_PI: f64 = 0.7978845608028654;
+const GELU_COEFFICIENT: f64 = 0.044715;
+
+/// max(0, x).
+pub fn relu<T: Float>(x: T) -> T {
+    if x < T::ZERO {
+        T::ZERO
+    } else {
+        x
+    }
+}
+
+/// 1 / (1 + e⁻ˣ), without overflowing for large negative x.
+pub fn sigmoid<T: Float>(x: T) -> T {
+    if x >= T::ZERO {
+        T::ONE / (T::ONE + (-x).exp())
+    } else {
+        let e = x.exp();
+        e / (T::ONE + e)
+    }
+}
+
+/// x σ(x), also known as swish.
+pub fn silu<T: Float>(x: T) -> T {
+    x * sigmoid(x)
+}
+
+pub fn gelu<T: Float>(x: T) -> T {
+    let half = T::from_f64(0.5);
+    half * x * (T::ONE + gelu_inner(x).tanh())
+}
+
+pub(crate) fn sigmoid_grad<T: Float>(x: T) -> T {
+    let s = sigmoid(x);
+    s * (T::ONE - s)
+}
+
+pub(crate) fn silu_grad<T: Float>(x: T) -> T {
+    let s = sigmoid(x);
+    s * (T::ONE + x * (T::ONE - s))
+}
+
+pub(crate) fn gelu_grad<T: Float>(x: T) -> T {
+    let half = T::from_f64(0.5);
+    let t = gelu_inner(x).tanh();
+    let inner_grad =
+        T::from_f64(SQRT_2_OVER_PI) * (T::ONE + T::from_f64(3. * GELU_COEFFICIENT) * x * x);
+    half * (T::ONE + t) + half * x * (T::ONE - t * t) * inner_grad
+}
+
+// √(2/π)(x + 0.044715x³)
+fn gelu_inner<T: Float>(x: T) -> T {
+    T::from_f64(SQRT_2_OVER_PI) * (x + T::from_f64(GELU_COEFFICIENT) * x * x * x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values() {
+        assert_eq!(relu(-2.), 0.);
+        assert_eq!(relu(3.), 3.);
+
+        assert_eq!(sigmoid(0.), 0.5);
+        assert!((sigmoid(2.) - 0.8807970779778823f64).abs() < 1e-12);
+        assert!((sigmoid(-2.) - 0.11920292202211755f64).abs() < 1e-12);
+        // No overflow far out in either direction
+        assert_eq!(sigmoid(-1000f32), 0.);
+        assert_eq!(sigmoid(1000f32), 1.);
+
+        assert!((silu(1.) - 0.7310585786300049f64).abs() < 1e-12);
+        assert_eq!(silu(-1000f32), 0.);
+
+        // Reference values from PyTorch's gelu(approximate="tanh")
+        assert_eq!(gelu(0.), 0.);
+        assert!((gelu(1.) - 0.8411919906082768f64).abs() < 1e-12);
+        assert!((gelu(-1.) + 0.15880800939172324f64).abs() < 1e-12);
+        assert_eq!(gelu(10f32), 10.);
+    }
+}
@@ -0,0 +1,379 @@
+// Loss functions over autograd variables, each reducing per-element losses with a `Reduction`.
+//
+// The classification losses take class scores along the last axis, so logits of shape
+// [batch, seq, vocab] pair with targets of shape [batch, seq]. Targets equal to the ignore
+// index, such as padding, contribute nothing and aren't counted by the mean; if every target is
+// ignored the mean is 0 / 0 = NaN, as in PyTorch.
+use anyhow::Result;
+
+use crate::autograd::variable::Var;
+use crate::exceptions::{LossError, MatrixError};
+use crate::matrix::element::Float;
+use crate::matrix::tensor::Tensor;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Reduction {
+    /// The mean over every element, or every target which isn't ignored.
+    #[default]
+    Mean,
+    Sum,
+    /// The per-element losses, with the shape of the targets.
+    None,
+}
+
+/// Settings for `CrossEntropy::loss`. The default is a plain mean cross-entropy.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CrossEntropy {
+    pub reduction: Reduction,
+    /// The weight ε moved from the target class onto the uniform distribution, so the target
+    /// distribution is (1 - ε) one_hot + ε / classes.
+    pub label_smoothing: f64,
+    pub ignore_index: Option<usize>,
+}
+
+impl CrossEntropy {
+    /// The cross-entropy between the softmax of `logits` and the target classes.
+    pub fn loss<T: Float>(&self, logits: &Var<T>, targets: &Tensor<usize>) -> Result<Var<T>> {
+        if !(0. ..=1.).contains(&self.label_smoothing) {
+            Err(LossError::InvalidLabelSmoothing(self.label_smoothing))?;
+        }
+        let log_probs = logits.log_softmax_axis(class_axis(logits, targets)?)?;
+        negative_log_likelihood(
+            &log_probs,
+            targets,
+            self.label_smoothing,
+            self.ignore_index,
+            self.reduction,
+        )
+    }
+}
+
+/// The mean cross-entropy from logits. See `CrossEntropy` for the other options.
+pub fn cross_entropy<T: Float>(logits: &Var<T>, targets: &Tensor<usize>) -> Result<Var<T>> {
+    CrossEntropy::default().loss(logits, targets)
+}
+
+/// The negative log-likelihood of the target classes, given log probabilities such as the
+/// output of `log_softmax_axis`.
+pub fn nll_loss<T: Float>(
+    log_probs: &Var<T>,
+    targets: &Tensor<usize>,
+    ignore_index: Option<usize>,
+    reduction: Reduction,
+) -> Result<Var<T>> {
+    class_axis(log_probs, targets)?;
+    negative_log_likelihood(log_probs, targets, 0., ignore_index, reduction)
+}
+
+/// The squared error between predictions and targets of the same shape.
+pub fn mse_loss<T: Float>(
+    predictions: &Var<T>,
+    targets: &Var<T>,
+    reduction: Reduction,
+) -> Result<Var<T>> {
+    expect_same_shape(predictions, targets)?;
+    let errors = predictions.sub(targets)?.powi(2);
+    Ok(reduce(errors, reduction, predictions.value().numel()))
+}
+
+/// The absolute error between predictions and targets of the same shape.
+pub fn l1_loss<T: Float>(
+    predictions: &Var<T>,
+    targets: &Var<T>,
+    reduction: Reduction,
+) -> Result<Var<T>> {
+    expect_same_shape(predictions, targets)?;
+    let errors = predictions.sub(targets)?.abs();
+    Ok(reduce(errors, reduction, predictions.value().numel()))
+}
+
+fn expect_same_shape<T: Float>(predictions: &Var<T>, targets: &Var<T>) -> Result<()> {
+    if predictions.shape() != targets.shape() {
+        Err(MatrixError::ShapeMismatch {
+            expected: predictions.shape(),
+            actual: targets.shape(),
+        })?;
+    }
+    Ok(())
+}
+
+// The last axis, checking the targets have the scores' shape without it
+fn class_axis<T: Float>(scores: &Var<T>, targets: &Tensor<usize>) -> Result<usize> {
+    let shape = scores.shape();
+    if shape.is_empty() || targets.shape() != &shape[..shape.len() - 1] {
+        Err(MatrixError::ShapeMismatch {
+            expected: shape[..shape.len().saturating_sub(1)].to_vec(),
+            actual: targets.shape().to_vec(),
+        })?;
+    }
+    Ok(shape.len() - 1)
+}
+
+fn negative_log_likelihood<T: Float>(
+    log_probs: &Var<T>,
+    targets: &Tensor<usize>,
+    label_smoothing: f64,
+    ignore_index: Option<usize>,
+    reduction: Reduction,
+) -> Result<Var<T>> {
+    let shape = log_probs.shape();
+    let axis = shape.len() - 1;
+    let classes = shape[axis];
+    let ignored = |target: usize| Some(target) == ignore_index;
+    if let Some(target) = targets.iter().find(|t| !ignored(**t) && **t >= classes) {
+        Err(LossError::TargetOutOfRange {
+            target: *target,
+            classes,
+        })?;
+    }
+
+    // Ignored targets point at class 0, then have their losses replaced with zero. Reading the
+    // target's log probability by index, and replacing rather than multiplying by zero, keeps
+    // masked logits of -inf from turning the loss into NaN.
+    let lanes = Tensor::from_vec(
+        targets
+            .iter()
+            .map(|t| if ignored(*t) { 0 } else { *t })
+            .collect(),
+        targets.shape(),
+    )?;
+    let mut log_likelihoods = log_probs.gather_axis(axis, &lanes)?;
+    if label_smoothing > 0. {
+        // (1 - ε) log p_target + ε / classes Σ log p
+        let uniform = log_probs
+            .sum_axis(axis, false)?
+            .scale(T::from_f64(label_smoothing / classes as f64));
+        log_likelihoods = log_likelihoods
+            .scale(T::from_f64(1. - label_smoothing))
+            .add(&uniform)?;
+    }
+    let mut losses = log_likelihoods.neg();
+    if ignore_index.is_some() {
+        let ignored_mask = Tensor::from_vec(
+            targets.iter().map(|t| ignored(*t) as u8).collect(),
+            targets.shape(),
+        )?;
+        losses = losses.masked_fill(&ignored_mask, T::ZERO)?;
+    }
+    let count = targets.iter().filter(|t| !ignored(**t)).count();
+    Ok(reduce(losses, reduction, count))
+}
+
+fn reduce<T: Float>(losses: Var<T>, reduction: Reduction, count: usize) -> Var<T> {
+    match reduction {
+        Reduction::Mean => losses.sum().scale(T::ONE / T::from_usize(count)),
+        Reduction::Sum => losses.sum(),
+        Reduction::None => losses,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_close;
+    use crate::autograd::gradcheck::GradCheck;
+    use crate::matrix::init::Init;
+    use crate::matrix::random::Rng;
+
+    fn random(shape: &[usize], seed: u64) -> Tensor<f64> {
+        Init::Normal { mean: 0., std: 1. }.tensor(shape, &mut Rng::new(seed))
+    }
+
+    fn targets(data: &[usize], shape: &[usize]) -> Tensor<usize> {
+        Tensor::from_slice(data, shape).unwrap()
+    }
+
+    #[test]
+    fn test_cross_entropy() {
+        let logits = Tensor::from_slice(&[1f64, 2., 3., 0., 0., 0.], &[2, 3]).unwrap();
+        let labels = targets(&[2, 0], &[2]);
+        let log_probs = crate::ops::softmax::log_softmax_axis(&logits, 1).unwrap();
+        let expected = [-log_probs.get(&[0, 2]).unwrap(), 3f64.ln()];
+
+        let loss = CrossEntropy {
+            reduction: Reduction::None,
+            ..Default::default()
+        };
+        let losses = loss.loss(&Var::constant(logits.clone()), &labels).unwrap();
+        assert_close!(
+            *losses.value(),
+            Tensor::from_slice(&expected, &[2]).unwrap()
+        );
+        let mean = cross_entropy(&Var::constant(logits.clone()), &labels).unwrap();
+        assert_close!(
+            *mean.value(),
+            Tensor::scalar((expected[0] + expected[1]) / 2.)
+        );
+
+        // With full smoothing the target is uniform whatever the labels
+        let smoothed = CrossEntropy {
+            label_smoothing: 1.,
+            reduction: Reduction::Sum,
+            ..Default::default()
+        };
+        let uniform = smoothed
+            .loss(&Var::constant(logits.clone()), &labels)
+            .unwrap();
+        let expected = -log_probs.sum() / 3.;
+        assert_close!(*uniform.value(), Tensor::scalar(expected));
+
+        // The gradient of the mean is (softmax - one_hot) / batch
+        let logits = Var::parameter(logits);
+        cross_entropy(&logits, &labels).unwrap().backward().unwrap();
+        let mut expected = log_probs.map(f64::exp);
+        for (row, label) in labels.iter().enumerate() {
+            let p = expected.get(&[row, *label]).unwrap();
+            expected.set(&[row, *label], p - 1.).unwrap();
+        }
+        assert_close!(logits.grad().unwrap(), expected.map(|g| g / 2.));
+
+        // Masked logits have zero probability without making the loss NaN
+        let masked =
+            Var::parameter(Tensor::from_slice(&[1., 2., f64::NEG_INFINITY], &[1, 3]).unwrap());
+        let loss = cross_entropy(&masked, &targets(&[0], &[1])).unwrap();
+        assert_close!(
+            *loss.value(),
+            Tensor::scalar((1f64.exp() + 2f64.exp()).ln() - 1.)
+        );
+        loss.backward().unwrap();
+        let p = 1. / (1. + 1f64.exp());
+        assert_close!(
+            masked.grad().unwrap(),
+            Tensor::from_slice(&[p - 1., 1. - p, 0.], &[1, 3]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_ignore_index() {
+        let logits = random(&[2, 3, 5], 0);
+        let labels = targets(&[1, 4, 0, 0, 3, 0], &[2, 3]);
+        let loss = CrossEntropy {
+            ignore_index: Some(0),
+            reduction: Reduction::None,
+            ..Default::default()
+        };
+        let logits = Var::parameter(logits);
+        let losses = loss.loss(&logits, &labels).unwrap();
+        assert_eq!(losses.shape(), [2, 3]);
+        assert_eq!(losses.value().get(&[0, 2]).unwrap(), 0.);
+
+        // The mean is over the three targets which aren't padding
+        let mean = CrossEntropy {
+            ignore_index: Some(0),
+            ..Default::default()
+        };
+        let total = losses.value().sum();
+        mean.loss(&logits, &labels).unwrap().backward().unwrap();
+        assert_close!(
+            *mean.loss(&logits, &labels).unwrap().value(),
+            Tensor::scalar(total / 3.)
+        );
+        // And ignored positions get no gradient
+        let grad = logits.grad().unwrap();
+        assert!(grad
+            .narrow(1, 2, 1)
+            .unwrap()
+            .contiguous()
+            .iter()
+            .all(|g| *g == 0.));
+
+        // Masked logits, even at the class ignored rows point at, and NaNs in ignored rows
+        // don't reach the loss
+        let masked = Tensor::from_slice(
+            &[
+                0.,
+                1.,
+                f64::NEG_INFINITY,
+                f64::NEG_INFINITY,
+                1.,
+                2.,
+                f64::NAN,
+                0.,
+                0.,
+            ],
+            &[3, 3],
+        )
+        .unwrap();
+        let masked = Var::parameter(masked);
+        let loss = mean.loss(&masked, &targets(&[1, 0, 0], &[3])).unwrap();
+        assert_close!(*loss.value(), Tensor::scalar((1. + 1f64.exp()).ln() - 1.));
+        loss.backward().unwrap();
+        let grad = masked.grad().unwrap();
+        assert!(grad.as_slice()[..6].iter().all(|g| g.is_finite()));
+        assert!(grad.as_slice()[3..6].iter().all(|g| *g == 0.));
+
+        let all_padding = targets(&[0; 6], &[2, 3]);
+        assert!(mean.loss(&logits, &all_padding).unwrap().value().as_slice()[0].is_nan());
+    }
+
+    #[test]
+    fn test_regression() {
+        let predictions = Tensor::from_slice(&[1f32, 2., 3., 4.], &[2, 2]).unwrap();
+        let targets = Var::constant(Tensor::from_slice(&[1.5, 2., 1., 5.], &[2, 2]).unwrap());
+        let predictions = Var::parameter(predictions);
+
+        let mse = mse_loss(&predictions, &targets, Reduction::None).unwrap();
+        assert_eq!(mse.value().as_slice(), &[0.25, 0., 4., 1.]);
+        let mse = mse_loss(&predictions, &targets, Reduction::Mean).unwrap();
+        assert_eq!(mse.value().as_slice(), &[1.3125]);
+        mse.backward().unwrap();
+        assert_eq!(
+            predictions.grad().unwrap().as_slice(),
+            &[-0.25, 0., 1., -0.5]
+        );
+
+        predictions.zero_grad();
+        let l1 = l1_loss(&predictions, &targets, Reduction::Sum).unwrap();
+        assert_eq!(l1.value().as_slice(), &[3.5]);
+        l1.backward().unwrap();
+        assert_eq!(predictions.grad().unwrap().as_slice(), &[-1., 0., 1., -1.]);
+    }
+
+    #[test]
+    fn test_gradients() {
+        let labels = targets(&[3, 0, 4, 1, 2, 4], &[2, 3]);
+        let checker = GradCheck::default();
+        for reduction in [Reduction::Mean, Reduction::Sum, Reduction::None] {
+            for (label_smoothing, ignore_index) in [(0., None), (0.1, Some(4)), (1., Some(0))] {
+                let loss = CrossEntropy {
+                    reduction,
+                    label_smoothing,
+                    ignore_index,
+                };
+                checker.assert_passes(|x| loss.loss(&x[0], &labels), &[random(&[2, 3, 5], 1)]);
+            }
+            checker.assert_passes(
+                |x| nll_loss(&x[0].log_softmax_axis(2)?, &labels, Some(1), reduction),
+                &[random(&[2, 3, 5], 2)],
+            );
+            for loss in [mse_loss, l1_loss] {
+                checker.assert_passes(
+                    |x| loss(&x[0], &x[1], reduction),
+                    &[random(&[4, 3], 3), random(&[4, 3], 4)],
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let logits = Var::constant(Tensor::<f32>::zeros(&[2, 3]));
+        assert!(cross_entropy(&logits, &targets(&[0, 3], &[2])).is_err());
+        assert!(cross_entropy(&logits, &targets(&[0, 1, 2], &[3])).is_err());
+        assert!(cross_entropy(&Var::constant(Tensor::scalar(0f32)), &targets(&[0], &[])).is_err());
+        let ignoring = CrossEntropy {
+            ignore_index: Some(3),
+            ..Default::default()
+        };
+        assert!(ignoring.loss(&logits, &targets(&[0, 3], &[2])).is_ok());
+        let oversmoothed = CrossEntropy {
+            label_smoothing: 1.5,
+            ..Default::default()
+        };
+        assert!(oversmoothed.loss(&logits, &targets(&[0, 1], &[2])).is_err());
+
+        let other = Var::constant(Tensor::zeros(&[3, 2]));
+        assert!(mse_loss(&logits, &other, Reduction::Mean).is_err());
+        assert!(l1_loss(&logits, &other, Reduction::Mean).is_err());
+    }
+}
@@ -1 +1,3 @@
-pub(crate) mod relu;
+pub mod activation;
+pub mod loss;
+pub mod softmax;
@@ -0,0 +1,108 @@
+use anyhow::Result;
+
+use crate::matrix::element::Float;
+use crate::matrix::tensor::Tensor;
+use crate::matrix::vector::FloatVector;
+
+// Both are computed relative to the largest element, so no exponential can overflow and at
+// least one term of the normalising sum is exactly 1.
+
+pub fn softmax<const SIZE: usize, T: Float>(state: FloatVector<SIZE, T>) -> FloatVector<SIZE, T> {
+    let max = state.max();
+    let exps = state.map(|element| (element - max).exp());
+    &exps / exps.sum()
+}
+
+pub fn log_softmax<const SIZE: usize, T: Float>(
+    state: FloatVector<SIZE, T>,
+) -> FloatVector<SIZE, T> {
+    let logsumexp = state.logsumexp();
+    state.map(|element| element - logsumexp)
+}
+
+/// The softmax over `axis`, so that the elements along it sum to 1.
+pub fn softmax_axis<T: Float>(state: &Tensor<T>, axis: usize) -> Result<Tensor<T>> {
+    let max = state.max_axis(axis, true)?;
+    let mut exps = state.sub(&max)?;
+    exps.map_inplace(T::exp);
+    let sums = exps.sum_axis(axis, true)?;
+    exps.div_inplace(&sums)?;
+    Ok(exps)
+}
+
+pub fn log_softmax_axis<T: Float>(state: &Tensor<T>, axis: usize) -> Result<Tensor<T>> {
+    state.sub(&state.logsumexp_axis(axis, true)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_close;
+
+    #[test]
+    fn test_softmax() {
+        let state = FloatVector::from_elements([1., 2., 3.]);
+        let total: f32 = [1f32, 2., 3.].iter().map(|x| x.exp()).sum();
+        let expected = state.map(|x| x.exp() / total);
+        assert_close!(softmax(state.clone()), expected, atol = 1e-6);
+        assert_close!(log_softmax(state), expected.map(f32::ln), atol = 1e-6);
+
+        let tensor = Tensor::from_slice(&[1., 2., 3., 0., 0., 0.], &[2, 3]).unwrap();
+        let rows = softmax_axis(&tensor, 1).unwrap();
+        assert_close!(
+            rows.narrow(0, 0, 1).unwrap(),
+            Tensor::from(&expected).reshape(&[1, 3]).unwrap(),
+            atol = 1e-6
+        );
+        assert_close!(
+            rows.narrow(0, 1, 1).unwrap(),
+            Tensor::full(&[1, 3], 1. / 3.),
+            atol = 1e-6
+        );
+        let columns = softmax_axis(&tensor, 0).unwrap();
+        assert_close!(
+            columns.sum_axis(0, false).unwrap(),
+            Tensor::ones(&[3]),
+            atol = 1e-6
+        );
+        assert_close!(
+            log_softmax_axis(&tensor, 1).unwrap(),
+            rows.map(f32::ln),
+            atol = 1e-6
+        );
+        assert!(softmax_axis(&tensor, 2).is_err());
+    }
+
+    #[test]
+    fn test_large_magnitudes() {
+        // A naive exp(x) / Σ exp(x) gives inf / inf = NaN for these
+        let state = FloatVector::from_elements([1000., 1001., 1002.]);
+        let shifted = softmax(FloatVector::from_elements([0., 1., 2.]));
+        assert_close!(softmax(state.clone()), shifted, atol = 1e-6);
+        // Floats near 1000 are 6e-5 apart, which bounds how close the log probabilities can be
+        assert_close!(
+            log_softmax(state),
+            log_softmax(FloatVector::from_elements([0., 1., 2.])),
+            atol = 1e-4
+        );
+
+        // And 0 / 0 = NaN for these, as every exp(x) underflows
+        let state = FloatVector::from_elements([-1000., -1001., -1002.]);
+        let reversed: FloatVector<3> = shifted.iter().rev().copied().collect();
+        assert_close!(softmax(state), reversed, atol = 1e-6);
+
+        // Widely spread inputs give exact zeros and ones, and finite log probabilities
+        let tensor = Tensor::from_slice(&[-1e30, 0., 1e30, 88., 89., -104.], &[2, 3]).unwrap();
+        let probs = softmax_axis(&tensor, 1).unwrap();
+        assert!(probs.iter().all(|p| p.is_finite()));
+        assert_eq!(&probs.as_slice()[..3], &[0., 0., 1.]);
+        let log_probs = log_softmax_axis(&tensor, 1).unwrap();
+        assert!(log_probs.iter().all(|p| p.is_finite()));
+        assert_eq!(log_probs.get(&[0, 2]).unwrap(), 0.);
+        assert_close!(
+            probs.sum_axis(1, false).unwrap(),
+            Tensor::ones(&[2]),
+            atol = 1e-6
+        );
+    }
+}
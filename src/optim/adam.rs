@@ -0,0 +1,384 @@
+// Adam and AdamW.
+//
+// Adam keeps exponential moving averages of each gradient m ← β₁m + (1 - β₁)g and of its square
+// v ← β₂v + (1 - β₂)g², corrects them for starting at zero, and moves the parameter by
+// lr m̂ / (√v̂ + ε), so every element takes steps of about the learning rate whatever the scale
+// of its gradients. Each parameter counts its own steps for the correction, as one which first
+// gets a gradient late in training starts from zero averages all the same.
+//
+// That normalisation also shrinks weight decay added to the gradient, so AdamW decouples it,
+// scaling the parameter by 1 - lr λ directly before the update.
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+
+use crate::exceptions::OptimizerError;
+use crate::matrix::element::Float;
+use crate::matrix::tensor::Tensor;
+use crate::optim::optimizer::{check_unique, load_buffers, Optimizer, OptimizerState, ParamGroup};
+
+const EXP_AVG: &str = "exp_avg";
+const EXP_AVG_SQ: &str = "exp_avg_sq";
+const STEP: &str = "step";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdamConfig {
+    pub beta1: f64,
+    pub beta2: f64,
+    pub eps: f64,
+    /// Apply weight decay to the parameters directly, as AdamW, rather than to the gradients.
+    pub decoupled_weight_decay: bool,
+}
+
+impl Default for AdamConfig {
+    fn default() -> Self {
+        AdamConfig {
+            beta1: 0.9,
+            beta2: 0.999,
+            eps: 1e-8,
+            decoupled_weight_decay: false,
+        }
+    }
+}
+
+impl AdamConfig {
+    /// The defaults with decoupled weight decay.
+    pub fn adamw() -> Self {
+        AdamConfig {
+            decoupled_weight_decay: true,
+            ..Default::default()
+        }
+    }
+}
+
+// The moving averages of a parameter's gradient and squared gradient, and how many steps they
+// have been updated for
+struct Moments<T: Float> {
+    exp_avg: Tensor<T>,
+    exp_avg_sq: Tensor<T>,
+    step: u64,
+}
+
+pub struct Adam<T: Float = f32> {
+    groups: Vec<ParamGroup<T>>,
+    config: AdamConfig,
+    moments: HashMap<String, Moments<T>>,
+    step: u64,
+}
+
+impl<T: Float> Adam<T> {
+    pub fn new(groups: Vec<ParamGroup<T>>, config: AdamConfig) -> Result<Self> {
+        check_unique(&groups)?;
+        for beta in [config.beta1, config.beta2] {
+            if !(0. ..1.).contains(&beta) {
+                Err(OptimizerError::InvalidHyperparameter(format!(
+                    "betas must be in [0, 1), got {beta}"
+                )))?;
+            }
+        }
+        Ok(Adam {
+            groups,
+            config,
+            moments: HashMap::new(),
+            step: 0,
+        })
+    }
+
+    /// AdamW with the default betas and epsilon.
+    pub fn adamw(groups: Vec<ParamGroup<T>>) -> Result<Self> {
+        Adam::new(groups, AdamConfig::adamw())
+    }
+}
+
+impl<T: Float> Optimizer<T> for Adam<T> {
+    fn step(&mut self) -> Result<()> {
+        self.step += 1;
+        let AdamConfig {
+            beta1,
+            beta2,
+            eps,
+            decoupled_weight_decay,
+        } = self.config;
+        for group in &self.groups {
+            let lr = T::from_f64(group.lr);
+            let weight_decay = T::from_f64(group.weight_decay);
+            for (name, param) in &group.params {
+                let Some(mut grad) = param.grad() else {
+                    continue;
+                };
+                if weight_decay != T::ZERO {
+                    if decoupled_weight_decay {
+                        param.update_value(|value| {
+                            value.map_inplace(|p| p * (T::ONE - lr * weight_decay))
+                        });
+                    } else {
+                        grad = grad.zip_map(&param.value(), |g, p| g + weight_decay * p)?;
+                    }
+                }
+                let moments = self.moments.entry(name.clone()).or_insert_with(|| Moments {
+                    exp_avg: Tensor::zeros(grad.shape()),
+                    exp_avg_sq: Tensor::zeros(grad.shape()),
+                    step: 0,
+                });
+                moments.step += 1;
+                // Dividing the averages by these removes their bias towards zero
+                let correction1 = 1. - beta1.powi(moments.step as i32);
+                let correction2 = 1. - beta2.powi(moments.step as i32);
+                let step_size = T::from_f64(group.lr / correction1);
+                let sqrt_correction2 = T::from_f64(correction2.sqrt());
+                let (beta1, beta2, eps) =
+                    (T::from_f64(beta1), T::from_f64(beta2), T::from_f64(eps));
+
+                moments.exp_avg = moments
+                    .exp_avg
+                    .zip_map(&grad, |m, g| beta1 * m + (T::ONE - beta1) * g)?;
+                moments.exp_avg_sq = moments
+                    .exp_avg_sq
+                    .zip_map(&grad, |v, g| beta2 * v + (T::ONE - beta2) * g * g)?;
+
+                let update = moments.exp_avg.zip_map(&moments.exp_avg_sq, |m, v| {
+                    step_size * m / (v.sqrt() / sqrt_correction2 + eps)
+                })?;
+                param.update_value(|value| value.sub_inplace(&update))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn param_groups(&self) -> &[ParamGroup<T>] {
+        &self.groups
+    }
+
+    fn param_groups_mut(&mut self) -> &mut [ParamGroup<T>] {
+        &mut self.groups
+    }
+
+    fn state(&self) -> OptimizerState<T> {
+        let mut buffers = BTreeMap::new();
+        for (name, moments) in &self.moments {
+            buffers.insert(format!("{name}.{EXP_AVG}"), moments.exp_avg.clone());
+            buffers.insert(format!("{name}.{EXP_AVG_SQ}"), moments.exp_avg_sq.clone());
+            // Exact in f32 up to 2^24 steps
+            buffers.insert(
+                format!("{name}.{STEP}"),
+                Tensor::scalar(T::from_f64(moments.step as f64)),
+            );
+        }
+        OptimizerState {
+            step: self.step,
+            buffers,
+        }
+    }
+
+    fn load_state(&mut self, state: &OptimizerState<T>) -> Result<()> {
+        let [mut exp_avgs, mut exp_avg_sqs, mut steps] =
+            load_buffers(&self.groups, state, &[EXP_AVG, EXP_AVG_SQ], &[STEP])?
+                .try_into()
+                .unwrap();
+        let mut moments = HashMap::new();
+        for (name, exp_avg) in exp_avgs.drain() {
+            let missing = |kind| OptimizerError::MissingBuffer(format!("{name}.{kind}"));
+            let exp_avg_sq = exp_avg_sqs
+                .remove(&name)
+                .ok_or_else(|| missing(EXP_AVG_SQ))?;
+            let step = steps.remove(&name).ok_or_else(|| missing(STEP))?;
+            moments.insert(
+                name,
+                Moments {
+                    exp_avg,
+                    exp_avg_sq,
+                    step: step.as_slice()[0].to_f64() as u64,
+                },
+            );
+        }
+        if let Some(name) = exp_avg_sqs.keys().chain(steps.keys()).next() {
+            Err(OptimizerError::MissingBuffer(format!("{name}.{EXP_AVG}")))?;
+        }
+        self.moments = moments;
+        self.step = state.step;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_close;
+    use crate::autograd::variable::Var;
+    use crate::matrix::init::Init;
+    use crate::matrix::random::Rng;
+    use crate::ops::loss::cross_entropy;
+    use crate::optim::optimizer::decay_groups;
+
+    // Take steps minimising Σ (mask ⊙ p)², returning the parameter after each
+    fn descend(optimizer: &mut Adam, param: &Var, mask: &[f32], steps: usize) -> Vec<Tensor> {
+        let mask = Var::constant(Tensor::from_slice(mask, &[mask.len()]).unwrap());
+        (0..steps)
+            .map(|_| {
+                optimizer.zero_grad();
+                param.mul(&mask).unwrap().powi(2).sum().backward().unwrap();
+                optimizer.step().unwrap();
+                param.value().clone()
+            })
+            .collect()
+    }
+
+    fn optimizer(param: &Var, weight_decay: f64, config: AdamConfig) -> Adam {
+        let group = ParamGroup::new(vec![("p".to_owned(), param.clone())], 0.1)
+            .with_weight_decay(weight_decay);
+        Adam::new(vec![group], config).unwrap()
+    }
+
+    fn column(steps: &[Tensor], element: usize) -> Tensor {
+        let values = steps.iter().map(|step| step.as_slice()[element]).collect();
+        Tensor::from_vec(values, &[steps.len()]).unwrap()
+    }
+
+    #[test]
+    fn test_adam() {
+        // Reference values from the update rule in f64
+        let p = Var::parameter(Tensor::ones(&[2]));
+        let mut adam = optimizer(&p, 0.5, AdamConfig::default());
+        let steps = descend(&mut adam, &p, &[1., 0.], 3);
+        assert_close!(
+            column(&steps, 0),
+            Tensor::from_slice(&[0.9, 0.80041223, 0.70158627], &[3]).unwrap()
+        );
+        // The decay is the whole gradient of the second element, which Adam scales up to a
+        // full step
+        assert!((steps[0].as_slice()[1] - 0.9).abs() < 1e-6);
+
+        let p = Var::parameter(Tensor::ones(&[2]));
+        let mut adamw = optimizer(&p, 0.5, AdamConfig::adamw());
+        let steps = descend(&mut adamw, &p, &[1., 0.], 3);
+        assert_close!(
+            column(&steps, 0),
+            Tensor::from_slice(&[0.85, 0.70824844, 0.57497393], &[3]).unwrap()
+        );
+        // Whereas AdamW only shrinks it by 1 - lr λ each step
+        assert_close!(
+            column(&steps, 1),
+            Tensor::from_slice(&[0.95, 0.9025, 0.857375], &[3]).unwrap()
+        );
+
+        let bad_beta = AdamConfig {
+            beta1: 1.,
+            ..Default::default()
+        };
+        assert!(Adam::new(
+            vec![ParamGroup::new(vec![("p".to_owned(), p)], 0.1)],
+            bad_beta
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_late_parameter() {
+        // A parameter which first gets a gradient after others have taken steps is still
+        // corrected from its own first step, so moves by the full learning rate
+        let early = Var::parameter(Tensor::ones(&[1]));
+        let late = Var::parameter(Tensor::ones(&[1]));
+        let params = vec![
+            ("early".to_owned(), early.clone()),
+            ("late".to_owned(), late.clone()),
+        ];
+        let mut adam =
+            Adam::new(vec![ParamGroup::new(params, 0.1)], AdamConfig::default()).unwrap();
+        for _ in 0..5 {
+            adam.zero_grad();
+            early.powi(2).sum().backward().unwrap();
+            adam.step().unwrap();
+        }
+        adam.zero_grad();
+        late.powi(2).sum().backward().unwrap();
+        adam.step().unwrap();
+        assert_close!(
+            late.value().clone(),
+            Tensor::from_slice(&[0.9], &[1]).unwrap()
+        );
+
+        let state = adam.state();
+        assert_eq!(state.step, 6);
+        assert_eq!(state.buffers["early.step"], Tensor::scalar(5.));
+        assert_eq!(state.buffers["late.step"], Tensor::scalar(1.));
+    }
+
+    #[test]
+    fn test_resume() {
+        let p = Var::parameter(Tensor::from_slice(&[1., -2.], &[2]).unwrap());
+        let mut adam = optimizer(&p, 0.01, AdamConfig::adamw());
+        descend(&mut adam, &p, &[1., 1.], 3);
+        let path =
+            std::env::temp_dir().join(format!("adam_state_{}.safetensors", std::process::id()));
+        adam.state().save(&path).unwrap();
+        let expected = descend(&mut adam, &p, &[1., 1.], 2);
+
+        let resumed_p = Var::parameter(Tensor::from_slice(&[1., -2.], &[2]).unwrap());
+        descend(
+            &mut optimizer(&resumed_p, 0.01, AdamConfig::adamw()),
+            &resumed_p,
+            &[1., 1.],
+            3,
+        );
+        let mut resumed = optimizer(&resumed_p, 0.01, AdamConfig::adamw());
+        let state = OptimizerState::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        resumed.load_state(&state).unwrap();
+        assert_eq!(descend(&mut resumed, &resumed_p, &[1., 1.], 2), expected);
+
+        for buffer in ["p.exp_avg_sq", "p.step"] {
+            let mut partial = state.clone();
+            partial.buffers.remove(buffer);
+            assert!(resumed.load_state(&partial).is_err());
+        }
+        // Steps are single values
+        let mut bad_step = state.clone();
+        bad_step.buffers.insert(
+            "p.step".to_owned(),
+            Tensor::from_slice(&[3., 3.], &[2]).unwrap(),
+        );
+        assert!(resumed.load_state(&bad_step).is_err());
+    }
+
+    #[test]
+    fn test_classifier() {
+        // Separate three Gaussian clusters with a linear classifier
+        let mut rng = Rng::new(0);
+        let centres: Tensor = Init::Normal { mean: 0., std: 3. }.tensor(&[3, 4], &mut rng);
+        let labels: Vec<usize> = (0..60).map(|i| i % 3).collect();
+        let noise: Tensor = Init::Normal { mean: 0., std: 0.5 }.tensor(&[60, 4], &mut rng);
+        let mut inputs = noise;
+        for (row, label) in labels.iter().enumerate() {
+            for column in 0..4 {
+                let value =
+                    inputs.get(&[row, column]).unwrap() + centres.get(&[*label, column]).unwrap();
+                inputs.set(&[row, column], value).unwrap();
+            }
+        }
+        let inputs = Var::constant(inputs);
+        let labels = Tensor::from_vec(labels, &[60]).unwrap();
+
+        let weights = Var::parameter(Tensor::zeros(&[3, 4]));
+        let bias = Var::parameter(Tensor::zeros(&[3]));
+        let params = vec![
+            ("weights".to_owned(), weights.clone()),
+            ("bias".to_owned(), bias.clone()),
+        ];
+        let mut adam = Adam::adamw(decay_groups(params, 0.05, 0.01)).unwrap();
+        let loss = |inputs: &Var| {
+            cross_entropy(
+                &inputs.matmul_nt(&weights).unwrap().add(&bias).unwrap(),
+                &labels,
+            )
+            .unwrap()
+        };
+        let initial = loss(&inputs).value().as_slice()[0];
+        for _ in 0..100 {
+            adam.zero_grad();
+            loss(&inputs).backward().unwrap();
+            adam.step().unwrap();
+        }
+        let last = loss(&inputs).value().as_slice()[0];
+        assert!(last < 0.1 * initial, "loss went from {initial} to {last}");
+    }
+}
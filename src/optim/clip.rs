@@ -0,0 +1,233 @@
+// Gradient clipping, and diagnostics for catching training as it diverges.
+//
+// Clipping by the global norm scales every gradient by the same factor, so the update keeps its
+// direction, whereas clipping by value clamps each element separately. Norms are computed in
+// f64, as a sum of squares over millions of f32 elements loses precision.
+//
+// A single NaN or infinite gradient is enough to ruin every parameter it reaches, so
+// `step_if_finite` checks for them first and throws the gradients away instead of stepping.
+use anyhow::Result;
+use log::warn;
+
+use crate::autograd::variable::Var;
+use crate::matrix::element::Float;
+use crate::optim::optimizer::Optimizer;
+
+/// The gradient norms of a set of parameters, as computed by `grad_stats`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GradStats {
+    /// The L2 norm of each parameter's gradient, in order. Parameters without a gradient are
+    /// left out.
+    pub norms: Vec<(String, f64)>,
+    /// The L2 norm of all the gradients together.
+    pub total_norm: f64,
+    /// The parameters whose gradients contain NaN or infinite elements.
+    pub non_finite: Vec<String>,
+}
+
+impl GradStats {
+    pub fn is_finite(&self) -> bool {
+        self.non_finite.is_empty()
+    }
+
+    /// The norms of each layer's gradients together, taking the layer of a parameter to be its
+    /// name up to the last dot, so `blocks.0.attn.weight` and `blocks.0.attn.bias` both belong
+    /// to `blocks.0.attn`. Layers are in the order their first parameter appears.
+    pub fn layer_norms(&self) -> Vec<(String, f64)> {
+        let mut layers: Vec<(String, f64)> = Vec::new();
+        for (name, norm) in &self.norms {
+            let layer = name
+                .rsplit_once('.')
+                .map_or(name.as_str(), |(layer, _)| layer);
+            match layers.iter_mut().find(|(existing, _)| existing == layer) {
+                Some((_, squares)) => *squares += norm * norm,
+                None => layers.push((layer.to_owned(), norm * norm)),
+            }
+        }
+        layers
+            .into_iter()
+            .map(|(layer, squares)| (layer, squares.sqrt()))
+            .collect()
+    }
+}
+
+/// Measure the gradients of the given parameters.
+pub fn grad_stats<T: Float>(params: &[(String, Var<T>)]) -> GradStats {
+    let mut stats = GradStats {
+        norms: Vec::new(),
+        total_norm: 0.,
+        non_finite: Vec::new(),
+    };
+    let mut total_squares = 0.;
+    for (name, param) in params {
+        let Some(grad) = param.grad() else {
+            continue;
+        };
+        let squares: f64 = grad.iter().map(|g| g.to_f64() * g.to_f64()).sum();
+        if grad.iter().any(|g| !g.to_f64().is_finite()) {
+            stats.non_finite.push(name.clone());
+        }
+        stats.norms.push((name.clone(), squares.sqrt()));
+        total_squares += squares;
+    }
+    stats.total_norm = total_squares.sqrt();
+    stats
+}
+
+/// Scale the gradients down so their global L2 norm is at most `max_norm`, returning the norm
+/// from before clipping. Gradients with a non-finite norm are left as they are, as there's no
+/// factor which would fix them.
+pub fn clip_grad_norm<T: Float>(params: &[(String, Var<T>)], max_norm: f64) -> f64 {
+    let total_norm = grad_stats(params).total_norm;
+    if total_norm.is_finite() && total_norm > max_norm {
+        // The small constant keeps the clipped norm just under the maximum
+        let factor = T::from_f64(max_norm / (total_norm + 1e-6));
+        for (_, param) in params {
+            if let Some(grad) = param.grad() {
+                param.set_grad(Some(grad.map(|g| g * factor)));
+            }
+        }
+    }
+    total_norm
+}
+
+/// Clamp every gradient element to [-clip_value, clip_value].
+pub fn clip_grad_value<T: Float>(params: &[(String, Var<T>)], clip_value: f64) {
+    let (low, high) = (T::from_f64(-clip_value), T::from_f64(clip_value));
+    for (_, param) in params {
+        if let Some(grad) = param.grad() {
+            let clamped = grad.map(|g| {
+                if g < low {
+                    low
+                } else if g > high {
+                    high
+                } else {
+                    g
+                }
+            });
+            param.set_grad(Some(clamped));
+        }
+    }
+}
+
+/// Step the optimizer if every gradient is finite. Otherwise clear the gradients and skip the
+/// step, logging which parameters were affected. Returns the statistics either way, so the
+/// caller can tell which happened with `is_finite`.
+pub fn step_if_finite<T: Float>(optimizer: &mut (impl Optimizer<T> + ?Sized)) -> Result<GradStats> {
+    let stats = grad_stats(&optimizer.named_parameters());
+    if stats.is_finite() {
+        optimizer.step()?;
+    } else {
+        warn!(
+            "Skipping the optimizer step, as the gradients of {:?} aren't finite",
+            stats.non_finite
+        );
+        optimizer.zero_grad();
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matrix::tensor::Tensor;
+    use crate::optim::optimizer::ParamGroup;
+    use crate::optim::sgd::{Sgd, SgdConfig};
+
+    // Parameters whose gradients are the given values
+    fn with_grads(grads: &[(&str, &[f32])]) -> Vec<(String, Var)> {
+        grads
+            .iter()
+            .map(|(name, grad)| {
+                let param = Var::parameter(Tensor::zeros(&[grad.len()]));
+                param.set_grad(Some(Tensor::from_slice(grad, &[grad.len()]).unwrap()));
+                (name.to_string(), param)
+            })
+            .collect()
+    }
+
+    fn grad(params: &[(String, Var)], index: usize) -> Vec<f32> {
+        params[index].1.grad().unwrap().into_vec()
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut params = with_grads(&[
+            ("blocks.0.weight", &[3., 4.]),
+            ("blocks.0.bias", &[12.]),
+            ("head.weight", &[0., 84.]),
+        ]);
+        params.push(("frozen".to_owned(), Var::parameter(Tensor::zeros(&[1]))));
+        let stats = grad_stats(&params);
+        assert_eq!(
+            stats.norms,
+            [
+                ("blocks.0.weight".to_owned(), 5.),
+                ("blocks.0.bias".to_owned(), 12.),
+                ("head.weight".to_owned(), 84.)
+            ]
+        );
+        assert_eq!(stats.total_norm, 85.);
+        assert_eq!(
+            stats.layer_norms(),
+            [("blocks.0".to_owned(), 13.), ("head".to_owned(), 84.)]
+        );
+        assert!(stats.is_finite());
+
+        let params = with_grads(&[
+            ("a", &[1., f32::NAN]),
+            ("b", &[1.]),
+            ("c", &[f32::INFINITY]),
+        ]);
+        let stats = grad_stats(&params);
+        assert_eq!(stats.non_finite, ["a", "c"]);
+        assert!(!stats.is_finite() && stats.total_norm.is_nan());
+    }
+
+    #[test]
+    fn test_clip_norm() {
+        let params = with_grads(&[("a", &[3., 4.]), ("b", &[0., 12.])]);
+        assert_eq!(clip_grad_norm(&params, 26.), 13.);
+        assert_eq!(grad(&params, 0), [3., 4.]);
+
+        assert_eq!(clip_grad_norm(&params, 1.3), 13.);
+        let clipped = grad_stats(&params);
+        assert!(clipped.total_norm <= 1.3 && clipped.total_norm > 1.3 - 1e-5);
+        // Every gradient is scaled by the same factor
+        let (a, b) = (grad(&params, 0), grad(&params, 1));
+        assert!((a[0] / 3. - b[1] / 12.).abs() < 1e-7);
+
+        let params = with_grads(&[("a", &[f32::INFINITY, 1.])]);
+        assert_eq!(clip_grad_norm(&params, 1.), f64::INFINITY);
+        assert_eq!(grad(&params, 0), [f32::INFINITY, 1.]);
+    }
+
+    #[test]
+    fn test_clip_value() {
+        let params = with_grads(&[("a", &[-3., 0.5]), ("b", &[2., f32::NEG_INFINITY])]);
+        clip_grad_value(&params, 1.);
+        assert_eq!(grad(&params, 0), [-1., 0.5]);
+        assert_eq!(grad(&params, 1), [1., -1.]);
+    }
+
+    #[test]
+    fn test_skip_step() {
+        let params = with_grads(&[("a", &[1., 2.])]);
+        let mut sgd = Sgd::new(
+            vec![ParamGroup::new(params.clone(), 0.5)],
+            SgdConfig::default(),
+        )
+        .unwrap();
+        assert!(step_if_finite(&mut sgd).unwrap().is_finite());
+        assert_eq!(params[0].1.value().as_slice(), &[-0.5, -1.]);
+
+        params[0]
+            .1
+            .set_grad(Some(Tensor::from_slice(&[f32::NAN, 1.], &[2]).unwrap()));
+        let stats = step_if_finite(&mut sgd).unwrap();
+        assert_eq!(stats.non_finite, ["a"]);
+        // The parameters are untouched and the bad gradients are gone
+        assert_eq!(params[0].1.value().as_slice(), &[-0.5, -1.]);
+        assert!(params[0].1.grad().is_none());
+    }
+}
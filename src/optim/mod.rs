@@ -0,0 +1,5 @@
+pub mod adam;
+pub mod clip;
+pub mod optimizer;
+pub mod scheduler;
+pub mod sgd;
@@ -0,0 +1,259 @@
+// The interface shared by optimizers, and the parameter groups and state they work with.
+//
+// An optimizer owns handles to named parameters, split into groups which can each have their
+// own learning rate and weight decay. Each `step` updates every parameter with a gradient from
+// the last backward pass. Whatever an optimizer accumulates between steps, such as momentum,
+// is kept per parameter and can be saved as an `OptimizerState` to resume training later.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::autograd::variable::Var;
+use crate::exceptions::{FormatError, MatrixError, OptimizerError};
+use crate::io::safetensors::{self, SafeElement, SafeTensors, TensorData};
+use crate::matrix::element::Float;
+use crate::matrix::tensor::Tensor;
+
+const STEP_KEY: &str = "step";
+
+/// Named parameters sharing a learning rate and weight decay.
+#[derive(Debug, Clone)]
+pub struct ParamGroup<T: Float = f32> {
+    pub params: Vec<(String, Var<T>)>,
+    pub lr: f64,
+    pub weight_decay: f64,
+}
+
+impl<T: Float> ParamGroup<T> {
+    pub fn new(params: Vec<(String, Var<T>)>, lr: f64) -> Self {
+        ParamGroup {
+            params,
+            lr,
+            weight_decay: 0.,
+        }
+    }
+
+    pub fn with_weight_decay(mut self, weight_decay: f64) -> Self {
+        self.weight_decay = weight_decay;
+        self
+    }
+}
+
+/// Split parameters into a group with weight decay and one without. Parameters with fewer than
+/// two dimensions, such as biases and normalisation gains, go in the group without, as decaying
+/// them towards zero only hurts the fit.
+pub fn decay_groups<T: Float>(
+    params: Vec<(String, Var<T>)>,
+    lr: f64,
+    weight_decay: f64,
+) -> Vec<ParamGroup<T>> {
+    let (decayed, undecayed) = params
+        .into_iter()
+        .partition(|(_, param)| param.shape().len() >= 2);
+    vec![
+        ParamGroup::new(decayed, lr).with_weight_decay(weight_decay),
+        ParamGroup::new(undecayed, lr),
+    ]
+}
+
+pub trait Optimizer<T: Float = f32> {
+    /// Update every parameter which has a gradient.
+    fn step(&mut self) -> Result<()>;
+
+    fn param_groups(&self) -> &[ParamGroup<T>];
+
+    /// The groups, e.g. for a scheduler to change their learning rates.
+    fn param_groups_mut(&mut self) -> &mut [ParamGroup<T>];
+
+    /// What the optimizer has accumulated over its steps. Hyperparameters aren't included, as
+    /// they come from whoever constructs the optimizer.
+    fn state(&self) -> OptimizerState<T>;
+
+    /// Restore the state saved by an optimizer of the same kind over the same parameters.
+    fn load_state(&mut self, state: &OptimizerState<T>) -> Result<()>;
+
+    /// Handles to every parameter, from all the groups.
+    fn named_parameters(&self) -> Vec<(String, Var<T>)> {
+        self.param_groups()
+            .iter()
+            .flat_map(|group| group.params.iter().cloned())
+            .collect()
+    }
+
+    /// Clear the gradients of every parameter, ready for the next backward pass.
+    fn zero_grad(&self) {
+        for group in self.param_groups() {
+            for (_, param) in &group.params {
+                param.zero_grad();
+            }
+        }
+    }
+}
+
+/// An optimizer's step count and per-parameter buffers, named `{parameter}.{buffer}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptimizerState<T: Float = f32> {
+    pub step: u64,
+    pub buffers: BTreeMap<String, Tensor<T>>,
+}
+
+impl<T: Float + SafeElement> OptimizerState<T> {
+    /// Encode as a safetensors file, with the step count in the metadata.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let tensors: Vec<(&str, TensorData)> = self
+            .buffers
+            .iter()
+            .map(|(name, buffer)| (name.as_str(), buffer.into()))
+            .collect();
+        let metadata = BTreeMap::from([(STEP_KEY.to_owned(), self.step.to_string())]);
+        safetensors::to_bytes(&tensors, &metadata)
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        let file = SafeTensors::from_bytes(bytes)?;
+        let step = file
+            .metadata()
+            .get(STEP_KEY)
+            .and_then(|step| step.parse().ok())
+            .ok_or_else(|| FormatError::InvalidFile("missing optimizer step".to_owned()))?;
+        let buffers = file
+            .names()
+            .map(|name| Ok((name.to_owned(), file.get(name)?)))
+            .collect::<Result<_>>()?;
+        Ok(OptimizerState { step, buffers })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        OptimizerState::from_bytes(std::fs::read(path)?)
+    }
+}
+
+// Check no parameter is registered twice, as its state would be ambiguous
+pub(crate) fn check_unique<T: Float>(groups: &[ParamGroup<T>]) -> Result<()> {
+    let mut names = HashSet::new();
+    for (name, _) in groups.iter().flat_map(|group| &group.params) {
+        if !names.insert(name) {
+            Err(OptimizerError::DuplicateParameter(name.clone()))?;
+        }
+    }
+    Ok(())
+}
+
+// Split saved buffers by kind into maps from parameter names, checking each belongs to a
+// parameter and has its shape. Buffers of the scalar kinds, which follow the others in the
+// result, hold a single value instead.
+pub(crate) fn load_buffers<T: Float>(
+    groups: &[ParamGroup<T>],
+    state: &OptimizerState<T>,
+    kinds: &[&str],
+    scalar_kinds: &[&str],
+) -> Result<Vec<HashMap<String, Tensor<T>>>> {
+    let params: HashMap<&str, &Var<T>> = groups
+        .iter()
+        .flat_map(|group| &group.params)
+        .map(|(name, param)| (name.as_str(), param))
+        .collect();
+    let mut buffers = vec![HashMap::new(); kinds.len() + scalar_kinds.len()];
+    for (key, buffer) in &state.buffers {
+        let unknown = || OptimizerError::UnknownBuffer(key.clone());
+        // Parameter names can contain dots, but buffer kinds don't
+        let (name, kind) = key.rsplit_once('.').ok_or_else(unknown)?;
+        let kind = kinds
+            .iter()
+            .chain(scalar_kinds)
+            .position(|k| *k == kind)
+            .ok_or_else(unknown)?;
+        let param = params.get(name).ok_or_else(unknown)?;
+        let expected = if kind < kinds.len() {
+            param.shape()
+        } else {
+            vec![]
+        };
+        if buffer.shape() != expected {
+            Err(MatrixError::ShapeMismatch {
+                expected,
+                actual: buffer.shape().to_vec(),
+            })?;
+        }
+        buffers[kind].insert(name.to_owned(), buffer.clone());
+    }
+    Ok(buffers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decay_groups() {
+        let params = vec![
+            ("weight".to_owned(), Var::parameter(Tensor::zeros(&[2, 3]))),
+            ("bias".to_owned(), Var::parameter(Tensor::zeros(&[2]))),
+            ("norm.gain".to_owned(), Var::parameter(Tensor::ones(&[3]))),
+        ];
+        let groups: Vec<ParamGroup> = decay_groups(params, 1e-3, 0.1);
+        let names = |group: &ParamGroup| -> Vec<String> {
+            group.params.iter().map(|(name, _)| name.clone()).collect()
+        };
+        assert_eq!(names(&groups[0]), ["weight"]);
+        assert_eq!(groups[0].weight_decay, 0.1);
+        assert_eq!(names(&groups[1]), ["bias", "norm.gain"]);
+        assert_eq!(groups[1].weight_decay, 0.);
+        assert!(groups.iter().all(|group| group.lr == 1e-3));
+
+        let repeated = ParamGroup::new(groups[0].params.clone(), 1.);
+        assert!(check_unique(&[groups[0].clone(), repeated]).is_err());
+        assert!(check_unique(&groups).is_ok());
+    }
+
+    #[test]
+    fn test_state() {
+        let groups = vec![ParamGroup::new(
+            vec![(
+                "layer.0.weight".to_owned(),
+                Var::parameter(Tensor::zeros(&[2])),
+            )],
+            1.,
+        )];
+        let state = OptimizerState {
+            step: 3,
+            buffers: BTreeMap::from([
+                (
+                    "layer.0.weight.momentum".to_owned(),
+                    Tensor::from_slice(&[1f32, 2.], &[2]).unwrap(),
+                ),
+                ("layer.0.weight.count".to_owned(), Tensor::scalar(3.)),
+            ]),
+        };
+        let loaded = OptimizerState::from_bytes(state.to_bytes().unwrap()).unwrap();
+        assert_eq!(loaded, state);
+        let buffers = load_buffers(&groups, &loaded, &["momentum", "other"], &["count"]).unwrap();
+        assert_eq!(buffers[0]["layer.0.weight"].as_slice(), &[1., 2.]);
+        assert!(buffers[1].is_empty());
+        assert_eq!(buffers[2]["layer.0.weight"], Tensor::scalar(3.));
+
+        assert!(load_buffers(&groups, &loaded, &["other"], &["count"]).is_err());
+        // Scalar buffers aren't shaped like the parameter
+        assert!(load_buffers(&groups, &loaded, &["momentum", "count"], &[]).is_err());
+        let mut wrong_shape = state.clone();
+        wrong_shape
+            .buffers
+            .insert("layer.0.weight.momentum".to_owned(), Tensor::zeros(&[3]));
+        assert!(load_buffers(&groups, &wrong_shape, &["momentum"], &["count"]).is_err());
+        let mut unknown = state;
+        unknown
+            .buffers
+            .insert("layer.1.weight.momentum".to_owned(), Tensor::zeros(&[2]));
+        assert!(load_buffers(&groups, &unknown, &["momentum"], &["count"]).is_err());
+
+        // The step count is required
+        let bytes = safetensors::to_bytes(&[], &BTreeMap::new()).unwrap();
+        assert!(OptimizerState::<f32>::from_bytes(bytes).is_err());
+    }
+}
@@ -0,0 +1,559 @@
+// Learning-rate schedules, and a scheduler which applies one to an optimizer's groups.
+//
+// A schedule maps the number of optimizer steps taken so far to a factor, which scales the
+// learning rate each group started with. Schedules compose: `Sequence` switches between them at
+// given steps, each starting from its own step 0, and `Product` multiplies them, e.g. to warm
+// up into an inverse square root decay.
+//
+// The scheduler's state includes the schedule itself, so a resumed run continues on the same
+// curve even if it's started with different settings.
+use std::f64::consts::PI;
+use std::path::Path;
+
+use anyhow::Result;
+use serde_json::{json, Value};
+
+use crate::exceptions::{FormatError, OptimizerError};
+use crate::matrix::element::Float;
+use crate::optim::optimizer::Optimizer;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Schedule {
+    Constant,
+    /// Increase linearly to 1 over the first `steps` steps, starting from 1 / steps.
+    LinearWarmup {
+        steps: usize,
+    },
+    /// Decay from 1 to `min_factor` along half a cosine over `steps` steps, then stay there.
+    Cosine {
+        steps: usize,
+        min_factor: f64,
+    },
+    /// 1 for `warmup_steps` steps, then decay with the inverse square root of the step, as in
+    /// "Attention Is All You Need". Multiply by `LinearWarmup` to warm up first.
+    InverseSqrt {
+        warmup_steps: usize,
+    },
+    /// Multiply by `gamma` every `step_size` steps.
+    Step {
+        step_size: usize,
+        gamma: f64,
+    },
+    /// Rise from 1 / div_factor to 1 over the first `pct_start` of `steps`, then fall to
+    /// 1 / (div_factor * final_div_factor), both along half a cosine.
+    OneCycle {
+        steps: usize,
+        pct_start: f64,
+        div_factor: f64,
+        final_div_factor: f64,
+    },
+    /// Each schedule in turn, switching at the milestones, of which there's one fewer.
+    Sequence {
+        schedules: Vec<Schedule>,
+        milestones: Vec<usize>,
+    },
+    /// The product of the factors of every schedule.
+    Product(Vec<Schedule>),
+}
+
+// Interpolate from `start` to `end` along half a cosine as `t` goes from 0 to 1
+fn cosine_anneal(start: f64, end: f64, t: f64) -> f64 {
+    end + (start - end) * (1. + (PI * t.clamp(0., 1.)).cos()) / 2.
+}
+
+impl Schedule {
+    /// The factor for the learning rate of the update after `step` steps.
+    pub fn factor(&self, step: usize) -> f64 {
+        match self {
+            Schedule::Constant => 1.,
+            Schedule::LinearWarmup { steps } => ((step + 1) as f64 / *steps as f64).min(1.),
+            Schedule::Cosine { steps, min_factor } => {
+                cosine_anneal(1., *min_factor, step as f64 / *steps as f64)
+            }
+            Schedule::InverseSqrt { warmup_steps } => {
+                (*warmup_steps as f64 / (step + 1).max(*warmup_steps) as f64).sqrt()
+            }
+            Schedule::Step { step_size, gamma } => gamma.powi((step / step_size) as i32),
+            Schedule::OneCycle {
+                steps,
+                pct_start,
+                div_factor,
+                final_div_factor,
+            } => {
+                let rise = pct_start * *steps as f64;
+                let step = step as f64;
+                if step < rise {
+                    cosine_anneal(1. / div_factor, 1., step / rise)
+                } else {
+                    let end = 1. / (div_factor * final_div_factor);
+                    cosine_anneal(1., end, (step - rise) / (*steps as f64 - rise))
+                }
+            }
+            Schedule::Sequence {
+                schedules,
+                milestones,
+            } => {
+                let index = milestones.partition_point(|milestone| *milestone <= step);
+                let start = if index == 0 { 0 } else { milestones[index - 1] };
+                schedules[index].factor(step - start)
+            }
+            Schedule::Product(schedules) => schedules
+                .iter()
+                .map(|schedule| schedule.factor(step))
+                .product(),
+        }
+    }
+
+    /// Check the schedule's settings make sense.
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: &str| -> Result<()> {
+            Err(OptimizerError::InvalidHyperparameter(format!("{message} in {self:?}")).into())
+        };
+        match self {
+            Schedule::LinearWarmup { steps } | Schedule::Cosine { steps, .. } if *steps == 0 => {
+                invalid("steps must be positive")
+            }
+            Schedule::InverseSqrt { warmup_steps: 0 } => invalid("warmup must be positive"),
+            Schedule::Step { step_size: 0, .. } => invalid("the step size must be positive"),
+            Schedule::OneCycle {
+                steps,
+                pct_start,
+                div_factor,
+                final_div_factor,
+            } => {
+                if *steps == 0 || !(0. ..1.).contains(pct_start) {
+                    invalid("the rise must be part of a positive number of steps")
+                } else if *div_factor <= 0. || *final_div_factor <= 0. {
+                    invalid("the division factors must be positive")
+                } else {
+                    Ok(())
+                }
+            }
+            Schedule::Sequence {
+                schedules,
+                milestones,
+            } => {
+                if schedules.len() != milestones.len() + 1 {
+                    return invalid("there must be one more schedule than milestones");
+                }
+                if milestones.windows(2).any(|pair| pair[0] >= pair[1]) {
+                    return invalid("the milestones must increase");
+                }
+                schedules.iter().try_for_each(Schedule::validate)
+            }
+            Schedule::Product(schedules) => schedules.iter().try_for_each(Schedule::validate),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        match self {
+            Schedule::Constant => json!({"type": "constant"}),
+            Schedule::LinearWarmup { steps } => json!({"type": "linear_warmup", "steps": steps}),
+            Schedule::Cosine { steps, min_factor } => {
+                json!({"type": "cosine", "steps": steps, "min_factor": min_factor})
+            }
+            Schedule::InverseSqrt { warmup_steps } => {
+                json!({"type": "inverse_sqrt", "warmup_steps": warmup_steps})
+            }
+            Schedule::Step { step_size, gamma } => {
+                json!({"type": "step", "step_size": step_size, "gamma": gamma})
+            }
+            Schedule::OneCycle {
+                steps,
+                pct_start,
+                div_factor,
+                final_div_factor,
+            } => json!({
+                "type": "one_cycle",
+                "steps": steps,
+                "pct_start": pct_start,
+                "div_factor": div_factor,
+                "final_div_factor": final_div_factor,
+            }),
+            Schedule::Sequence {
+                schedules,
+                milestones,
+            } => json!({
+                "type": "sequence",
+                "schedules": schedules.iter().map(Schedule::to_json).collect::<Vec<_>>(),
+                "milestones": milestones,
+            }),
+            Schedule::Product(schedules) => json!({
+                "type": "product",
+                "schedules": schedules.iter().map(Schedule::to_json).collect::<Vec<_>>(),
+            }),
+        }
+    }
+
+    pub fn from_json(value: &Value) -> Result<Schedule> {
+        let invalid = || FormatError::InvalidFile(format!("invalid schedule {value}"));
+        let usize_field = |name: &str| -> Result<usize> {
+            Ok(value[name].as_u64().ok_or_else(invalid)? as usize)
+        };
+        let f64_field = |name: &str| value[name].as_f64().ok_or_else(invalid);
+        let schedules = || -> Result<Vec<Schedule>> {
+            value["schedules"]
+                .as_array()
+                .ok_or_else(invalid)?
+                .iter()
+                .map(Schedule::from_json)
+                .collect()
+        };
+        let schedule = match value["type"].as_str().ok_or_else(invalid)? {
+            "constant" => Schedule::Constant,
+            "linear_warmup" => Schedule::LinearWarmup {
+                steps: usize_field("steps")?,
+            },
+            "cosine" => Schedule::Cosine {
+                steps: usize_field("steps")?,
+                min_factor: f64_field("min_factor")?,
+            },
+            "inverse_sqrt" => Schedule::InverseSqrt {
+                warmup_steps: usize_field("warmup_steps")?,
+            },
+            "step" => Schedule::Step {
+                step_size: usize_field("step_size")?,
+                gamma: f64_field("gamma")?,
+            },
+            "one_cycle" => Schedule::OneCycle {
+                steps: usize_field("steps")?,
+                pct_start: f64_field("pct_start")?,
+                div_factor: f64_field("div_factor")?,
+                final_div_factor: f64_field("final_div_factor")?,
+            },
+            "sequence" => Schedule::Sequence {
+                schedules: schedules()?,
+                milestones: value["milestones"]
+                    .as_array()
+                    .ok_or_else(invalid)?
+                    .iter()
+                    .map(|milestone| Ok(milestone.as_u64().ok_or_else(invalid)? as usize))
+                    .collect::<Result<_>>()?,
+            },
+            "product" => Schedule::Product(schedules()?),
+            _ => Err(invalid())?,
+        };
+        Ok(schedule)
+    }
+}
+
+/// Everything needed to resume a scheduler.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchedulerState {
+    pub schedule: Schedule,
+    pub base_lrs: Vec<f64>,
+    pub step: usize,
+}
+
+impl SchedulerState {
+    pub fn to_json(&self) -> String {
+        json!({
+            "schedule": self.schedule.to_json(),
+            "base_lrs": self.base_lrs,
+            "step": self.step,
+        })
+        .to_string()
+    }
+
+    pub fn from_json(text: &str) -> Result<SchedulerState> {
+        let value: Value = serde_json::from_str(text)
+            .map_err(|error| FormatError::InvalidFile(error.to_string()))?;
+        let invalid = || FormatError::InvalidFile("invalid scheduler state".to_owned());
+        let base_lrs = value["base_lrs"]
+            .as_array()
+            .ok_or_else(invalid)?
+            .iter()
+            .map(|lr| lr.as_f64().ok_or_else(invalid))
+            .collect::<Result<_, _>>()?;
+        Ok(SchedulerState {
+            schedule: Schedule::from_json(&value["schedule"])?,
+            base_lrs,
+            step: value["step"].as_u64().ok_or_else(invalid)? as usize,
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, self.to_json())?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<SchedulerState> {
+        SchedulerState::from_json(&std::fs::read_to_string(path)?)
+    }
+}
+
+/// Sets each group's learning rate to its initial value times the schedule's factor. Call
+/// `step` after each optimizer step.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LrScheduler {
+    state: SchedulerState,
+}
+
+impl LrScheduler {
+    /// Start the schedule from the optimizer's current learning rates, setting them for its
+    /// first step.
+    pub fn new<T: Float>(
+        schedule: Schedule,
+        optimizer: &mut (impl Optimizer<T> + ?Sized),
+    ) -> Result<Self> {
+        schedule.validate()?;
+        let base_lrs = optimizer
+            .param_groups()
+            .iter()
+            .map(|group| group.lr)
+            .collect();
+        let scheduler = LrScheduler {
+            state: SchedulerState {
+                schedule,
+                base_lrs,
+                step: 0,
+            },
+        };
+        scheduler.apply(optimizer);
+        Ok(scheduler)
+    }
+
+    /// Resume a saved scheduler, setting the optimizer's learning rates to where it left off.
+    pub fn from_state<T: Float>(
+        state: SchedulerState,
+        optimizer: &mut (impl Optimizer<T> + ?Sized),
+    ) -> Result<Self> {
+        state.schedule.validate()?;
+        let groups = optimizer.param_groups().len();
+        if state.base_lrs.len() != groups {
+            Err(OptimizerError::GroupCount {
+                expected: groups,
+                actual: state.base_lrs.len(),
+            })?;
+        }
+        let scheduler = LrScheduler { state };
+        scheduler.apply(optimizer);
+        Ok(scheduler)
+    }
+
+    /// Move on to the next step's learning rates.
+    pub fn step<T: Float>(&mut self, optimizer: &mut (impl Optimizer<T> + ?Sized)) {
+        self.state.step += 1;
+        self.apply(optimizer);
+    }
+
+    /// The learning rate of each group for the next optimizer step.
+    pub fn lrs(&self) -> Vec<f64> {
+        let factor = self.state.schedule.factor(self.state.step);
+        self.state.base_lrs.iter().map(|lr| lr * factor).collect()
+    }
+
+    pub fn state(&self) -> &SchedulerState {
+        &self.state
+    }
+
+    fn apply<T: Float>(&self, optimizer: &mut (impl Optimizer<T> + ?Sized)) {
+        for (group, lr) in optimizer.param_groups_mut().iter_mut().zip(self.lrs()) {
+            group.lr = lr;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::autograd::variable::Var;
+    use crate::matrix::tensor::Tensor;
+    use crate::optim::optimizer::ParamGroup;
+    use crate::optim::sgd::{Sgd, SgdConfig};
+
+    fn factors(schedule: &Schedule, steps: usize) -> Vec<f64> {
+        (0..steps).map(|step| schedule.factor(step)).collect()
+    }
+
+    fn assert_factors(schedule: &Schedule, expected: &[f64]) {
+        let actual = factors(schedule, expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-12, "{actual:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn test_schedules() {
+        assert_factors(&Schedule::Constant, &[1., 1.]);
+        assert_factors(
+            &Schedule::LinearWarmup { steps: 4 },
+            &[0.25, 0.5, 0.75, 1., 1.],
+        );
+        assert_factors(
+            &Schedule::Cosine {
+                steps: 4,
+                min_factor: 0.2,
+            },
+            &[
+                1.,
+                0.2 + 0.8 * 0.8535533905932737,
+                0.6,
+                0.2 + 0.8 * 0.1464466094067262,
+                0.2,
+                0.2,
+            ],
+        );
+        assert_factors(
+            &Schedule::InverseSqrt { warmup_steps: 4 },
+            &[1., 1., 1., 1., (4f64 / 5.).sqrt(), (4f64 / 6.).sqrt()],
+        );
+        assert_factors(
+            &Schedule::Step {
+                step_size: 2,
+                gamma: 0.5,
+            },
+            &[1., 1., 0.5, 0.5, 0.25],
+        );
+        assert_factors(
+            &Schedule::OneCycle {
+                steps: 6,
+                pct_start: 1. / 3.,
+                div_factor: 10.,
+                final_div_factor: 100.,
+            },
+            &[
+                0.1,
+                0.55,
+                1.,
+                0.001 + 0.999 * 0.8535533905932737,
+                0.5005,
+                0.001 + 0.999 * 0.1464466094067262,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_composition() {
+        // Warm up for two steps, then decay over four
+        let warmup_cosine = Schedule::Sequence {
+            schedules: vec![
+                Schedule::LinearWarmup { steps: 2 },
+                Schedule::Cosine {
+                    steps: 4,
+                    min_factor: 0.,
+                },
+            ],
+            milestones: vec![2],
+        };
+        assert_factors(&warmup_cosine, &[0.5, 1., 1., 0.8535533905932737, 0.5]);
+
+        let noam = Schedule::Product(vec![
+            Schedule::LinearWarmup { steps: 4 },
+            Schedule::InverseSqrt { warmup_steps: 4 },
+        ]);
+        assert_factors(&noam, &[0.25, 0.5, 0.75, 1., (0.8f64).sqrt()]);
+
+        let mismatched = Schedule::Sequence {
+            schedules: vec![Schedule::Constant],
+            milestones: vec![2],
+        };
+        assert!(mismatched.validate().is_err());
+        assert!(Schedule::Product(vec![Schedule::LinearWarmup { steps: 0 }])
+            .validate()
+            .is_err());
+        assert!(warmup_cosine.validate().is_ok());
+    }
+
+    #[test]
+    fn test_scheduler() {
+        let groups = vec![
+            ParamGroup::new(
+                vec![("a".to_owned(), Var::parameter(Tensor::scalar(1.)))],
+                1.,
+            ),
+            ParamGroup::new(
+                vec![("b".to_owned(), Var::parameter(Tensor::scalar(1.)))],
+                0.1,
+            ),
+        ];
+        let mut sgd = Sgd::new(groups, SgdConfig::default()).unwrap();
+        let schedule = Schedule::Product(vec![
+            Schedule::LinearWarmup { steps: 2 },
+            Schedule::Step {
+                step_size: 3,
+                gamma: 0.1,
+            },
+        ]);
+        let mut scheduler = LrScheduler::new(schedule, &mut sgd).unwrap();
+        let group_lrs =
+            |sgd: &Sgd| -> Vec<f64> { sgd.param_groups().iter().map(|group| group.lr).collect() };
+        assert_eq!(group_lrs(&sgd), [0.5, 0.05]);
+        for _ in 0..3 {
+            sgd.step().unwrap();
+            scheduler.step(&mut sgd);
+        }
+        assert_eq!(group_lrs(&sgd), scheduler.lrs());
+        assert!((group_lrs(&sgd)[0] - 0.1).abs() < 1e-12);
+
+        // A resumed scheduler continues on the saved curve, whatever the optimizer's rates
+        let path = std::env::temp_dir().join(format!("scheduler_{}.json", std::process::id()));
+        scheduler.state().save(&path).unwrap();
+        let state = SchedulerState::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&state, scheduler.state());
+        let mut restarted = Sgd::new(
+            vec![
+                ParamGroup::new(
+                    vec![("a".to_owned(), Var::parameter(Tensor::scalar(1.)))],
+                    5.,
+                ),
+                ParamGroup::new(
+                    vec![("b".to_owned(), Var::parameter(Tensor::scalar(1.)))],
+                    5.,
+                ),
+            ],
+            SgdConfig::default(),
+        )
+        .unwrap();
+        let mut resumed = LrScheduler::from_state(state, &mut restarted).unwrap();
+        for _ in 0..4 {
+            scheduler.step(&mut sgd);
+            resumed.step(&mut restarted);
+            assert_eq!(group_lrs(&restarted), group_lrs(&sgd));
+        }
+
+        let mut one_group = Sgd::new(
+            vec![ParamGroup::new(
+                vec![("a".to_owned(), Var::parameter(Tensor::scalar(1.)))],
+                1.,
+            )],
+            SgdConfig::default(),
+        )
+        .unwrap();
+        assert!(LrScheduler::from_state(scheduler.state().clone(), &mut one_group).is_err());
+    }
+
+    #[test]
+    fn test_json() {
+        let schedule = Schedule::Sequence {
+            schedules: vec![
+                Schedule::Product(vec![
+                    Schedule::LinearWarmup { steps: 10 },
+                    Schedule::InverseSqrt { warmup_steps: 10 },
+                ]),
+                Schedule::OneCycle {
+                    steps: 100,
+                    pct_start: 0.3,
+                    div_factor: 25.,
+                    final_div_factor: 1e4,
+                },
+                Schedule::Step {
+                    step_size: 5,
+                    gamma: 0.9,
+                },
+                Schedule::Cosine {
+                    steps: 50,
+                    min_factor: 0.1,
+                },
+                Schedule::Constant,
+            ],
+            milestones: vec![20, 120, 150, 200],
+        };
+        assert_eq!(Schedule::from_json(&schedule.to_json()).unwrap(), schedule);
+        assert!(Schedule::from_json(&json!({"type": "cosine", "steps": 10})).is_err());
+        assert!(Schedule::from_json(&json!({"type": "exponential"})).is_err());
+        assert!(SchedulerState::from_json("{}").is_err());
+    }
+}
@@ -0,0 +1,218 @@
+// Stochastic gradient descent, optionally with momentum.
+//
+// With weight decay λ the gradient is g + λp. With momentum μ a buffer b ← μb + g accumulates
+// the gradients, starting from the first one, and the parameter moves along b, or along g + μb
+// with Nesterov momentum, which looks ahead to where the momentum is taking it.
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use crate::exceptions::OptimizerError;
+use crate::matrix::element::Float;
+use crate::matrix::tensor::Tensor;
+use crate::optim::optimizer::{check_unique, load_buffers, Optimizer, OptimizerState, ParamGroup};
+
+const MOMENTUM_BUFFER: &str = "momentum_buffer";
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SgdConfig {
+    pub momentum: f64,
+    pub nesterov: bool,
+}
+
+pub struct Sgd<T: Float = f32> {
+    groups: Vec<ParamGroup<T>>,
+    config: SgdConfig,
+    momentum_buffers: HashMap<String, Tensor<T>>,
+    step: u64,
+}
+
+impl<T: Float> Sgd<T> {
+    pub fn new(groups: Vec<ParamGroup<T>>, config: SgdConfig) -> Result<Self> {
+        check_unique(&groups)?;
+        if config.nesterov && config.momentum <= 0. {
+            Err(OptimizerError::InvalidHyperparameter(
+                "Nesterov momentum needs a positive momentum".to_owned(),
+            ))?;
+        }
+        Ok(Sgd {
+            groups,
+            config,
+            momentum_buffers: HashMap::new(),
+            step: 0,
+        })
+    }
+}
+
+impl<T: Float> Optimizer<T> for Sgd<T> {
+    fn step(&mut self) -> Result<()> {
+        let momentum = T::from_f64(self.config.momentum);
+        for group in &self.groups {
+            let (lr, weight_decay) = (T::from_f64(group.lr), T::from_f64(group.weight_decay));
+            for (name, param) in &group.params {
+                let Some(mut grad) = param.grad() else {
+                    continue;
+                };
+                if weight_decay != T::ZERO {
+                    grad = grad.zip_map(&param.value(), |g, p| g + weight_decay * p)?;
+                }
+                if momentum != T::ZERO {
+                    let buffer = match self.momentum_buffers.remove(name) {
+                        Some(buffer) => buffer.zip_map(&grad, |b, g| momentum * b + g)?,
+                        None => grad.clone(),
+                    };
+                    grad = if self.config.nesterov {
+                        grad.zip_map(&buffer, |g, b| g + momentum * b)?
+                    } else {
+                        buffer.clone()
+                    };
+                    self.momentum_buffers.insert(name.clone(), buffer);
+                }
+                param.update_value(|value| value.sub_inplace(&grad.map(|g| lr * g)))?;
+            }
+        }
+        self.step += 1;
+        Ok(())
+    }
+
+    fn param_groups(&self) -> &[ParamGroup<T>] {
+        &self.groups
+    }
+
+    fn param_groups_mut(&mut self) -> &mut [ParamGroup<T>] {
+        &mut self.groups
+    }
+
+    fn state(&self) -> OptimizerState<T> {
+        OptimizerState {
+            step: self.step,
+            buffers: self
+                .momentum_buffers
+                .iter()
+                .map(|(name, buffer)| (format!("{name}.{MOMENTUM_BUFFER}"), buffer.clone()))
+                .collect(),
+        }
+    }
+
+    fn load_state(&mut self, state: &OptimizerState<T>) -> Result<()> {
+        let [momentum_buffers] = load_buffers(&self.groups, state, &[MOMENTUM_BUFFER], &[])?
+            .try_into()
+            .unwrap();
+        self.momentum_buffers = momentum_buffers;
+        self.step = state.step;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_close;
+    use crate::autograd::variable::Var;
+
+    // Take steps minimising Σ p², whose gradient is 2p, returning the parameter after each
+    fn descend(optimizer: &mut Sgd, param: &Var, steps: usize) -> Vec<f32> {
+        (0..steps)
+            .map(|_| {
+                optimizer.zero_grad();
+                param.powi(2).sum().backward().unwrap();
+                optimizer.step().unwrap();
+                param.value().as_slice()[0]
+            })
+            .collect()
+    }
+
+    fn param(value: f32) -> Var {
+        Var::parameter(Tensor::scalar(value))
+    }
+
+    fn optimizer(param: &Var, lr: f64, weight_decay: f64, config: SgdConfig) -> Sgd {
+        let group = ParamGroup::new(vec![("p".to_owned(), param.clone())], lr)
+            .with_weight_decay(weight_decay);
+        Sgd::new(vec![group], config).unwrap()
+    }
+
+    #[test]
+    fn test_sgd() {
+        let p = param(1.);
+        let mut sgd = optimizer(&p, 0.1, 0., SgdConfig::default());
+        assert_close!(
+            Tensor::from_vec(descend(&mut sgd, &p, 2), &[2]).unwrap(),
+            Tensor::from_slice(&[0.8, 0.64], &[2]).unwrap()
+        );
+
+        // (2 + 0.5) p per step
+        let p = param(1.);
+        let mut sgd = optimizer(&p, 0.1, 0.5, SgdConfig::default());
+        assert_close!(
+            Tensor::from_vec(descend(&mut sgd, &p, 1), &[1]).unwrap(),
+            Tensor::from_slice(&[0.75], &[1]).unwrap()
+        );
+
+        // Parameters without gradients are left alone
+        let frozen = param(1.);
+        let mut sgd = optimizer(&frozen, 0.1, 0.5, SgdConfig::default());
+        sgd.step().unwrap();
+        assert_eq!(frozen.value().as_slice(), &[1.]);
+    }
+
+    #[test]
+    fn test_momentum() {
+        let config = SgdConfig {
+            momentum: 0.9,
+            nesterov: false,
+        };
+        let p = param(1.);
+        let mut sgd = optimizer(&p, 0.1, 0., config);
+        // Buffers 2, then 0.9 * 2 + 1.6
+        assert_close!(
+            Tensor::from_vec(descend(&mut sgd, &p, 2), &[2]).unwrap(),
+            Tensor::from_slice(&[0.8, 0.46], &[2]).unwrap()
+        );
+
+        let nesterov = SgdConfig {
+            nesterov: true,
+            ..config
+        };
+        let p = param(1.);
+        let mut sgd = optimizer(&p, 0.1, 0., nesterov);
+        // Steps along 2 + 0.9 * 2, then 1.24 + 0.9 * (0.9 * 2 + 1.24)
+        assert_close!(
+            Tensor::from_vec(descend(&mut sgd, &p, 2), &[2]).unwrap(),
+            Tensor::from_slice(&[0.62, 0.2224], &[2]).unwrap()
+        );
+
+        let p = param(1.);
+        let no_momentum = SgdConfig {
+            momentum: 0.,
+            nesterov: true,
+        };
+        assert!(Sgd::new(
+            vec![ParamGroup::new(vec![("p".to_owned(), p)], 0.1)],
+            no_momentum
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_resume() {
+        let config = SgdConfig {
+            momentum: 0.9,
+            nesterov: true,
+        };
+        let p = param(1.);
+        let mut sgd = optimizer(&p, 0.1, 0.01, config);
+        descend(&mut sgd, &p, 3);
+        let state = OptimizerState::from_bytes(sgd.state().to_bytes().unwrap()).unwrap();
+        assert_eq!(state.step, 3);
+        let expected = descend(&mut sgd, &p, 2);
+
+        // A new optimizer picks up from the saved state, not from scratch
+        let resumed_p = param(1.);
+        let mut resumed = optimizer(&resumed_p, 0.1, 0.01, config);
+        descend(&mut resumed, &resumed_p, 3);
+        let mut resumed = optimizer(&resumed_p, 0.1, 0.01, config);
+        resumed.load_state(&state).unwrap();
+        assert_eq!(descend(&mut resumed, &resumed_p, 2), expected);
+    }
+}
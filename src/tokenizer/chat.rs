@@ -0,0 +1,374 @@
+// Rendering of multi-turn conversations into token ids using chat templates.
+//
+// A template is written as plain text with two placeholders, `{role}` and `{content}`, and
+// special tokens written as `<|name|>`. Special token names may themselves contain `{role}`,
+// which gives one special token per role (e.g. `<|{role}|>` expands to `<|user|>`...).
+use crate::exceptions::TokenizerError;
+use crate::tokenizer::tokenizer::{encode_bytes, Vocab};
+use anyhow::Result;
+use std::collections::HashMap;
+
+const ROLE_PLACEHOLDER: &str = "{role}";
+const CONTENT_PLACEHOLDER: &str = "{content}";
+const SPECIAL_START: &str = "<|";
+const SPECIAL_END: &str = "|>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::System, Role::User, Role::Assistant];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+}
+
+impl Message {
+    pub fn new(role: Role, content: &str) -> Self {
+        Message {
+            role,
+            content: content.to_owned(),
+        }
+    }
+
+    pub fn system(content: &str) -> Self {
+        Message::new(Role::System, content)
+    }
+
+    pub fn user(content: &str) -> Self {
+        Message::new(Role::User, content)
+    }
+
+    pub fn assistant(content: &str) -> Self {
+        Message::new(Role::Assistant, content)
+    }
+}
+
+/// The built-in template styles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplateStyle {
+    /// `<|im_start|>user\nHello<|im_end|>\n`
+    ChatMl,
+    /// `<|user|>\nHello<|end|>\n`, with one special token per role, as used by Phi-3.
+    Phi3,
+    /// `user: Hello\n`, with no special tokens at all.
+    Plain,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Text(String),
+    // The special token text, which may still contain the role placeholder
+    Special(String),
+    Role,
+    Content,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatTemplate {
+    // Rendered once at the start of the conversation
+    prefix: Vec<Segment>,
+    // Rendered for every message
+    message: Vec<Segment>,
+}
+
+impl ChatTemplate {
+    pub fn builtin(style: TemplateStyle) -> Self {
+        let (prefix, message) = match style {
+            TemplateStyle::ChatMl => ("", "<|im_start|>{role}\n{content}<|im_end|>\n"),
+            TemplateStyle::Phi3 => ("", "<|{role}|>\n{content}<|end|>\n"),
+            TemplateStyle::Plain => ("", "{role}: {content}\n"),
+        };
+        ChatTemplate::parse(prefix, message).expect("Built-in templates are valid")
+    }
+
+    /// Parse a user-defined template.
+    ///
+    /// `message` is rendered for every message and must contain `{content}` exactly once.
+    /// `prefix` is rendered once before the first message and may only contain text and
+    /// special tokens.
+    pub fn parse(prefix: &str, message: &str) -> Result<Self> {
+        let prefix = parse_segments(prefix)?;
+        if prefix
+            .iter()
+            .any(|segment| !matches!(segment, Segment::Text(_) | Segment::Special(_)))
+        {
+            return Err(invalid_template("the prefix cannot contain placeholders"));
+        }
+        if prefix.iter().any(
+            |segment| matches!(segment, Segment::Special(name) if name.contains(ROLE_PLACEHOLDER)),
+        ) {
+            return Err(invalid_template(
+                "the prefix cannot contain role special tokens",
+            ));
+        }
+
+        let message = parse_segments(message)?;
+        let n_content = message
+            .iter()
+            .filter(|segment| **segment == Segment::Content)
+            .count();
+        if n_content != 1 {
+            return Err(invalid_template(&format!(
+                "the message template must contain {CONTENT_PLACEHOLDER} exactly once, found {n_content}"
+            )));
+        }
+        Ok(ChatTemplate { prefix, message })
+    }
+
+    /// All the special tokens the template can emit, in order of first appearance.
+    pub fn special_tokens(&self) -> Vec<String> {
+        let mut tokens: Vec<String> = Vec::new();
+        for segment in self.prefix.iter().chain(self.message.iter()) {
+            if let Segment::Special(name) = segment {
+                let expanded: Vec<String> = if name.contains(ROLE_PLACEHOLDER) {
+                    Role::ALL
+                        .iter()
+                        .map(|role| name.replace(ROLE_PLACEHOLDER, role.as_str()))
+                        .collect()
+                } else {
+                    vec![name.clone()]
+                };
+                for token in expanded {
+                    if !tokens.contains(&token) {
+                        tokens.push(token);
+                    }
+                }
+            }
+        }
+        tokens
+    }
+
+    /// Render the conversation as a string. Useful for debugging templates.
+    pub fn render_text(&self, messages: &[Message], add_generation_prompt: bool) -> String {
+        let mut rendered = String::new();
+        for (segment, _) in self.segments(messages, add_generation_prompt) {
+            rendered.push_str(&segment);
+        }
+        rendered
+    }
+
+    // Flatten the template over the messages, returning each rendered piece along with whether
+    // it is a special token and whether it belongs to an assistant turn.
+    fn segments(
+        &self,
+        messages: &[Message],
+        add_generation_prompt: bool,
+    ) -> Vec<(String, SegmentKind)> {
+        let mut rendered: Vec<(String, SegmentKind)> = Vec::new();
+        for segment in &self.prefix {
+            rendered.push(render_segment(segment, Role::System, "", false));
+        }
+        for message in messages {
+            // Everything from the content onwards belongs to the turn itself, the header before
+            // it is provided as context.
+            let mut in_turn = false;
+            for segment in &self.message {
+                in_turn |= *segment == Segment::Content;
+                let is_assistant = in_turn && message.role == Role::Assistant;
+                rendered.push(render_segment(
+                    segment,
+                    message.role,
+                    &message.content,
+                    is_assistant,
+                ));
+            }
+        }
+        if add_generation_prompt {
+            for segment in self
+                .message
+                .iter()
+                .take_while(|segment| **segment != Segment::Content)
+            {
+                rendered.push(render_segment(segment, Role::Assistant, "", false));
+            }
+        }
+        rendered
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SegmentKind {
+    special: bool,
+    assistant: bool,
+}
+
+fn render_segment(
+    segment: &Segment,
+    role: Role,
+    content: &str,
+    assistant: bool,
+) -> (String, SegmentKind) {
+    let (text, special) = match segment {
+        Segment::Text(text) => (text.clone(), false),
+        Segment::Special(name) => (name.replace(ROLE_PLACEHOLDER, role.as_str()), true),
+        Segment::Role => (role.as_str().to_owned(), false),
+        Segment::Content => (content.to_owned(), false),
+    };
+    (text, SegmentKind { special, assistant })
+}
+
+fn invalid_template(reason: &str) -> anyhow::Error {
+    TokenizerError::InvalidTemplate(reason.to_owned()).into()
+}
+
+fn parse_segments(template: &str) -> Result<Vec<Segment>> {
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut text = String::new();
+    let mut rest = template;
+
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with(SPECIAL_START) {
+            let end = rest.find(SPECIAL_END).ok_or_else(|| {
+                invalid_template(&format!("unterminated special token in {template:?}"))
+            })?;
+            let token = &rest[..end + SPECIAL_END.len()];
+            let name = &token[SPECIAL_START.len()..token.len() - SPECIAL_END.len()];
+            if name.is_empty() || name.replace(ROLE_PLACEHOLDER, "").contains(['{', '}']) {
+                return Err(invalid_template(&format!("bad special token {token:?}")));
+            }
+            if !text.is_empty() {
+                segments.push(Segment::Text(std::mem::take(&mut text)));
+            }
+            segments.push(Segment::Special(token.to_owned()));
+            rest = &rest[token.len()..];
+        } else if c == '{' {
+            let placeholder = if rest.starts_with(ROLE_PLACEHOLDER) {
+                (Segment::Role, ROLE_PLACEHOLDER.len())
+            } else if rest.starts_with(CONTENT_PLACEHOLDER) {
+                (Segment::Content, CONTENT_PLACEHOLDER.len())
+            } else {
+                return Err(invalid_template(&format!(
+                    "unknown placeholder at {rest:?}, expected {ROLE_PLACEHOLDER} or {CONTENT_PLACEHOLDER}"
+                )));
+            };
+            if !text.is_empty() {
+                segments.push(Segment::Text(std::mem::take(&mut text)));
+            }
+            segments.push(placeholder.0);
+            rest = &rest[placeholder.1..];
+        } else {
+            text.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+    Ok(segments)
+}
+
+/// Ids assigned to special tokens. These sit above every merged token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpecialTokens {
+    ids: HashMap<String, u16>,
+}
+
+impl SpecialTokens {
+    /// Assign consecutive ids from `first_id`, failing if they don't all fit in a u16.
+    pub fn new(tokens: &[String], first_id: u16) -> Result<Self> {
+        let ids = tokens
+            .iter()
+            .enumerate()
+            .map(|(idx, token)| {
+                u16::try_from(idx)
+                    .ok()
+                    .and_then(|idx| first_id.checked_add(idx))
+                    .map(|id| (token.clone(), id))
+                    .ok_or(TokenizerError::VocabOverflow(tokens.len()))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(SpecialTokens { ids })
+    }
+
+    /// Assign ids to every special token of the template, starting after the last merge.
+    pub fn for_template(template: &ChatTemplate, merges: &[((u16, u16), u16)]) -> Result<Self> {
+        let special_tokens = template.special_tokens();
+        let first_id = match merges.iter().map(|(_, merge_to)| *merge_to).max() {
+            Some(last_id) => last_id
+                .checked_add(1)
+                .ok_or(TokenizerError::VocabOverflow(special_tokens.len()))?,
+            None => u8::MAX as u16 + 1,
+        };
+        SpecialTokens::new(&special_tokens, first_id)
+    }
+
+    pub fn id(&self, token: &str) -> Option<u16> {
+        self.ids.get(token).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Add the special tokens to the vocab so that `decode` can render them.
+    pub fn extend_vocab(&self, vocab: &mut Vocab) {
+        for (token, id) in &self.ids {
+            vocab.insert(*id, token.as_bytes().to_vec());
+        }
+    }
+}
+
+/// The result of encoding a conversation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatEncoding {
+    pub ids: Vec<u16>,
+    /// True for tokens which belong to an assistant turn (its content and closing tokens), which
+    /// are the tokens a loss should be computed on.
+    pub assistant_mask: Vec<bool>,
+}
+
+/// Render the messages with the template and encode them.
+///
+/// Text between special tokens is encoded piece by piece, so a merge never spans the boundary
+/// between a message header and its content. If `add_generation_prompt` is set, the header of an
+/// assistant turn is appended so the model can continue from it.
+pub fn encode_chat(
+    messages: &[Message],
+    template: &ChatTemplate,
+    merges: &[((u16, u16), u16)],
+    special_tokens: &SpecialTokens,
+    add_generation_prompt: bool,
+) -> Result<ChatEncoding> {
+    let mut ids: Vec<u16> = Vec::new();
+    let mut assistant_mask: Vec<bool> = Vec::new();
+
+    for (text, kind) in template.segments(messages, add_generation_prompt) {
+        let segment_ids = if kind.special {
+            vec![special_tokens
+                .id(&text)
+                .ok_or(TokenizerError::UnknownSpecialToken(text))?]
+        } else {
+            encode_bytes(text.as_bytes(), merges)
+        };
+        assistant_mask.extend(std::iter::repeat_n(kind.assistant, segment_ids.len()));
+        ids.extend(segment_ids);
+    }
+    Ok(ChatEncoding {
+        ids,
+        assistant_mask,
+    })
+}
+
+#[cfg(test)]
+#[path = "./unit_tests/chat_tests.rs"]
+mod chat_tests;
@@ -1,3 +1,5 @@
+pub mod chat;
 mod macros;
+#[allow(clippy::module_inception)]
 pub mod tokenizer;
 mod utils;
@@ -10,18 +10,17 @@ use std::fs::File;
 use std::io::Read;
 use tqdm;
 
-pub fn bpe_on_str(
-    input_str: &str,
-    n_merges: u32,
-) -> Result<(Vec<((u16, u16), u16)>, HashMap<u16, Vec<u8>>)> {
+/// Ordered list of merges, each mapping a pair of tokens to the new token.
+pub type Merges = Vec<((u16, u16), u16)>;
+/// Mapping from merged token ids to the bytes they decode to.
+pub type Vocab = HashMap<u16, Vec<u8>>;
+
+pub fn bpe_on_str(input_str: &str, n_merges: u32) -> Result<(Merges, Vocab)> {
     let encoded: Vec<u8> = input_str.bytes().collect();
     bpe(encoded, n_merges)
 }
 
-pub fn bpe_on_file(
-    input_file: &str,
-    n_merges: u32,
-) -> Result<(Vec<((u16, u16), u16)>, HashMap<u16, Vec<u8>>)> {
+pub fn bpe_on_file(input_file: &str, n_merges: u32) -> Result<(Merges, Vocab)> {
     info!("Starting BPE algorithm on {input_file}");
     let mut f = File::open(input_file)?;
 
@@ -31,21 +30,18 @@ pub fn bpe_on_file(
     bpe(buffer, n_merges)
 }
 
-pub fn bpe(
-    input_bytes: Vec<u8>,
-    n_merges: u32,
-) -> Result<(Vec<((u16, u16), u16)>, HashMap<u16, Vec<u8>>)> {
+pub fn bpe(input_bytes: Vec<u8>, n_merges: u32) -> Result<(Merges, Vocab)> {
     let start_len = input_bytes.len();
 
     // Split the input string by the split byte. This makes the algorithm run much faster, but means that you cannot have multi-word tokens.
-    let split_byte: u8 = ' ' as u8;
+    let split_byte: u8 = b' ';
     let mut words: Vec<(Vec<u16>, u32)> = to_word_tokens(input_bytes, split_byte);
 
     // Added tokens start at 256 (max byte value + 1)
     let mut next_token_id: u16 = u8::MAX as u16 + 1;
 
-    let mut merges: Vec<((u16, u16), u16)> = Vec::new();
-    let mut vocab: HashMap<u16, Vec<u8>> = HashMap::new();
+    let mut merges: Merges = Vec::new();
+    let mut vocab: Vocab = HashMap::new();
 
     // Fill out the initial pairs which occur in the words
     let mut pairs: HashMap<(u16, u16), u32> = HashMap::new(); // pair, and number of occurrences in the input
@@ -75,9 +71,13 @@ pub fn bpe(
                     best_pairs.clear();
                     best_pair_n_matches = *n_occurrences;
                 }
-                best_pairs.push(pair.clone());
+                best_pairs.push(*pair);
             }
         }
+        // Every pair is unique, so there is nothing left to merge
+        if best_pairs.is_empty() {
+            break;
+        }
 
         for merge_from_pair in best_pairs {
             if merge_iter >= n_merges {
@@ -114,9 +114,9 @@ pub fn bpe(
 
             // Remove the old pair from our pairs
             pairs.remove(&merge_from_pair);
-            let words_to_merge = words_with_pair.remove(&merge_from_pair).expect(&format!(
-                "This should be in the words_by_pair {merge_from_pair:?}"
-            ));
+            let words_to_merge = words_with_pair.remove(&merge_from_pair).unwrap_or_else(|| {
+                panic!("This should be in the words_by_pair {merge_from_pair:?}")
+            });
             for word_index in words_to_merge {
                 let (ref mut tokens, word_occs) = words
                     .get_mut(word_index)
@@ -151,9 +151,7 @@ pub fn bpe(
                         let replaced_pair = (pair.1, tokens[token_idx + 1]);
                         replaced_pairs.push(replaced_pair);
                         // If a pair was removed, indicate this
-                        if !pairs_in_word.contains_key(&replaced_pair) {
-                            pairs_in_word.insert(replaced_pair.clone(), 0);
-                        }
+                        pairs_in_word.entry(replaced_pair).or_insert(0);
                     }
                     // Check for a pair replacement before the new pair
                     if token_idx > 0 {
@@ -187,12 +185,12 @@ pub fn bpe(
                 // Account for any pairs which have been removed from our word
                 for (pair, n_occurrences) in pairs_in_word {
                     if n_occurrences == 0 && pair != merge_from_pair {
-                        debug! {"Removing word {word_index} from pair: {:?}", pair.clone()};
+                        debug! {"Removing word {word_index} from pair: {:?}", pair};
                         words_with_pair
                             .get_mut(&pair)
-                            .expect(&format!(
-                                "We expect pair {pair:?} to be in there before removal"
-                            ))
+                            .unwrap_or_else(|| {
+                                panic!("We expect pair {pair:?} to be in there before removal")
+                            })
                             .remove(&word_index);
                     }
                 }
@@ -206,33 +204,78 @@ pub fn bpe(
             .iter()
             .map(|(tokens, n_occurrences)| tokens.len() * (*n_occurrences as usize))
             .sum::<usize>())
-        / start_len;
+    .checked_div(start_len)
+    .unwrap_or(0);
     info!("Compression of tokens by: {compression:.2}%");
     Ok((merges, vocab))
 }
 
-pub fn encode(input_str: &str, merges: Vec<((u16, u16), u16)>) -> Result<Vec<u16>> {
-    let mut encoded: Vec<u16> = input_str
-        .bytes()
-        .map(|val: u8| -> u16 { val as u16 })
-        .collect();
+pub fn encode(input_str: &str, merges: Merges) -> Result<Vec<u16>> {
+    Ok(encode_bytes(input_str.as_bytes(), &merges))
+}
+
+/// Apply each merge in order over the raw bytes, returning the resulting tokens.
+///
+/// This is the reference encoder: it makes one pass over the input per merge.
+pub fn encode_bytes(input_bytes: &[u8], merges: &[((u16, u16), u16)]) -> Vec<u16> {
+    let mut encoded: Vec<u16> = input_bytes.iter().map(|val| *val as u16).collect();
 
     for (merge_from, merge_to) in merges {
-        let mut i: usize = 0;
-        while i < (encoded.len() - 1) {
-            if (encoded[i], encoded[i + 1]) == merge_from {
-                encoded[i] = merge_to;
-                encoded.remove(i + 1);
-            } else {
-                i += 1;
-            }
+        apply_merge(&mut encoded, *merge_from, *merge_to);
+    }
+    encoded
+}
+
+pub fn encode_fast(input_str: &str, merges: &[((u16, u16), u16)]) -> Result<Vec<u16>> {
+    Ok(encode_bytes_fast(input_str.as_bytes(), merges))
+}
+
+/// Gives the same tokens as `encode_bytes`, but only makes a pass for merges which apply.
+///
+/// A merged token is always created before any merge which uses it, so applying the earliest
+/// merge present in the tokens at each step is the same as applying every merge in order.
+pub fn encode_bytes_fast(input_bytes: &[u8], merges: &[((u16, u16), u16)]) -> Vec<u16> {
+    let mut ranks: HashMap<(u16, u16), (usize, u16)> = HashMap::new();
+    for (rank, (merge_from, merge_to)) in merges.iter().enumerate() {
+        ranks.entry(*merge_from).or_insert((rank, *merge_to));
+    }
+
+    let mut encoded: Vec<u16> = input_bytes.iter().map(|val| *val as u16).collect();
+    while let Some((merge_from, (_, merge_to))) = encoded
+        .windows(2)
+        .filter_map(|pair| {
+            let pair = (pair[0], pair[1]);
+            ranks.get(&pair).map(|rank| (pair, *rank))
+        })
+        .min_by_key(|(_, (rank, _))| *rank)
+    {
+        apply_merge(&mut encoded, merge_from, merge_to);
+    }
+    encoded
+}
+
+// Replace every occurrence of the pair, scanning from the left
+fn apply_merge(encoded: &mut Vec<u16>, merge_from: (u16, u16), merge_to: u16) {
+    let mut merged: Vec<u16> = Vec::with_capacity(encoded.len());
+    let mut i: usize = 0;
+    while i < encoded.len() {
+        if i + 1 < encoded.len() && (encoded[i], encoded[i + 1]) == merge_from {
+            merged.push(merge_to);
+            i += 2;
+        } else {
+            merged.push(encoded[i]);
+            i += 1;
         }
     }
+    *encoded = merged;
+}
 
-    Ok(encoded)
+pub fn decode(encoded: Vec<u16>, vocab: Vocab) -> Result<String> {
+    let decoded = decode_bytes(&encoded, &vocab)?;
+    Ok(str::from_utf8(&decoded)?.to_owned())
 }
 
-pub fn decode(encoded: Vec<u16>, vocab: HashMap<u16, Vec<u8>>) -> Result<String> {
+pub fn decode_bytes(encoded: &[u16], vocab: &Vocab) -> Result<Vec<u8>> {
     let decoded: Vec<u8> = encoded
         .iter()
         .map(|element| {
@@ -249,9 +292,13 @@ pub fn decode(encoded: Vec<u16>, vocab: HashMap<u16, Vec<u8>>) -> Result<String>
         .drain(..)
         .flatten()
         .collect();
-    Ok(str::from_utf8(&decoded)?.to_owned())
+    Ok(decoded)
 }
 
 #[cfg(test)]
 #[path = "./unit_tests/tokenizer_tests.rs"]
 mod tokenizer_tests;
+
+#[cfg(test)]
+#[path = "./unit_tests/tokenizer_proptests.rs"]
+mod tokenizer_proptests;
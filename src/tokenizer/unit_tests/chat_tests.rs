@@ -0,0 +1,157 @@
+use super::*;
+use crate::tokenizer::tokenizer::{bpe_on_str, decode, Merges};
+mod tests {
+    use super::*;
+
+    fn conversation() -> Vec<Message> {
+        vec![
+            Message::system("Be nice"),
+            Message::user("hello there"),
+            Message::assistant("hello, hello"),
+        ]
+    }
+
+    fn train() -> (Merges, Vocab) {
+        bpe_on_str("hello there hello nice hello there user assistant", 8).unwrap()
+    }
+
+    #[test]
+    fn test_render_text() {
+        let template = ChatTemplate::builtin(TemplateStyle::ChatMl);
+        assert_eq!(
+            template.render_text(&conversation()[1..], true),
+            "<|im_start|>user\nhello there<|im_end|>\n<|im_start|>assistant\nhello, hello<|im_end|>\n<|im_start|>assistant\n"
+        );
+
+        let template = ChatTemplate::builtin(TemplateStyle::Phi3);
+        assert_eq!(
+            template.special_tokens(),
+            vec!["<|system|>", "<|user|>", "<|assistant|>", "<|end|>"]
+        );
+        assert_eq!(
+            template.render_text(&conversation()[..1], false),
+            "<|system|>\nBe nice<|end|>\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_chat() {
+        let (merges, mut vocab) = train();
+        let template = ChatTemplate::builtin(TemplateStyle::ChatMl);
+        let special_tokens = SpecialTokens::for_template(&template, &merges).unwrap();
+        let max_merge = merges.iter().map(|(_, token)| *token).max().unwrap();
+        assert_eq!(special_tokens.id("<|im_start|>"), Some(max_merge + 1));
+        assert_eq!(special_tokens.id("<|im_end|>"), Some(max_merge + 2));
+
+        let encoding =
+            encode_chat(&conversation(), &template, &merges, &special_tokens, false).unwrap();
+        assert_eq!(encoding.ids.len(), encoding.assistant_mask.len());
+        assert_eq!(encoding.ids[0], special_tokens.id("<|im_start|>").unwrap());
+
+        // Special tokens decode back to the rendered text once added to the vocab
+        special_tokens.extend_vocab(&mut vocab);
+        assert_eq!(
+            decode(encoding.ids.clone(), vocab.clone()).unwrap(),
+            template.render_text(&conversation(), false)
+        );
+
+        // Only the assistant content and its closing tokens are masked in
+        let masked: Vec<u16> = encoding
+            .ids
+            .iter()
+            .zip(encoding.assistant_mask.iter())
+            .filter(|(_, is_assistant)| **is_assistant)
+            .map(|(id, _)| *id)
+            .collect();
+        assert_eq!(decode(masked, vocab).unwrap(), "hello, hello<|im_end|>\n");
+        // The mask is one contiguous run at the end of the conversation
+        let first_masked = encoding.assistant_mask.iter().position(|m| *m).unwrap();
+        assert!(encoding.assistant_mask[first_masked..].iter().all(|m| *m));
+    }
+
+    #[test]
+    fn test_generation_prompt() {
+        let (merges, _) = train();
+        let template = ChatTemplate::builtin(TemplateStyle::Phi3);
+        let special_tokens = SpecialTokens::for_template(&template, &merges).unwrap();
+
+        let without =
+            encode_chat(&conversation(), &template, &merges, &special_tokens, false).unwrap();
+        let with = encode_chat(&conversation(), &template, &merges, &special_tokens, true).unwrap();
+        assert_eq!(&with.ids[..without.ids.len()], &without.ids[..]);
+        assert_eq!(
+            &with.ids[without.ids.len()..],
+            &[special_tokens.id("<|assistant|>").unwrap(), b'\n' as u16]
+        );
+        assert!(!with.assistant_mask[without.ids.len()..].iter().any(|m| *m));
+    }
+
+    #[test]
+    fn test_custom_template() {
+        let template = ChatTemplate::parse("<|bos|>", "[{role}] {content}<|eos|>").unwrap();
+        assert_eq!(template.special_tokens(), vec!["<|bos|>", "<|eos|>"]);
+        assert_eq!(
+            template.render_text(&conversation()[1..2], false),
+            "<|bos|>[user] hello there<|eos|>"
+        );
+
+        // Content is never parsed for special tokens
+        let special_tokens = SpecialTokens::new(&template.special_tokens(), 256).unwrap();
+        let encoding = encode_chat(
+            &[Message::user("<|eos|>")],
+            &template,
+            &[],
+            &special_tokens,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            encoding.ids.iter().filter(|id| **id == 257).count(),
+            1,
+            "Only the template's own eos should be a special token"
+        );
+
+        for (prefix, message) in [
+            ("", "{role}: no content"),
+            ("", "{content}{content}"),
+            ("", "{name}: {content}"),
+            ("", "<|unterminated {content}"),
+            ("{role}", "{content}"),
+            ("<|{role}|>", "{content}"),
+        ] {
+            assert!(
+                ChatTemplate::parse(prefix, message).is_err(),
+                "{prefix:?} {message:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_vocab_overflow() {
+        let template = ChatTemplate::builtin(TemplateStyle::ChatMl);
+        let tokens = template.special_tokens();
+        assert!(SpecialTokens::new(&tokens, u16::MAX - 1).is_ok());
+        assert!(SpecialTokens::new(&tokens, u16::MAX).is_err());
+        assert!(SpecialTokens::for_template(&template, &[((0, 1), u16::MAX - 2)]).is_ok());
+        assert!(SpecialTokens::for_template(&template, &[((0, 1), u16::MAX - 1)]).is_err());
+        assert!(SpecialTokens::for_template(&template, &[((0, 1), u16::MAX)]).is_err());
+    }
+
+    #[test]
+    fn test_missing_special_token() {
+        let template = ChatTemplate::builtin(TemplateStyle::ChatMl);
+        let special_tokens = SpecialTokens::new(&["<|im_start|>".to_owned()], 256).unwrap();
+        assert!(encode_chat(&conversation(), &template, &[], &special_tokens, false).is_err());
+
+        // The plain template doesn't need any
+        let template = ChatTemplate::builtin(TemplateStyle::Plain);
+        let special_tokens = SpecialTokens::for_template(&template, &[]).unwrap();
+        assert!(special_tokens.is_empty());
+        let encoding =
+            encode_chat(&conversation(), &template, &[], &special_tokens, false).unwrap();
+        assert_eq!(
+            encoding.ids.len(),
+            template.render_text(&conversation(), false).len()
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use super::*;
+use proptest::prelude::*;
+mod tests {
+    use super::*;
+
+    // Keep the corpora small, as every case trains a tokenizer
+    const MAX_CORPUS_LEN: usize = 200;
+    const MAX_MERGES: u32 = 40;
+
+    // Text drawn from a small alphabet, so that pairs repeat and merges actually happen
+    fn repetitive_text() -> impl Strategy<Value = String> {
+        proptest::string::string_regex("[ab c\u{e9}\u{1f980}]{0,200}").unwrap()
+    }
+
+    fn any_corpus() -> impl Strategy<Value = Vec<u8>> {
+        prop_oneof![
+            proptest::collection::vec(any::<u8>(), 0..MAX_CORPUS_LEN),
+            repetitive_text().prop_map(String::into_bytes),
+        ]
+    }
+
+    fn assert_in_vocab(encoded: &[u16], vocab: &Vocab) {
+        for token in encoded {
+            assert!(
+                *token <= u8::MAX as u16 || vocab.contains_key(token),
+                "token {token} is not in the vocab"
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_roundtrip_bytes(
+            corpus in any_corpus(),
+            input in proptest::collection::vec(any::<u8>(), 0..MAX_CORPUS_LEN),
+            n_merges in 0..MAX_MERGES,
+        ) {
+            let (merges, vocab) = bpe(corpus.clone(), n_merges).unwrap();
+            prop_assert!(merges.len() <= n_merges as usize);
+
+            for input in [&input, &corpus] {
+                let encoded = encode_bytes(input, &merges);
+                assert_in_vocab(&encoded, &vocab);
+                prop_assert_eq!(&decode_bytes(&encoded, &vocab).unwrap(), input);
+            }
+        }
+
+        #[test]
+        fn test_roundtrip_text(
+            corpus in repetitive_text(),
+            input in prop_oneof![repetitive_text(), any::<String>()],
+            n_merges in 0..MAX_MERGES,
+        ) {
+            let (merges, vocab) = bpe_on_str(&corpus, n_merges).unwrap();
+
+            for input in [&input, &corpus] {
+                let encoded = encode(input, merges.clone()).unwrap();
+                assert_in_vocab(&encoded, &vocab);
+                prop_assert_eq!(&decode(encoded, vocab.clone()).unwrap(), input);
+            }
+        }
+
+        #[test]
+        fn test_fast_encoder_matches_reference(
+            corpus in any_corpus(),
+            input in any_corpus(),
+            n_merges in 0..MAX_MERGES,
+        ) {
+            let (merges, _) = bpe(corpus.clone(), n_merges).unwrap();
+
+            for input in [&input, &corpus] {
+                prop_assert_eq!(
+                    encode_bytes_fast(input, &merges),
+                    encode_bytes(input, &merges)
+                );
+            }
+        }
+
+        #[test]
+        fn test_merges_compress(corpus in any_corpus(), n_merges in 0..MAX_MERGES) {
+            // Every merge was learnt from the corpus, so each one shortens its encoding
+            let (merges, _) = bpe(corpus.clone(), n_merges).unwrap();
+            prop_assert!(encode_bytes(&corpus, &merges).len() + merges.len() <= corpus.len());
+        }
+    }
+
+    #[test]
+    fn test_empty_input() {
+        let (merges, vocab) = bpe_on_str("", 10).unwrap();
+        assert!(merges.is_empty());
+        assert_eq!(encode("", merges.clone()).unwrap(), Vec::<u16>::new());
+        assert_eq!(encode_fast("", &merges).unwrap(), Vec::<u16>::new());
+        assert_eq!(decode(Vec::new(), vocab).unwrap(), "");
+    }
+
+    #[test]
+    fn test_unknown_token() {
+        let (_, vocab) = bpe_on_str("aaaa", 1).unwrap();
+        assert!(decode_bytes(&[u16::MAX], &vocab).is_err());
+    }
+}
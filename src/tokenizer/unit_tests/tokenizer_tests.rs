@@ -17,7 +17,7 @@ mod tests {
 
         let vocab_words: HashSet<String> = vocab
             .values()
-            .flat_map(|vocab_bytes| str::from_utf8(&vocab_bytes).ok())
+            .flat_map(|vocab_bytes| str::from_utf8(vocab_bytes).ok())
             .map(|val| val.to_owned())
             .collect();
 
@@ -38,7 +38,7 @@ mod tests {
         let encoded = encode(&input_file_as_str, merges.clone()).unwrap();
 
         let mut merge_token_occs: HashMap<u16, u16> =
-            merges.iter().map(|(_, token)| (token.clone(), 0)).collect();
+            merges.iter().map(|(_, token)| (*token, 0)).collect();
 
         for text_char in encoded.iter() {
             if let Some(count) = merge_token_occs.get_mut(text_char) {
@@ -5,7 +5,7 @@ pub fn to_word_tokens(input_bytes: Vec<u8>, split_byte: u8) -> Vec<(Vec<u16>, u3
     let mut current_word: Vec<u16> = Vec::new();
 
     for input_byte in input_bytes {
-        if input_byte == split_byte && current_word.len() > 0 {
+        if input_byte == split_byte && !current_word.is_empty() {
             if let Some(n_occurances) = words.get_mut(&current_word) {
                 *n_occurances += 1;
             } else {
@@ -15,12 +15,8 @@ pub fn to_word_tokens(input_bytes: Vec<u8>, split_byte: u8) -> Vec<(Vec<u16>, u3
         }
         current_word.push(input_byte as u16);
     }
-    if current_word.len() > 0 {
-        if words.contains_key(&current_word) {
-            *words.get_mut(&current_word).unwrap() += 1;
-        } else {
-            words.insert(current_word, 1);
-        }
+    if !current_word.is_empty() {
+        *words.entry(current_word).or_insert(0) += 1;
     }
     words.drain().collect()
 }
@@ -32,7 +28,7 @@ mod tests {
     #[test]
     fn test_split() {
         // Make sure our splitting function works ok
-        let split_byte: u8 = ' ' as u8;
+        let split_byte: u8 = b' ';
 
         // Check a repeated string with a space before
         let n_repeats: u32 = 6;